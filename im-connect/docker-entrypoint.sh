@@ -1,6 +1,10 @@
 #!/bin/bash
 set -e
 
+# 受信任的反向代理地址（逗号分隔的 IP 或 CIDR 网段），转换为 TOML 字符串数组
+TRUSTED_PROXIES="${TRUSTED_PROXIES:-127.0.0.1,::1}"
+TRUSTED_PROXIES_TOML=$(echo "$TRUSTED_PROXIES" | tr -d ' ' | sed 's/,*$//; s/^/"/; s/,/", "/g; s/$/"/')
+
 # 从环境变量生成配置文件
 cat > /app/config.toml <<EOF
 [mqtt]
@@ -9,6 +13,17 @@ port = ${MQTT_PORT:-1883}
 
 [connect]
 port = ${CONNECT_PORT:-3001}
+node_id = "${IM_CONNECT_NODE_ID:-}"
+
+[admin]
+token = "${IM_CONNECT_ADMIN_TOKEN:-}"
+
+[im_server]
+url = "${IM_SERVER_URL:-http://im-server:3000}"
+internal_token = "${IM_INTERNAL_TOKEN:-}"
+
+[limits]
+trusted_proxies = [${TRUSTED_PROXIES_TOML}]
 
 [redis]
 host = "${REDIS_HOST:-redis}"
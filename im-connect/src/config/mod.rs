@@ -1,7 +1,7 @@
 mod settings;
 
 use std::{fs, path::Path};
-pub use settings::AppConfig;
+pub use settings::{AdminSettings, AppConfig, BackpressureSettings, DeliverySettings, ImServerSettings, LimitSettings, ShutdownSettings, TrustedProxy};
 
 impl AppConfig {
     pub fn load() -> Self {
@@ -11,12 +11,13 @@ impl AppConfig {
         
         // 读取配置文件，如果文件不存在则使用默认配置
         let config: AppConfig = fs::read_to_string(Path::new(&path)).map(|content| {
-            toml::from_str(&content).expect(&format!("invalid im-connect config file: {}", path))
+            toml::from_str(&content).unwrap_or_else(|_| panic!("invalid im-connect config file: {}", path))
         }).unwrap_or_else(|_| {
             let default_content = r#"
 [mqtt]
 host = "127.0.0.1"
 port = 1883
+pool_size = 4
 
 [connect]
 port = 3001
@@ -30,6 +31,30 @@ db = 0
 [jwt]
 secret = "your-secret-key-change-in-production"
 expiration_hours = 24
+
+[im_server]
+url = "http://127.0.0.1:3000"
+
+[delivery]
+ack_timeout_ms = 10000
+max_retries = 3
+max_pending = 256
+
+[backpressure]
+queue_capacity = 256
+write_timeout_ms = 10000
+
+[limits]
+max_connections = 0
+max_connections_per_user = 10
+max_connections_per_ip = 200
+max_missed_heartbeats = 3
+trusted_proxies = ["127.0.0.1", "::1"]
+
+[shutdown]
+drain_secs = 20
+reconnect_min_ms = 1000
+reconnect_max_ms = 10000
 "#;
             toml::from_str(default_content).expect("invalid default config")
         });
@@ -1,15 +1,25 @@
 use serde::Deserialize;
+use std::net::IpAddr;
+use std::str::FromStr;
 use im_share::JwtSettings;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct MqttSettings {
     pub host: String,
     pub port: u16,
+    #[serde(default = "default_mqtt_pool_size")]
+    pub pool_size: usize,  // 共享 MQTT 连接数，用户 topic 按哈希分布到各个连接上
+}
+
+fn default_mqtt_pool_size() -> usize {
+    4
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConnectSettings {
     pub port: u16,
+    #[serde(default)]
+    pub node_id: Option<String>,  // 节点 ID（跨节点路由表和节点 topic 使用），多个实例必须不同；不配置时启动时随机生成
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -31,6 +41,188 @@ fn default_redis_db() -> u8 {
     0
 }
 
+/// 端到端送达确认：客户端连接时通过 ?ack=true 开启
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeliverySettings {
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,  // 推送后多久没有收到确认就重发
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,  // 最多重发次数，仍未确认时断开连接，未确认的消息转存为离线消息
+    #[serde(default = "default_max_pending")]
+    pub max_pending: usize,  // 每个连接最多等待确认的消息数，超出时最早的一条直接转存为离线消息
+}
+
+fn default_ack_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_max_pending() -> usize {
+    256
+}
+
+/// 连接数上限和心跳超时（上限为 0 表示不限制）
+#[derive(Debug, Clone, Deserialize)]
+pub struct LimitSettings {
+    #[serde(default)]
+    pub max_connections: usize,  // 本节点最多保持的连接数，超出时拒绝新连接（关闭码 1013 / HTTP 503）
+    #[serde(default = "default_max_connections_per_user")]
+    pub max_connections_per_user: usize,  // 同一用户最多同时保持的连接数（关闭码 4029 / HTTP 429）
+    #[serde(default = "default_max_connections_per_ip")]
+    pub max_connections_per_ip: usize,  // 同一 IP 最多同时保持的连接数（关闭码 4029 / HTTP 429）
+    #[serde(default = "default_max_missed_heartbeats")]
+    pub max_missed_heartbeats: u32,  // WebSocket 连续这么多个心跳周期没有收到客户端的任何帧（包括 pong）时断开（关闭码 4008）
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: Vec<TrustedProxy>,  // 只有来自这些地址（单个 IP 或 CIDR 网段）的请求才使用 X-Real-IP 作为客户端 IP，其他请求使用 TCP 对端地址
+}
+
+fn default_max_connections_per_user() -> usize {
+    10
+}
+
+fn default_max_connections_per_ip() -> usize {
+    200
+}
+
+fn default_max_missed_heartbeats() -> u32 {
+    3
+}
+
+fn default_trusted_proxies() -> Vec<TrustedProxy> {
+    ["127.0.0.1", "::1"]
+        .iter()
+        .map(|s| s.parse().expect("invalid default trusted proxy"))
+        .collect()
+}
+
+/// 受信任的代理地址：单个 IP（如 "127.0.0.1"）或 CIDR 网段（如 docker 网络的 "172.28.0.0/16"）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl TrustedProxy {
+    /// 地址是否在该网段内（IPv4 映射的 IPv6 地址按 IPv4 比较）
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net) as u128, u32::from(ip) as u128, 32, self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    let shift = bits - prefix_len;
+    if shift >= bits {
+        return true;
+    }
+    (net >> shift) == (ip >> shift)
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network = addr
+            .parse::<IpAddr>()
+            .map_err(|_| format!("无效的代理地址: {}", s))?
+            .to_canonical();
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("无效的网段前缀长度: {}", s))?,
+            None => max_len,
+        };
+        Ok(Self { network, prefix_len })
+    }
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// 管理接口（/admin/*）
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct AdminSettings {
+    #[serde(default)]
+    pub token: Option<String>,  // 管理接口的 Bearer token，不配置或为空时管理接口不可用
+}
+
+/// 客户端通过长连接发送的命令（发消息、已读、正在输入）转发到 im-server 的内部接口（/internal/*）
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImServerSettings {
+    #[serde(default = "default_im_server_url")]
+    pub url: String,  // im-server 地址（Docker 环境为 http://im-server:3000）
+    #[serde(default)]
+    pub internal_token: Option<String>,  // 内部接口的共享密钥，必须与 im-server 的 [internal] token 相同；不配置时命令返回 UPSTREAM 错误
+}
+
+fn default_im_server_url() -> String {
+    "http://127.0.0.1:3000".to_string()
+}
+
+/// 每个连接的发送队列：客户端写得慢时推送在队列中积压
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackpressureSettings {
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,  // 每个连接最多积压的推送数，满时丢弃最早的临时推送；没有可丢弃的临时推送时转存离线消息并断开
+    #[serde(default = "default_write_timeout_ms")]
+    pub write_timeout_ms: u64,  // 单帧写入 socket 的超时时间（毫秒），超时视为客户端已经无法接收，断开连接
+}
+
+fn default_queue_capacity() -> usize {
+    256
+}
+
+fn default_write_timeout_ms() -> u64 {
+    10_000
+}
+
+/// 优雅退出：收到 SIGTERM 后停止接受新连接，通知客户端到其他节点重连
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShutdownSettings {
+    #[serde(default = "default_drain_secs")]
+    pub drain_secs: u64,  // 等待连接写完待发送的帧、转存未确认消息并关闭的最长时间（秒），超时后直接退出
+    #[serde(default = "default_reconnect_min_ms")]
+    pub reconnect_min_ms: u64,  // reconnect 帧中建议的重连等待时间下限（毫秒）
+    #[serde(default = "default_reconnect_max_ms")]
+    pub reconnect_max_ms: u64,  // 重连等待时间上限（毫秒），每个连接在上下限之间随机，避免所有客户端同时重连
+}
+
+fn default_drain_secs() -> u64 {
+    20
+}
+
+fn default_reconnect_min_ms() -> u64 {
+    1_000
+}
+
+fn default_reconnect_max_ms() -> u64 {
+    10_000
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub mqtt: MqttSettings,
@@ -39,6 +231,18 @@ pub struct AppConfig {
     pub redis: RedisSettings,
     #[serde(default = "default_jwt_settings")]
     pub jwt: JwtSettings,
+    #[serde(default = "default_delivery_settings")]
+    pub delivery: DeliverySettings,
+    #[serde(default = "default_shutdown_settings")]
+    pub shutdown: ShutdownSettings,
+    #[serde(default = "default_backpressure_settings")]
+    pub backpressure: BackpressureSettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
+    #[serde(default = "default_limit_settings")]
+    pub limits: LimitSettings,
+    #[serde(default = "default_im_server_settings")]
+    pub im_server: ImServerSettings,
 }
 
 fn default_redis_settings() -> RedisSettings {
@@ -57,3 +261,92 @@ fn default_jwt_settings() -> JwtSettings {
     }
 }
 
+fn default_delivery_settings() -> DeliverySettings {
+    DeliverySettings {
+        ack_timeout_ms: default_ack_timeout_ms(),
+        max_retries: default_max_retries(),
+        max_pending: default_max_pending(),
+    }
+}
+
+fn default_limit_settings() -> LimitSettings {
+    LimitSettings {
+        max_connections: 0,
+        max_connections_per_user: default_max_connections_per_user(),
+        max_connections_per_ip: default_max_connections_per_ip(),
+        max_missed_heartbeats: default_max_missed_heartbeats(),
+        trusted_proxies: default_trusted_proxies(),
+    }
+}
+
+fn default_im_server_settings() -> ImServerSettings {
+    ImServerSettings {
+        url: default_im_server_url(),
+        internal_token: None,
+    }
+}
+
+fn default_backpressure_settings() -> BackpressureSettings {
+    BackpressureSettings {
+        queue_capacity: default_queue_capacity(),
+        write_timeout_ms: default_write_timeout_ms(),
+    }
+}
+
+fn default_shutdown_settings() -> ShutdownSettings {
+    ShutdownSettings {
+        drain_secs: default_drain_secs(),
+        reconnect_min_ms: default_reconnect_min_ms(),
+        reconnect_max_ms: default_reconnect_max_ms(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn single_address_matches_only_itself() {
+        let proxy: TrustedProxy = "127.0.0.1".parse().unwrap();
+        assert!(proxy.contains(ip("127.0.0.1")));
+        assert!(proxy.contains(ip("::ffff:127.0.0.1")));
+        assert!(!proxy.contains(ip("127.0.0.2")));
+        assert!(!proxy.contains(ip("::1")));
+    }
+
+    #[test]
+    fn cidr_matches_whole_subnet() {
+        let proxy: TrustedProxy = "172.28.0.0/16".parse().unwrap();
+        assert!(proxy.contains(ip("172.28.0.5")));
+        assert!(proxy.contains(ip("172.28.255.255")));
+        assert!(!proxy.contains(ip("172.29.0.1")));
+
+        let v6: TrustedProxy = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12:3456::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+
+        let any: TrustedProxy = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("8.8.8.8")));
+        assert!(!any.contains(ip("2001:4860::8888")));
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!("nginx".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("::/129".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/".parse::<TrustedProxy>().is_err());
+    }
+
+    #[test]
+    fn deserializes_from_config() {
+        let limits: LimitSettings =
+            toml::from_str(r#"trusted_proxies = ["::1", "172.28.0.0/16"]"#).unwrap();
+        assert_eq!(limits.trusted_proxies.len(), 2);
+        assert!(limits.trusted_proxies.iter().any(|p| p.contains(ip("172.28.3.4"))));
+    }
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Path, Query, Extension, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    http::{StatusCode, header},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+use im_share::NoticeFrame;
+use crate::config::AdminSettings;
+use crate::handlers::registry::ConnectionRegistry;
+
+// im-connect 管理接口：查看在线连接、强制断开连接、广播系统通知
+// 只作用于本进程内的连接；部署多个节点时需要逐个节点调用
+// 请求需要带 Authorization: Bearer <[admin] token>，没有配置 token 时接口不可用
+
+/// 断开连接时默认的原因（写入关闭帧）
+const DEFAULT_DISCONNECT_REASON: &str = "disconnected by admin";
+
+/// 比较管理 token，耗时与内容无关
+fn token_matches(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected.bytes().zip(provided.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// 管理接口鉴权中间件
+pub async fn require_admin(
+    Extension(admin_cfg): Extension<AdminSettings>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = admin_cfg.token.as_deref().filter(|t| !t.is_empty()) else {
+        return (StatusCode::NOT_FOUND, "管理接口未启用").into_response();
+    };
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "));
+    match provided {
+        Some(token) if token_matches(expected, token) => next.run(request).await,
+        _ => {
+            warn!(path = %request.uri().path(), "管理接口鉴权失败");
+            (StatusCode::UNAUTHORIZED, "无效的管理 token").into_response()
+        }
+    }
+}
+
+/// 列出所有在线连接
+pub async fn list_connections(
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+) -> impl IntoResponse {
+    let connections = registry.list();
+    Json(serde_json::json!({
+        "count": connections.len(),
+        "connections": connections,
+    }))
+}
+
+/// 查询某个用户的在线连接
+pub async fn get_user_connections(
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    Path(open_id): Path<String>,
+) -> impl IntoResponse {
+    let connections = registry.by_user(&open_id);
+    Json(serde_json::json!({
+        "open_id": open_id,
+        "count": connections.len(),
+        "connections": connections,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct DisconnectQuery {
+    /// 写入关闭帧的原因
+    pub reason: Option<String>,
+}
+
+/// 强制断开某个连接（不吊销会话，客户端可以重连）
+pub async fn disconnect_connection(
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    Path(connection_id): Path<u64>,
+    Query(query): Query<DisconnectQuery>,
+) -> impl IntoResponse {
+    let reason = query.reason.unwrap_or_else(|| DEFAULT_DISCONNECT_REASON.to_string());
+    if !registry.disconnect(connection_id, &reason) {
+        return (StatusCode::NOT_FOUND, "连接不存在").into_response();
+    }
+    info!(connection_id = connection_id, reason = %reason, "管理员断开连接");
+    Json(serde_json::json!({"disconnected": 1})).into_response()
+}
+
+/// 强制断开某个用户的所有连接
+pub async fn disconnect_user(
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    Path(open_id): Path<String>,
+    Query(query): Query<DisconnectQuery>,
+) -> impl IntoResponse {
+    let reason = query.reason.unwrap_or_else(|| DEFAULT_DISCONNECT_REASON.to_string());
+    let disconnected = registry.disconnect_user(&open_id, &reason);
+    info!(open_id = %open_id, disconnected = disconnected, reason = %reason, "管理员断开用户的所有连接");
+    Json(serde_json::json!({"disconnected": disconnected}))
+}
+
+#[derive(Deserialize)]
+pub struct BroadcastNoticeRequest {
+    pub message: String,
+    /// info / warning / critical，默认 info
+    pub level: Option<String>,
+}
+
+/// 向本进程所有在线连接广播系统通知
+pub async fn broadcast_notice(
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    Json(req): Json<BroadcastNoticeRequest>,
+) -> impl IntoResponse {
+    if req.message.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "通知内容不能为空").into_response();
+    }
+    let notice = NoticeFrame::new(req.message, req.level.unwrap_or_else(|| "info".to_string()));
+    let delivered = registry.broadcast(&notice);
+    info!(delivered = delivered, total = registry.count(), level = %notice.level, "管理员广播系统通知");
+    Json(serde_json::json!({"delivered": delivered})).into_response()
+}
@@ -0,0 +1,141 @@
+use axum::body::Bytes;
+use axum::extract::ws::{Message, Utf8Bytes, WebSocket};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use im_share::{WireFormat, encode_frame, is_expired, now_timestamp};
+use im_share::inbox::SEQ_FIELD;
+
+/// 一条发往客户端的推送（MQTT / Redis 中保存的 JSON）
+/// 只解析一次，去重、送达确认、过期判断、日志和 MessagePack 转码都使用这份结果
+pub struct Push {
+    pub text: String,
+    pub json: Option<serde_json::Value>,
+}
+
+impl Push {
+    pub fn new(text: String) -> Self {
+        let json = serde_json::from_str(&text).ok();
+        Self { text, json }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&serde_json::Value> {
+        self.json.as_ref()?.get(name)
+    }
+
+    pub fn seq(&self) -> Option<i64> {
+        self.field(SEQ_FIELD)?.as_i64()
+    }
+
+    pub fn message_id(&self) -> Option<&str> {
+        self.field("message_id")?.as_str()
+    }
+
+    /// 信封中声明的有效期（expires_at / ttl）已过，不再推送
+    pub fn is_expired(&self) -> bool {
+        self.json.as_ref().is_some_and(|json| is_expired(json, now_timestamp()))
+    }
+}
+
+enum Transport {
+    WebSocket(Box<WebSocket>),
+    /// SSE / 长轮询：发往客户端的帧写入有界通道，由 HTTP 响应取走；客户端通过 HTTP 提交的命令从 inbound 读出
+    Channel {
+        outbound: mpsc::Sender<Message>,
+        inbound: mpsc::Receiver<Message>,
+    },
+}
+
+/// HTTP 传输（SSE / 长轮询）一侧的通道端点
+pub struct ChannelEnds {
+    /// 发往客户端的帧，ping 和 close 也会写入，由 HTTP 响应自行转换或忽略
+    pub frames: mpsc::Receiver<Message>,
+    /// 客户端提交的命令帧，全部 drop 后连接视为已关闭
+    pub commands: mpsc::Sender<Message>,
+}
+
+/// 客户端连接，发往客户端的帧都按握手时协商的编码写出：
+/// JSON 为文本帧，MessagePack 为二进制帧（SSE / 长轮询只使用 JSON）
+/// 每次写入都有超时，客户端停止读取导致写入一直阻塞时返回错误，连接随之断开
+pub struct ClientSocket {
+    transport: Transport,
+    format: WireFormat,
+    write_timeout: Duration,
+}
+
+impl ClientSocket {
+    pub fn new(socket: WebSocket, format: WireFormat, write_timeout: Duration) -> Self {
+        Self { transport: Transport::WebSocket(Box::new(socket)), format, write_timeout }
+    }
+
+    /// 基于通道的连接（SSE / 长轮询），capacity 为还没有被 HTTP 响应取走的帧数上限
+    pub fn channel(capacity: usize, write_timeout: Duration) -> (Self, ChannelEnds) {
+        let (outbound, frames) = mpsc::channel(capacity.max(1));
+        let (commands, inbound) = mpsc::channel(capacity.max(1));
+        let socket = Self {
+            transport: Transport::Channel { outbound, inbound },
+            format: WireFormat::Json,
+            write_timeout,
+        };
+        (socket, ChannelEnds { frames, commands })
+    }
+
+    /// 只有 WebSocket 能收到客户端的 pong，心跳超时检查只对它生效
+    /// SSE / 长轮询的客户端消失由写入失败和会话空闲回收发现
+    pub fn is_websocket(&self) -> bool {
+        matches!(self.transport, Transport::WebSocket(_))
+    }
+
+    /// 原样发送（ping/pong/close 等控制帧）
+    pub async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        let write = async {
+            match &mut self.transport {
+                Transport::WebSocket(socket) => socket.send(message).await,
+                Transport::Channel { outbound, .. } => outbound.send(message).await.map_err(|_| {
+                    axum::Error::new(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "客户端已断开"))
+                }),
+            }
+        };
+        match tokio::time::timeout(self.write_timeout, write).await {
+            Ok(result) => result,
+            Err(_) => Err(axum::Error::new(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "写入客户端连接超时，客户端接收太慢",
+            ))),
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        match &mut self.transport {
+            Transport::WebSocket(socket) => socket.recv().await,
+            Transport::Channel { inbound, .. } => inbound.recv().await.map(Ok),
+        }
+    }
+
+    /// 发送响应帧、resync 帧等由 im-connect 生成的帧
+    pub async fn send_frame<T: Serialize + ?Sized>(&mut self, frame: &T) -> Result<(), axum::Error> {
+        let bytes = encode_frame(self.format, frame).map_err(axum::Error::new)?;
+        let message = match self.format {
+            WireFormat::Json => Message::Text(Utf8Bytes::try_from(bytes).map_err(axum::Error::new)?),
+            WireFormat::MessagePack => Message::Binary(Bytes::from(bytes)),
+        };
+        self.send(message).await
+    }
+
+    /// 发送推送：JSON 连接直接转发原文，MessagePack 连接转码；不是 JSON 的推送原样作为文本帧转发
+    pub async fn send_push(&mut self, push: &Push) -> Result<(), axum::Error> {
+        match (&push.json, self.format) {
+            (Some(json), WireFormat::MessagePack) => self.send_frame(json).await,
+            _ => self.send(Message::Text(Utf8Bytes::from(&push.text))).await,
+        }
+    }
+}
+
+/// 客户端发来的请求帧：文本帧按 JSON 解析，二进制帧按 MessagePack 解析
+pub fn client_frame(message: Message) -> Option<(WireFormat, Bytes)> {
+    match message {
+        Message::Text(text) => Some((WireFormat::Json, text.into())),
+        Message::Binary(data) => Some((WireFormat::MessagePack, data)),
+        _ => None,
+    }
+}
@@ -0,0 +1,176 @@
+use im_share::{ClientCommand, ClientFrame, ServerResponse, WireFormat, decode_frame};
+use im_share::auth::{ACTING_USER_HEADER, INTERNAL_TOKEN_HEADER};
+use im_share::protocol::{MarkReadCommand, SendMessageCommand, TypingCommand};
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use crate::config::ImServerSettings;
+use crate::handlers::delivery::DeliveryTracker;
+use crate::handlers::presence::ConnectionPresence;
+use tracing::{debug, warn};
+
+/// 转发命令到 im-server 的超时时间
+const UPSTREAM_TIMEOUT_SECS: u64 = 10;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(UPSTREAM_TIMEOUT_SECS))
+        .build()
+        .expect("创建 HTTP 客户端失败")
+});
+
+/// 转发到 im-server 失败时的错误（code 与 im-server 的 ErrorCode 一致）
+struct UpstreamError {
+    code: String,
+    message: String,
+}
+
+impl UpstreamError {
+    fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { code: code.into(), message: message.into() }
+    }
+}
+
+/// 以当前连接用户的身份调用 im-server 内部接口
+/// 以 [im_server] internal_token 认证并指明用户，不使用握手时的 token（连接可能比 token 活得久）；
+/// im-server 的处理程序与公开接口相同，会照常做权限检查
+#[derive(Clone)]
+pub struct ImServerClient {
+    base_url: String,
+    internal_token: Option<String>,
+    open_id: String,
+}
+
+impl ImServerClient {
+    pub fn new(settings: &ImServerSettings, open_id: String) -> Self {
+        Self {
+            base_url: settings.url.trim_end_matches('/').to_string(),
+            internal_token: settings.internal_token.clone().filter(|t| !t.is_empty()),
+            open_id,
+        }
+    }
+
+    async fn call(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> Result<serde_json::Value, UpstreamError> {
+        let Some(internal_token) = self.internal_token.as_deref() else {
+            warn!(open_id = %self.open_id, "未配置 [im_server] internal_token，无法转发命令");
+            return Err(UpstreamError::new("UPSTREAM", "服务暂时不可用"));
+        };
+        let url = format!("{}/internal{}", self.base_url, path);
+        let mut request = HTTP_CLIENT
+            .request(method, &url)
+            .header(INTERNAL_TOKEN_HEADER, internal_token)
+            .header(ACTING_USER_HEADER, &self.open_id);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            warn!(url = %url, error = %e, "转发命令到 im-server 失败");
+            UpstreamError::new("UPSTREAM", "服务暂时不可用")
+        })?;
+        let status = response.status();
+        let body: serde_json::Value = response.json().await.unwrap_or(serde_json::Value::Null);
+
+        if status.is_success() {
+            Ok(body)
+        } else {
+            // im-server 的错误响应格式：{"code": "INVALID_INPUT", "message": "..."}
+            let code = body.get("code").and_then(|v| v.as_str()).unwrap_or("UPSTREAM");
+            let message = body.get("message").and_then(|v| v.as_str()).unwrap_or("请求失败");
+            Err(UpstreamError::new(code, message))
+        }
+    }
+
+    async fn send_message(&self, cmd: SendMessageCommand) -> Result<serde_json::Value, UpstreamError> {
+        match cmd.chat_type {
+            1 => self.call(Method::POST, "/im/messages/single", Some(serde_json::json!({
+                "from_id": self.open_id,
+                "to_id": cmd.to_id,
+                "message_body": cmd.message_body,
+                "message_content_type": cmd.message_content_type,
+                "extra": cmd.extra,
+                "reply_to": cmd.reply_to,
+            }))).await,
+            2 => self.call(Method::POST, "/im/messages/group", Some(serde_json::json!({
+                "group_id": cmd.to_id,
+                "from_id": self.open_id,
+                "message_body": cmd.message_body,
+                "message_content_type": cmd.message_content_type,
+                "extra": cmd.extra,
+                "reply_to": cmd.reply_to,
+            }))).await,
+            _ => Err(UpstreamError::new("INVALID_INPUT", "无效的 chat_type")),
+        }
+    }
+
+    async fn mark_read(&self, cmd: MarkReadCommand) -> Result<serde_json::Value, UpstreamError> {
+        let message_id = urlencoding::encode(&cmd.message_id);
+        match (cmd.chat_type, cmd.group_id) {
+            (1, _) => self.call(Method::POST, &format!("/im/messages/single/{}/read", message_id), None).await,
+            (2, Some(group_id)) => {
+                let group_id = urlencoding::encode(&group_id);
+                self.call(Method::POST, &format!("/im/messages/group/{}/{}/read", group_id, message_id), None).await
+            }
+            (2, None) => Err(UpstreamError::new("INVALID_INPUT", "群聊消息需要 group_id")),
+            _ => Err(UpstreamError::new("INVALID_INPUT", "无效的 chat_type")),
+        }
+    }
+
+    async fn typing(&self, cmd: TypingCommand) -> Result<serde_json::Value, UpstreamError> {
+        self.call(Method::POST, "/im/typing", Some(serde_json::json!({
+            "chat_type": cmd.chat_type,
+            "to_id": cmd.to_id,
+            "typing": cmd.typing,
+        }))).await
+    }
+}
+
+/// 尽量从请求帧中取出请求ID（帧无法完整解析时也能带回给客户端）
+pub fn frame_id(data: &[u8], format: WireFormat) -> String {
+    decode_frame::<serde_json::Value>(format, data)
+        .ok()
+        .and_then(|v| v.get("id").and_then(|id| id.as_str()).map(|s| s.to_string()))
+        .unwrap_or_default()
+}
+
+/// 处理客户端发来的请求帧，返回发回同一连接的响应帧
+pub async fn handle_client_frame(
+    data: &[u8],
+    format: WireFormat,
+    client: &ImServerClient,
+    presence: &ConnectionPresence,
+    delivery: &DeliveryTracker,
+) -> ServerResponse {
+    let frame = match decode_frame::<ClientFrame>(format, data) {
+        Ok(frame) => frame,
+        Err(e) => {
+            // 尽量带回请求ID，方便客户端对应
+            return ServerResponse::error(frame_id(data, format), "BAD_FRAME", format!("无法解析请求帧: {}", e));
+        }
+    };
+
+    let result = match frame.command {
+        ClientCommand::SendMessage(cmd) => client.send_message(cmd).await,
+        ClientCommand::MarkRead(cmd) => client.mark_read(cmd).await,
+        ClientCommand::Typing(cmd) => client.typing(cmd).await,
+        ClientCommand::Ack(cmd) => {
+            let acked = delivery.ack(&cmd.seqs, &cmd.message_ids).await;
+            debug!(open_id = %client.open_id, seqs = cmd.seqs.len(), message_ids = cmd.message_ids.len(), acked = acked, "收到客户端消息确认");
+            Ok(serde_json::json!({"acked": acked}))
+        }
+        ClientCommand::SetPresence(cmd) => presence
+            .set_status(cmd.status)
+            .await
+            .map(|_| serde_json::json!({"status": cmd.status}))
+            .map_err(|e| {
+                warn!(open_id = %client.open_id, error = %e, "更新在线状态失败");
+                UpstreamError::new("INTERNAL", "更新在线状态失败")
+            }),
+        // 补发在连接建立时完成，之后再收到 resume 说明客户端时序有误
+        ClientCommand::Resume(_) => Err(UpstreamError::new("BAD_FRAME", "resume 只能作为连接建立后的第一帧")),
+    };
+
+    match result {
+        Ok(data) => ServerResponse::ok(frame.id, Some(data)),
+        Err(e) => ServerResponse::error(frame.id, e.code, e.message),
+    }
+}
@@ -0,0 +1,1071 @@
+use axum::{
+    extract::ws::{Message, CloseFrame},
+    response::{IntoResponse, Response},
+    http::{HeaderMap, StatusCode, header},
+};
+use axum::body::Bytes;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn, error};
+use im_share::{mqtt_user_topic, get_subscription_identity, now_timestamp, RedisClient, verify_token, JwtSettings, ServerResponse, ServerFrame, PresenceRegistry, RouteRegistry, ClientFrame, ClientCommand, InboxLog, OfflineQueue, WireFormat, decode_frame};
+use im_share::protocol::{ReconnectFrame, ResyncFrame, ADMIN_DISCONNECT_CLOSE_CODE, HEARTBEAT_TIMEOUT_CLOSE_CODE, RECONNECT_CLOSE_CODE, SLOW_CONSUMER_CLOSE_CODE};
+use im_share::redeem_ws_ticket;
+use im_share::ws_ticket::ticket_from_subprotocols;
+use im_share::presence::PRESENCE_HEARTBEAT_SECS;
+use im_share::inbox::INBOX_LOG_MAX_ENTRIES;
+use im_share::{is_session_revoked, IncomingMessage, Shutdown, ShutdownGuard};
+use im_share::session::{KICKED_CLOSE_CODE, KICKED_EVENT_TYPE};
+use crate::config::{DeliverySettings, ImServerSettings, LimitSettings, ShutdownSettings, TrustedProxy};
+use crate::handlers::codec::{ClientSocket, Push, client_frame};
+use crate::handlers::commands::{ImServerClient, frame_id, handle_client_frame};
+use crate::handlers::delivery::{DeliveryTracker, PendingMessage, RETRANSMIT_CHECK_INTERVAL_MS};
+use crate::handlers::presence::ConnectionPresence;
+use crate::handlers::registry::{ConnectionInfo, ConnectionRegistry, ControlCommand, LimitExceeded, RegisteredConnection};
+use crate::mqtt::{MqttPool, Outbound, TopicSubscription};
+
+// 一次客户端连接（WebSocket、SSE、长轮询共用）
+// 认证、订阅用户 topic、离线补发、送达确认、在线状态和优雅退出都在这里，传输方式只决定 ClientSocket 的读写
+
+/// 客户端声明 ?resume=true 时等待第一帧 resume 命令的时间（毫秒），超时按没有补发起点处理
+const RESUME_FRAME_TIMEOUT_MS: u64 = 1000;
+
+/// 每个连接同时处理中的客户端命令上限，超过时直接回复 BUSY，不再转发给 im-server
+const MAX_INFLIGHT_COMMANDS: usize = 8;
+
+#[derive(Deserialize)]
+pub struct ConnectQuery {
+    /// 断线重连时客户端已处理的最后一条推送的 seq，也可以在连接后第一帧通过 resume 命令提供
+    pub last_seq: Option<i64>,
+    /// 客户端会在连接后第一帧发送 resume 命令：补发前等待这一帧（最多 RESUME_FRAME_TIMEOUT_MS）
+    #[serde(default)]
+    pub resume: bool,
+    /// 开启端到端送达确认：带 seq 的推送需要客户端 ack，超时重发
+    #[serde(default)]
+    pub ack: bool,
+    /// im-server 签发的一次性票据（浏览器无法设置 Authorization 头），WebSocket 也可以通过 Sec-WebSocket-Protocol: ticket.<票据> 提交
+    pub ticket: Option<String>,
+}
+
+/// 一次连接请求（WebSocket 握手、SSE、长轮询）
+pub struct ConnectRequest {
+    /// websocket / sse / long_poll
+    pub transport: &'static str,
+    pub subscription_id: String,
+    pub query: ConnectQuery,
+    pub headers: HeaderMap,
+    /// TCP 对端地址，经过 nginx 时为 nginx 的地址
+    pub peer_addr: SocketAddr,
+}
+
+impl ConnectRequest {
+    /// 客户端 IP：对端是受信任的代理时使用 X-Real-IP，否则使用 TCP 对端地址（防止客户端伪造请求头绕过同一 IP 的连接数上限）
+    fn remote_addr(&self, trusted_proxies: &[TrustedProxy]) -> String {
+        let peer_ip = self.peer_addr.ip().to_canonical();
+        if !trusted_proxies.iter().any(|proxy| proxy.contains(peer_ip)) {
+            return peer_ip.to_string();
+        }
+        self.headers
+            .get("x-real-ip")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.trim().parse::<IpAddr>().ok())
+            .unwrap_or(peer_ip)
+            .to_string()
+    }
+}
+
+/// 拒绝连接请求的原因
+pub enum Rejection {
+    /// 直接返回的 HTTP 响应（节点下线、认证失败等）
+    Response(Response),
+    /// 超过连接数上限：WebSocket 完成握手后用关闭码告诉客户端是否应该重连，SSE / 长轮询返回 503 / 429
+    Limit(LimitExceeded),
+}
+
+impl From<Response> for Rejection {
+    fn from(response: Response) -> Self {
+        Rejection::Response(response)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Rejection::Response(response) => response,
+            Rejection::Limit(limit) => (limit.status(), axum::Json(serde_json::json!({
+                "code": limit.close_code(),
+                "reason": limit.reason(),
+            }))).into_response(),
+        }
+    }
+}
+
+/// 认证通过、等待建立的连接（已登记到连接表并占用连接数配额）
+pub struct Connection {
+    mqtt_pool: Arc<MqttPool>,
+    redis_client: Arc<RedisClient>,
+    control: RegisteredConnection,
+    max_missed_heartbeats: u32,
+    pub subscription_id: String,
+    user_mqtt_id: u64,
+    pub user_open_id: String,
+    server_client: ImServerClient,
+    resume_seq: Option<i64>,
+    wait_resume: bool,
+    delivery: DeliveryTracker,
+    shutdown: Shutdown,
+    shutdown_cfg: ShutdownSettings,
+    _guard: ShutdownGuard,
+}
+
+/// 校验连接请求（节点状态、连接数上限、token 或一次性票据、会话吊销），解析用户身份
+#[allow(clippy::too_many_arguments)]
+pub async fn accept_connection(
+    mqtt_pool: Arc<MqttPool>,
+    redis_client: Arc<RedisClient>,
+    registry: Arc<ConnectionRegistry>,
+    jwt_cfg: &JwtSettings,
+    delivery_cfg: DeliverySettings,
+    limits: &LimitSettings,
+    im_server_cfg: &ImServerSettings,
+    shutdown: Shutdown,
+    shutdown_cfg: ShutdownSettings,
+    request: ConnectRequest,
+) -> Result<Connection, Rejection> {
+    let remote_addr = request.remote_addr(&limits.trusted_proxies);
+    let user_agent = request.headers
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    let ConnectRequest { transport, subscription_id, query, headers, .. } = request;
+    
+    // 节点正在下线，客户端应重连到其他节点
+    if shutdown.is_triggered() {
+        warn!(%subscription_id, "节点正在下线，拒绝连接请求");
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "节点正在下线，请重连").into_response().into());
+    }
+    
+    // 节点总数和来源 IP 的上限在认证之前检查，用户的上限在认证之后登记连接时检查
+    if let Err(limit) = registry.check_capacity(&remote_addr, limits) {
+        warn!(%subscription_id, remote_addr = %remote_addr, reason = limit.reason(), "超过连接数上限，拒绝连接请求");
+        return Err(Rejection::Limit(limit));
+    }
+    
+    // 从请求头获取 token
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .map(|s| s.to_string());
+    
+    // 没有 Authorization 头时（浏览器）兑换一次性票据，票据不写入日志
+    let ticket = query.ticket.clone().or_else(|| {
+        headers
+            .get(header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|h| h.to_str().ok())
+            .and_then(ticket_from_subprotocols)
+            .map(|s| s.to_string())
+    });
+    let token = match (token, ticket) {
+        (Some(t), _) => t,
+        (None, Some(ticket)) => match redeem_ws_ticket(&redis_client, &ticket).await {
+            Ok(Some(t)) => {
+                info!(%subscription_id, "WebSocket 票据兑换成功");
+                t
+            }
+            Ok(None) => {
+                warn!(%subscription_id, "WebSocket 票据无效、已过期或已被使用");
+                return Err((StatusCode::UNAUTHORIZED, "无效的 WebSocket 票据").into_response().into());
+            }
+            Err(e) => {
+                error!(%subscription_id, error = %e, "兑换 WebSocket 票据失败");
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "兑换 WebSocket 票据失败").into_response().into());
+            }
+        },
+        (None, None) => {
+            warn!(%subscription_id, "WebSocket 升级请求缺少 Authorization token 或票据");
+            return Err((StatusCode::UNAUTHORIZED, "缺少认证 token").into_response().into());
+        }
+    };
+    
+    // 验证 token
+    let claims = match verify_token(&token, jwt_cfg) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!(%subscription_id, error = %e, "WebSocket token 验证失败");
+            return Err((StatusCode::UNAUTHORIZED, "无效的认证 token").into_response().into());
+        }
+    };
+    
+    info!(
+        %subscription_id,
+        user_id = %claims.user_id,
+        is_open_id = %claims.is_open_id,
+        "WebSocket token 验证成功"
+    );
+    
+    // 绑定了登录会话的 token 只能连接自己的会话，会话被挤下线或被踢出后拒绝连接（Redis 不可用时跳过吊销检查）
+    if let Some(sid) = claims.sid.as_deref() {
+        if sid != subscription_id {
+            warn!(%subscription_id, "token 所属会话与连接的订阅 ID 不一致");
+            return Err((StatusCode::UNAUTHORIZED, "token 与订阅 ID 不匹配").into_response().into());
+        }
+        match is_session_revoked(&redis_client, sid).await {
+            Ok(true) => {
+                warn!(%subscription_id, "会话已被吊销，拒绝 WebSocket 连接");
+                return Err((StatusCode::UNAUTHORIZED, "会话已失效，请重新登录").into_response().into());
+            }
+            Ok(false) => {}
+            Err(e) => warn!(%subscription_id, error = %e, "检查会话吊销状态失败"),
+        }
+    }
+    
+    // 从 token 中提取用户信息
+    // 如果 token 中包含 open_id（is_open_id = true），直接从 token 获取，无需查询数据库
+    // 这样可以避免不必要的数据库查询，提高性能
+    let (user_mqtt_id, user_open_id) = if claims.is_open_id {
+        // Token 中包含 open_id 的数字形式（雪花算法生成的）
+        // 直接使用，无需查询数据库
+        let open_id = claims.user_id.to_string();
+        info!(
+            %subscription_id,
+            open_id = %open_id,
+            mqtt_id = %claims.user_id,
+            "从 token 直接获取用户信息（无需查询数据库）"
+        );
+        (claims.user_id, open_id)
+    } else {
+        // Token 中包含的是数据库 ID（向后兼容旧 token）
+        // 从 Redis 读取登录时记录的订阅 ID 对应的 open_id 和 mqtt_id
+        warn!(
+            %subscription_id,
+            user_id = %claims.user_id,
+            "Token 使用数据库 ID（旧格式），需要通过 subscription_id 查询用户信息"
+        );
+        match get_subscription_identity(&redis_client, &subscription_id).await {
+            Ok(Some(identity)) if identity.user_id == claims.user_id => {
+                info!(
+                    %subscription_id,
+                    open_id = %identity.open_id,
+                    mqtt_id = %identity.mqtt_id,
+                    "从 Redis 获取订阅 ID 对应的用户信息（向后兼容旧 token）"
+                );
+                (identity.mqtt_id, identity.open_id)
+            },
+            Ok(Some(identity)) => {
+                warn!(%subscription_id, user_id = %claims.user_id, owner = %identity.user_id, "订阅 ID 不属于 token 对应的用户");
+                return Err((StatusCode::UNAUTHORIZED, "token 与订阅 ID 不匹配").into_response().into());
+            },
+            Ok(None) => {
+                warn!(%subscription_id, "订阅 ID 没有对应的用户信息（未登录或已过期）");
+                return Err((StatusCode::UNAUTHORIZED, "订阅 ID 无效，请重新登录").into_response().into());
+            },
+            Err(e) => {
+                error!(%subscription_id, error = %e, "查询用户信息失败");
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "查询用户信息失败").into_response().into());
+            }
+        }
+    };
+    
+    // 登记到本进程的连接表（检查连接数上限），管理接口通过它查询连接、下达断开和通知命令
+    let control = match registry.register(ConnectionInfo {
+        connection_id: 0,
+        subscription_id: subscription_id.clone(),
+        open_id: user_open_id.clone(),
+        mqtt_id: user_mqtt_id,
+        transport,
+        remote_addr: remote_addr.clone(),
+        user_agent,
+        connected_at: now_timestamp(),
+        queue_depth: 0,
+    }, limits) {
+        Ok(control) => control,
+        Err(limit) => {
+            warn!(%subscription_id, open_id = %user_open_id, remote_addr = %remote_addr, reason = limit.reason(), "超过连接数上限，拒绝连接请求");
+            return Err(Rejection::Limit(limit));
+        }
+    };
+    
+    // 客户端发送的命令以当前用户身份转发到 im-server 的内部接口
+    let server_client = ImServerClient::new(im_server_cfg, user_open_id.clone());
+    let delivery = DeliveryTracker::new(
+        delivery_cfg,
+        query.ack,
+        OfflineQueue::new(redis_client.clone()),
+        user_open_id.clone(),
+    );
+    
+    Ok(Connection {
+        mqtt_pool,
+        redis_client,
+        control,
+        max_missed_heartbeats: limits.max_missed_heartbeats,
+        subscription_id,
+        user_mqtt_id,
+        user_open_id,
+        server_client,
+        resume_seq: query.last_seq,
+        wait_resume: query.resume,
+        delivery,
+        shutdown: shutdown.clone(),
+        shutdown_cfg,
+        // 连接清理完成前进程不退出（最多等待 drain_secs）
+        _guard: shutdown.guard(),
+    })
+}
+
+/// 驱动一次连接直到断开
+pub async fn handle_connection(mut socket: ClientSocket, connection: Connection) {
+    let Connection {
+        mqtt_pool,
+        redis_client,
+        mut control,
+        max_missed_heartbeats,
+        subscription_id,
+        user_mqtt_id,
+        user_open_id,
+        server_client,
+        resume_seq,
+        wait_resume,
+        delivery,
+        shutdown,
+        shutdown_cfg,
+        _guard,
+    } = connection;
+    // 通过共享 MQTT 连接池订阅用户 topic（基于 mqtt_id，同一用户的多个设备共用一个 broker 订阅）
+    let topic = mqtt_user_topic(&user_mqtt_id.to_string());
+    let mut rx = match mqtt_pool.subscribe(&topic, &subscription_id).await {
+        Ok(r) => {
+            info!(
+                subscription_id = %subscription_id,
+                open_id = %user_open_id,
+                mqtt_id = %user_mqtt_id, 
+                topic = %topic,
+                "✅ MQTT订阅成功（共享连接池）"
+            );
+            r
+        },
+        Err(e) => {
+            error!(
+                subscription_id = %subscription_id,
+                open_id = %user_open_id,
+                mqtt_id = %user_mqtt_id, 
+                topic = %topic,
+                error = %e, 
+                "❌ MQTT订阅失败"
+            );
+            // 发送关闭帧并关闭连接
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    
+    info!(
+        subscription_id = %subscription_id,
+        open_id = %user_open_id,
+        mqtt_id = %user_mqtt_id, 
+        %topic, 
+        "WS已连接，已订阅MQTT（基于唯一标识符open_id，subscription_id仅用于本次连接）"
+    );
+    
+    control.attach_queue(rx.queue());
+    
+    // 登记设备在线状态和连接所在的节点（Redis），im-server 据此判断用户是否在线、把消息直接发到本节点
+    let presence = ConnectionPresence::new(
+        PresenceRegistry::new(redis_client.clone()),
+        RouteRegistry::new(redis_client.clone()),
+        mqtt_pool.clone(),
+        user_open_id.clone(),
+        subscription_id.clone(),
+        control.id(),
+    );
+    match presence.connect().await {
+        Ok(()) => info!(
+            subscription_id = %subscription_id,
+            open_id = %user_open_id,
+            mqtt_id = %user_mqtt_id,
+            "设备已登记为在线"
+        ),
+        Err(e) => warn!(
+            subscription_id = %subscription_id,
+            open_id = %user_open_id,
+            error = %e,
+            "登记设备在线状态失败（下一次心跳会重试）"
+        ),
+    }
+    
+    // 补发断线/离线期间的消息：订阅已经建立，之后发布的消息都会进入实时通道，之前的都在 Redis 中，
+    // 两者之间没有空隙，重叠的部分按 seq 去重
+    let mut connection_closed = false;
+    let mut pending_frame = None;
+    let mut delivered = HashSet::new();
+    // 没有补发起点时仍然接受第一帧 resume 命令，记录在此之前推送过的 seq，补发时跳过
+    let mut late_resume = None;
+    let (last_seq, request_id) = match wait_for_resume(&mut socket, resume_seq, wait_resume).await {
+        ResumeStart::Resume { last_seq, request_id } => (Some(last_seq), request_id),
+        ResumeStart::Fresh { first_frame } => {
+            pending_frame = first_frame;
+            (None, None)
+        }
+        ResumeStart::Closed => {
+            info!(%subscription_id, open_id = %user_open_id, "等待补发起点时WS已关闭");
+            connection_closed = true;
+            (None, None)
+        }
+    };
+    if !connection_closed {
+        match replay_missed_messages(&mut socket, &redis_client, &delivery, &subscription_id, &user_open_id, last_seq, &HashSet::new()).await {
+            Ok(outcome) => {
+                if let Some(request_id) = request_id
+                    && let Err(e) = socket.send_frame(&outcome.response(request_id)).await {
+                        warn!(%subscription_id, error = %e, "发送 resume 响应失败");
+                        connection_closed = true;
+                    }
+                if last_seq.is_none() && pending_frame.is_none() && socket.is_websocket() {
+                    late_resume = Some(outcome.delivered.clone());
+                }
+                delivered = outcome.delivered;
+            }
+            Err(e) => {
+                warn!(%subscription_id, open_id = %user_open_id, error = %e, "补发消息时WS写入失败");
+                connection_closed = true;
+            }
+        }
+    }
+
+    // 定期发送 ping 保持连接活跃，同时刷新设备在线状态心跳
+    let mut ping_interval = tokio::time::interval(tokio::time::Duration::from_secs(PRESENCE_HEARTBEAT_SECS));
+    ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    // 最后一次收到客户端任何帧（包括 pong）的时间，连续 max_missed_heartbeats 个心跳周期没有收到时断开（0 表示不检查）
+    let mut last_seen = tokio::time::Instant::now();
+    let liveness_timeout = (max_missed_heartbeats > 0 && socket.is_websocket())
+        .then(|| Duration::from_secs(PRESENCE_HEARTBEAT_SECS * u64::from(max_missed_heartbeats)));
+    
+    // 开启送达确认时定期检查超时未确认的消息，没有开启时定期把已写出的推送批量从离线队列移除
+    let mut retransmit_interval = tokio::time::interval(Duration::from_millis(RETRANSMIT_CHECK_INTERVAL_MS));
+    retransmit_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    
+    // 客户端命令在独立任务中转发到 im-server，响应通过通道回到本循环写回 socket
+    // 避免一条慢请求阻塞消息推送
+    let (response_tx, mut response_rx) = tokio::sync::mpsc::channel::<ServerResponse>(64);
+    let commands = CommandRunner {
+        permits: Arc::new(tokio::sync::Semaphore::new(MAX_INFLIGHT_COMMANDS)),
+        response_tx,
+        server_client,
+        presence: presence.clone(),
+        delivery: delivery.clone(),
+    };
+    
+    // 等待 resume 时收到的第一帧是普通命令，补发完成后再处理（此时没有其他命令在处理，不会超过上限）
+    if let Some((format, data)) = pending_frame {
+        let _ = commands.spawn(format, data);
+    }
+    
+    // connection_closed 同时用于跟踪连接是否已经关闭，避免在已关闭的连接上发送关闭帧
+    while !connection_closed {
+        tokio::select! {
+            _ = shutdown.triggered() => {
+                // 写出已经到达的推送和响应，再通知客户端带随机等待时间重连到其他节点
+                let reconnect = ReconnectFrame::jittered("shutdown", shutdown_cfg.reconnect_min_ms, shutdown_cfg.reconnect_max_ms);
+                info!(%subscription_id, open_id = %user_open_id, retry_after_ms = reconnect.retry_after_ms, "节点正在下线，通知客户端重连");
+                let flushed = flush_pending(&mut socket, &mut rx, &mut response_rx, &mut delivered, &delivery, &user_open_id).await;
+                if flushed.is_ok() && socket.send_frame(&ServerFrame::Reconnect(reconnect)).await.is_ok() {
+                    let _ = socket.send(Message::Close(Some(CloseFrame {
+                        code: RECONNECT_CLOSE_CODE,
+                        reason: "reconnect".into(),
+                    }))).await;
+                }
+                connection_closed = true;
+                break;
+            }
+            Some(command) = control.recv() => {
+                match command {
+                    ControlCommand::Disconnect { reason } => {
+                        info!(%subscription_id, open_id = %user_open_id, connection_id = control.id(), reason = %reason, "连接被管理员断开");
+                        let _ = socket.send(Message::Close(Some(CloseFrame {
+                            code: ADMIN_DISCONNECT_CLOSE_CODE,
+                            reason: reason.into(),
+                        }))).await;
+                        connection_closed = true;
+                        break;
+                    }
+                    ControlCommand::Notice(notice) => {
+                        if let Err(e) = socket.send_frame(&ServerFrame::Notice(notice)).await {
+                            warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "推送系统通知失败");
+                            connection_closed = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            _ = ping_interval.tick() => {
+                // 客户端长时间没有任何响应（网络中断、进程挂起），TCP 连接可能还没有断开，主动关闭释放资源
+                if let Some(timeout) = liveness_timeout
+                    && last_seen.elapsed() >= timeout {
+                        warn!(%subscription_id, open_id = %user_open_id, idle_secs = last_seen.elapsed().as_secs(), "客户端心跳超时，断开连接");
+                        let _ = socket.send(Message::Close(Some(CloseFrame {
+                            code: HEARTBEAT_TIMEOUT_CLOSE_CODE,
+                            reason: "heartbeat timeout".into(),
+                        }))).await;
+                        connection_closed = true;
+                        break;
+                    }
+                // 定期发送 ping 保持连接活跃
+                if let Err(e) = socket.send(Message::Ping(vec![].into())).await {
+                    warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "发送 ping 失败");
+                    connection_closed = true;
+                    break;
+                }
+                if let Err(e) = presence.heartbeat().await {
+                    warn!(%subscription_id, open_id = %user_open_id, error = %e, "刷新在线状态心跳失败");
+                }
+            }
+            _ = retransmit_interval.tick() => {
+                // 没有开启送达确认时只把已写出的推送批量从离线队列移除
+                if !delivery.enabled() {
+                    delivery.flush_written().await;
+                    continue;
+                }
+                let due = delivery.due();
+                if due.exhausted {
+                    warn!(%subscription_id, open_id = %user_open_id, "客户端多次重发后仍未确认消息，断开连接");
+                    break;
+                }
+                let mut send_failed = false;
+                for payload in due.resend {
+                    debug!(%subscription_id, open_id = %user_open_id, "重发未确认的消息");
+                    if let Err(e) = socket.send_push(&Push::new(payload)).await {
+                        warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "重发消息失败");
+                        send_failed = true;
+                        break;
+                    }
+                }
+                if send_failed {
+                    connection_closed = true;
+                    break;
+                }
+            }
+            incoming = rx.recv() => {
+                match incoming {
+                    Some(Outbound::Overflow) => {
+                        // 积压的消息在下面释放订阅时转存为离线消息，客户端重连后补发
+                        warn!(%subscription_id, open_id = %user_open_id, "客户端接收太慢，发送队列已溢出，断开连接");
+                        let _ = socket.send(Message::Close(Some(CloseFrame {
+                            code: SLOW_CONSUMER_CLOSE_CODE,
+                            reason: "slow consumer".into(),
+                        }))).await;
+                        connection_closed = true;
+                        break;
+                    }
+                    Some(Outbound::Message(msg)) => {
+                        // 直接使用原始消息，不进行ID转换（前端可以处理 open_id）
+                        // 不是 UTF-8 的消息原样作为二进制帧转发
+                        let push = match String::from_utf8(msg.payload) {
+                            Ok(text) => Push::new(text),
+                            Err(e) => {
+                                info!(%subscription_id, mqtt_id = %user_mqtt_id, topic = %msg.topic, "转发二进制MQTT消息到WebSocket客户端");
+                                if let Err(e) = socket.send(Message::Binary(Bytes::from(e.into_bytes()))).await {
+                                    warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "❌ 发送消息到客户端失败");
+                                    connection_closed = true;
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+                        let seq = push.seq();
+                        // 补发时已经推送过的消息不再重复推送
+                        if let Some(seq) = seq
+                            && delivered.remove(&seq) {
+                                debug!(%subscription_id, open_id = %user_open_id, seq = seq, "实时消息已在补发中推送，跳过");
+                                continue;
+                            }
+                        // 在发送队列中等待期间过期的推送（正在输入、通话邀请等）不再发送
+                        if push.is_expired() {
+                            debug!(%subscription_id, open_id = %user_open_id, message_id = ?push.message_id(), "推送已过期，跳过");
+                            continue;
+                        }
+                        // 下线通知只转发给被踢的会话，随后关闭连接；同一用户的其他设备忽略
+                        if push.field("type").and_then(|t| t.as_str()) == Some(KICKED_EVENT_TYPE) {
+                            if push.field("subscription_id").and_then(|s| s.as_str()) != Some(subscription_id.as_str()) {
+                                continue;
+                            }
+                            info!(%subscription_id, open_id = %user_open_id, reason = ?push.field("reason"), "会话已被吊销，通知客户端后断开连接");
+                            if socket.send_push(&push).await.is_ok() {
+                                let _ = socket.send(Message::Close(Some(CloseFrame {
+                                    code: KICKED_CLOSE_CODE,
+                                    reason: KICKED_EVENT_TYPE.into(),
+                                }))).await;
+                            }
+                            connection_closed = true;
+                            break;
+                        }
+                        info!(
+                            subscription_id = %subscription_id,
+                            open_id = %user_open_id,
+                            mqtt_id = %user_mqtt_id,
+                            topic = %msg.topic,
+                            message_id = ?push.message_id(),
+                            seq = ?seq,
+                            chat_type = ?push.field("chat_type"),
+                            from_user_id = ?push.field("from_user_id"),
+                            payload_len = push.text.len(),
+                            "📨 收到MQTT消息（连接池分发），推送到WebSocket客户端"
+                        );
+
+                        if let Err(e) = socket.send_push(&push).await {
+                            warn!(
+                                %subscription_id, 
+                                user_id = %user_mqtt_id, 
+                                error = %e, 
+                                "❌ 发送消息到客户端失败"
+                            );
+                            // 发送失败通常意味着连接已断开，退出循环
+                            connection_closed = true;
+                            break;
+                        }
+                        track_delivery(&delivery, &user_open_id, &push).await;
+                        if let (Some(pushed), Some(seq)) = (late_resume.as_mut(), seq) {
+                            pushed.insert(seq);
+                            // 超过收件箱日志的容量后补发已经无法覆盖，不再等待 resume 命令
+                            if pushed.len() as i64 > INBOX_LOG_MAX_ENTRIES {
+                                late_resume = None;
+                            }
+                        }
+                    }
+                    None => {
+                        // 分发通道关闭说明连接池已经不存在（进程正在退出）
+                        warn!(
+                            subscription_id = %subscription_id,
+                            open_id = %user_open_id,
+                            mqtt_id = %user_mqtt_id,
+                            "MQTT 分发通道已关闭"
+                        );
+                        break;
+                    }
+                }
+            }
+            Some(response) = response_rx.recv() => {
+                let request_id = response.id.clone();
+                if let Err(e) = socket.send_frame(&ServerFrame::Response(response)).await {
+                    warn!(%subscription_id, user_id = %user_mqtt_id, request_id = %request_id, error = %e, "发送响应帧失败");
+                    connection_closed = true;
+                    break;
+                }
+            }
+            from_client = socket.recv() => {
+                if let Some(Ok(_)) = from_client {
+                    last_seen = tokio::time::Instant::now();
+                }
+                match from_client {
+                    Some(Ok(Message::Close(_))) | None => {
+                        info!(%subscription_id, user_id = %user_mqtt_id, open_id = %user_open_id, "WS关闭");
+                        connection_closed = true;
+                        break;
+                    }
+                    Some(Ok(Message::Ping(data))) => {
+                        // 收到 ping，回复 pong
+                        if let Err(e) = socket.send(Message::Pong(data)).await {
+                            warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "回复 pong 失败");
+                            connection_closed = true;
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Pong(_))) => {
+                        // 收到 pong，连接正常（活跃时间已在上面刷新）
+                    }
+                    Some(Ok(message)) => {
+                        // 客户端请求帧：{"id": "...", "type": "send_message", "data": {...}}
+                        let Some((format, data)) = client_frame(message) else {
+                            continue;
+                        };
+                        // 第一帧是 resume 命令时从它的 last_seq 补发，跳过已经推送过的消息
+                        if let Some(pushed) = late_resume.take()
+                            && let Ok(ClientFrame { id, command: ClientCommand::Resume(cmd) }) = decode_frame::<ClientFrame>(format, &data) {
+                                info!(%subscription_id, open_id = %user_open_id, last_seq = cmd.last_seq, "收到连接后的 resume 命令，补发消息");
+                                let replayed = replay_missed_messages(&mut socket, &redis_client, &delivery, &subscription_id, &user_open_id, Some(cmd.last_seq), &pushed).await;
+                                let sent = match replayed {
+                                    Ok(outcome) => {
+                                        delivered.extend(outcome.delivered.iter().copied());
+                                        socket.send_frame(&outcome.response(id)).await
+                                    }
+                                    Err(e) => Err(e),
+                                };
+                                if let Err(e) = sent {
+                                    warn!(%subscription_id, open_id = %user_open_id, error = %e, "补发消息时WS写入失败");
+                                    connection_closed = true;
+                                    break;
+                                }
+                                continue;
+                            }
+                        if let Some(busy) = commands.spawn(format, data) {
+                            warn!(%subscription_id, open_id = %user_open_id, request_id = %busy.id, "处理中的命令过多，拒绝请求");
+                            if let Err(e) = socket.send_frame(&ServerFrame::Response(busy)).await {
+                                warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "发送响应帧失败");
+                                connection_closed = true;
+                                break;
+                            }
+                        }
+                    }
+                    Some(Err(e)) => {
+                        warn!(%subscription_id, user_id = %user_mqtt_id, open_id = %user_open_id, error = %e, "WS接收错误");
+                        // 检查错误类型，如果是连接重置或已关闭，不需要发送关闭帧
+                        let error_str = e.to_string();
+                        let is_connection_reset = error_str.contains("Connection reset")
+                            || error_str.contains("connection reset")
+                            || error_str.contains("Broken pipe")
+                            || error_str.contains("broken pipe")
+                            || error_str.contains("Connection aborted")
+                            || error_str.contains("connection aborted")
+                            || error_str.contains("Sending after closing")
+                            || error_str.contains("sending after closing");
+                        
+                        // 只有在连接仍然有效时才尝试发送关闭帧
+                        if !is_connection_reset {
+                            if let Err(close_err) = socket.send(Message::Close(None)).await {
+                                // 如果发送关闭帧也失败，说明连接已经关闭
+                                let close_err_str = close_err.to_string();
+                                if close_err_str.contains("Sending after closing") 
+                                    || close_err_str.contains("sending after closing") {
+                                    connection_closed = true;
+                                }
+                            }
+                        } else {
+                            connection_closed = true;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    
+    // 设备下线，记录最后活跃时间
+    if let Err(e) = presence.disconnect().await {
+        warn!(
+            subscription_id = %subscription_id,
+            open_id = %user_open_id,
+            error = %e,
+            "清除设备在线状态失败（条目会在心跳超时后自动过期）"
+        );
+    } else {
+        info!(
+            subscription_id = %subscription_id,
+            open_id = %user_open_id,
+            mqtt_id = %user_mqtt_id,
+            "设备已从在线状态移除"
+        );
+    }
+    
+    // 没有开启送达确认时移除最后一批已写出的推送；开启时仍未确认的消息转存为离线消息，下次连接时重新推送
+    delivery.flush_written().await;
+    let unacked = delivery.drain();
+    if !unacked.is_empty() {
+        info!(
+            subscription_id = %subscription_id,
+            open_id = %user_open_id,
+            message_count = unacked.len(),
+            "连接断开时仍有未确认的消息，转存为离线消息"
+        );
+        persist_unacked(delivery.offline(), &user_open_id, unacked).await;
+    }
+    
+    // 释放 topic 订阅：同一用户的其他设备仍在线时只减少引用计数，最后一个设备离开时连接池才向 broker 取消订阅
+    // 发送队列中还没有写给客户端的可靠推送转存为离线消息
+    let queued = rx.close().await;
+    info!(
+        subscription_id = %subscription_id,
+        open_id = %user_open_id,
+        mqtt_id = %user_mqtt_id, 
+        %topic,
+        queued = queued.len(),
+        "已释放 MQTT topic 订阅"
+    );
+    persist_queued(delivery.offline(), &user_open_id, &delivered, queued).await;
+    
+    // 尝试优雅关闭连接（如果连接仍然有效）
+    // 注意：如果连接已经被重置或关闭，发送关闭帧可能会失败，这是正常的
+    if !connection_closed
+        && let Err(e) = socket.send(Message::Close(None)).await {
+            // 检查错误类型，如果是"发送后关闭"错误，不需要记录警告（这是预期的）
+            let error_str = e.to_string();
+            if !error_str.contains("Sending after closing") && !error_str.contains("sending after closing") {
+                warn!(%subscription_id, user_id = %user_mqtt_id, error = %e, "发送关闭帧失败（连接可能已关闭）");
+            }
+        }
+    info!(%subscription_id, user_id = %user_mqtt_id, "WebSocket 连接已清理");
+}
+
+/// 在独立任务中处理客户端命令，同时处理中的命令数受 permits 限制
+struct CommandRunner {
+    permits: Arc<tokio::sync::Semaphore>,
+    response_tx: tokio::sync::mpsc::Sender<ServerResponse>,
+    server_client: ImServerClient,
+    presence: ConnectionPresence,
+    delivery: DeliveryTracker,
+}
+
+impl CommandRunner {
+    /// 没有空闲名额时不启动任务，返回 BUSY 响应由调用方直接写回
+    fn spawn(&self, format: WireFormat, data: Bytes) -> Option<ServerResponse> {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            return Some(ServerResponse::error(frame_id(&data, format), "BUSY", "处理中的请求过多，请稍后重试"));
+        };
+        let response_tx = self.response_tx.clone();
+        let server_client = self.server_client.clone();
+        let presence = self.presence.clone();
+        let delivery = self.delivery.clone();
+        tokio::spawn(async move {
+            let response = handle_client_frame(&data, format, &server_client, &presence, &delivery).await;
+            let _ = response_tx.send(response).await;
+            drop(permit);
+        });
+        None
+    }
+}
+
+/// 下线前写出已经到达但还没有转发的推送和响应
+/// 未确认的推送随后和其他待确认消息一起转存为离线消息
+async fn flush_pending(
+    socket: &mut ClientSocket,
+    rx: &mut TopicSubscription,
+    response_rx: &mut tokio::sync::mpsc::Receiver<ServerResponse>,
+    delivered: &mut HashSet<i64>,
+    delivery: &DeliveryTracker,
+    open_id: &str,
+) -> Result<(), axum::Error> {
+    while let Ok(response) = response_rx.try_recv() {
+        socket.send_frame(&ServerFrame::Response(response)).await?;
+    }
+    while let Some(Outbound::Message(msg)) = rx.try_recv() {
+        let Ok(text) = String::from_utf8(msg.payload) else {
+            continue;
+        };
+        let push = Push::new(text);
+        // 连接即将关闭，下线通知不再处理
+        if push.field("type").and_then(|t| t.as_str()) == Some(KICKED_EVENT_TYPE) {
+            continue;
+        }
+        if let Some(seq) = push.seq()
+            && delivered.remove(&seq) {
+                continue;
+            }
+        if push.is_expired() {
+            continue;
+        }
+        socket.send_push(&push).await?;
+        track_delivery(delivery, open_id, &push).await;
+    }
+    Ok(())
+}
+
+/// 补发的起点
+enum ResumeStart {
+    /// 从 last_seq 之后补发；request_id 为通过 resume 命令指定时的请求ID（需要回复响应帧）
+    Resume { last_seq: i64, request_id: Option<String> },
+    /// 客户端没有提供序号（首次登录或旧版客户端），推送 Redis 离线队列中的消息
+    /// first_frame 为等待期间收到的普通命令帧
+    Fresh { first_frame: Option<(WireFormat, Bytes)> },
+    /// 等待期间连接已关闭
+    Closed,
+}
+
+/// 确定补发起点：握手 URL 中的 last_seq 参数，或客户端声明 ?resume=true 后第一帧 resume 命令
+/// 没有声明时不等待，直接推送离线消息（之后的第一帧 resume 命令仍然会补发）
+async fn wait_for_resume(socket: &mut ClientSocket, last_seq: Option<i64>, declared: bool) -> ResumeStart {
+    if let Some(last_seq) = last_seq {
+        return ResumeStart::Resume { last_seq, request_id: None };
+    }
+    if !declared {
+        return ResumeStart::Fresh { first_frame: None };
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(RESUME_FRAME_TIMEOUT_MS);
+    loop {
+        let message = match tokio::time::timeout_at(deadline, socket.recv()).await {
+            Err(_) => return ResumeStart::Fresh { first_frame: None },
+            Ok(None) | Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) => return ResumeStart::Closed,
+            Ok(Some(Ok(message))) => message,
+        };
+        // ping 由底层自动回复 pong，其余控制帧忽略
+        let Some((format, data)) = client_frame(message) else {
+            continue;
+        };
+        return match decode_frame::<ClientFrame>(format, &data) {
+            Ok(ClientFrame { id, command: ClientCommand::Resume(cmd) }) => ResumeStart::Resume {
+                last_seq: cmd.last_seq,
+                request_id: Some(id),
+            },
+            _ => ResumeStart::Fresh { first_frame: Some((format, data)) },
+        };
+    }
+}
+
+#[derive(Default)]
+struct ReplayOutcome {
+    /// 已补发的序号，实时通道中再收到时跳过
+    delivered: HashSet<i64>,
+    replayed: usize,
+    truncated: bool,
+    latest_seq: Option<i64>,
+}
+
+impl ReplayOutcome {
+    /// resume 命令的响应帧
+    fn response(&self, request_id: String) -> ServerFrame {
+        ServerFrame::Response(ServerResponse::ok(request_id, Some(serde_json::json!({
+            "replayed": self.replayed,
+            "latest_seq": self.latest_seq,
+            "truncated": self.truncated,
+        }))))
+    }
+}
+
+/// 补发断线/离线期间的消息
+/// 1. 提供了 last_seq 时从收件箱日志补发之后的消息，日志不完整时先推送 resync 帧
+/// 2. 推送 Redis 离线队列中的消息（首次连接的主要来源），按序号恢复时跳过并移除日志中已覆盖的部分；
+///    队列曾经溢出时推送 resync 帧，客户端通过历史消息接口补齐被丢弃的消息
+///
+/// 离线消息读取时不删除，送达（确认或写入）后才从队列移除；already_pushed 中的消息本连接已经推送过，不再补发
+async fn replay_missed_messages(
+    socket: &mut ClientSocket,
+    redis_client: &Arc<RedisClient>,
+    delivery: &DeliveryTracker,
+    subscription_id: &str,
+    open_id: &str,
+    last_seq: Option<i64>,
+    already_pushed: &HashSet<i64>,
+) -> Result<ReplayOutcome, axum::Error> {
+    let mut outcome = ReplayOutcome::default();
+    // 离线消息中序号不超过它的，客户端已经处理过
+    let mut known_up_to = None;
+
+    if let Some(last_seq) = last_seq {
+        match InboxLog::new(redis_client.clone()).since(open_id, last_seq).await {
+            Ok(replay) => {
+                info!(
+                    subscription_id = %subscription_id,
+                    open_id = %open_id,
+                    last_seq = last_seq,
+                    latest_seq = replay.latest_seq,
+                    message_count = replay.entries.len(),
+                    truncated = replay.truncated,
+                    "从收件箱日志补发消息"
+                );
+                if replay.truncated {
+                    socket.send_frame(&ServerFrame::Resync(ResyncFrame::new(last_seq, replay.latest_seq))).await?;
+                }
+                for (seq, message) in replay.entries {
+                    if already_pushed.contains(&seq) {
+                        continue;
+                    }
+                    outcome.delivered.insert(seq);
+                    let push = Push::new(message);
+                    if push.is_expired() {
+                        info!(subscription_id = %subscription_id, open_id = %open_id, seq = seq, "跳过已过期的消息（不推送给客户端）");
+                        continue;
+                    }
+                    socket.send_push(&push).await?;
+                    track_delivery(delivery, open_id, &push).await;
+                    outcome.replayed += 1;
+                }
+                if last_seq <= replay.latest_seq {
+                    known_up_to = Some(last_seq);
+                }
+                outcome.truncated = replay.truncated;
+                outcome.latest_seq = Some(replay.latest_seq);
+            }
+            Err(e) => warn!(
+                subscription_id = %subscription_id,
+                open_id = %open_id,
+                error = %e,
+                "读取收件箱日志失败，只推送 Redis 离线消息"
+            ),
+        }
+    }
+
+    // 共享连接池使用 clean_session=true，broker 不为用户保留会话，离线期间的消息全部来自 Redis
+    let offline = delivery.offline();
+    let batch = match offline.peek(open_id).await {
+        Ok(batch) => batch,
+        Err(e) => {
+            warn!(subscription_id = %subscription_id, open_id = %open_id, error = %e, "从 Redis 获取离线消息失败");
+            Default::default()
+        }
+    };
+    if let Some(known) = known_up_to
+        && let Err(e) = offline.remove_up_to(open_id, known).await {
+            warn!(subscription_id = %subscription_id, open_id = %open_id, error = %e, "移除已处理过的离线消息失败");
+        }
+    if let Some(overflowed_up_to) = batch.overflowed_up_to {
+        // 日志补发已经推送过 resync 帧时不再重复推送
+        if !outcome.truncated && known_up_to.is_none_or(|known| known < overflowed_up_to) {
+            warn!(subscription_id = %subscription_id, open_id = %open_id, overflowed_up_to = overflowed_up_to, "离线消息曾超出上限被丢弃，通知客户端重新同步");
+            socket.send_frame(&ServerFrame::Resync(ResyncFrame::new(last_seq.unwrap_or(0), overflowed_up_to))).await?;
+            outcome.truncated = true;
+        }
+        if let Err(e) = offline.clear_overflow(open_id).await {
+            warn!(subscription_id = %subscription_id, open_id = %open_id, error = %e, "清除离线消息溢出标记失败");
+        }
+    }
+    let offline_count = batch.entries.len();
+    for (seq, message) in batch.entries {
+        if outcome.delivered.contains(&seq) || already_pushed.contains(&seq) || known_up_to.is_some_and(|known| seq <= known) {
+            continue;
+        }
+        outcome.delivered.insert(seq);
+        let push = Push::new(message);
+        socket.send_push(&push).await?;
+        track_delivery(delivery, open_id, &push).await;
+        outcome.replayed += 1;
+    }
+    if offline_count > 0 {
+        info!(
+            subscription_id = %subscription_id,
+            open_id = %open_id,
+            message_count = offline_count,
+            replayed = outcome.replayed,
+            "✅ Redis 离线消息推送完成"
+        );
+    }
+
+    Ok(outcome)
+}
+
+/// 推送后登记到待确认队列（只登记带 seq 的推送），队列已满时最早的一条转存为离线消息
+/// 没有开启送达确认时写入即视为送达，从离线队列移除
+async fn track_delivery(delivery: &DeliveryTracker, open_id: &str, push: &Push) {
+    let Some(seq) = push.seq() else {
+        return;
+    };
+    if let Some(evicted) = delivery.track(seq, push.message_id().map(|id| id.to_string()), &push.text) {
+        warn!(open_id = %open_id, seq = evicted.seq, "待确认的消息过多，最早的一条转存为离线消息");
+        persist_unacked(delivery.offline(), open_id, vec![evicted]).await;
+    }
+    delivery.written(seq).await;
+}
+
+/// 未确认的消息转存为 Redis 离线消息（已在队列中的不会重复写入）
+async fn persist_unacked(offline: &OfflineQueue, open_id: &str, messages: Vec<PendingMessage>) {
+    for message in messages {
+        if let Err(e) = offline.push(open_id, &message.payload).await {
+            warn!(open_id = %open_id, seq = message.seq, error = %e, "未确认的消息转存失败（仍可通过收件箱日志按序号补发）");
+        }
+    }
+}
+
+/// 发送队列中没有写出的可靠推送转存为 Redis 离线消息（已在补发中推送过的跳过）
+async fn persist_queued(offline: &OfflineQueue, open_id: &str, delivered: &HashSet<i64>, queued: Vec<IncomingMessage>) {
+    for msg in queued {
+        let Ok(text) = String::from_utf8(msg.payload) else {
+            continue;
+        };
+        let push = Push::new(text);
+        if push.seq().is_some_and(|seq| delivered.contains(&seq)) {
+            continue;
+        }
+        if let Err(e) = offline.push(open_id, &push.text).await {
+            warn!(open_id = %open_id, seq = ?push.seq(), error = %e, "积压的消息转存失败（仍可通过收件箱日志按序号补发）");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use im_share::OfflineQueue;
+use crate::config::DeliverySettings;
+
+/// 检查待确认消息是否超时的间隔（毫秒）
+pub const RETRANSMIT_CHECK_INTERVAL_MS: u64 = 1000;
+
+/// 没有开启确认时写出多少条推送后从离线队列批量移除一次（其余的在定时检查和连接断开时移除）
+const WRITTEN_BATCH_SIZE: usize = 32;
+
+/// 已写入 socket、等待客户端确认的消息
+pub struct PendingMessage {
+    pub seq: i64,
+    pub message_id: Option<String>,
+    pub payload: String,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// 没有开启确认时已写出、还没有从离线队列移除的推送序号
+/// 只记录这个连接实际写出的序号：序号不一定连续（例如中间一条发布时转存为离线消息，后一条直接推送），不能按范围移除
+#[derive(Default)]
+struct WrittenBatch {
+    seqs: Vec<i64>,
+}
+
+impl WrittenBatch {
+    /// 记录一条已写出的推送，攒满 WRITTEN_BATCH_SIZE 条时取出整批
+    fn record(&mut self, seq: i64) -> Option<Vec<i64>> {
+        self.seqs.push(seq);
+        if self.seqs.len() < WRITTEN_BATCH_SIZE {
+            return None;
+        }
+        Some(self.take())
+    }
+
+    fn take(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.seqs)
+    }
+}
+
+/// 一次超时检查的结果
+#[derive(Default)]
+pub struct DueMessages {
+    /// 需要重发的消息
+    pub resend: Vec<String>,
+    /// 有消息超过最大重发次数仍未确认，客户端已经没有响应
+    pub exhausted: bool,
+}
+
+/// 当前连接的端到端送达确认
+/// 消息写入 socket 只代表进了客户端的接收缓冲，应用可能在后台从未处理；
+/// 开启后带 seq 的推送要等客户端 ack 才算送达，超时重发，连接断开时仍未确认的消息转存为离线消息
+/// 没有开启确认的连接（旧版客户端）不记录任何消息，写入 socket 即视为送达
+/// 送达的消息从 Redis 离线队列中移除（补发的消息在此之前一直保留在队列中）
+#[derive(Clone)]
+pub struct DeliveryTracker {
+    enabled: bool,
+    settings: DeliverySettings,
+    pending: Arc<Mutex<BTreeMap<i64, PendingMessage>>>,
+    written: Arc<Mutex<WrittenBatch>>,
+    offline: OfflineQueue,
+    open_id: String,
+}
+
+impl DeliveryTracker {
+    pub fn new(settings: DeliverySettings, enabled: bool, offline: OfflineQueue, open_id: String) -> Self {
+        Self {
+            enabled,
+            settings,
+            pending: Arc::new(Mutex::new(BTreeMap::new())),
+            written: Arc::new(Mutex::new(WrittenBatch::default())),
+            offline,
+            open_id,
+        }
+    }
+
+    pub fn offline(&self) -> &OfflineQueue {
+        &self.offline
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 登记一条刚写入 socket 的推送（只登记带 seq 的推送）
+    /// 队列已满时返回被挤出的最早一条，由调用方转存为离线消息
+    pub fn track(&self, seq: i64, message_id: Option<String>, payload: &str) -> Option<PendingMessage> {
+        if !self.enabled {
+            return None;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        pending.insert(seq, PendingMessage {
+            seq,
+            message_id,
+            payload: payload.to_string(),
+            sent_at: Instant::now(),
+            attempts: 0,
+        });
+        if pending.len() > self.settings.max_pending.max(1) {
+            return pending.pop_first().map(|(_, message)| message);
+        }
+        None
+    }
+
+    /// 客户端确认，按 seq 或 message_id 匹配，返回确认掉的条数
+    /// 确认的消息同时从离线队列移除（客户端直接按 seq 确认的也一并移除，可能来自之前的连接）
+    pub async fn ack(&self, seqs: &[i64], message_ids: &[String]) -> usize {
+        let (acked, settled) = {
+            let mut pending = self.pending.lock().unwrap();
+            let before = pending.len();
+            let mut settled = seqs.to_vec();
+            for seq in seqs {
+                pending.remove(seq);
+            }
+            if !message_ids.is_empty() {
+                pending.retain(|seq, message| {
+                    let matched = message.message_id.as_ref().is_some_and(|id| message_ids.contains(id));
+                    if matched {
+                        settled.push(*seq);
+                    }
+                    !matched
+                });
+            }
+            (before - pending.len(), settled)
+        };
+        self.settle(&settled).await;
+        acked
+    }
+
+    /// 写入 socket 之后调用：没有开启确认时写入即视为送达
+    /// 不逐条移除，每 WRITTEN_BATCH_SIZE 条把写出的序号一次从离线队列移除
+    pub async fn written(&self, seq: i64) {
+        if self.enabled {
+            return;
+        }
+        let batch = self.written.lock().unwrap().record(seq);
+        if let Some(seqs) = batch {
+            self.settle(&seqs).await;
+        }
+    }
+
+    /// 移除还没有批量移除的已写出推送（定时检查和连接断开时调用）
+    pub async fn flush_written(&self) {
+        let seqs = self.written.lock().unwrap().take();
+        self.settle(&seqs).await;
+    }
+
+    async fn settle(&self, seqs: &[i64]) {
+        if let Err(e) = self.offline.remove(&self.open_id, seqs).await {
+            warn!(open_id = %self.open_id, error = %e, "从离线队列移除已送达的消息失败（下次连接时会重复补发，客户端按 seq 去重）");
+        }
+    }
+
+    /// 取出确认超时需要重发的消息
+    pub fn due(&self) -> DueMessages {
+        let mut due = DueMessages::default();
+        if !self.enabled {
+            return due;
+        }
+        let timeout = Duration::from_millis(self.settings.ack_timeout_ms);
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        for message in pending.values_mut() {
+            if now.duration_since(message.sent_at) < timeout {
+                continue;
+            }
+            if message.attempts >= self.settings.max_retries {
+                due.exhausted = true;
+                continue;
+            }
+            message.attempts += 1;
+            message.sent_at = now;
+            due.resend.push(message.payload.clone());
+        }
+        due
+    }
+
+    /// 连接断开时取出全部未确认的消息
+    pub fn drain(&self) -> Vec<PendingMessage> {
+        std::mem::take(&mut *self.pending.lock().unwrap()).into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_batch_flushes_when_full() {
+        let mut batch = WrittenBatch::default();
+        for seq in 1..WRITTEN_BATCH_SIZE as i64 {
+            assert_eq!(batch.record(seq), None);
+        }
+        let flushed = batch.record(WRITTEN_BATCH_SIZE as i64).unwrap();
+        assert_eq!(flushed, (1..=WRITTEN_BATCH_SIZE as i64).collect::<Vec<_>>());
+        assert!(batch.take().is_empty());
+    }
+
+    #[test]
+    fn written_batch_keeps_only_written_seqs() {
+        // 序号 2 发布时转存为离线消息，没有经过这个连接；3 直接推送
+        let mut batch = WrittenBatch::default();
+        assert_eq!(batch.record(1), None);
+        assert_eq!(batch.record(3), None);
+        assert_eq!(batch.take(), vec![1, 3]);
+        assert!(batch.take().is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State, Extension, ws::Message},
+    response::{IntoResponse, sse::{Event, KeepAlive, Sse}},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use axum::body::Bytes;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use im_share::{RedisClient, JwtSettings, Shutdown};
+use crate::config::{BackpressureSettings, DeliverySettings, ImServerSettings, LimitSettings, ShutdownSettings};
+use crate::handlers::codec::{ChannelEnds, ClientSocket};
+use crate::handlers::connection::{ConnectQuery, ConnectRequest, Connection, accept_connection, handle_connection};
+use crate::handlers::registry::ConnectionRegistry;
+use crate::mqtt::MqttPool;
+
+// WebSocket 被拦截时的备用传输：SSE 和 HTTP 长轮询
+// 认证、离线补发、送达确认与 /ws/{subscription_id} 完全相同（同一个 handle_connection），
+// 只是发往客户端的帧经由通道交给 HTTP 响应，客户端命令通过 POST /sessions/{session_id}/commands 提交，
+// 命令的响应帧和推送一样从事件流 / 轮询结果返回
+// session_id 是随机生成的会话凭证，只在建立会话的响应中返回
+
+/// 单次长轮询最多等待新帧的时间（秒）
+const LONG_POLL_WAIT_SECS: u64 = 25;
+/// 长轮询会话超过这个时间没有轮询视为客户端已离开，关闭连接（秒）
+const LONG_POLL_IDLE_SECS: u64 = 60;
+/// 单次长轮询最多返回的帧数
+const LONG_POLL_MAX_FRAMES: usize = 100;
+/// 检查空闲长轮询会话的间隔（秒）
+const SESSION_REAP_INTERVAL_SECS: u64 = 10;
+
+struct HttpSession {
+    subscription_id: String,
+    commands: tokio::sync::mpsc::Sender<Message>,
+    /// 长轮询会话待取走的帧；SSE 会话的帧直接由事件流取走
+    frames: Option<Arc<tokio::sync::Mutex<tokio::sync::mpsc::Receiver<Message>>>>,
+    last_poll: Mutex<Instant>,
+}
+
+/// 本进程内的 SSE / 长轮询会话
+/// 会话被移除时命令通道随之关闭，连接按客户端断开处理（推送积压、未确认的消息转存为离线消息）
+pub struct HttpSessions {
+    sessions: Mutex<HashMap<String, Arc<HttpSession>>>,
+}
+
+impl HttpSessions {
+    pub fn new() -> Arc<Self> {
+        let sessions = Arc::new(Self { sessions: Mutex::new(HashMap::new()) });
+        let weak = Arc::downgrade(&sessions);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(SESSION_REAP_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                let Some(sessions) = weak.upgrade() else {
+                    break;
+                };
+                sessions.reap_idle();
+            }
+        });
+        sessions
+    }
+
+    fn get(&self, session_id: &str) -> Option<Arc<HttpSession>> {
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    fn remove(&self, session_id: &str) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+
+    /// 登记会话并在后台驱动连接
+    /// SSE 会话在连接结束后移除；长轮询会话保留到客户端取走最后的帧（或空闲超时），否则 reconnect / close 帧会丢失
+    fn start(self: &Arc<Self>, socket: ClientSocket, connection: Connection, ends: ChannelEnds, long_poll: bool) -> (String, Option<tokio::sync::mpsc::Receiver<Message>>) {
+        let session_id = uuid::Uuid::new_v4().simple().to_string();
+        let (frames, sse_frames) = if long_poll {
+            (Some(Arc::new(tokio::sync::Mutex::new(ends.frames))), None)
+        } else {
+            (None, Some(ends.frames))
+        };
+        self.sessions.lock().unwrap().insert(session_id.clone(), Arc::new(HttpSession {
+            subscription_id: connection.subscription_id.clone(),
+            commands: ends.commands,
+            frames,
+            last_poll: Mutex::new(Instant::now()),
+        }));
+
+        let sessions = self.clone();
+        let id = session_id.clone();
+        tokio::spawn(async move {
+            handle_connection(socket, connection).await;
+            if !long_poll {
+                sessions.remove(&id);
+            }
+        });
+        (session_id, sse_frames)
+    }
+
+    fn reap_idle(&self) {
+        let idle = Duration::from_secs(LONG_POLL_IDLE_SECS);
+        self.sessions.lock().unwrap().retain(|session_id, session| {
+            let alive = session.frames.is_none() || session.last_poll.lock().unwrap().elapsed() < idle;
+            if !alive {
+                info!(%session_id, subscription_id = %session.subscription_id, "长轮询会话长时间没有轮询，关闭连接");
+            }
+            alive
+        });
+    }
+}
+
+/// 建立 SSE 连接
+/// 第一个事件为 {"type": "session", "session_id": "..."}，之后每个事件的 data 与 WebSocket 文本帧相同；
+/// 连接被服务端关闭时最后一个事件为 event: close，data 为 {"code": 1012, "reason": "reconnect"}
+#[allow(clippy::too_many_arguments)]
+pub async fn sse_handler(
+    State(mqtt_pool): State<Arc<MqttPool>>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(jwt_cfg): Extension<JwtSettings>,
+    Extension(delivery_cfg): Extension<DeliverySettings>,
+    Extension(backpressure_cfg): Extension<BackpressureSettings>,
+    Extension(limits): Extension<LimitSettings>,
+    Extension(im_server_cfg): Extension<ImServerSettings>,
+    Extension(shutdown): Extension<Shutdown>,
+    Extension(shutdown_cfg): Extension<ShutdownSettings>,
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(sessions): Extension<Arc<HttpSessions>>,
+    Path(subscription_id): Path<String>,
+    Query(query): Query<ConnectQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!(%subscription_id, "收到 SSE 连接请求");
+
+    let request = ConnectRequest {
+        transport: "sse",
+        subscription_id,
+        query,
+        headers,
+        peer_addr,
+    };
+    let connection = match accept_connection(
+        mqtt_pool,
+        redis_client,
+        registry,
+        &jwt_cfg,
+        delivery_cfg,
+        &limits,
+        &im_server_cfg,
+        shutdown,
+        shutdown_cfg,
+        request,
+    ).await {
+        Ok(connection) => connection,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let (socket, ends) = ClientSocket::channel(
+        backpressure_cfg.queue_capacity,
+        Duration::from_millis(backpressure_cfg.write_timeout_ms),
+    );
+    let subscription_id = connection.subscription_id.clone();
+    let open_id = connection.user_open_id.clone();
+    let (session_id, frames) = sessions.start(socket, connection, ends, false);
+    let frames = frames.expect("SSE 会话的帧由事件流取走");
+    info!(%subscription_id, open_id = %open_id, %session_id, "SSE 会话已建立");
+
+    let opening = Event::default().data(serde_json::json!({"type": "session", "session_id": session_id}).to_string());
+    let stream = futures_util::stream::unfold((Some(opening), frames, false), |(opening, mut frames, closed)| async move {
+        if let Some(event) = opening {
+            return Some((Ok::<_, Infallible>(event), (None, frames, false)));
+        }
+        if closed {
+            return None;
+        }
+        loop {
+            let event = match frames.recv().await? {
+                Message::Text(text) => Event::default().data(text.as_str()),
+                Message::Ping(_) => Event::default().comment("ping"),
+                Message::Close(frame) => {
+                    let (code, reason) = frame.map(|f| (f.code, f.reason.to_string())).unwrap_or((1000, String::new()));
+                    let event = Event::default()
+                        .event("close")
+                        .data(serde_json::json!({"code": code, "reason": reason}).to_string());
+                    return Some((Ok(event), (None, frames, true)));
+                }
+                // SSE 只使用 JSON 编码，不会出现二进制帧
+                _ => continue,
+            };
+            return Some((Ok(event), (None, frames, false)));
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// 建立长轮询会话，返回 {"session_id": "...", "wait_secs": 25}
+#[allow(clippy::too_many_arguments)]
+pub async fn open_long_poll(
+    State(mqtt_pool): State<Arc<MqttPool>>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(jwt_cfg): Extension<JwtSettings>,
+    Extension(delivery_cfg): Extension<DeliverySettings>,
+    Extension(backpressure_cfg): Extension<BackpressureSettings>,
+    Extension(limits): Extension<LimitSettings>,
+    Extension(im_server_cfg): Extension<ImServerSettings>,
+    Extension(shutdown): Extension<Shutdown>,
+    Extension(shutdown_cfg): Extension<ShutdownSettings>,
+    Extension(registry): Extension<Arc<ConnectionRegistry>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(sessions): Extension<Arc<HttpSessions>>,
+    Path(subscription_id): Path<String>,
+    Query(query): Query<ConnectQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!(%subscription_id, "收到长轮询连接请求");
+
+    let request = ConnectRequest {
+        transport: "long_poll",
+        subscription_id,
+        query,
+        headers,
+        peer_addr,
+    };
+    let connection = match accept_connection(
+        mqtt_pool,
+        redis_client,
+        registry,
+        &jwt_cfg,
+        delivery_cfg,
+        &limits,
+        &im_server_cfg,
+        shutdown,
+        shutdown_cfg,
+        request,
+    ).await {
+        Ok(connection) => connection,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let (socket, ends) = ClientSocket::channel(
+        backpressure_cfg.queue_capacity,
+        Duration::from_millis(backpressure_cfg.write_timeout_ms),
+    );
+    let subscription_id = connection.subscription_id.clone();
+    let open_id = connection.user_open_id.clone();
+    let (session_id, _) = sessions.start(socket, connection, ends, true);
+    info!(%subscription_id, open_id = %open_id, %session_id, "长轮询会话已建立");
+
+    Json(serde_json::json!({
+        "session_id": session_id,
+        "wait_secs": LONG_POLL_WAIT_SECS,
+    })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PollQuery {
+    /// 没有新帧时最多等待的秒数，不超过 LONG_POLL_WAIT_SECS
+    pub wait: Option<u64>,
+}
+
+/// 取走长轮询会话中待发送的帧：没有帧时最多等待 wait 秒
+/// 返回 {"frames": [...], "closed": false}；closed 为 true 时会话已结束，close_code / close_reason 含义与 WebSocket 关闭码相同
+pub async fn poll_frames(
+    Extension(sessions): Extension<Arc<HttpSessions>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<PollQuery>,
+) -> impl IntoResponse {
+    let Some(session) = sessions.get(&session_id) else {
+        return (StatusCode::NOT_FOUND, "会话不存在或已结束，请重新建立连接").into_response();
+    };
+    let Some(frames) = session.frames.clone() else {
+        return (StatusCode::BAD_REQUEST, "SSE 会话不支持轮询").into_response();
+    };
+    *session.last_poll.lock().unwrap() = Instant::now();
+
+    let wait = Duration::from_secs(query.wait.unwrap_or(LONG_POLL_WAIT_SECS).min(LONG_POLL_WAIT_SECS));
+    let mut frames = frames.lock().await;
+    let mut batch = Vec::new();
+    let mut close = None;
+    let mut ended = false;
+    let deadline = tokio::time::Instant::now() + wait;
+    // 先等第一帧，之后只取已经到达的帧
+    while batch.len() < LONG_POLL_MAX_FRAMES && close.is_none() {
+        let message = if batch.is_empty() {
+            match tokio::time::timeout_at(deadline, frames.recv()).await {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        } else {
+            match frames.try_recv() {
+                Ok(message) => Some(message),
+                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => None,
+            }
+        };
+        match message {
+            Some(Message::Text(text)) => match serde_json::from_str::<serde_json::Value>(text.as_str()) {
+                Ok(frame) => batch.push(frame),
+                Err(e) => warn!(%session_id, error = %e, "长轮询帧不是 JSON，跳过"),
+            },
+            Some(Message::Close(frame)) => {
+                close = Some(frame.map(|f| (f.code, f.reason.to_string())).unwrap_or((1000, String::new())));
+            }
+            // ping 由轮询本身代替
+            Some(_) => continue,
+            None => {
+                ended = true;
+                break;
+            }
+        }
+    }
+    drop(frames);
+    *session.last_poll.lock().unwrap() = Instant::now();
+
+    let closed = close.is_some() || ended;
+    if closed {
+        sessions.remove(&session_id);
+        info!(%session_id, subscription_id = %session.subscription_id, "长轮询会话已结束");
+    }
+    let (close_code, close_reason) = close.unzip();
+    Json(serde_json::json!({
+        "frames": batch,
+        "closed": closed,
+        "close_code": close_code,
+        "close_reason": close_reason,
+    })).into_response()
+}
+
+/// 提交客户端命令（SSE / 长轮询会话），请求体与 WebSocket 文本帧相同：{"id": "...", "type": "ack", "data": {...}}
+/// 命令异步处理，响应帧从事件流 / 轮询结果返回
+pub async fn post_command(
+    Extension(sessions): Extension<Arc<HttpSessions>>,
+    Path(session_id): Path<String>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(session) = sessions.get(&session_id) else {
+        return (StatusCode::NOT_FOUND, "会话不存在或已结束，请重新建立连接").into_response();
+    };
+    let Ok(text) = String::from_utf8(body.to_vec()) else {
+        return (StatusCode::BAD_REQUEST, "命令必须是 JSON 文本").into_response();
+    };
+    if session.commands.send(Message::Text(text.into())).await.is_err() {
+        return (StatusCode::NOT_FOUND, "会话已结束，请重新建立连接").into_response();
+    }
+    debug!(%session_id, subscription_id = %session.subscription_id, "已接收会话命令");
+    (StatusCode::ACCEPTED, Json(serde_json::json!({"status": "accepted"}))).into_response()
+}
@@ -0,0 +1,16 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use std::sync::Arc;
+use crate::mqtt::MqttPool;
+
+/// 每个 WebSocket 连接的发送队列积压（depth）、历史最高积压、丢弃的临时推送数，按积压从多到少排序
+/// 以及连接池中每个 MQTT 连接的状态
+pub async fn connection_queues(State(mqtt_pool): State<Arc<MqttPool>>) -> impl IntoResponse {
+    let connections = mqtt_pool.queue_stats().await;
+    let total_depth: usize = connections.iter().map(|c| c.depth).sum();
+    Json(serde_json::json!({
+        "connection_count": connections.len(),
+        "total_depth": total_depth,
+        "connections": connections,
+        "mqtt_connections": mqtt_pool.connection_states(),
+    }))
+}
@@ -1,2 +1,10 @@
 pub mod websocket;
-
+pub mod connection;
+pub mod fallback;
+pub mod commands;
+pub mod presence;
+pub mod delivery;
+pub mod codec;
+pub mod metrics;
+pub mod registry;
+pub mod admin;
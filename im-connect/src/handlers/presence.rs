@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use tracing::warn;
+use im_share::{PresenceChanged, PresenceRegistry, PresenceStatus, RouteRegistry};
+use im_share::presence::PRESENCE_CHANGED_TOPIC;
+use crate::mqtt::MqttPool;
+
+/// 当前 WebSocket 连接（设备）的在线状态
+/// 写入 Redis 注册表；上线、下线和切换状态时额外发布一条变化通知，
+/// 由 im-server 去抖后推送给好友（心跳不改变状态，不发通知）
+/// 同时在跨节点路由表中登记连接所在的节点，随心跳刷新
+#[derive(Clone)]
+pub struct ConnectionPresence {
+    registry: PresenceRegistry,
+    routes: RouteRegistry,
+    mqtt: Arc<MqttPool>,
+    open_id: String,
+    device_id: String,
+    connection_id: u64,
+}
+
+impl ConnectionPresence {
+    pub fn new(
+        registry: PresenceRegistry,
+        routes: RouteRegistry,
+        mqtt: Arc<MqttPool>,
+        open_id: String,
+        device_id: String,
+        connection_id: u64,
+    ) -> Self {
+        Self { registry, routes, mqtt, open_id, device_id, connection_id }
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<()> {
+        self.routes.register(&self.open_id, self.mqtt.node_id(), self.connection_id).await?;
+        self.registry.connect(&self.open_id, &self.device_id).await?;
+        self.publish_changed().await;
+        Ok(())
+    }
+
+    pub async fn heartbeat(&self) -> anyhow::Result<()> {
+        self.routes.register(&self.open_id, self.mqtt.node_id(), self.connection_id).await?;
+        self.registry.heartbeat(&self.open_id, &self.device_id).await?;
+        Ok(())
+    }
+
+    pub async fn set_status(&self, status: PresenceStatus) -> anyhow::Result<()> {
+        self.registry.set_status(&self.open_id, &self.device_id, status).await?;
+        self.publish_changed().await;
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        self.routes.unregister(&self.open_id, self.mqtt.node_id(), self.connection_id).await?;
+        self.registry.disconnect(&self.open_id, &self.device_id).await?;
+        self.publish_changed().await;
+        Ok(())
+    }
+
+    async fn publish_changed(&self) {
+        let event = PresenceChanged { open_id: self.open_id.clone() };
+        let payload = match serde_json::to_vec(&event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(open_id = %self.open_id, error = %e, "在线状态变化通知编码失败");
+                return;
+            }
+        };
+        if let Err(e) = self.mqtt.publish(PRESENCE_CHANGED_TOPIC, payload).await {
+            warn!(open_id = %self.open_id, error = %e, "发布在线状态变化通知失败");
+        }
+    }
+}
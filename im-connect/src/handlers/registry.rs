@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use axum::http::StatusCode;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use im_share::NoticeFrame;
+use im_share::protocol::{CONNECTION_LIMIT_CLOSE_CODE, NODE_FULL_CLOSE_CODE};
+use crate::config::LimitSettings;
+use crate::mqtt::OutboundQueue;
+
+/// 每个连接待处理的管理命令数上限，连接卡住时新的命令直接丢弃
+const CONTROL_BUFFER: usize = 8;
+
+/// 管理员对某个连接下达的命令，由连接自己的循环执行
+pub enum ControlCommand {
+    /// 关闭连接（不吊销会话，客户端可以重连）
+    Disconnect { reason: String },
+    /// 推送系统通知
+    Notice(NoticeFrame),
+}
+
+/// 一个在线连接的描述
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub connection_id: u64,
+    pub subscription_id: String,
+    pub open_id: String,
+    pub mqtt_id: u64,
+    /// websocket / sse / long_poll
+    pub transport: &'static str,
+    pub remote_addr: String,
+    pub user_agent: Option<String>,
+    pub connected_at: i64,
+    pub queue_depth: usize,
+}
+
+/// 超过的连接数上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// 本节点总连接数
+    Node,
+    /// 同一用户的连接数
+    User,
+    /// 同一 IP 的连接数
+    Ip,
+}
+
+impl LimitExceeded {
+    /// WebSocket 关闭码：节点已满时客户端可以退避后重连，用户 / IP 超限时不应自动重连
+    pub fn close_code(&self) -> u16 {
+        match self {
+            LimitExceeded::Node => NODE_FULL_CLOSE_CODE,
+            LimitExceeded::User | LimitExceeded::Ip => CONNECTION_LIMIT_CLOSE_CODE,
+        }
+    }
+
+    /// SSE / 长轮询返回的 HTTP 状态码
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitExceeded::Node => StatusCode::SERVICE_UNAVAILABLE,
+            LimitExceeded::User | LimitExceeded::Ip => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LimitExceeded::Node => "node full",
+            LimitExceeded::User => "too many connections for user",
+            LimitExceeded::Ip => "too many connections from ip",
+        }
+    }
+}
+
+/// 上限为 0 表示不限制
+fn over_limit(count: usize, limit: usize) -> bool {
+    limit > 0 && count >= limit
+}
+
+struct Entry {
+    info: ConnectionInfo,
+    /// 订阅建立后才有发送队列
+    queue: Option<Arc<OutboundQueue>>,
+    control: mpsc::Sender<ControlCommand>,
+}
+
+/// 本进程内的在线连接（WebSocket、SSE、长轮询），供管理接口查询和下达命令
+pub struct ConnectionRegistry {
+    entries: Mutex<HashMap<u64, Entry>>,
+    next_id: AtomicU64,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            entries: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// 认证之前先检查节点总数和来源 IP 的上限，超限的请求不必再做认证
+    pub fn check_capacity(&self, remote_addr: &str, limits: &LimitSettings) -> Result<(), LimitExceeded> {
+        let entries = self.entries.lock().unwrap();
+        Self::check(&entries, None, remote_addr, limits)
+    }
+
+    fn check(entries: &HashMap<u64, Entry>, open_id: Option<&str>, remote_addr: &str, limits: &LimitSettings) -> Result<(), LimitExceeded> {
+        if over_limit(entries.len(), limits.max_connections) {
+            return Err(LimitExceeded::Node);
+        }
+        if over_limit(entries.values().filter(|e| e.info.remote_addr == remote_addr).count(), limits.max_connections_per_ip) {
+            return Err(LimitExceeded::Ip);
+        }
+        if let Some(open_id) = open_id
+            && over_limit(entries.values().filter(|e| e.info.open_id == open_id).count(), limits.max_connections_per_user) {
+                return Err(LimitExceeded::User);
+            }
+        Ok(())
+    }
+
+    /// 检查上限并登记连接（connection_id 在这里分配），返回的 RegisteredConnection 被 drop 时自动移除
+    pub fn register(self: &Arc<Self>, mut info: ConnectionInfo, limits: &LimitSettings) -> Result<RegisteredConnection, LimitExceeded> {
+        let mut entries = self.entries.lock().unwrap();
+        Self::check(&entries, Some(&info.open_id), &info.remote_addr, limits)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info.connection_id = id;
+        let (control, commands) = mpsc::channel(CONTROL_BUFFER);
+        entries.insert(id, Entry { info, queue: None, control });
+        Ok(RegisteredConnection {
+            registry: self.clone(),
+            id,
+            commands,
+        })
+    }
+
+    fn snapshot(entry: &Entry) -> ConnectionInfo {
+        let mut info = entry.info.clone();
+        info.queue_depth = entry.queue.as_ref().map(|q| q.stats().depth).unwrap_or(0);
+        info
+    }
+
+    /// 所有连接，按建立时间排序
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self.entries.lock().unwrap().values().map(Self::snapshot).collect();
+        connections.sort_by_key(|c| c.connection_id);
+        connections
+    }
+
+    /// 某个用户的所有连接
+    pub fn by_user(&self, open_id: &str) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.info.open_id == open_id)
+            .map(Self::snapshot)
+            .collect();
+        connections.sort_by_key(|c| c.connection_id);
+        connections
+    }
+
+    pub fn count(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// 把命令发给满足条件的连接，返回成功下达的连接数
+    fn send_where(&self, filter: impl Fn(&ConnectionInfo) -> bool, command: impl Fn() -> ControlCommand) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| filter(&entry.info))
+            .filter(|entry| entry.control.try_send(command()).is_ok())
+            .count()
+    }
+
+    /// 断开某个连接，连接不存在时返回 false
+    pub fn disconnect(&self, connection_id: u64, reason: &str) -> bool {
+        self.send_where(|info| info.connection_id == connection_id, || ControlCommand::Disconnect { reason: reason.to_string() }) > 0
+    }
+
+    /// 断开某个用户的所有连接，返回断开的连接数
+    pub fn disconnect_user(&self, open_id: &str, reason: &str) -> usize {
+        self.send_where(|info| info.open_id == open_id, || ControlCommand::Disconnect { reason: reason.to_string() })
+    }
+
+    /// 向所有连接推送系统通知，返回推送的连接数
+    pub fn broadcast(&self, notice: &NoticeFrame) -> usize {
+        self.send_where(|_| true, || ControlCommand::Notice(notice.clone()))
+    }
+}
+
+/// 已登记的连接，持有管理命令的接收端
+pub struct RegisteredConnection {
+    registry: Arc<ConnectionRegistry>,
+    id: u64,
+    commands: mpsc::Receiver<ControlCommand>,
+}
+
+impl RegisteredConnection {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// 订阅建立后关联发送队列，管理接口据此显示积压
+    pub fn attach_queue(&self, queue: Arc<OutboundQueue>) {
+        if let Some(entry) = self.registry.entries.lock().unwrap().get_mut(&self.id) {
+            entry.queue = Some(queue);
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<ControlCommand> {
+        self.commands.recv().await
+    }
+}
+
+impl Drop for RegisteredConnection {
+    fn drop(&mut self) {
+        self.registry.entries.lock().unwrap().remove(&self.id);
+    }
+}
@@ -1,6 +1,5 @@
 use dotenvy::dotenv;
-use tracing::info;
-use tracing_subscriber;
+use tracing::{info, warn};
 use axum::serve;
 use tokio::net::TcpListener;
 use std::net::SocketAddr;
@@ -10,8 +9,10 @@ use tower_http::cors::{CorsLayer, Any};
 mod handlers;
 mod routes;
 mod config;
+mod mqtt;
 
 use crate::config::AppConfig;
+use im_share::{Shutdown, generate_snowflake_id, shutdown_signal};
 
 #[tokio::main]
 async fn main() {
@@ -36,13 +37,44 @@ async fn main() {
             .expect("Redis 连接失败")
     );
     
-    // 传递 MQTT 连接信息，每个用户会创建自己的 MQTT 客户端
-    let mqtt_info = handlers::websocket::MqttConnectionInfo {
-        host: cfg.mqtt.host.clone(),
-        port: cfg.mqtt.port,
-    };
+    // 节点 ID：跨节点路由表记录用户连接在哪个节点上，im-server 据此直接发布到节点 topic
+    let node_id = cfg.connect.node_id
+        .clone()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| generate_snowflake_id().to_string());
+    
+    // 所有 WebSocket 连接共用的 MQTT 连接池
+    let mqtt_pool = mqtt::MqttPool::connect(&node_id, &cfg.mqtt.host, cfg.mqtt.port, cfg.mqtt.pool_size, cfg.backpressure.queue_capacity);
+    if let Err(e) = mqtt_pool.subscribe_node_topic().await {
+        warn!(node_id = %node_id, error = %e, "订阅节点 topic 失败，直接投递到本节点的消息将无法送达");
+    }
+    if cfg.im_server.internal_token.as_deref().is_none_or(str::is_empty) {
+        warn!(url = %cfg.im_server.url, "未配置 [im_server] internal_token，客户端通过长连接发送的命令将无法转发到 im-server");
+    }
+
+    // 优雅退出：收到 SIGTERM 后拒绝新的 WebSocket 升级，已有连接推送 reconnect 帧、写完待发送的帧后关闭，
+    // 全部连接清理完成（或超过 [shutdown] drain_secs）后取消 MQTT 订阅并断开连接
+    let shutdown = Shutdown::new();
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            shutdown.trigger();
+        });
+    }
 
-    let app = routes::create_routes(mqtt_info, redis_client, cfg.jwt.clone())
+    let app = routes::create_routes(
+        mqtt_pool.clone(),
+        redis_client,
+        cfg.jwt.clone(),
+        cfg.delivery.clone(),
+        cfg.backpressure.clone(),
+        cfg.limits.clone(),
+        shutdown.clone(),
+        cfg.shutdown.clone(),
+        cfg.admin.clone(),
+        cfg.im_server.clone(),
+    )
         .layer(
             CorsLayer::new()
                 .allow_origin(Any) // 开发时允许所有来源，生产环境应限制为特定域名
@@ -59,7 +91,22 @@ async fn main() {
     
     info!(%addr, "im-connect WebSocket 监听启动");
     
-    serve(listener, app.into_make_service())
+    let graceful = {
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    };
+    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(graceful)
         .await
         .unwrap();
+
+    // 已升级的 WebSocket 连接不受 serve 的优雅退出管理，等待各连接自行推送 reconnect 帧并清理
+    let deadline = std::time::Duration::from_secs(cfg.shutdown.drain_secs);
+    if shutdown.wait_idle(deadline).await {
+        info!("WebSocket 连接已全部关闭");
+    } else {
+        warn!(remaining = shutdown.active(), drain_secs = cfg.shutdown.drain_secs, "等待 WebSocket 连接关闭超时，强制退出");
+    }
+    mqtt_pool.shutdown().await;
+    info!("im-connect 已退出");
 }
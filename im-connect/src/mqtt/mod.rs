@@ -0,0 +1,332 @@
+mod queue;
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, oneshot};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+use im_share::{ImMqtt, IncomingMessage, MqttConfig, MqttState, NodeDelivery, node_topic};
+pub use queue::{DeliveryClass, Outbound, OutboundQueue, QueueStats};
+
+/// 某个 topic 在本进程内的路由：订阅在哪个连接上，以及需要转发给哪些 WebSocket 连接的发送队列
+/// 最后一个订阅者离开后路由保留到取消订阅请求发出为止，期间新的订阅者排在取消订阅之后重新订阅
+struct TopicRoute {
+    connection: usize,
+    sinks: HashMap<u64, Arc<OutboundQueue>>,
+    /// 最后一个排队的 broker 请求是订阅（true）还是取消订阅（false）
+    subscribed: bool,
+    /// 排队的 broker 请求编号，请求完成时编号未变且没有订阅者才移除路由
+    generation: u64,
+    /// 上一个 broker 请求完成的信号，下一个请求等它完成后再发出，保证同一 topic 的订阅和取消订阅按顺序到达 broker
+    last_op: Option<oneshot::Receiver<()>>,
+}
+
+impl TopicRoute {
+    /// 在持有路由表锁时排队一个 broker 请求，返回（请求编号，上一个请求的完成信号，本请求的完成通知）
+    fn queue_op(&mut self, subscribe: bool) -> (u64, Option<oneshot::Receiver<()>>, oneshot::Sender<()>) {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.subscribed = subscribe;
+        self.generation += 1;
+        (self.generation, self.last_op.replace(done_rx), done_tx)
+    }
+}
+
+/// 等待同一 topic 上一个 broker 请求完成（发送方被丢弃也视为完成）
+async fn wait_previous(previous: Option<oneshot::Receiver<()>>) {
+    if let Some(previous) = previous {
+        let _ = previous.await;
+    }
+}
+
+/// 共享 MQTT 连接池
+/// - 进程内只保持少量 MQTT 连接，用户 topic 按哈希固定分布到其中一个连接上
+/// - 同一 topic 被多个 WebSocket（同一用户的多个设备）订阅时只向 broker 订阅一次，按引用计数在最后一个订阅者离开时取消订阅
+/// - 连接收到的消息按 topic 查分发表，转发给本进程内订阅了该 topic 的所有 WebSocket
+///
+/// 连接使用 clean_session = true：离线消息由 Redis 离线队列负责，broker 不需要为这些连接保留会话
+///
+/// 另外订阅本节点的 topic（im/node/{node_id}）：im-server 查询跨节点路由表后把消息直接发到用户所在的节点，
+/// 收到后按其中的用户 topic 分发，与从 broker 收到的用户 topic 消息走同一条路径
+pub struct MqttPool {
+    node_id: String,
+    node_topic: String,
+    connections: Vec<ImMqtt>,
+    routes: Mutex<HashMap<String, TopicRoute>>,
+    next_subscription_id: AtomicU64,
+    /// 每个 WebSocket 连接的发送队列容量（条）
+    queue_capacity: usize,
+}
+
+impl MqttPool {
+    pub fn connect(node_id: &str, host: &str, port: u16, size: usize, queue_capacity: usize) -> Arc<Self> {
+        // 每个节点使用不同的 client_id 前缀，多个 im-connect 实例之间不会互相踢掉连接
+        let connections: Vec<ImMqtt> = (0..size.max(1))
+            .map(|index| {
+                let client_id = format!("im-connect-{}-{}", node_id, index);
+                ImMqtt::connect(MqttConfig::new(host, port, client_id).with_clean_session(true))
+            })
+            .collect();
+        info!(host = %host, port = port, pool_size = connections.len(), node_id = %node_id, "MQTT 连接池已创建");
+
+        let pool = Arc::new(Self {
+            node_id: node_id.to_string(),
+            node_topic: node_topic(node_id),
+            connections,
+            routes: Mutex::new(HashMap::new()),
+            next_subscription_id: AtomicU64::new(1),
+            queue_capacity,
+        });
+        for index in 0..pool.connections.len() {
+            let weak = Arc::downgrade(&pool);
+            let mut rx = pool.connections[index].messages();
+            tokio::spawn(async move {
+                loop {
+                    let msg = match rx.recv().await {
+                        Ok(msg) => msg,
+                        Err(RecvError::Lagged(skipped)) => {
+                            // 跳过的消息无法知道属于哪些 topic，让可能受影响的连接全部按溢出处理：
+                            // 转存队列中的消息后断开，客户端带 last_seq 重连时从收件箱 / 离线队列补回
+                            warn!(connection = index, skipped = skipped, "MQTT 分发积压，部分消息被跳过，受影响的连接按溢出处理");
+                            let Some(pool) = weak.upgrade() else {
+                                break;
+                            };
+                            pool.overflow_connection(index).await;
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let Some(pool) = weak.upgrade() else {
+                        break;
+                    };
+                    pool.dispatch(msg).await;
+                }
+                warn!(connection = index, "MQTT 连接的分发任务已退出");
+            });
+        }
+        pool
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// 订阅本节点的 topic，接收 im-server 按路由表直接投递的消息（启动时调用一次）
+    pub async fn subscribe_node_topic(&self) -> anyhow::Result<()> {
+        let connection = self.connection_for(&self.node_topic);
+        self.connections[connection].subscribe(&self.node_topic).await?;
+        info!(topic = %self.node_topic, connection = connection, "节点 topic 已订阅");
+        Ok(())
+    }
+
+    fn connection_for(&self, topic: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        topic.hash(&mut hasher);
+        (hasher.finish() % self.connections.len() as u64) as usize
+    }
+
+    /// 把收到的消息放入本进程内订阅了该 topic 的所有 WebSocket 的发送队列（不等待，队列满时按推送类型处理）
+    async fn dispatch(&self, msg: IncomingMessage) {
+        // 节点 topic 上的消息拆开后按其中的用户 topic 分发
+        let msg = if msg.topic == self.node_topic {
+            match serde_json::from_slice::<NodeDelivery>(&msg.payload) {
+                Ok(delivery) => IncomingMessage { topic: delivery.topic, payload: delivery.payload.into_bytes() },
+                Err(e) => {
+                    warn!(topic = %msg.topic, error = %e, "节点 topic 消息格式错误，丢弃");
+                    return;
+                }
+            }
+        } else {
+            msg
+        };
+        let routes = self.routes.lock().await;
+        let Some(route) = routes.get(&msg.topic) else {
+            debug!(topic = %msg.topic, "MQTT 消息没有本地订阅者，丢弃");
+            return;
+        };
+        // 推送类型每条消息只解析一次，所有订阅者共用
+        let class = DeliveryClass::of(&msg.payload);
+        for sink in route.sinks.values() {
+            sink.push(msg.clone(), class);
+        }
+    }
+
+    /// MQTT 连接的分发任务积压丢消息时，把可能受影响的发送队列标记为溢出
+    /// 节点 topic 上的消息会分发到任意用户 topic，节点 topic 所在的连接积压时标记全部发送队列
+    async fn overflow_connection(&self, connection: usize) {
+        let all = connection == self.connection_for(&self.node_topic);
+        let routes = self.routes.lock().await;
+        for route in routes.values().filter(|route| all || route.connection == connection) {
+            for sink in route.sinks.values() {
+                sink.mark_overflowed();
+            }
+        }
+    }
+
+    /// 为 WebSocket 连接订阅 topic，返回的 TopicSubscription 被 drop 时自动释放引用
+    pub async fn subscribe(self: &Arc<Self>, topic: &str, subscription_id: &str) -> anyhow::Result<TopicSubscription> {
+        let queue = Arc::new(OutboundQueue::new(subscription_id, topic, self.queue_capacity));
+        let tx = queue.clone();
+        let id = self.next_subscription_id.fetch_add(1, Ordering::Relaxed);
+        let subscription = TopicSubscription {
+            pool: self.clone(),
+            topic: topic.to_string(),
+            id,
+            queue,
+            released: false,
+        };
+
+        // 在路由表锁内登记订阅者并排队 broker 请求，释放锁后再等待请求发出，不阻塞其他 topic 的分发和订阅
+        let connection = self.connection_for(topic);
+        let queued = {
+            let mut routes = self.routes.lock().await;
+            let route = routes.entry(topic.to_string()).or_insert_with(|| TopicRoute {
+                connection,
+                sinks: HashMap::new(),
+                subscribed: false,
+                generation: 0,
+                last_op: None,
+            });
+            route.sinks.insert(id, tx);
+            if route.subscribed {
+                debug!(topic = %topic, subscribers = route.sinks.len(), "复用已有的 MQTT 订阅");
+                None
+            } else {
+                Some(route.queue_op(true))
+            }
+        };
+
+        if let Some((_, previous, done)) = queued {
+            wait_previous(previous).await;
+            let result = self.connections[connection].subscribe(topic).await;
+            let _ = done.send(());
+            match result {
+                Ok(_) => info!(topic = %topic, connection = connection, "MQTT topic 已订阅"),
+                Err(e) => {
+                    // 释放本订阅者；没有其他订阅者时排队取消订阅
+                    subscription.close().await;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(subscription)
+    }
+
+    /// 所有连接的发送队列统计，按积压从多到少排序
+    pub async fn queue_stats(&self) -> Vec<QueueStats> {
+        let routes = self.routes.lock().await;
+        let mut stats: Vec<QueueStats> = routes
+            .values()
+            .flat_map(|route| route.sinks.values().map(|sink| sink.stats()))
+            .collect();
+        stats.sort_by_key(|s| std::cmp::Reverse(s.depth));
+        stats
+    }
+
+    async fn release(&self, topic: &str, id: u64) {
+        let (connection, (generation, previous, done)) = {
+            let mut routes = self.routes.lock().await;
+            let Some(route) = routes.get_mut(topic) else {
+                return;
+            };
+            route.sinks.remove(&id);
+            if !route.sinks.is_empty() || !route.subscribed {
+                return;
+            }
+            (route.connection, route.queue_op(false))
+        };
+
+        wait_previous(previous).await;
+        match self.connections[connection].unsubscribe(topic).await {
+            Ok(()) => info!(topic = %topic, connection = connection, "最后一个订阅者已离开，MQTT topic 已取消订阅"),
+            Err(e) => warn!(topic = %topic, connection = connection, error = %e, "取消 MQTT 订阅失败"),
+        }
+        let _ = done.send(());
+
+        // 取消订阅期间没有新的订阅者时移除路由
+        let mut routes = self.routes.lock().await;
+        if routes.get(topic).is_some_and(|route| route.generation == generation && route.sinks.is_empty()) {
+            routes.remove(topic);
+        }
+    }
+
+    /// 每个 MQTT 连接的状态（断开后自动重连，重连期间发布直接失败）
+    pub fn connection_states(&self) -> Vec<MqttState> {
+        self.connections.iter().map(|connection| connection.state()).collect()
+    }
+
+    /// 通过 topic 所在的连接发布消息（QoS 1）
+    pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.connections[self.connection_for(topic)].publish(topic, payload).await
+    }
+
+    /// 进程退出前取消仍然存在的 topic 订阅并断开所有连接（已入队的发布会先写出）
+    pub async fn shutdown(&self) {
+        let routes = std::mem::take(&mut *self.routes.lock().await);
+        for (topic, route) in routes {
+            for sink in route.sinks.values() {
+                sink.close();
+            }
+            if !route.subscribed {
+                continue;
+            }
+            if let Err(e) = self.connections[route.connection].unsubscribe(&topic).await {
+                warn!(topic = %topic, connection = route.connection, error = %e, "退出时取消 MQTT 订阅失败");
+            }
+        }
+        for (index, connection) in self.connections.iter().enumerate() {
+            if let Err(e) = connection.disconnect().await {
+                warn!(connection = index, error = %e, "断开 MQTT 连接失败");
+            }
+        }
+        info!("MQTT 连接池已关闭");
+    }
+}
+
+/// 一个 WebSocket 对某个 topic 的订阅
+pub struct TopicSubscription {
+    pool: Arc<MqttPool>,
+    topic: String,
+    id: u64,
+    queue: Arc<OutboundQueue>,
+    released: bool,
+}
+
+impl TopicSubscription {
+    pub async fn recv(&mut self) -> Option<Outbound> {
+        self.queue.recv().await
+    }
+
+    pub fn queue(&self) -> Arc<OutboundQueue> {
+        self.queue.clone()
+    }
+
+    /// 取出已经到达但还没有处理的消息（不等待）
+    pub fn try_recv(&mut self) -> Option<Outbound> {
+        self.queue.try_recv()
+    }
+
+    /// 释放订阅，返回发送队列中还没有写给客户端的可靠推送，由调用方转存为离线消息
+    pub async fn close(mut self) -> Vec<IncomingMessage> {
+        self.pool.release(&self.topic, self.id).await;
+        self.released = true;
+        self.queue.close();
+        self.queue.drain_reliable()
+    }
+}
+
+impl Drop for TopicSubscription {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let pool = self.pool.clone();
+        let topic = std::mem::take(&mut self.topic);
+        let id = self.id;
+        tokio::spawn(async move {
+            pool.release(&topic, id).await;
+        });
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::warn;
+use im_share::IncomingMessage;
+use im_share::inbox::SEQ_FIELD;
+
+/// 过时即无意义的推送类型（message 字段里的 type）
+const TRANSIENT_TYPES: [&str; 2] = ["typing", "presence"];
+
+/// 推送在队列满时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryClass {
+    /// 正在输入、在线状态：队列满时丢弃最早的一条同类推送
+    Transient,
+    /// 聊天消息等：不能丢弃，队列满时连接转存离线消息后断开
+    Reliable,
+}
+
+impl DeliveryClass {
+    /// 带 seq 的推送一定需要可靠送达；没有 seq 时看 message 字段里的 type，无法解析的按需要可靠送达处理
+    pub fn of(payload: &[u8]) -> Self {
+        let Ok(json) = serde_json::from_slice::<serde_json::Value>(payload) else {
+            return DeliveryClass::Reliable;
+        };
+        if json.get(SEQ_FIELD).is_some() {
+            return DeliveryClass::Reliable;
+        }
+        let inner_type = json
+            .get("message")
+            .and_then(|m| m.as_str())
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+            .and_then(|m| m.get("type").and_then(|t| t.as_str()).map(|t| t.to_string()));
+        match inner_type {
+            Some(t) if TRANSIENT_TYPES.contains(&t.as_str()) => DeliveryClass::Transient,
+            _ => DeliveryClass::Reliable,
+        }
+    }
+}
+
+/// 从队列取出的结果
+pub enum Outbound {
+    Message(IncomingMessage),
+    /// 队列已满且没有可以丢弃的临时推送，客户端跟不上推送速度，连接应转存剩余消息后断开
+    Overflow,
+}
+
+/// 一个连接的发送队列统计
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub subscription_id: String,
+    pub topic: String,
+    pub depth: usize,
+    pub capacity: usize,
+    pub high_watermark: usize,
+    pub dropped: u64,
+    pub overflowed: bool,
+}
+
+struct QueueState {
+    items: VecDeque<(IncomingMessage, DeliveryClass)>,
+    overflowed: bool,
+    closed: bool,
+    dropped: u64,
+    high_watermark: usize,
+}
+
+/// 每个 WebSocket 连接的有界发送队列
+/// MQTT 分发任务只入队不等待，连接写 socket 慢时在这里积压，不会拖慢同一 MQTT 连接上的其他用户
+pub struct OutboundQueue {
+    subscription_id: String,
+    topic: String,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(subscription_id: &str, topic: &str, capacity: usize) -> Self {
+        Self {
+            subscription_id: subscription_id.to_string(),
+            topic: topic.to_string(),
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState {
+                items: VecDeque::new(),
+                overflowed: false,
+                closed: false,
+                dropped: 0,
+                high_watermark: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    /// 入队，队列满时：
+    /// - 先丢弃最早的一条临时推送腾出位置；新推送本身是临时推送且队列里没有临时推送时丢弃新推送
+    /// - 需要可靠送达的推送没有位置时标记溢出，之后的可靠推送继续保留，由连接转存为离线消息
+    ///
+    /// class 由分发方解析一次后传入，同一条消息分发给多个连接时不重复解析
+    pub fn push(&self, msg: IncomingMessage, class: DeliveryClass) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        if state.items.len() >= self.capacity && !state.overflowed {
+            match state.items.iter().position(|(_, c)| *c == DeliveryClass::Transient) {
+                Some(index) => {
+                    state.items.remove(index);
+                    state.dropped += 1;
+                }
+                None if class == DeliveryClass::Transient => {
+                    state.dropped += 1;
+                    return;
+                }
+                None => {
+                    state.overflowed = true;
+                    warn!(
+                        subscription_id = %self.subscription_id,
+                        topic = %self.topic,
+                        depth = state.items.len(),
+                        "客户端跟不上推送速度，发送队列已满"
+                    );
+                }
+            }
+        }
+        if state.overflowed && class == DeliveryClass::Transient {
+            state.dropped += 1;
+            return;
+        }
+        state.items.push_back((msg, class));
+        state.high_watermark = state.high_watermark.max(state.items.len());
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    /// MQTT 分发积压丢了消息（不知道是否属于本连接），按溢出处理
+    pub fn mark_overflowed(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed || state.overflowed {
+            return;
+        }
+        state.overflowed = true;
+        drop(state);
+        warn!(subscription_id = %self.subscription_id, topic = %self.topic, "MQTT 分发积压，发送队列按溢出处理");
+        self.notify.notify_one();
+    }
+
+    /// 等待下一条推送；队列已关闭（连接池正在退出）且为空时返回 None
+    pub async fn recv(&self) -> Option<Outbound> {
+        loop {
+            if let Some(outbound) = self.try_recv() {
+                return Some(outbound);
+            }
+            if self.state.lock().unwrap().closed {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Outbound> {
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed {
+            return Some(Outbound::Overflow);
+        }
+        state.items.pop_front().map(|(msg, _)| Outbound::Message(msg))
+    }
+
+    /// 停止接收新的推送
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// 取出仍在队列中的可靠推送（临时推送直接丢弃）
+    pub fn drain_reliable(&self) -> Vec<IncomingMessage> {
+        let mut state = self.state.lock().unwrap();
+        std::mem::take(&mut state.items)
+            .into_iter()
+            .filter(|(_, class)| *class == DeliveryClass::Reliable)
+            .map(|(msg, _)| msg)
+            .collect()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            subscription_id: self.subscription_id.clone(),
+            topic: self.topic.clone(),
+            depth: state.items.len(),
+            capacity: self.capacity,
+            high_watermark: state.high_watermark,
+            dropped: state.dropped,
+            overflowed: state.overflowed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reliable(seq: i64) -> IncomingMessage {
+        let payload = serde_json::json!({ SEQ_FIELD: seq, "message": "{\"type\":\"text\"}" });
+        IncomingMessage { topic: "user/a".to_string(), payload: payload.to_string().into_bytes() }
+    }
+
+    fn transient(kind: &str) -> IncomingMessage {
+        let inner = serde_json::json!({ "type": kind }).to_string();
+        let payload = serde_json::json!({ "message": inner });
+        IncomingMessage { topic: "user/a".to_string(), payload: payload.to_string().into_bytes() }
+    }
+
+    fn push(queue: &OutboundQueue, msg: IncomingMessage) {
+        let class = DeliveryClass::of(&msg.payload);
+        queue.push(msg, class);
+    }
+
+    fn next_seq(queue: &OutboundQueue) -> Option<i64> {
+        match queue.try_recv()? {
+            Outbound::Message(msg) => {
+                let json: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+                json.get(SEQ_FIELD).and_then(|v| v.as_i64())
+            }
+            Outbound::Overflow => panic!("队列不应溢出"),
+        }
+    }
+
+    #[test]
+    fn classifies_payloads() {
+        assert_eq!(DeliveryClass::of(&reliable(1).payload), DeliveryClass::Reliable);
+        assert_eq!(DeliveryClass::of(&transient("typing").payload), DeliveryClass::Transient);
+        assert_eq!(DeliveryClass::of(&transient("presence").payload), DeliveryClass::Transient);
+        assert_eq!(DeliveryClass::of(&transient("text").payload), DeliveryClass::Reliable);
+        assert_eq!(DeliveryClass::of(b"not json"), DeliveryClass::Reliable);
+    }
+
+    #[test]
+    fn keeps_order_within_capacity() {
+        let queue = OutboundQueue::new("s1", "user/a", 3);
+        for seq in 1..=3 {
+            push(&queue, reliable(seq));
+        }
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.high_watermark, stats.dropped), (3, 3, 0));
+        assert!(!stats.overflowed);
+        assert_eq!(next_seq(&queue), Some(1));
+        assert_eq!(next_seq(&queue), Some(2));
+        assert_eq!(next_seq(&queue), Some(3));
+        assert!(queue.try_recv().is_none());
+    }
+
+    #[test]
+    fn full_queue_drops_oldest_transient_for_reliable() {
+        let queue = OutboundQueue::new("s1", "user/a", 3);
+        push(&queue, transient("typing"));
+        push(&queue, reliable(1));
+        push(&queue, transient("presence"));
+        push(&queue, reliable(2));
+
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.dropped), (3, 1));
+        assert!(!stats.overflowed);
+        // 最早的 typing 被丢弃，剩下 seq 1、presence、seq 2
+        assert_eq!(next_seq(&queue), Some(1));
+        assert_eq!(next_seq(&queue), None);
+        assert_eq!(next_seq(&queue), Some(2));
+    }
+
+    #[test]
+    fn full_queue_drops_new_transient_without_overflow() {
+        let queue = OutboundQueue::new("s1", "user/a", 2);
+        push(&queue, reliable(1));
+        push(&queue, reliable(2));
+        push(&queue, transient("typing"));
+
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.dropped), (2, 1));
+        assert!(!stats.overflowed);
+    }
+
+    #[test]
+    fn reliable_overflow_keeps_reliable_and_drops_transient() {
+        let queue = OutboundQueue::new("s1", "user/a", 2);
+        push(&queue, reliable(1));
+        push(&queue, reliable(2));
+        push(&queue, reliable(3));
+        // 溢出后临时推送直接丢弃，可靠推送继续保留等待转存
+        push(&queue, transient("typing"));
+        push(&queue, reliable(4));
+
+        let stats = queue.stats();
+        assert!(stats.overflowed);
+        assert_eq!((stats.depth, stats.dropped), (4, 1));
+        assert!(matches!(queue.try_recv(), Some(Outbound::Overflow)));
+
+        let seqs: Vec<i64> = queue
+            .drain_reliable()
+            .iter()
+            .map(|msg| serde_json::from_slice::<serde_json::Value>(&msg.payload).unwrap()[SEQ_FIELD].as_i64().unwrap())
+            .collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4]);
+        assert_eq!(queue.stats().depth, 0);
+    }
+
+    #[test]
+    fn closed_queue_ignores_pushes() {
+        let queue = OutboundQueue::new("s1", "user/a", 2);
+        queue.close();
+        push(&queue, reliable(1));
+        assert_eq!(queue.stats().depth, 0);
+        assert!(queue.try_recv().is_none());
+    }
+
+    #[test]
+    fn mark_overflowed_reports_overflow() {
+        let queue = OutboundQueue::new("s1", "user/a", 2);
+        push(&queue, reliable(1));
+        queue.mark_overflowed();
+        assert!(queue.stats().overflowed);
+        assert!(matches!(queue.try_recv(), Some(Outbound::Overflow)));
+    }
+}
@@ -1,13 +1,55 @@
-use axum::{Router, routing::get, Extension};
+use axum::{Router, middleware, routing::{delete, get, post}, Extension};
 use std::sync::Arc;
-use im_share::{RedisClient, JwtSettings};
-use crate::handlers::websocket;
+use im_share::{RedisClient, JwtSettings, Shutdown};
+use crate::config::{AdminSettings, BackpressureSettings, DeliverySettings, ImServerSettings, LimitSettings, ShutdownSettings};
+use crate::handlers::{admin, fallback, metrics, websocket};
+use crate::handlers::registry::ConnectionRegistry;
+use crate::handlers::fallback::HttpSessions;
+use crate::mqtt::MqttPool;
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_routes(
+    mqtt_pool: Arc<MqttPool>,
+    redis_client: Arc<RedisClient>,
+    jwt_cfg: JwtSettings,
+    delivery_cfg: DeliverySettings,
+    backpressure_cfg: BackpressureSettings,
+    limits: LimitSettings,
+    shutdown: Shutdown,
+    shutdown_cfg: ShutdownSettings,
+    admin_cfg: AdminSettings,
+    im_server_cfg: ImServerSettings,
+) -> Router {
+    // 管理和监控接口：需要 [admin] token，nginx 不代理 /admin/* 和 /metrics/*
+    let admin_routes = Router::new()
+        .route("/admin/connections", get(admin::list_connections))
+        .route("/admin/connections/{connection_id}", delete(admin::disconnect_connection))
+        .route("/admin/users/{open_id}/connections", get(admin::get_user_connections))
+        .route("/admin/users/{open_id}/connections", delete(admin::disconnect_user))
+        .route("/admin/notices", post(admin::broadcast_notice))
+        // 每个连接的发送队列积压情况
+        .route("/metrics/connections", get(metrics::connection_queues))
+        .route_layer(middleware::from_fn(admin::require_admin));
 
-pub fn create_routes(mqtt_info: websocket::MqttConnectionInfo, redis_client: Arc<RedisClient>, jwt_cfg: JwtSettings) -> Router {
     Router::new()
         .route("/ws/{subscription_id}", get(websocket::ws_handler))
+        // WebSocket 被拦截时的备用传输：SSE 和长轮询，认证、补发和确认与 WebSocket 相同
+        .route("/sse/{subscription_id}", get(fallback::sse_handler))
+        .route("/poll/{subscription_id}", post(fallback::open_long_poll))
+        .route("/sessions/{session_id}/frames", get(fallback::poll_frames))
+        .route("/sessions/{session_id}/commands", post(fallback::post_command))
+        .merge(admin_routes)
         .layer(Extension(redis_client))
         .layer(Extension(jwt_cfg))
-        .with_state(mqtt_info)
+        .layer(Extension(delivery_cfg))
+        .layer(Extension(backpressure_cfg))
+        .layer(Extension(limits))
+        .layer(Extension(shutdown))
+        .layer(Extension(shutdown_cfg))
+        .layer(Extension(HttpSessions::new()))
+        .layer(Extension(ConnectionRegistry::new()))
+        .layer(Extension(admin_cfg))
+        .layer(Extension(im_server_cfg))
+        .with_state(mqtt_pool)
 }
 
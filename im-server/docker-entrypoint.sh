@@ -21,6 +21,9 @@ database = "${MYSQL_DATABASE:-violet}"
 secret = "${JWT_SECRET:-337eb69ef604dec5cdb04481242877fea7db31e4c1fd236497033431ab41d499}"
 expiration_hours = ${JWT_EXPIRATION_HOURS:-24}
 
+[internal]
+token = "${IM_INTERNAL_TOKEN:-}"
+
 [redis]
 host = "${REDIS_HOST:-redis}"
 port = ${REDIS_PORT:-6379}
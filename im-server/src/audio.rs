@@ -0,0 +1,526 @@
+use im_share::VoiceInfo;
+
+// 语音文件解析：校验格式，提取时长和用于气泡 UI 的波形
+// 只解析容器，不引入解码器：
+// - WAV（PCM）：直接按采样峰值计算波形
+// - Ogg（Opus / Vorbis）和 M4A（AAC）：按每个音频包的大小估算能量包络（VBR 编码下包越大音量越高）
+
+/// 波形采样点数
+pub const WAVEFORM_POINTS: usize = 64;
+/// 波形最大值（0-100）
+const WAVEFORM_MAX: u32 = 100;
+
+/// 解析语音文件，返回格式、时长和波形
+pub fn analyze_audio(data: &[u8]) -> Result<VoiceInfo, String> {
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+        analyze_wav(data)
+    } else if data.len() >= 4 && &data[0..4] == b"OggS" {
+        analyze_ogg(data)
+    } else if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        analyze_m4a(data)
+    } else {
+        Err("不支持的音频格式，仅支持 ogg/opus、m4a 和 wav".to_string())
+    }
+}
+
+/// 把按 rate 计数的时长换算为毫秒，rate 为 0 或溢出时视为文件损坏
+fn ticks_to_ms(ticks: u64, rate: u64) -> Result<u64, String> {
+    if rate == 0 {
+        return Err("音频采样率无效".to_string());
+    }
+    ticks.checked_mul(1000)
+        .map(|v| v / rate)
+        .ok_or_else(|| "音频时长信息无效".to_string())
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> Option<u64> {
+    data.get(offset..offset + 8).map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+/// 把长度为 count 的数值序列降采样为 WAVEFORM_POINTS 个点（取每段最大值），并归一化到 0-100
+fn downsample(count: usize, values: impl Iterator<Item = u32>) -> Vec<u8> {
+    let mut buckets = vec![0u32; WAVEFORM_POINTS];
+    if count == 0 {
+        return vec![0; WAVEFORM_POINTS];
+    }
+    for (i, value) in values.take(count).enumerate() {
+        let bucket = &mut buckets[i * WAVEFORM_POINTS / count];
+        *bucket = (*bucket).max(value);
+    }
+    // 值的个数少于采样点数时，空的点沿用前一个点
+    for i in 1..WAVEFORM_POINTS {
+        if count < WAVEFORM_POINTS && buckets[i] == 0 {
+            buckets[i] = buckets[i - 1];
+        }
+    }
+    let max = buckets.iter().copied().max().unwrap_or(0).max(1);
+    buckets.iter().map(|v| (*v as u64 * WAVEFORM_MAX as u64 / max as u64) as u8).collect()
+}
+
+fn analyze_wav(data: &[u8]) -> Result<VoiceInfo, String> {
+    let mut offset = 12;
+    let mut format = None;
+    let mut samples = None;
+    while offset + 8 <= data.len() {
+        let chunk_id = &data[offset..offset + 4];
+        let chunk_size = read_u32_le(data, offset + 4).ok_or("WAV 文件已损坏")? as usize;
+        let body_start = offset + 8;
+        let body_end = body_start.saturating_add(chunk_size).min(data.len());
+        match chunk_id {
+            b"fmt " => {
+                let audio_format = read_u16_le(data, body_start).ok_or("WAV 格式信息不完整")?;
+                let channels = read_u16_le(data, body_start + 2).ok_or("WAV 格式信息不完整")?;
+                let sample_rate = read_u32_le(data, body_start + 4).ok_or("WAV 格式信息不完整")?;
+                let block_align = read_u16_le(data, body_start + 12).ok_or("WAV 格式信息不完整")?;
+                let bits = read_u16_le(data, body_start + 14).ok_or("WAV 格式信息不完整")?;
+                format = Some((audio_format, channels, sample_rate, block_align, bits));
+            }
+            b"data" => {
+                // 长度为 0xFFFFFFFF 表示录制时长度未知（流式写入），取到文件末尾；其他情况数据不完整视为文件损坏
+                if chunk_size != u32::MAX as usize && body_end < body_start.saturating_add(chunk_size) {
+                    return Err("WAV 音频数据不完整".to_string());
+                }
+                samples = Some(&data[body_start..body_end]);
+            }
+            _ => {}
+        }
+        // chunk 按 2 字节对齐
+        offset = body_start.saturating_add(chunk_size).saturating_add(chunk_size & 1);
+    }
+
+    let (audio_format, channels, sample_rate, block_align, bits) = format.ok_or("WAV 文件缺少 fmt 信息")?;
+    let samples = samples.ok_or("WAV 文件缺少音频数据")?;
+    // 1 = PCM，3 = IEEE float，0xFFFE = WAVE_FORMAT_EXTENSIBLE
+    if !matches!(audio_format, 1 | 3 | 0xFFFE) {
+        return Err("仅支持 PCM 编码的 WAV 文件".to_string());
+    }
+    let bytes_per_sample = (bits / 8) as usize;
+    if channels == 0
+        || sample_rate == 0
+        || !matches!(bits, 8 | 16 | 24 | 32)
+        || (block_align as usize) < bytes_per_sample * channels as usize {
+        return Err("WAV 格式信息无效".to_string());
+    }
+
+    let frame_count = samples.len() / block_align as usize;
+    let duration_ms = ticks_to_ms(frame_count as u64, sample_rate as u64)?;
+
+    // 只取第一个声道计算峰值
+    let amplitudes = samples
+        .chunks_exact(block_align as usize)
+        .map(|frame| {
+            let s = &frame[..bytes_per_sample];
+            match (bits, audio_format) {
+                (8, _) => (s[0] as i32 - 128).unsigned_abs() << 8,
+                (16, _) => (i16::from_le_bytes([s[0], s[1]]) as i32).unsigned_abs(),
+                (24, _) => (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 16).unsigned_abs(),
+                (32, 3) => (f32::from_le_bytes([s[0], s[1], s[2], s[3]]).abs().min(1.0) * 32767.0) as u32,
+                _ => (i32::from_le_bytes([s[0], s[1], s[2], s[3]]) >> 16).unsigned_abs(),
+            }
+        });
+
+    Ok(VoiceInfo {
+        format: "wav".to_string(),
+        duration_ms,
+        waveform: downsample(frame_count, amplitudes),
+    })
+}
+
+fn analyze_ogg(data: &[u8]) -> Result<VoiceInfo, String> {
+    let mut offset = 0;
+    let mut packets: Vec<u32> = Vec::new();
+    let mut current_packet = 0u32;
+    let mut last_granule: Option<u64> = None;
+    let mut first_packet: Vec<u8> = Vec::new();
+    let mut first_packet_done = false;
+
+    while offset + 27 <= data.len() {
+        if &data[offset..offset + 4] != b"OggS" {
+            return Err("Ogg 文件已损坏".to_string());
+        }
+        let granule = i64::from_le_bytes(data[offset + 6..offset + 14].try_into().unwrap());
+        let segment_count = data[offset + 26] as usize;
+        let table_start = offset + 27;
+        let data_start = table_start + segment_count;
+        let lacing = data.get(table_start..data_start).ok_or("Ogg 文件已损坏")?;
+
+        let mut pos = data_start;
+        for &lace in lacing {
+            if !first_packet_done {
+                let end = (pos + lace as usize).min(data.len());
+                first_packet.extend_from_slice(&data[pos.min(end)..end]);
+            }
+            pos += lace as usize;
+            current_packet += lace as u32;
+            if lace < 255 {
+                packets.push(current_packet);
+                current_packet = 0;
+                first_packet_done = true;
+            }
+        }
+        // granule 为 -1 表示该页没有结束的包
+        if granule >= 0 {
+            last_granule = Some(granule as u64);
+        }
+        // 最后一页被截断时 granule 记录的时长比实际数据长
+        if pos > data.len() {
+            return Err("Ogg 文件数据不完整".to_string());
+        }
+        offset = pos;
+    }
+
+    let (format, header_packets, duration_ms) = if first_packet.starts_with(b"OpusHead") {
+        // Opus 的 granule 固定为 48kHz，需要减去 pre_skip
+        let pre_skip = read_u16_le(&first_packet, 10).ok_or("Opus 头信息不完整")? as u64;
+        let granule = last_granule.ok_or("Ogg 文件缺少时长信息")?;
+        ("opus", 2, ticks_to_ms(granule.saturating_sub(pre_skip), 48000)?)
+    } else if first_packet.starts_with(b"\x01vorbis") {
+        let sample_rate = read_u32_le(&first_packet, 12).ok_or("Vorbis 头信息不完整")? as u64;
+        if sample_rate == 0 {
+            return Err("Vorbis 采样率无效".to_string());
+        }
+        let granule = last_granule.ok_or("Ogg 文件缺少时长信息")?;
+        ("vorbis", 3, ticks_to_ms(granule, sample_rate)?)
+    } else {
+        return Err("仅支持 Opus 或 Vorbis 编码的 Ogg 文件".to_string());
+    };
+
+    let audio_packets = packets.get(header_packets..).unwrap_or(&[]);
+    Ok(VoiceInfo {
+        format: format.to_string(),
+        duration_ms,
+        waveform: downsample(audio_packets.len(), audio_packets.iter().copied()),
+    })
+}
+
+/// 在 data 中查找直接子 box，返回 box 内容
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    iter_boxes(data).find(|(t, _)| t == box_type).map(|(_, body)| body)
+}
+
+/// 遍历 MP4 box，返回（类型，内容）
+fn iter_boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut offset = 0usize;
+    std::iter::from_fn(move || {
+        if offset + 8 > data.len() {
+            return None;
+        }
+        let size = read_u32_be(data, offset)? as u64;
+        let box_type: [u8; 4] = data[offset + 4..offset + 8].try_into().ok()?;
+        let (header_len, size) = match size {
+            0 => (8, (data.len() - offset) as u64),
+            1 => (16, read_u64_be(data, offset + 8)?),
+            _ => (8, size),
+        };
+        if size < header_len as u64 {
+            return None;
+        }
+        // box 超出文件末尾（文件被截断）时停止遍历
+        let end = offset.checked_add(usize::try_from(size).ok()?)?;
+        let body = data.get(offset + header_len..end)?;
+        offset = end;
+        Some((box_type, body))
+    })
+}
+
+fn analyze_m4a(data: &[u8]) -> Result<VoiceInfo, String> {
+    let moov = find_box(data, b"moov").ok_or("M4A 文件缺少 moov 信息")?;
+
+    // 找到音频轨道（hdlr 类型为 soun）
+    let mdia = iter_boxes(moov)
+        .filter(|(t, _)| t == b"trak")
+        .filter_map(|(_, trak)| find_box(trak, b"mdia"))
+        .find(|mdia| find_box(mdia, b"hdlr").and_then(|h| h.get(8..12)) == Some(b"soun"))
+        .ok_or("M4A 文件缺少音频轨道")?;
+
+    // mdhd：version(1) flags(3) 之后是创建/修改时间、timescale、duration
+    let mdhd = find_box(mdia, b"mdhd").ok_or("M4A 文件缺少 mdhd 信息")?;
+    let (timescale, duration) = match mdhd.first() {
+        Some(1) => (read_u32_be(mdhd, 20), read_u64_be(mdhd, 24)),
+        Some(0) => (read_u32_be(mdhd, 12), read_u32_be(mdhd, 16).map(|d| d as u64)),
+        _ => (None, None),
+    };
+    let timescale = timescale.filter(|t| *t > 0).ok_or("M4A 时长信息无效")? as u64;
+    let duration_ms = ticks_to_ms(duration.ok_or("M4A 时长信息无效")?, timescale)?;
+
+    // stsz：每个音频帧的大小
+    let sample_sizes: Vec<u32> = find_box(mdia, b"minf")
+        .and_then(|minf| find_box(minf, b"stbl"))
+        .and_then(|stbl| find_box(stbl, b"stsz"))
+        .map(|stsz| {
+            let fixed_size = read_u32_be(stsz, 4).unwrap_or(0);
+            let count = read_u32_be(stsz, 8).unwrap_or(0) as usize;
+            if fixed_size != 0 {
+                vec![fixed_size; count.min(WAVEFORM_POINTS)]
+            } else {
+                (0..count).map_while(|i| read_u32_be(stsz, 12 + i * 4)).collect()
+            }
+        })
+        .unwrap_or_default();
+
+    Ok(VoiceInfo {
+        format: "m4a".to_string(),
+        duration_ms,
+        waveform: downsample(sample_sizes.len(), sample_sizes.into_iter()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+        let block_align = 2 * channels;
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&sample_rate.to_le_bytes());
+        out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+        out
+    }
+
+    /// 一个 Ogg 页，每个包都在本页结束
+    fn ogg_page(granule: i64, packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(b"OggS");
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&granule.to_le_bytes());
+        out.extend_from_slice(&[0; 12]);
+        out.push(lacing.len() as u8);
+        out.extend_from_slice(&lacing);
+        for packet in packets {
+            out.extend_from_slice(packet);
+        }
+        out
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(1);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        head
+    }
+
+    fn opus(pre_skip: u16, granule: i64, packet_sizes: &[usize]) -> Vec<u8> {
+        let mut out = ogg_page(0, &[opus_head(pre_skip)]);
+        out.extend(ogg_page(0, &[b"OpusTags".to_vec()]));
+        let packets: Vec<Vec<u8>> = packet_sizes.iter().map(|n| vec![0xAB; *n]).collect();
+        out.extend(ogg_page(granule, &packets));
+        out
+    }
+
+    fn vorbis(sample_rate: u32, granule: i64) -> Vec<u8> {
+        let mut ident = b"\x01vorbis".to_vec();
+        ident.extend_from_slice(&0u32.to_le_bytes());
+        ident.push(1);
+        ident.extend_from_slice(&sample_rate.to_le_bytes());
+        ident.extend_from_slice(&[0; 13]);
+        let mut out = ogg_page(0, &[ident]);
+        out.extend(ogg_page(0, &[b"\x03vorbis".to_vec(), b"\x05vorbis".to_vec()]));
+        out.extend(ogg_page(granule, &[vec![1; 40], vec![1; 80]]));
+        out
+    }
+
+    fn mp4_box(box_type: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = (8 + body.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(box_type);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn m4a(timescale: u32, duration: u32, handler: &[u8; 4], sample_sizes: &[u32]) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 12]);
+        let mut mdhd = vec![0; 12];
+        mdhd.extend_from_slice(&timescale.to_be_bytes());
+        mdhd.extend_from_slice(&duration.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 4]);
+        let mut stsz = vec![0; 8];
+        stsz.extend_from_slice(&(sample_sizes.len() as u32).to_be_bytes());
+        for size in sample_sizes {
+            stsz.extend_from_slice(&size.to_be_bytes());
+        }
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsz", &stsz));
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &[mp4_box(b"hdlr", &hdlr), mp4_box(b"mdhd", &mdhd), minf].concat());
+        let moov = mp4_box(b"moov", &mp4_box(b"trak", &mdia));
+        [mp4_box(b"ftyp", b"M4A \0\0\0\0"), moov, mp4_box(b"mdat", &[0; 16])].concat()
+    }
+
+    #[test]
+    fn wav_duration_and_waveform() {
+        // 8kHz 单声道 0.5 秒，前半段安静、后半段响
+        let samples: Vec<i16> = (0..4000).map(|i| if i < 2000 { 100 } else { 20000 }).collect();
+        let voice = analyze_audio(&wav(8000, 1, &samples)).unwrap();
+        assert_eq!(voice.format, "wav");
+        assert_eq!(voice.duration_ms, 500);
+        assert_eq!(voice.waveform.len(), WAVEFORM_POINTS);
+        assert_eq!(voice.waveform[0], 0);
+        assert_eq!(voice.waveform[WAVEFORM_POINTS - 1], 100);
+    }
+
+    #[test]
+    fn wav_stereo_counts_frames_not_samples() {
+        let voice = analyze_audio(&wav(16000, 2, &vec![1000; 32000])).unwrap();
+        assert_eq!(voice.duration_ms, 1000);
+    }
+
+    #[test]
+    fn wav_truncated_data_is_rejected() {
+        let mut data = wav(8000, 1, &vec![1000; 8000]);
+        data.truncate(data.len() - 100);
+        assert!(analyze_audio(&data).is_err());
+    }
+
+    #[test]
+    fn wav_streaming_length_reads_to_end() {
+        let mut data = wav(8000, 1, &vec![1000; 4000]);
+        data[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(analyze_audio(&data).unwrap().duration_ms, 500);
+    }
+
+    #[test]
+    fn wav_malformed_headers_are_rejected() {
+        let valid = wav(8000, 1, &[0; 80]);
+
+        let mut zero_rate = valid.clone();
+        zero_rate[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert!(analyze_audio(&zero_rate).is_err());
+
+        let mut compressed = valid.clone();
+        compressed[20..22].copy_from_slice(&0x55u16.to_le_bytes());
+        assert!(analyze_audio(&compressed).is_err());
+
+        let mut no_fmt = valid.clone();
+        no_fmt[12..16].copy_from_slice(b"junk");
+        assert!(analyze_audio(&no_fmt).is_err());
+
+        assert!(analyze_audio(&valid[..12]).is_err());
+    }
+
+    #[test]
+    fn opus_duration_subtracts_pre_skip() {
+        let voice = analyze_audio(&opus(312, 48000 * 2 + 312, &[20, 60, 120])).unwrap();
+        assert_eq!(voice.format, "opus");
+        assert_eq!(voice.duration_ms, 2000);
+        // 波形由音频包大小估算，头部的两个包不计入
+        assert_eq!(voice.waveform.len(), WAVEFORM_POINTS);
+        assert_eq!(voice.waveform.iter().copied().max(), Some(100));
+        assert_eq!(voice.waveform[0], 16);
+    }
+
+    #[test]
+    fn opus_multi_segment_packet() {
+        let voice = analyze_audio(&opus(0, 48000, &[600])).unwrap();
+        assert_eq!(voice.duration_ms, 1000);
+    }
+
+    #[test]
+    fn vorbis_duration_uses_sample_rate() {
+        let voice = analyze_audio(&vorbis(44100, 44100 * 3)).unwrap();
+        assert_eq!(voice.format, "vorbis");
+        assert_eq!(voice.duration_ms, 3000);
+    }
+
+    #[test]
+    fn ogg_malformed_input_is_rejected() {
+        // 最后一页被截断
+        let mut truncated = opus(312, 48000 + 312, &[100, 100]);
+        truncated.truncate(truncated.len() - 50);
+        assert!(analyze_audio(&truncated).is_err());
+
+        // 第二页的同步标记损坏
+        let mut corrupt = opus(0, 48000, &[10]);
+        let second_page = ogg_page(0, &[opus_head(0)]).len();
+        corrupt[second_page] = b'X';
+        assert!(analyze_audio(&corrupt).is_err());
+
+        // 音频页的 granule 都是 -1 时只有头部页的 0，时长为 0（上传时按无效语音拒绝）
+        assert_eq!(analyze_audio(&opus(0, -1, &[10])).unwrap().duration_ms, 0);
+        let mut no_granule = ogg_page(-1, &[opus_head(0)]);
+        no_granule.extend(ogg_page(-1, &[vec![1; 10]]));
+        assert!(analyze_audio(&no_granule).is_err());
+
+        // 采样率为 0 或时长溢出
+        assert!(analyze_audio(&vorbis(0, 1000)).is_err());
+        assert!(analyze_audio(&vorbis(44100, i64::MAX)).is_err());
+
+        // 不支持的编码
+        assert!(analyze_audio(&ogg_page(100, &[b"\x80theora".to_vec()])).is_err());
+    }
+
+    #[test]
+    fn m4a_duration_and_waveform() {
+        let voice = analyze_audio(&m4a(44100, 44100 * 3, b"soun", &[100, 400, 200])).unwrap();
+        assert_eq!(voice.format, "m4a");
+        assert_eq!(voice.duration_ms, 3000);
+        assert_eq!(voice.waveform.iter().copied().max(), Some(100));
+    }
+
+    #[test]
+    fn m4a_malformed_input_is_rejected() {
+        assert!(analyze_audio(&m4a(0, 1000, b"soun", &[10])).is_err());
+        assert!(analyze_audio(&m4a(44100, 1000, b"vide", &[10])).is_err());
+
+        // moov 被截断
+        let valid = m4a(44100, 44100, b"soun", &[10, 20]);
+        let moov_end = valid.len() - 24;
+        assert!(analyze_audio(&valid[..moov_end - 4]).is_err());
+    }
+
+    #[test]
+    fn unknown_format_is_rejected() {
+        assert!(analyze_audio(b"ID3\x04\0\0\0\0\0\0").is_err());
+        assert!(analyze_audio(b"").is_err());
+    }
+
+    #[test]
+    fn truncated_files_never_panic() {
+        let fixtures = [
+            wav(8000, 1, &[500; 400]),
+            opus(312, 48000, &[30, 300, 40]),
+            vorbis(22050, 22050),
+            m4a(1000, 2500, b"soun", &[5, 6, 7]),
+        ];
+        for fixture in &fixtures {
+            let full = analyze_audio(fixture).unwrap().duration_ms;
+            for len in 0..fixture.len() {
+                // 截断的文件要么报错，要么时长不超过完整文件（例如 m4a 截掉末尾的 mdat）
+                if let Ok(voice) = analyze_audio(&fixture[..len]) {
+                    assert!(voice.duration_ms <= full, "len {} gave {}ms > {}ms", len, voice.duration_ms, full);
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use serde::Deserialize;
-use std::{fs, path::Path};
+use std::{collections::HashMap, fs, path::Path};
 use im_share::JwtSettings;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -61,6 +61,8 @@ pub struct UploadSettings {
     pub thumbnail_max_height: u32,
     #[serde(default = "default_save_original")]
     pub save_original: bool,
+    #[serde(default = "default_max_voice_duration_secs")]
+    pub max_voice_duration_secs: u64,
 }
 
 fn default_upload_path() -> String {
@@ -95,6 +97,151 @@ fn default_save_original() -> bool {
     true
 }
 
+fn default_max_voice_duration_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportSettings {
+    #[serde(default = "default_export_expire_hours")]
+    pub expire_hours: u64,  // 导出文件保留时长（小时），过期后删除文件
+    #[serde(default = "default_export_max_messages")]
+    pub max_messages: usize,  // 单个导出任务最多导出的消息条数
+    #[serde(default = "default_export_cleanup_interval_secs")]
+    pub cleanup_interval_secs: u64,  // 过期导出清理任务的执行间隔（秒）
+}
+
+fn default_export_expire_hours() -> u64 {
+    72
+}
+
+fn default_export_max_messages() -> usize {
+    50000
+}
+
+fn default_export_cleanup_interval_secs() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetentionSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub single_days: u32,  // 单聊消息保留天数，0 表示永久保留
+    #[serde(default)]
+    pub group_days: u32,  // 群聊消息默认保留天数，0 表示永久保留
+    #[serde(default)]
+    pub groups: HashMap<String, u32>,  // 按群组覆盖保留天数（key 为 group_id），0 表示永久保留
+    #[serde(default = "default_retention_interval_secs")]
+    pub interval_secs: u64,  // 归档任务执行间隔（秒）
+    #[serde(default = "default_retention_batch_size")]
+    pub batch_size: u32,  // 每批迁移的消息条数
+}
+
+fn default_retention_interval_secs() -> u64 {
+    3600
+}
+
+fn default_retention_batch_size() -> u32 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkPreviewSettings {
+    #[serde(default = "default_link_preview_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_link_preview_timeout_ms")]
+    pub timeout_ms: u64,  // 抓取页面的超时时间（毫秒）
+    #[serde(default = "default_link_preview_inline_wait_ms")]
+    pub inline_wait_ms: u64,  // 发送消息时最多等待预览的时间（毫秒），超时后消息不带预览发出，抓取在后台继续并写入缓存
+    #[serde(default = "default_link_preview_max_bytes")]
+    pub max_bytes: usize,  // 最多读取的页面字节数
+    #[serde(default = "default_link_preview_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,  // 预览结果在 Redis 中的缓存时间（秒）
+    #[serde(default)]
+    pub allow_private_networks: bool,  // 是否允许抓取内网地址（仅用于本地测试，生产环境必须关闭）
+}
+
+fn default_link_preview_enabled() -> bool {
+    true
+}
+
+fn default_link_preview_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_link_preview_inline_wait_ms() -> u64 {
+    300
+}
+
+fn default_link_preview_max_bytes() -> usize {
+    512 * 1024
+}
+
+fn default_link_preview_cache_ttl_secs() -> u64 {
+    86400
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresenceSettings {
+    #[serde(default = "default_presence_notify_debounce_ms")]
+    pub notify_debounce_ms: u64,  // 在线状态变化通知的去抖时间（毫秒），窗口内的多次变化只推送最终状态
+    #[serde(default)]
+    pub notify_group_members: bool,  // 是否同时通知同群成员（默认只通知好友）
+}
+
+fn default_presence_notify_debounce_ms() -> u64 {
+    3000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DevicePolicySettings {
+    #[serde(default = "default_device_policy_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_device_limits")]
+    pub limits: HashMap<String, u32>,  // 每类设备同时保留的登录会话数（key 为 device_type），超出时最早的会话被挤下线；未列出的类型不限制
+}
+
+fn default_device_policy_enabled() -> bool {
+    true
+}
+
+fn default_device_limits() -> HashMap<String, u32> {
+    HashMap::from([
+        ("mobile".to_string(), 1),
+        ("desktop".to_string(), 1),
+        ("web".to_string(), 1),
+    ])
+}
+
+impl DevicePolicySettings {
+    /// 某类设备的会话数上限，None 表示不限制
+    pub fn limit_for(&self, device_type: &str) -> Option<u32> {
+        if !self.enabled {
+            return None;
+        }
+        self.limits.get(device_type).copied()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShutdownSettings {
+    #[serde(default = "default_shutdown_deadline_secs")]
+    pub deadline_secs: u64,  // 收到 SIGTERM 后等待进行中的请求和后台任务完成的最长时间（秒），超时后直接退出
+}
+
+fn default_shutdown_deadline_secs() -> u64 {
+    30
+}
+
+/// 内部接口（/internal/*），供 im-connect 转发客户端通过长连接发送的命令
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct InternalSettings {
+    #[serde(default)]
+    pub token: Option<String>,  // im-connect 的共享密钥（im-connect [im_server] internal_token），不配置或为空时内部接口不可用
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct SrsSettings {
     #[serde(default = "default_srs_host")]
@@ -147,6 +294,70 @@ pub struct AppConfig {
     pub upload: UploadSettings,
     #[serde(default = "default_srs_settings")]
     pub srs: SrsSettings,
+    #[serde(default = "default_export_settings")]
+    pub export: ExportSettings,
+    #[serde(default = "default_retention_settings")]
+    pub retention: RetentionSettings,
+    #[serde(default = "default_link_preview_settings")]
+    pub link_preview: LinkPreviewSettings,
+    #[serde(default = "default_presence_settings")]
+    pub presence: PresenceSettings,
+    #[serde(default = "default_device_policy_settings")]
+    pub device_policy: DevicePolicySettings,
+    #[serde(default = "default_shutdown_settings")]
+    pub shutdown: ShutdownSettings,
+    #[serde(default)]
+    pub internal: InternalSettings,
+}
+
+fn default_shutdown_settings() -> ShutdownSettings {
+    ShutdownSettings {
+        deadline_secs: default_shutdown_deadline_secs(),
+    }
+}
+
+fn default_device_policy_settings() -> DevicePolicySettings {
+    DevicePolicySettings {
+        enabled: true,
+        limits: default_device_limits(),
+    }
+}
+
+fn default_presence_settings() -> PresenceSettings {
+    PresenceSettings {
+        notify_debounce_ms: 3000,
+        notify_group_members: false,
+    }
+}
+
+fn default_link_preview_settings() -> LinkPreviewSettings {
+    LinkPreviewSettings {
+        enabled: true,
+        timeout_ms: 3000,
+        inline_wait_ms: 300,
+        max_bytes: 512 * 1024,
+        cache_ttl_secs: 86400,
+        allow_private_networks: false,
+    }
+}
+
+fn default_retention_settings() -> RetentionSettings {
+    RetentionSettings {
+        enabled: false,
+        single_days: 0,
+        group_days: 0,
+        groups: HashMap::new(),
+        interval_secs: 3600,
+        batch_size: 1000,
+    }
+}
+
+fn default_export_settings() -> ExportSettings {
+    ExportSettings {
+        expire_hours: 72,
+        max_messages: 50000,
+        cleanup_interval_secs: 600,
+    }
 }
 
 fn default_srs_settings() -> SrsSettings {
@@ -170,6 +381,7 @@ fn default_upload_settings() -> UploadSettings {
         thumbnail_max_width: 800,
         thumbnail_max_height: 800,
         save_original: true,
+        max_voice_duration_secs: 300,
     }
 }
 
@@ -218,6 +430,7 @@ image_quality = 85
 thumbnail_max_width = 800
 thumbnail_max_height = 800
 save_original = true
+max_voice_duration_secs = 300
 
 [srs]
 host = "http://127.0.0.1:1985"
@@ -226,6 +439,40 @@ webrtc_port = 8000
 app = "live"
 client_host = "http://127.0.0.1:1985"
 client_http_host = "http://127.0.0.1:8080"
+
+[export]
+expire_hours = 72
+max_messages = 50000
+cleanup_interval_secs = 600
+
+[retention]
+enabled = false
+single_days = 0
+group_days = 0
+interval_secs = 3600
+batch_size = 1000
+
+[link_preview]
+enabled = true
+timeout_ms = 3000
+inline_wait_ms = 300
+max_bytes = 524288
+cache_ttl_secs = 86400
+
+[presence]
+notify_debounce_ms = 3000
+notify_group_members = false
+
+[device_policy]
+enabled = true
+
+[device_policy.limits]
+mobile = 1
+desktop = 1
+web = 1
+
+[shutdown]
+deadline_secs = 30
 "#;
             toml::from_str(default_content).expect("invalid default config")
         });
@@ -237,10 +484,9 @@ client_http_host = "http://127.0.0.1:8080"
         if let Ok(srs_http_host) = std::env::var("SRS_HTTP_HOST") {
             config.srs.http_host = srs_http_host;
         }
-        if let Ok(srs_webrtc_port) = std::env::var("SRS_WEBRTC_PORT") {
-            if let Ok(port) = srs_webrtc_port.parse::<u16>() {
-                config.srs.webrtc_port = port;
-            }
+        if let Ok(srs_webrtc_port) = std::env::var("SRS_WEBRTC_PORT")
+            && let Ok(port) = srs_webrtc_port.parse::<u16>() {
+            config.srs.webrtc_port = port;
         }
         if let Ok(srs_app) = std::env::var("SRS_APP") {
             config.srs.app = srs_app;
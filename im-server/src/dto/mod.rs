@@ -14,6 +14,12 @@ pub struct CreateUserReq {
 pub struct LoginReq {
     pub username: String, // 支持用户名或邮箱登录
     pub password: String,
+    /// 设备类型（mobile / desktop / web 等），提供时按设备创建登录会话并执行多设备策略
+    #[serde(default)]
+    pub device_type: Option<String>,
+    /// 设备描述（型号、系统、浏览器等），在设备列表中展示
+    #[serde(default)]
+    pub device_info: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -1,21 +1,27 @@
-use axum::{extract::{Extension, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Extension, State}, http::{header, HeaderMap, StatusCode}, response::IntoResponse, Json};
 use sqlx::MySqlPool;
 use std::sync::Arc;
 use uuid::Uuid;
-use tracing::{info, error};
+use tracing::{info, warn, error};
 use crate::{
     dto::{LoginReq, LoginResponse},
     error::{ErrorCode, ErrorResponse},
-    service::{UserService, SubscriptionService},
+    service::{UserService, SubscriptionService, DeviceSessionService},
+    service::device_session_service::normalize_device_type,
+    config::DevicePolicySettings,
+    mqtt::MqttPublisher,
 };
-use im_share::{JwtSettings, generate_token_with_open_id};
+use im_share::{JwtSettings, SubscriptionIdentity, generate_token_with_open_id, generate_session_token, store_subscription_identity};
+use im_share::ws_ticket::WS_TICKET_TTL_SECS;
+use crate::redis::RedisClient;
 
 /// 从数据库获取或创建订阅 ID
 async fn get_or_create_subscription_from_db(pool: &MySqlPool, user_id: u64) -> Result<String, sqlx::Error> {
-    // 先尝试从数据库获取现有的订阅 ID（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
+    // 先尝试从数据库获取现有的订阅 ID（只查询最近24小时内创建的订阅，过滤掉已不在线的用户；不复用设备会话）
     let existing = sqlx::query_scalar::<_, String>(
         "SELECT subscription_id FROM subscriptions 
          WHERE user_id = ? 
+         AND device_type IS NULL
          AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
          ORDER BY created_at DESC LIMIT 1"
     )
@@ -57,6 +63,9 @@ async fn get_or_create_subscription_from_db(pool: &MySqlPool, user_id: u64) -> R
 pub async fn login(
     Extension(pool): Extension<MySqlPool>,
     Extension(jwt_cfg): Extension<JwtSettings>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(publisher): Extension<MqttPublisher>,
+    Extension(device_policy): Extension<DevicePolicySettings>,
     State(subscription_service): State<Arc<SubscriptionService>>,
     Json(payload): Json<LoginReq>,
 ) -> impl IntoResponse {
@@ -68,8 +77,9 @@ pub async fn login(
     {
         Ok(user) => {
             // 确保用户有 open_id
-            let open_id = if user.open_id.is_none() {
-                match user_service.ensure_open_id(user.id).await {
+            let open_id = match user.open_id.as_ref() {
+                Some(open_id) => open_id.clone(),
+                None => match user_service.ensure_open_id(user.id).await {
                     Ok(oid) => {
                         info!(user_id = %user.id, open_id = %oid, "为用户生成 open_id");
                         oid
@@ -84,41 +94,102 @@ pub async fn login(
                             )),
                         ));
                     }
-                }
-            } else {
-                user.open_id.as_ref().unwrap().clone()
+                },
             };
             
             // 从 open_id 解析数字（用于 JWT）
             let open_id_number = open_id.parse::<u64>()
                 .map_err(|_| {
                     error!(user_id = %user.id, open_id = %open_id, "open_id 不是数字格式，无法生成 token");
-                    return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse::new(ErrorCode::Internal, "用户 open_id 格式错误")));
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse::new(ErrorCode::Internal, "用户 open_id 格式错误")))
                 })?;
             
-            match generate_token_with_open_id(open_id_number, &jwt_cfg) {
-                Ok(token) => {
-                    // 生成或获取订阅 ID（必须保存到数据库）
-                    let subscription_id = match get_or_create_subscription_from_db(&pool, user.id).await {
-                        Ok(sub_id) => {
-                            info!(user_id = %user.id, open_id = %open_id, subscription_id = %sub_id, "登录成功，订阅 ID 已从数据库获取或创建");
-                            // 同步到内存中的订阅服务（用于快速查询）
-                            subscription_service.add_subscription_id(sub_id.clone(), user.id);
-                            sub_id
-                        },
-                        Err(e) => {
-                            error!(user_id = %user.id, open_id = %open_id, error = %e, "从数据库获取或创建订阅 ID 失败");
-                            // 数据库操作失败，返回错误
+            // 带设备类型登录：每次登录创建新会话，token 绑定该会话，并按多设备策略挤掉同类设备的旧会话
+            // 旧版客户端（不提供设备类型）沿用按用户复用订阅 ID 的方式
+            let device_type = match payload.device_type.as_deref() {
+                Some(raw) => match normalize_device_type(raw) {
+                    Some(device_type) => Some(device_type),
+                    None => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(ErrorResponse::new(
+                                ErrorCode::InvalidInput,
+                                "device_type 只能包含字母、数字、- 和 _，且不超过 32 个字符",
+                            )),
+                        ));
+                    }
+                },
+                None => None,
+            };
+
+            let subscription_id = match device_type.as_deref() {
+                Some(device_type) => {
+                    let sessions = DeviceSessionService::new(pool.clone(), redis_client.clone());
+                    let sub_id = match sessions.create_session(user.id, device_type, payload.device_info.as_deref()).await {
+                        Ok(sub_id) => sub_id,
+                        Err(code) => {
                             return Err((
                                 StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(ErrorResponse::new(
-                                    ErrorCode::Internal,
-                                    "创建订阅 ID 失败，请稍后重试",
-                                )),
+                                Json(ErrorResponse::new(code, "创建登录会话失败，请稍后重试")),
                             ));
                         }
                     };
-                    
+                    if let Some(limit) = device_policy.limit_for(device_type) {
+                        let token_ttl_secs = jwt_cfg.expiration_hours * 3600;
+                        match sessions.enforce_limit(&publisher, user.id, &open_id, device_type, limit, token_ttl_secs).await {
+                            Ok(evicted) => {
+                                for session in &evicted {
+                                    subscription_service.remove_subscription(&session.subscription_id);
+                                }
+                                if !evicted.is_empty() {
+                                    info!(user_id = %user.id, open_id = %open_id, device_type = %device_type, count = evicted.len(), "同类设备超过上限，旧会话已被挤下线");
+                                }
+                            },
+                            // 新会话已经创建，策略执行失败不影响本次登录
+                            Err(e) => error!(user_id = %user.id, device_type = %device_type, error = ?e, "执行多设备策略失败"),
+                        }
+                    }
+                    info!(user_id = %user.id, open_id = %open_id, subscription_id = %sub_id, device_type = %device_type, "登录成功，已创建设备会话");
+                    sub_id
+                },
+                None => match get_or_create_subscription_from_db(&pool, user.id).await {
+                    Ok(sub_id) => {
+                        info!(user_id = %user.id, open_id = %open_id, subscription_id = %sub_id, "登录成功，订阅 ID 已从数据库获取或创建");
+                        sub_id
+                    },
+                    Err(e) => {
+                        error!(user_id = %user.id, open_id = %open_id, error = %e, "从数据库获取或创建订阅 ID 失败");
+                        // 数据库操作失败，返回错误
+                        return Err((
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(ErrorResponse::new(
+                                ErrorCode::Internal,
+                                "创建订阅 ID 失败，请稍后重试",
+                            )),
+                        ));
+                    }
+                },
+            };
+            // 同步到内存中的订阅服务（用于快速查询）
+            subscription_service.add_subscription_id(subscription_id.clone(), user.id);
+            // 记录订阅 ID 对应的用户身份，im-connect 握手时直接从 Redis 读取，无需回调 im-server
+            let identity = SubscriptionIdentity {
+                user_id: user.id,
+                mqtt_id: user.get_mqtt_id(),
+                open_id: open_id.clone(),
+            };
+            let token_ttl_secs = jwt_cfg.expiration_hours * 3600;
+            if let Err(e) = store_subscription_identity(&redis_client, &subscription_id, &identity, token_ttl_secs).await {
+                // token 中已包含 open_id，写入失败只影响旧格式 token 的连接
+                warn!(user_id = %user.id, subscription_id = %subscription_id, error = %e, "记录订阅 ID 对应的用户身份失败");
+            }
+
+            let token = match device_type {
+                Some(_) => generate_session_token(open_id_number, &subscription_id, &jwt_cfg),
+                None => generate_token_with_open_id(open_id_number, &jwt_cfg),
+            };
+            match token {
+                Ok(token) => {
                     info!(user_id = %user.id, open_id = %open_id, subscription_id = %subscription_id, "返回登录响应");
                     Ok(Json(LoginResponse { 
                         token, 
@@ -148,3 +219,31 @@ pub async fn login(
     }
 }
 
+/// 签发 WebSocket 一次性票据
+/// 浏览器无法在 WebSocket 握手时设置 Authorization 头，用票据代替 URL 中的长期 JWT：
+/// ws://.../ws/{subscription_id}?ticket=... 或 Sec-WebSocket-Protocol: ticket.<票据>
+pub async fn create_ws_ticket(
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // 认证中间件已经校验过 token，这里只需要取出原文
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    match im_share::issue_ws_ticket(&redis_client, token).await {
+        Ok(ticket) => Ok(Json(serde_json::json!({
+            "ticket": ticket,
+            "expires_in": WS_TICKET_TTL_SECS,
+        }))),
+        Err(e) => {
+            error!(error = %e, "签发 WebSocket 票据失败");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(ErrorCode::Internal, "签发 WebSocket 票据失败")),
+            ))
+        }
+    }
+}
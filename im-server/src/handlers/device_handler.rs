@@ -0,0 +1,110 @@
+use axum::{extract::{Path, State, Extension}, http::StatusCode, response::IntoResponse, Json};
+use sqlx::MySqlPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::warn;
+use crate::{
+    error::{ErrorCode, ErrorResponse},
+    middleware::auth::UserIdentity,
+    model::DeviceSession,
+    mqtt::MqttPublisher,
+    service::{DeviceSessionService, SubscriptionService},
+    redis::RedisClient,
+};
+use im_share::{JwtSettings, KickReason, PresenceRegistry};
+
+fn device_to_json(session: &DeviceSession, current: bool, online: bool) -> serde_json::Value {
+    let mut value = serde_json::to_value(session).unwrap_or_default();
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("current".to_string(), serde_json::json!(current));
+        obj.insert("online".to_string(), serde_json::json!(online));
+    }
+    value
+}
+
+/// 当前用户的登录设备列表（最近登录的在前）
+/// current 表示发起请求的会话，online 表示该会话当前有 WebSocket 连接
+pub async fn list_devices(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_identity): Extension<UserIdentity>,
+) -> impl IntoResponse {
+    let sessions = match DeviceSessionService::new(pool, redis_client.clone()).list_sessions(user_identity.db_id).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "查询登录设备失败")),
+            ));
+        }
+    };
+
+    // 在线状态中的 device_id 即连接的 subscription_id
+    let online: HashSet<String> = match PresenceRegistry::new(redis_client).get(&user_identity.open_id).await {
+        Ok(presence) => presence.devices.into_iter().map(|d| d.device_id).collect(),
+        Err(e) => {
+            warn!(open_id = %user_identity.open_id, error = %e, "查询设备在线状态失败");
+            HashSet::new()
+        }
+    };
+
+    let devices: Vec<serde_json::Value> = sessions
+        .iter()
+        .map(|session| {
+            let current = user_identity.session_id.as_deref() == Some(session.subscription_id.as_str());
+            device_to_json(session, current, online.contains(&session.subscription_id))
+        })
+        .collect();
+    Ok(Json(serde_json::json!({ "devices": devices })))
+}
+
+/// 远程踢出自己的某台设备：会话被吊销，该设备的连接收到 kicked 事件后断开，需要重新登录
+pub async fn kick_device(
+    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(jwt_cfg): Extension<JwtSettings>,
+    Extension(user_identity): Extension<UserIdentity>,
+    Path(subscription_id): Path<String>,
+) -> impl IntoResponse {
+    if user_identity.session_id.as_deref() == Some(subscription_id.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(ErrorCode::InvalidInput, "不能踢出当前设备")),
+        ));
+    }
+
+    let service = DeviceSessionService::new(pool, redis_client);
+    let session = match service.get_session(user_identity.db_id, &subscription_id).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new(ErrorCode::NotFound, "设备不存在")),
+            ));
+        }
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "查询登录设备失败")),
+            ));
+        }
+    };
+
+    let token_ttl_secs = jwt_cfg.expiration_hours * 3600;
+    if let Err(e) = service
+        .revoke(&publisher, &user_identity.open_id, &session, KickReason::Remote, token_ttl_secs)
+        .await
+    {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(e, "踢出设备失败")),
+        ));
+    }
+    subscription_service.remove_subscription(&subscription_id);
+
+    Ok(Json(serde_json::json!({
+        "status": "ok",
+        "subscription_id": subscription_id,
+    })))
+}
@@ -0,0 +1,174 @@
+use axum::{extract::{Path, Extension, Query}, http::StatusCode, response::IntoResponse, Json};
+use sqlx::MySqlPool;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+use im_share::Shutdown;
+use crate::{
+    config::{ExportSettings, UploadSettings},
+    error::{ErrorCode, ErrorResponse},
+    middleware::auth::UserIdentity,
+    model::ImExportJob,
+    service::{ImExportService, ImGroupService},
+    service::im_export_service::{EXPORT_FORMATS, EXPORT_SCOPE_ALL, EXPORT_SCOPE_GROUP, EXPORT_SCOPE_SINGLE},
+};
+
+#[derive(Deserialize)]
+pub struct CreateExportRequest {
+    /// 1 = 单聊，2 = 群聊，不传表示导出全部会话
+    pub chat_type: Option<i32>,
+    /// 单聊对方的 open_id 或群组ID
+    pub to_id: Option<String>,
+    /// 导出格式：json / csv / html，默认全部
+    pub formats: Option<Vec<String>>,
+}
+
+fn export_to_json(job: &ImExportJob) -> serde_json::Value {
+    let mut value = serde_json::to_value(job).unwrap_or_default();
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("download_url".to_string(), serde_json::json!(job.download_url()));
+    }
+    value
+}
+
+/// 创建会话导出任务（后台执行）
+pub async fn create_export(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(upload_settings): Extension<UploadSettings>,
+    Extension(export_settings): Extension<ExportSettings>,
+    Extension(shutdown): Extension<Shutdown>,
+    Extension(user_identity): Extension<UserIdentity>,
+    Json(req): Json<CreateExportRequest>,
+) -> impl IntoResponse {
+    let open_id = user_identity.open_id.clone();
+
+    // 校验导出格式
+    let formats = match req.formats {
+        Some(formats) if !formats.is_empty() => {
+            let mut normalized: Vec<String> = Vec::new();
+            for format in formats {
+                let format = format.trim().to_lowercase();
+                if !EXPORT_FORMATS.contains(&format.as_str()) {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse::new(ErrorCode::InvalidInput, format!("不支持的导出格式: {}", format))),
+                    ));
+                }
+                if !normalized.contains(&format) {
+                    normalized.push(format);
+                }
+            }
+            normalized
+        }
+        _ => EXPORT_FORMATS.iter().map(|f| f.to_string()).collect(),
+    };
+
+    // 校验导出范围
+    let scope_type = req.chat_type.unwrap_or(EXPORT_SCOPE_ALL);
+    let to_id = req.to_id.as_deref().map(str::trim).filter(|s| !s.is_empty()).map(|s| s.to_string());
+    let to_id = match scope_type {
+        EXPORT_SCOPE_ALL => None,
+        EXPORT_SCOPE_SINGLE => {
+            let Some(to_id) = to_id else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(ErrorCode::InvalidInput, "单聊导出需要指定 to_id")),
+                ));
+            };
+            Some(to_id)
+        }
+        EXPORT_SCOPE_GROUP => {
+            let Some(to_id) = to_id else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(ErrorCode::InvalidInput, "群聊导出需要指定 to_id")),
+                ));
+            };
+            // 统一 group_id 格式：确保有 group_ 前缀
+            let group_id = if to_id.starts_with("group_") {
+                to_id
+            } else {
+                format!("group_{}", to_id)
+            };
+
+            // 只有群成员可以导出群聊记录
+            let group_service = ImGroupService::new(pool.clone());
+            let members = group_service.get_group_members(&group_id).await.unwrap_or_default();
+            if !members.iter().any(|m| m.member_id == open_id) {
+                warn!(open_id = %open_id, group_id = %group_id, "非群成员尝试导出群聊记录");
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(ErrorResponse::new(ErrorCode::Forbidden, "您不是该群组成员，无法导出聊天记录")),
+                ));
+            }
+            Some(group_id)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(ErrorCode::InvalidInput, "无效的 chat_type")),
+            ));
+        }
+    };
+
+    let service = ImExportService::new(pool.clone());
+    let job = match service.create_job(&open_id, scope_type, to_id.as_deref(), &formats).await {
+        Ok(job) => job,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "创建导出任务失败")),
+            ));
+        }
+    };
+
+    // 后台执行导出，退出时在截止时间内等待导出完成
+    let background_job = job.clone();
+    let guard = shutdown.guard();
+    tokio::spawn(async move {
+        let _guard = guard;
+        ImExportService::new(pool)
+            .run_export(background_job, upload_settings, export_settings)
+            .await;
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(export_to_json(&job))))
+}
+
+/// 获取当前用户的导出任务列表
+pub async fn get_exports(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(user_identity): Extension<UserIdentity>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let service = ImExportService::new(pool);
+    let limit = params.get("limit").and_then(|s| s.parse::<i32>().ok()).unwrap_or(20).clamp(1, 100);
+
+    match service.list_jobs(&user_identity.open_id, limit).await {
+        Ok(jobs) => {
+            let exports: Vec<serde_json::Value> = jobs.iter().map(export_to_json).collect();
+            Ok(Json(serde_json::json!({"exports": exports})))
+        }
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(e, "获取导出任务失败")),
+        )),
+    }
+}
+
+/// 获取导出任务详情（包含下载地址）
+pub async fn get_export(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(user_identity): Extension<UserIdentity>,
+    Path(export_id): Path<String>,
+) -> impl IntoResponse {
+    let service = ImExportService::new(pool);
+
+    match service.get_job(&export_id, &user_identity.open_id).await {
+        Ok(job) => Ok(Json(export_to_json(&job))),
+        Err(e) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(e, "导出任务不存在")),
+        )),
+    }
+}
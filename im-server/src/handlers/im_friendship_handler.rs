@@ -4,12 +4,13 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{info, warn, error};
-use im_share::{mqtt_user_topic, ChatMessage, encode_message};
+use im_share::{mqtt_user_topic, ChatMessage, encode_message, PresenceRegistry, InboxLog};
 use crate::{
     error::{ErrorCode, ErrorResponse},
-    service::{ImFriendshipService, UserService, SubscriptionService},
+    service::{ImFriendshipService, UserService, SubscriptionService, ContactCardService},
     model::ImFriendshipRequest,
     mqtt::MqttPublisher,
+    redis::RedisClient,
 };
 
 #[derive(Deserialize)]
@@ -20,6 +21,14 @@ pub struct AddFriendRequest {
     pub message: Option<String>, // 好友验证信息
 }
 
+#[derive(Deserialize)]
+pub struct CardFriendshipRequest {
+    /// 名片中的用户 open_id
+    pub open_id: String,
+    pub remark: Option<String>,
+    pub message: Option<String>, // 好友验证信息
+}
+
 #[derive(Deserialize)]
 pub struct HandleFriendshipRequest {
     pub approve_status: i32, // 1: 同意, 2: 拒绝
@@ -200,8 +209,9 @@ pub async fn get_friends(
 }
 
 pub async fn add_friend(
-    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
     Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
     Extension(user_id): Extension<u64>,
     Json(req): Json<AddFriendRequest>,
 ) -> impl IntoResponse {
@@ -249,14 +259,13 @@ pub async fn add_friend(
     // 检查是否已经是好友
     info!("检查好友关系: from_id={}, to_id={}, from_user.name={}, from_user.open_id={:?}, to_user.name={}, to_user.open_id={:?}", 
           from_id, to_id, from_user.name, from_user.open_id, to_user.name, to_user.open_id);
-    if let Ok(is_friend) = friendship_service.is_friend(&from_id, &to_id).await {
-        if is_friend {
-            warn!("已经是好友: from_id={}, to_id={}", from_id, to_id);
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse::new(ErrorCode::InvalidInput, "已经是好友了")),
-            ));
-        }
+    if let Ok(is_friend) = friendship_service.is_friend(&from_id, &to_id).await
+        && is_friend {
+        warn!("已经是好友: from_id={}, to_id={}", from_id, to_id);
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(ErrorCode::InvalidInput, "已经是好友了")),
+        ));
     }
     
     // 检查是否已经有待处理的好友请求（双向检查）
@@ -331,29 +340,15 @@ pub async fn add_friend(
             };
             
             if let Ok(to_user) = to_user {
-                // 获取接收者的订阅ID
-                let subscription_ids = {
-                    let mut ids = subscription_service.get_subscription_ids(to_user.id);
-                    // 如果内存中没有，从数据库查询（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
-                    if ids.is_empty() {
-                        if let Ok(db_subscriptions) = sqlx::query_scalar::<_, String>(
-                            "SELECT subscription_id FROM subscriptions 
-                             WHERE user_id = ? 
-                             AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
-                             ORDER BY created_at DESC"
-                        )
-                        .bind(to_user.id)
-                        .fetch_all(&pool)
-                        .await
-                        {
-                            for sub_id in &db_subscriptions {
-                                subscription_service.add_subscription_id(sub_id.clone(), to_user.id);
-                            }
-                            ids = subscription_service.get_subscription_ids(to_user.id);
-                        }
-                    }
-                    ids
-                };
+                let to_open_id = to_user.get_external_id();
+                // 从在线状态注册表判断用户是否在线（im-connect 在连接建立/心跳/断开时维护）
+                let is_online = PresenceRegistry::new(redis_client.clone())
+                    .is_online(&to_open_id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(open_id = %to_open_id, error = %e, "查询在线状态失败，按离线处理");
+                        false
+                    });
                 
                 // 构建好友请求通知消息
                 let notification_message = ChatMessage {
@@ -374,17 +369,22 @@ pub async fn add_friend(
                     file_name: None,
                     file_type: None,
                     chat_type: Some(1), // 1 = 单聊（好友请求也是单聊的一种）
+                    link_preview: None,
+                    voice: None,
+                    location: None,
+                    card: None,
                 };
                 
                 // 无论用户是否在线，都通过 MQTT 发布通知
                 // MQTT broker 会自动处理离线消息（使用 QoS 1 和 clean_session=false）
                 let to_mqtt_id = to_user.get_mqtt_id();
                 let topic = mqtt_user_topic(&to_mqtt_id.to_string());
-                let is_online = !subscription_ids.is_empty();
                 info!(to_id = %to_id_clone, is_online = is_online, %topic, "通过MQTT发布好友请求通知（broker会自动处理离线消息）");
                 
                 match encode_message(&notification_message) {
                     Ok(payload) => {
+                        // 分配收件箱序号，客户端断线重连时据此补发
+                        let payload = InboxLog::new(redis_client.clone()).stamp(&to_open_id, payload).await;
                         if let Err(e) = publisher.publish(&topic, payload).await {
                             error!(to_id = %to_id_clone, %topic, error = %e, "好友请求MQTT发布失败");
                         } else {
@@ -552,6 +552,66 @@ pub async fn create_friendship_request(
     }
 }
 
+/// 通过名片消息发起好友请求（add_source = "card"）
+pub async fn create_card_friendship_request(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Json(req): Json<CardFriendshipRequest>,
+) -> impl IntoResponse {
+    let user_service = UserService::new(pool.clone());
+    let from_id = match user_service.get_by_id(user_id).await {
+        Ok(user) => user.get_external_id(),
+        Err(e) => {
+            warn!("获取当前用户失败: {:?}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+    
+    // 按对方当前的隐私设置校验是否允许通过名片添加
+    let card = ContactCardService::new(pool.clone(), redis_client).resolve(req.open_id.trim()).await;
+    if !card.available {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(ErrorCode::NotFound, "名片用户不存在")),
+        ));
+    }
+    if !card.can_add_friend {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(ErrorCode::Forbidden, "对方不允许添加好友")),
+        ));
+    }
+    
+    let request = ImFriendshipRequest {
+        id: uuid::Uuid::new_v4().to_string(),
+        from_id,
+        to_id: card.open_id.clone(),
+        remark: req.remark,
+        read_status: None,
+        add_source: Some("card".to_string()),
+        message: req.message,
+        approve_status: None,
+        create_time: None,
+        update_time: None,
+        sequence: None,
+        del_flag: None,
+        version: None,
+    };
+    
+    let service = ImFriendshipService::new(pool);
+    match service.create_friendship_request(request).await {
+        Ok(_) => Ok(Json(serde_json::json!({"status": "ok", "card": card}))),
+        Err(e) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(e, "创建好友请求失败")),
+        )),
+    }
+}
+
 pub async fn get_friendship_requests(
     Extension(pool): Extension<MySqlPool>,
     Extension(user_id): Extension<u64>,
@@ -279,13 +279,8 @@ pub async fn add_group_member(
     }
     
     // 在添加成员前，检查当前成员数
-    let current_members = match group_service.get_group_members(&group_id).await {
-        Ok(members) => members,
-        Err(_) => {
-            // 如果获取成员失败，可能是群组不存在，继续尝试添加（可能是新群组）
-            vec![]
-        }
-    };
+    // 如果获取成员失败，可能是群组不存在，继续尝试添加（可能是新群组）
+    let current_members = group_service.get_group_members(&group_id).await.unwrap_or_default();
     
     let current_member_count = current_members.len();
     // 添加成员后，成员数会变成 current_member_count + 1
@@ -328,7 +323,7 @@ pub async fn add_group_member(
                     group_id: new_group_id.clone(),
                     owner_id: owner_id.clone(),
                     group_type: 1, // 私有群
-                    group_name: format!("群聊"), // 默认名称，前端可以修改
+                    group_name: "群聊".to_string(), // 默认名称，前端可以修改
                     mute: Some(0),
                     apply_join_type: 1,
                     avatar: None,
@@ -628,7 +623,7 @@ pub async fn delete_group(
 }
 
 pub async fn dissolve_group(
-    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
     Extension(pool): Extension<MySqlPool>,
     Extension(redis_client): Extension<Arc<RedisClient>>,
     Extension(user_id): Extension<u64>,
@@ -638,7 +633,7 @@ pub async fn dissolve_group(
     use crate::service::{ImGroupService, UserService, ImMessageService};
     use std::time::{SystemTime, UNIX_EPOCH};
     use uuid::Uuid;
-    use im_share::{ChatMessage, mqtt_user_topic, encode_message};
+    use im_share::{ChatMessage, PresenceRegistry, InboxLog, mqtt_user_topic, encode_message};
     use std::collections::HashSet;
     
     let user_service = UserService::new(pool.clone());
@@ -756,42 +751,32 @@ pub async fn dissolve_group(
                     file_name: None,
                     file_type: None,
                     chat_type: Some(2), // 群聊
+                    link_preview: None,
+                    voice: None,
+                    location: None,
+                    card: None,
                 };
                 
                 // 获取成员的MQTT ID
                 let member_mqtt_id = member_user.get_mqtt_id();
                 
-                // 从数据库查询订阅ID并同步到内存（如果内存中没有）
-                let subscription_ids = {
-                    let mut ids = subscription_service.get_subscription_ids(member_user.id);
-                    if ids.is_empty() {
-                        // 如果内存中没有，从数据库查询（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
-                        if let Ok(db_subscriptions) = sqlx::query_scalar::<_, String>(
-                            "SELECT subscription_id FROM subscriptions 
-                             WHERE user_id = ? 
-                             AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
-                             ORDER BY created_at DESC"
-                        )
-                        .bind(member_user.id)
-                        .fetch_all(&pool)
-                        .await
-                        {
-                            for sub_id in &db_subscriptions {
-                                subscription_service.add_subscription_id(sub_id.clone(), member_user.id);
-                            }
-                            ids = subscription_service.get_subscription_ids(member_user.id);
-                        }
-                    }
-                    ids
-                };
+                // 从在线状态注册表判断用户是否在线（im-connect 在连接建立/心跳/断开时维护）
+                let is_online = PresenceRegistry::new(redis_client.clone())
+                    .is_online(&member_open_id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(open_id = %member_open_id, error = %e, "查询在线状态失败，按离线处理");
+                        false
+                    });
                 
                 // 通过 MQTT 推送系统消息
                 let topic = mqtt_user_topic(&member_mqtt_id.to_string());
-                let is_online = !subscription_ids.is_empty();
                 info!(member_id = %member_open_id, is_online = is_online, %topic, "通过MQTT推送群组解散系统消息");
                 
                 match encode_message(&chat_message) {
                     Ok(payload) => {
+                        // 分配收件箱序号，客户端断线重连时据此补发
+                        let payload = InboxLog::new(redis_client.clone()).stamp(&member_open_id, payload).await;
                         if let Err(e) = publisher.publish(&topic, payload).await {
                             warn!(member_id = %member_open_id, error = ?e, "推送群组解散系统消息失败");
                         }
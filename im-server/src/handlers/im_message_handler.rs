@@ -5,12 +5,18 @@ use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{error, info, warn};
-use im_share::{ChatMessage, mqtt_user_topic, encode_message};
+use im_share::{ChatMessage, ContactCard, LocationInfo, VoiceInfo, PresenceRegistry, RouteRegistry, InboxLog, OfflineQueue, mqtt_user_topic, encode_message};
 use crate::{
+    audio::analyze_audio,
     error::{ErrorCode, ErrorResponse},
-    service::{ImMessageService, SubscriptionService, UserService, ImChatService, ImGroupService},
+    config::{LinkPreviewSettings, UploadSettings},
+    handlers::upload_handler::local_upload_path,
+    service::{ImMessageService, SubscriptionService, UserService, ImChatService, ImGroupService, LinkPreviewService, ContactCardService},
+    service::link_preview_service::attach_preview_to_extra,
+    service::contact_card_service::card_open_id,
+    service::im_message_service::{VOICE_MESSAGE_CONTENT_TYPE, LOCATION_MESSAGE_CONTENT_TYPE, CARD_MESSAGE_CONTENT_TYPE, expiry_kind, stamp_message_expiry},
     model::{ImSingleMessage, ImGroupMessage},
-    mqtt::MqttPublisher,
+    mqtt::{Delivery, MqttPublisher},
     redis::RedisClient,
 };
 
@@ -34,10 +40,113 @@ pub struct SendGroupMessageRequest {
     pub reply_to: Option<String>,
 }
 
+/// 位置名称最大长度（字符）
+const MAX_LOCATION_NAME_CHARS: usize = 100;
+/// 位置地址最大长度（字符）
+const MAX_LOCATION_ADDRESS_CHARS: usize = 255;
+
+/// 按消息类型解析出的类型化元数据，随推送消息一起下发
+#[derive(Default)]
+struct MessageMeta {
+    voice: Option<VoiceInfo>,
+    location: Option<LocationInfo>,
+    card: Option<ContactCard>,
+}
+
+/// 按消息类型校验 extra 并补全类型化元数据，其他类型原样返回 extra
+async fn resolve_message_meta(
+    message_content_type: i32,
+    extra: Option<String>,
+    upload_settings: &UploadSettings,
+    card_service: &ContactCardService,
+) -> Result<(MessageMeta, Option<String>), String> {
+    let parse_extra = |what: &str| {
+        extra
+            .as_deref()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+            .filter(|v| v.is_object())
+            .ok_or_else(|| format!("{}消息的 extra 格式无效", what))
+    };
+
+    match message_content_type {
+        VOICE_MESSAGE_CONTENT_TYPE => {
+            let mut extra_json = parse_extra("语音")?;
+            let voice = resolve_voice(&extra_json, upload_settings).await?;
+            extra_json["voice"] = json!(voice);
+            Ok((MessageMeta { voice: Some(voice), ..Default::default() }, Some(extra_json.to_string())))
+        }
+        LOCATION_MESSAGE_CONTENT_TYPE => {
+            let mut extra_json = parse_extra("位置")?;
+            let location = validate_location(extra_json.get("location"))?;
+            extra_json["location"] = json!(location);
+            Ok((MessageMeta { location: Some(location), ..Default::default() }, Some(extra_json.to_string())))
+        }
+        CARD_MESSAGE_CONTENT_TYPE => {
+            let mut extra_json = parse_extra("名片")?;
+            let open_id = card_open_id(&extra_json).ok_or_else(|| "名片消息缺少 open_id".to_string())?;
+            let card = card_service.resolve(&open_id).await;
+            if !card.available {
+                return Err("名片用户不存在".to_string());
+            }
+            // 只保存 open_id，名称和头像在读取时重新解析
+            extra_json["card"] = json!({"open_id": open_id});
+            Ok((MessageMeta { card: Some(card), ..Default::default() }, Some(extra_json.to_string())))
+        }
+        _ => Ok((MessageMeta::default(), extra)),
+    }
+}
+
+/// 语音消息：根据 extra 中的 file_url 重新解析已上传的语音文件（不信任客户端传入的时长和波形）
+async fn resolve_voice(extra_json: &serde_json::Value, upload_settings: &UploadSettings) -> Result<VoiceInfo, String> {
+    let relative = extra_json
+        .get("file_url")
+        .and_then(|v| v.as_str())
+        .and_then(local_upload_path)
+        .ok_or_else(|| "语音消息的 file_url 无效".to_string())?;
+
+    let path = std::path::Path::new(&upload_settings.path).join(&relative);
+    let data = tokio::fs::read(&path).await.map_err(|e| {
+        warn!(path = %path.display(), error = %e, "读取语音文件失败");
+        "语音文件不存在".to_string()
+    })?;
+    let voice = analyze_audio(&data)?;
+    if voice.duration_ms > upload_settings.max_voice_duration_secs * 1000 {
+        return Err(format!("语音时长不能超过 {} 秒", upload_settings.max_voice_duration_secs));
+    }
+    Ok(voice)
+}
+
+/// 位置消息：校验经纬度范围以及名称、地址长度
+fn validate_location(value: Option<&serde_json::Value>) -> Result<LocationInfo, String> {
+    let mut location: LocationInfo = value
+        .cloned()
+        .and_then(|v| serde_json::from_value(v).ok())
+        .ok_or_else(|| "位置消息缺少 location 或格式无效".to_string())?;
+
+    if !location.latitude.is_finite() || !(-90.0..=90.0).contains(&location.latitude) {
+        return Err("纬度必须在 -90 到 90 之间".to_string());
+    }
+    if !location.longitude.is_finite() || !(-180.0..=180.0).contains(&location.longitude) {
+        return Err("经度必须在 -180 到 180 之间".to_string());
+    }
+
+    location.name = location.name.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    location.address = location.address.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    if location.name.as_ref().is_some_and(|s| s.chars().count() > MAX_LOCATION_NAME_CHARS) {
+        return Err(format!("位置名称不能超过 {} 个字符", MAX_LOCATION_NAME_CHARS));
+    }
+    if location.address.as_ref().is_some_and(|s| s.chars().count() > MAX_LOCATION_ADDRESS_CHARS) {
+        return Err(format!("位置地址不能超过 {} 个字符", MAX_LOCATION_ADDRESS_CHARS));
+    }
+    Ok(location)
+}
+
 pub async fn send_single_message(
-    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
     Extension(pool): Extension<MySqlPool>,
     Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(link_preview_settings): Extension<LinkPreviewSettings>,
+    Extension(upload_settings): Extension<UploadSettings>,
     Extension(_user_id): Extension<u64>, // 从认证中间件获取用户ID
     Json(req): Json<SendSingleMessageRequest>,
 ) -> impl IntoResponse {
@@ -110,6 +219,27 @@ pub async fn send_single_message(
     
     let message_id = Uuid::new_v4().to_string();
     
+    // 语音、位置、名片消息校验 extra 并附带类型化元数据
+    let card_service = ContactCardService::new(pool.clone(), redis_client.clone());
+    let (meta, extra) = match resolve_message_meta(req.message_content_type, req.extra.clone(), &upload_settings, &card_service).await {
+        Ok(resolved) => resolved,
+        Err(msg) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(ErrorCode::InvalidInput, msg)),
+            ));
+        }
+    };
+    
+    // 文本消息中包含链接时生成预览，写入 extra 并随推送消息一起下发（最多等待 inline_wait_ms，未命中缓存的抓取在后台完成）
+    let link_preview = LinkPreviewService::new(redis_client.clone(), link_preview_settings)
+        .preview_for_message(req.message_content_type, &req.message_body)
+        .await;
+    let extra = match &link_preview {
+        Some(preview) => attach_preview_to_extra(extra, preview),
+        None => extra,
+    };
+    
     // 保存消息到数据库（使用 open_id）
     let message = ImSingleMessage {
         message_id: message_id.clone(),
@@ -119,7 +249,8 @@ pub async fn send_single_message(
         message_time: now,
         message_content_type: req.message_content_type,
         read_status: 0,
-        extra: req.extra.clone(),
+        played_status: 0,
+        extra,
         del_flag: 1,
         sequence: now, // 使用时间戳作为序列号
         message_random: Some(Uuid::new_v4().to_string()),
@@ -141,12 +272,11 @@ pub async fn send_single_message(
             let mut file_name = None;
             let mut file_type = None;
             
-            if let Some(extra_str) = &req.extra {
-                if let Ok(extra_json) = serde_json::from_str::<serde_json::Value>(extra_str) {
-                    file_url = extra_json.get("file_url").and_then(|v| v.as_str()).map(|s| s.to_string());
-                    file_name = extra_json.get("file_name").and_then(|v| v.as_str()).map(|s| s.to_string());
-                    file_type = extra_json.get("file_type").and_then(|v| v.as_str()).map(|s| s.to_string());
-                }
+            if let Some(extra_str) = &req.extra
+                && let Ok(extra_json) = serde_json::from_str::<serde_json::Value>(extra_str) {
+                file_url = extra_json.get("file_url").and_then(|v| v.as_str()).map(|s| s.to_string());
+                file_name = extra_json.get("file_name").and_then(|v| v.as_str()).map(|s| s.to_string());
+                file_type = extra_json.get("file_type").and_then(|v| v.as_str()).map(|s| s.to_string());
             }
             
             // 获取 open_id 的数字形式（用于MQTT）
@@ -164,47 +294,35 @@ pub async fn send_single_message(
                 file_name,
                 file_type,
                 chat_type: Some(1), // 1 = 单聊
+                link_preview,
+                voice: meta.voice,
+                location: meta.location,
+                card: meta.card,
             };
             
-            // 从数据库查询订阅ID并同步到内存（如果内存中没有）
-            let subscription_ids = {
-                let mut ids = subscription_service.get_subscription_ids(to_user.id);
-                if ids.is_empty() {
-                    // 如果内存中没有，从数据库查询（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
-                    if let Ok(db_subscriptions) = sqlx::query_scalar::<_, String>(
-                        "SELECT subscription_id FROM subscriptions 
-                         WHERE user_id = ? 
-                         AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
-                         ORDER BY created_at DESC"
-                    )
-                    .bind(to_user.id)
-                    .fetch_all(&pool)
-                    .await
-                    {
-                        for sub_id in &db_subscriptions {
-                            subscription_service.add_subscription_id(sub_id.clone(), to_user.id);
-                        }
-                        ids = subscription_service.get_subscription_ids(to_user.id);
-                    }
-                }
-                ids
-            };
+            // 从在线状态注册表判断用户是否在线（im-connect 在连接建立/心跳/断开时维护）
+            let is_online = PresenceRegistry::new(redis_client.clone())
+                .is_online(&to_open_id)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(open_id = %to_open_id, error = %e, "查询在线状态失败，按离线处理");
+                    false
+                });
             
-            // 判断用户是否在线
-            let is_online = !subscription_ids.is_empty();
-            let is_call_invite = req.message_content_type == 4;
+            // 有有效期策略的消息（通话邀请等）是实时消息，过期后没有意义
+            let expiring_kind = expiry_kind(req.message_content_type);
             
-            // 重要：对于通话邀请消息（message_content_type === 4），如果用户不在线，只存储到数据库，不推送
-            // 因为通话邀请是实时消息，过期后没有意义，不应该在用户上线后弹出
-            if is_call_invite && !is_online {
+            // 重要：有时效的消息如果用户不在线，只存储到数据库，不推送，不应该在用户上线后弹出
+            if let Some(kind) = expiring_kind && !is_online {
                 info!(
                     to_id = %req.to_id,
                     to_open_id = %to_open_id,
                     user_db_id = to_user.id,
                     to_mqtt_id = %to_mqtt_id,
                     message_id = %message_id,
-                    message_content_type = 4,
-                    "语音/视频呼叫消息，用户不在线，只存储到数据库，不推送（通话邀请是实时消息，过期后无意义）"
+                    message_content_type = req.message_content_type,
+                    expiry_kind = kind,
+                    "有时效的消息，用户不在线，只存储到数据库，不推送（过期后无意义）"
                 );
                 // 只存储到数据库，不通过 MQTT 推送，也不存储到 Redis
                 return Ok(Json(json!({
@@ -216,20 +334,17 @@ pub async fn send_single_message(
             
             // 对于普通消息或在线用户的通话邀请，正常处理：
             // 1. 消息已保存到数据库（上面已完成）
-            // 2. 通过 MQTT 发布消息（broker 会自动处理离线消息，使用 QoS 1 和 clean_session=false）
-            // 这样即使 MQTT 推送失败或用户不在线，消息也不会丢失
-            // 注意：broker 只有在客户端已经订阅过 topic 的情况下才会存储离线消息
-            // 如果用户从未连接过，broker 不会存储消息，但消息已保存到数据库，用户重连后可以从数据库获取
+            // 2. 存储到 Redis 离线队列，用户重连时由 im-connect 补发
+            // 3. 通过 MQTT 发布给用户当前的连接（broker 不保存离线消息，用户不在线时这次发布没有接收者）
             let topic = mqtt_user_topic(&to_mqtt_id.to_string());
             info!(
                 to_id = %req.to_id, 
                 user_db_id = to_user.id, 
                 to_mqtt_id = %to_mqtt_id,
-                has_subscription = is_online, 
-                subscription_count = subscription_ids.len(), 
+                is_online = is_online, 
                 %topic, 
                 message_id = %message_id,
-                is_call_invite = is_call_invite,
+                expiry_kind = ?expiring_kind,
                 "消息已保存到数据库，准备通过MQTT发布"
             );
             
@@ -247,6 +362,10 @@ pub async fn send_single_message(
             
             match encode_message(&chat_message) {
                 Ok(payload) => {
+                    // 通话邀请等有时效的消息写入过期时间，过期后不再推送、不再补发
+                    let payload = stamp_message_expiry(payload, req.message_content_type, &req.message_body);
+                    // 分配收件箱序号，客户端断线重连时据此补发
+                    let payload = InboxLog::new(redis_client.clone()).stamp(&to_open_id, payload).await;
                     // 尝试解析 payload 以确认 chat_type 是否被正确序列化
                     if let Ok(decoded) = serde_json::from_slice::<serde_json::Value>(&payload) {
                         info!(
@@ -265,100 +384,60 @@ pub async fn send_single_message(
                         payload_len = payload.len(),
                         "准备发布MQTT消息"
                     );
-                    let mqtt_publish_result = publisher.publish(&topic, payload.clone()).await;
-                    
-                    // 混合方案：MQTT + Redis 离线消息
-                    // 1. MQTT 处理短期离线（用户曾经连接过，broker 会自动存储）
-                    // 2. Redis 处理长期离线或从未连接的用户（作为备份）
-                    // 重要：对于语音/视频呼叫消息（message_content_type === 4），如果用户不在线，不存储到 Redis
-                    // 因为通话邀请是实时消息，过期后没有意义
-                    // 注意：这里 is_online 一定是 true（因为离线用户的通话邀请已经在上面处理了）
-                    let should_store_to_redis = if is_call_invite && !is_online {
-                        // 语音/视频呼叫消息，用户不在线，不存储到 Redis
-                        info!(
-                            to_id = %req.to_id,
-                            to_open_id = %to_open_id,
-                            message_id = %message_id,
-                            message_content_type = 4,
-                            "语音/视频呼叫消息，用户不在线，不存储到 Redis（通话邀请是实时消息，过期后无意义）"
-                        );
-                        false
-                    } else {
-                        true
-                    };
+                    // 先写入 Redis 离线队列再发布，无论发布结果如何都保留：发布成功只表示消息已进入本地 MQTT 发送队列，
+                    // 路由表中的节点可能已经崩溃（路由条目要等 ROUTE_TTL_SECS 后才过期），消息不会到达任何连接
+                    // im-connect 把消息写给客户端（或收到客户端确认）后会从离线队列中删除，先写入保证删除不会早于写入
+                    // 有时效的消息发给离线用户时已经在上面返回；存储到 Redis 的有时效消息带有 expires_at，过期后不再补发
+                    if let Ok(payload_str) = String::from_utf8(payload.clone()) {
+                        if let Err(redis_err) = OfflineQueue::new(redis_client.clone()).push(&to_open_id, &payload_str).await {
+                            warn!(
+                                to_id = %req.to_id,
+                                to_open_id = %to_open_id,
+                                error = %redis_err,
+                                "Redis 离线消息存储失败（消息已保存到数据库，不会丢失）"
+                            );
+                        } else {
+                            info!(
+                                to_id = %req.to_id,
+                                to_open_id = %to_open_id,
+                                message_id = %message_id,
+                                is_online = is_online,
+                                "✅ 消息已存储到 Redis 离线队列"
+                            );
+                        }
+                    }
                     
-                    if let Err(e) = mqtt_publish_result {
-                        error!(
-                            to_id = %req.to_id, 
-                            to_mqtt_id = %to_mqtt_id,
-                            %topic, 
-                            message_id = %message_id,
-                            error = %e, 
-                            "MQTT 发布失败，将消息存储到 Redis 作为备份"
-                        );
-                        // MQTT 发布失败，存储到 Redis 作为备份（除非是语音/视频呼叫且用户不在线）
-                        if should_store_to_redis {
-                            if let Ok(payload_str) = String::from_utf8(payload.clone()) {
-                                if let Err(redis_err) = redis_client.add_offline_message(&to_open_id, &payload_str).await {
-                                    warn!(
-                                        to_id = %req.to_id,
-                                        to_open_id = %to_open_id,
-                                        error = %redis_err,
-                                        "Redis 离线消息存储失败（消息已保存到数据库，不会丢失）"
-                                    );
-                                } else {
-                                    info!(
-                                        to_id = %req.to_id,
-                                        to_open_id = %to_open_id,
-                                        message_id = %message_id,
-                                        "✅ 消息已存储到 Redis（MQTT 发布失败时的备份）"
-                                    );
-                                }
-                            }
+                    // 跨节点路由表中有用户的连接时直接发布到所在节点的 topic，否则发布到用户 topic
+                    match publisher
+                        .deliver(&RouteRegistry::new(redis_client.clone()), &to_open_id, &topic, payload)
+                        .await
+                    {
+                        Ok(Delivery::Routed { nodes }) => {
+                            info!(
+                                to_id = %req.to_id,
+                                to_open_id = %to_open_id,
+                                message_id = %message_id,
+                                nodes = nodes,
+                                "✅ 消息已保存到数据库，已发布到用户连接所在的节点"
+                            );
                         }
-                    } else {
-                        // MQTT 发布成功
-                        // 重要：对于普通消息，无论用户是否在线，都存储到 Redis 作为备份
-                        // 但对于语音/视频呼叫消息，如果用户不在线，不存储（因为过期后无意义）
-                        // 原因：
-                        // 1. 如果用户在线但 WebSocket 连接不稳定，消息可能丢失
-                        // 2. 如果用户在消息发布后才连接，MQTT broker 不会存储消息（因为订阅发生在发布之后）
-                        // 3. Redis 作为统一备份，确保消息不丢失
-                        if should_store_to_redis {
-                            if let Ok(payload_str) = String::from_utf8(payload.clone()) {
-                                if let Err(redis_err) = redis_client.add_offline_message(&to_open_id, &payload_str).await {
-                                    warn!(
-                                        to_id = %req.to_id,
-                                        to_open_id = %to_open_id,
-                                        error = %redis_err,
-                                        "Redis 离线消息存储失败（MQTT 已发布，消息可能不会丢失）"
-                                    );
-                                } else {
-                                    if is_online {
-                                        info!(
-                                            to_id = %req.to_id,
-                                            to_mqtt_id = %to_mqtt_id,
-                                            to_open_id = %to_open_id,
-                                            %topic,
-                                            message_id = %message_id,
-                                            "✅ 消息已保存到数据库，MQTT 发布成功，Redis 已备份（用户在线，三重保障）"
-                                        );
-                                    } else {
-                                        info!(
-                                            to_id = %req.to_id,
-                                            to_open_id = %to_open_id,
-                                            message_id = %message_id,
-                                            "✅ 消息已保存到数据库，MQTT broker 和 Redis 双重存储（用户离线，确保消息不丢失）"
-                                        );
-                                    }
-                                }
-                            }
-                        } else {
+                        Ok(Delivery::Broadcast) => {
                             info!(
                                 to_id = %req.to_id,
                                 to_open_id = %to_open_id,
+                                %topic,
                                 message_id = %message_id,
-                                "✅ 消息已保存到数据库，MQTT 发布成功（语音/视频呼叫消息，用户不在线，不存储到 Redis）"
+                                "✅ 消息已保存到数据库，已发布到用户 topic"
+                            );
+                        }
+                        Err(e) => {
+                            error!(
+                                to_id = %req.to_id, 
+                                to_mqtt_id = %to_mqtt_id,
+                                %topic, 
+                                message_id = %message_id,
+                                error = %e, 
+                                "MQTT 发布失败，消息保留在 Redis 离线队列中"
                             );
                         }
                     }
@@ -440,7 +519,7 @@ pub async fn send_single_message(
                 }
             }
             
-            Ok(Json(serde_json::json!({"status": "ok"})))
+            Ok(Json(serde_json::json!({"status": "ok", "message_id": message_id, "timestamp_ms": now})))
         },
         Err(e) => {
             error!("保存单聊消息失败: {:?}, 请求: from_id={}, to_id={}, message_body={}", 
@@ -460,7 +539,7 @@ pub async fn get_single_messages(
     Query(params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
     let service = ImMessageService::with_redis(pool.clone(), redis_client.clone());
-    let user_service = UserService::new(pool);
+    let user_service = UserService::new(pool.clone());
     
     // 将数据库ID转换为 open_id（因为数据库中的 from_id 和 to_id 都是 open_id）
     let from_open_id = match user_service.get_by_id(user_id).await {
@@ -478,7 +557,13 @@ pub async fn get_single_messages(
     let limit = params.get("limit").and_then(|s| s.parse::<i32>().ok()).unwrap_or(100);
     
     match service.get_single_messages(&from_open_id, &to_id, since_sequence, limit).await {
-        Ok(messages) => Ok(Json(serde_json::json!({"messages": messages}))),
+        Ok(mut messages) => {
+            // 名片消息按对方当前资料填充名称和头像
+            ContactCardService::new(pool, redis_client)
+                .fill_cards(messages.iter_mut().map(|m| (m.message_content_type, &mut m.extra)).collect())
+                .await;
+            Ok(Json(serde_json::json!({"messages": messages})))
+        },
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(e, "获取消息失败")),
@@ -516,9 +601,11 @@ pub async fn mark_single_message_read(
 }
 
 pub async fn send_group_message(
-    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
     Extension(pool): Extension<MySqlPool>,
     Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(link_preview_settings): Extension<LinkPreviewSettings>,
+    Extension(upload_settings): Extension<UploadSettings>,
     Json(req): Json<SendGroupMessageRequest>,
 ) -> impl IntoResponse {
     let service = ImMessageService::with_redis(pool.clone(), redis_client.clone());
@@ -674,14 +761,34 @@ pub async fn send_group_message(
     let mut file_name = None;
     let mut file_type = None;
     
-    if let Some(extra_str) = &req.extra {
-        if let Ok(extra_json) = serde_json::from_str::<serde_json::Value>(extra_str) {
-            file_url = extra_json.get("file_url").and_then(|v| v.as_str()).map(|s| s.to_string());
-            file_name = extra_json.get("file_name").and_then(|v| v.as_str()).map(|s| s.to_string());
-            file_type = extra_json.get("file_type").and_then(|v| v.as_str()).map(|s| s.to_string());
-        }
+    if let Some(extra_str) = &req.extra
+        && let Ok(extra_json) = serde_json::from_str::<serde_json::Value>(extra_str) {
+        file_url = extra_json.get("file_url").and_then(|v| v.as_str()).map(|s| s.to_string());
+        file_name = extra_json.get("file_name").and_then(|v| v.as_str()).map(|s| s.to_string());
+        file_type = extra_json.get("file_type").and_then(|v| v.as_str()).map(|s| s.to_string());
     }
     
+    // 语音、位置、名片消息校验 extra 并附带类型化元数据
+    let card_service = ContactCardService::new(pool.clone(), redis_client.clone());
+    let (meta, extra) = match resolve_message_meta(req.message_content_type, req.extra.clone(), &upload_settings, &card_service).await {
+        Ok(resolved) => resolved,
+        Err(msg) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(ErrorCode::InvalidInput, msg)),
+            ));
+        }
+    };
+    
+    // 文本消息中包含链接时生成预览，写入 extra 并随推送消息一起下发（最多等待 inline_wait_ms，未命中缓存的抓取在后台完成）
+    let link_preview = LinkPreviewService::new(redis_client.clone(), link_preview_settings)
+        .preview_for_message(req.message_content_type, &req.message_body)
+        .await;
+    let extra = match &link_preview {
+        Some(preview) => attach_preview_to_extra(extra, preview),
+        None => extra,
+    };
+    
     // 根据 chat_type 决定保存到哪个表：chat_type=1保存到单聊表，chat_type=2保存到群聊表
     if is_single_chat {
         // chat_type=1（单聊）：保存到单聊表
@@ -717,7 +824,8 @@ pub async fn send_group_message(
                 message_time: now,
                 message_content_type: req.message_content_type,
                 read_status: 0,
-                extra: req.extra.clone(),
+                played_status: 0,
+                extra: extra.clone(),
                 del_flag: 1,
                 sequence: now,
                 message_random: Some(Uuid::new_v4().to_string()),
@@ -759,7 +867,7 @@ pub async fn send_group_message(
             message_body: req.message_body.clone(),
             message_time: now,
             message_content_type: req.message_content_type,
-            extra: req.extra.clone(),
+            extra: extra.clone(),
             del_flag: 1,
             sequence: Some(now),
             message_random: Some(Uuid::new_v4().to_string()),
@@ -857,38 +965,24 @@ pub async fn send_group_message(
             file_name: file_name.clone(),
             file_type: file_type.clone(),
             chat_type: chat_type_for_message, // 根据 chat_type 决定：chat_type=1（单聊），chat_type=2（群聊）
+            link_preview: link_preview.clone(),
+            voice: meta.voice.clone(),
+            location: meta.location.clone(),
+            card: meta.card.clone(),
         };
         
-        // 从数据库查询订阅ID并同步到内存（如果内存中没有）
-        let subscription_ids = {
-            let mut ids = subscription_service.get_subscription_ids(member_user.id);
-            if ids.is_empty() {
-                // 如果内存中没有，从数据库查询（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
-                if let Ok(db_subscriptions) = sqlx::query_scalar::<_, String>(
-                    "SELECT subscription_id FROM subscriptions 
-                     WHERE user_id = ? 
-                     AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
-                     ORDER BY created_at DESC"
-                )
-                .bind(member_user.id)
-                .fetch_all(&pool)
-                .await
-                {
-                    for sub_id in &db_subscriptions {
-                        subscription_service.add_subscription_id(sub_id.clone(), member_user.id);
-                    }
-                    ids = subscription_service.get_subscription_ids(member_user.id);
-                }
-            }
-            ids
-        };
+        // 从在线状态注册表判断用户是否在线（im-connect 在连接建立/心跳/断开时维护）
+        let is_online = PresenceRegistry::new(redis_client.clone())
+            .is_online(&member_open_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(open_id = %member_open_id, error = %e, "查询在线状态失败，按离线处理");
+                false
+            });
         
-        // 通过 MQTT 发布消息给群成员
-        // 注意：broker 只有在客户端已经订阅过 topic 的情况下才会存储离线消息
-        // 如果用户从未连接过，broker 不会存储消息，但消息已保存到数据库，用户可以通过其他方式获取
+        // 通过 MQTT 发布消息给群成员，同时存储到 Redis 离线队列（broker 不保存离线消息）
         let topic = mqtt_user_topic(&member_mqtt_id.to_string());
-        let is_online = !subscription_ids.is_empty();
-        info!(group_id = %req.group_id, member_id = %member_id_str, is_online = is_online, subscription_count = subscription_ids.len(), %topic, "通过MQTT发布群消息（broker会自动处理离线消息，前提是用户曾经订阅过topic）");
+        info!(group_id = %req.group_id, member_id = %member_id_str, is_online = is_online, %topic, "通过MQTT发布群消息");
         
         // 添加调试日志，确认消息的 chat_type 是否正确设置
         let chat_type_str = if is_single_chat { "单聊" } else { "群聊" };
@@ -907,6 +1001,10 @@ pub async fn send_group_message(
         
         match encode_message(&chat_message) {
             Ok(payload) => {
+                // 通话邀请等有时效的消息写入过期时间，过期后不再推送、不再补发
+                let payload = stamp_message_expiry(payload, req.message_content_type, &req.message_body);
+                // 分配收件箱序号，客户端断线重连时据此补发
+                let payload = InboxLog::new(redis_client.clone()).stamp(&member_open_id, payload).await;
                 // 尝试解析 payload 以确认 chat_type 是否被正确序列化
                 if let Ok(decoded) = serde_json::from_slice::<serde_json::Value>(&payload) {
                     info!(
@@ -918,122 +1016,74 @@ pub async fn send_group_message(
                     );
                 }
                 
-                // 混合方案：MQTT + Redis 离线消息
-                // 1. MQTT 处理短期离线（用户曾经连接过，broker 会自动存储）
-                // 2. Redis 处理长期离线或从未连接的用户（作为备份）
-                // 先转换为 String，以便在多个地方使用
-                let payload_str_result = String::from_utf8(payload.clone());
-                
-                // 检查 payload 转换是否成功
-                if let Err(e) = &payload_str_result {
-                    error!(
-                        group_id = %req.group_id,
-                        member_id = %member_id_str,
-                        member_open_id = %member_open_id,
-                        message_id = %message_id,
-                        error = %e,
-                        "⚠️ 群组消息 payload 转换为 String 失败，无法存储到 Redis"
-                    );
-                }
-                
-                if let Err(e) = publisher.publish(&topic, payload).await {
-                    error!(group_id = %req.group_id, member_id = %member_id_str, %topic, error = %e, message_id = %message_id, chat_type = ?chat_type, "消息MQTT发布失败，将消息存储到 Redis 作为备份");
-                    // MQTT 发布失败，存储到 Redis 作为备份
-                    match payload_str_result {
-                        Ok(payload_str) => {
-                            if let Err(redis_err) = redis_client.add_offline_message(&member_open_id, &payload_str).await {
-                                warn!(
-                                    group_id = %req.group_id,
-                                    member_id = %member_id_str,
-                                    member_open_id = %member_open_id,
-                                    error = %redis_err,
-                                    "Redis 离线消息存储失败（消息已保存到数据库，不会丢失）"
-                                );
-                            } else {
-                                info!(
-                                    group_id = %req.group_id,
-                                    member_id = %member_id_str,
-                                    member_open_id = %member_open_id,
-                                    message_id = %message_id,
-                                    chat_type = ?chat_type,
-                                    "✅ 消息已存储到 Redis（MQTT 发布失败时的备份）"
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            error!(
+                // 先写入 Redis 离线队列再发布，无论发布结果如何都保留：发布成功只表示消息已进入本地 MQTT 发送队列，
+                // 路由表中的节点可能已经崩溃，消息不会到达任何连接；im-connect 把消息写给成员后会从离线队列中删除
+                match String::from_utf8(payload.clone()) {
+                    Ok(payload_str) => {
+                        if let Err(redis_err) = OfflineQueue::new(redis_client.clone()).push(&member_open_id, &payload_str).await {
+                            warn!(
                                 group_id = %req.group_id,
                                 member_id = %member_id_str,
                                 member_open_id = %member_open_id,
                                 message_id = %message_id,
-                                error = %e,
-                                "⚠️ 无法将消息存储到 Redis（payload 转换失败）"
+                                chat_type = ?chat_type,
+                                error = %redis_err,
+                                "❌ Redis 离线消息存储失败（消息已保存到数据库，不会丢失）"
                             );
-                        }
-                    }
-                } else {
-                    // MQTT 发布成功
-                    // 重要：无论用户是否在线，都存储到 Redis 作为备份
-                    // 原因：
-                    // 1. 如果用户在线但 WebSocket 连接不稳定，消息可能丢失
-                    // 2. 如果用户在消息发布后才连接，MQTT broker 不会存储消息（因为订阅发生在发布之后）
-                    // 3. Redis 作为统一备份，确保消息不丢失
-                    match payload_str_result {
-                        Ok(payload_str) => {
+                        } else {
                             info!(
                                 group_id = %req.group_id,
                                 member_id = %member_id_str,
                                 member_open_id = %member_open_id,
                                 message_id = %message_id,
                                 chat_type = ?chat_type,
-                                payload_length = payload_str.len(),
-                                "准备存储群组消息到 Redis"
-                            );
-                            
-                            if let Err(redis_err) = redis_client.add_offline_message(&member_open_id, &payload_str).await {
-                                warn!(
-                                    group_id = %req.group_id,
-                                    member_id = %member_id_str,
-                                    member_open_id = %member_open_id,
-                                    message_id = %message_id,
-                                    chat_type = ?chat_type,
-                                    error = %redis_err,
-                                    "❌ Redis 离线消息存储失败（MQTT 已发布，消息可能不会丢失）"
-                                );
-                            } else {
-                                if is_online {
-                                    info!(
-                                        group_id = %req.group_id,
-                                        member_id = %member_id_str,
-                                        member_open_id = %member_open_id,
-                                        %topic,
-                                        message_id = %message_id,
-                                        chat_type = ?chat_type,
-                                        "✅ 消息已保存到数据库，MQTT 发布成功，Redis 已备份（用户在线，三重保障）"
-                                    );
-                                } else {
-                                    info!(
-                                        group_id = %req.group_id,
-                                        member_id = %member_id_str,
-                                        member_open_id = %member_open_id,
-                                        message_id = %message_id,
-                                        chat_type = ?chat_type,
-                                        "✅ 消息已保存到数据库，MQTT broker 和 Redis 双重存储（用户离线，确保消息不丢失）"
-                                    );
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!(
-                                group_id = %req.group_id,
-                                member_id = %member_id_str,
-                                member_open_id = %member_open_id,
-                                message_id = %message_id,
-                                error = %e,
-                                "⚠️ 无法将消息存储到 Redis（payload 转换失败），MQTT 已发布"
+                                is_online = is_online,
+                                "✅ 群组消息已存储到 Redis 离线队列"
                             );
                         }
                     }
+                    Err(e) => {
+                        error!(
+                            group_id = %req.group_id,
+                            member_id = %member_id_str,
+                            member_open_id = %member_open_id,
+                            message_id = %message_id,
+                            error = %e,
+                            "⚠️ 群组消息 payload 转换为 String 失败，无法存储到 Redis"
+                        );
+                    }
+                }
+                
+                // 跨节点路由表中有成员的连接时直接发布到所在节点的 topic，否则发布到用户 topic
+                match publisher
+                    .deliver(&RouteRegistry::new(redis_client.clone()), &member_open_id, &topic, payload)
+                    .await
+                {
+                    Ok(Delivery::Routed { nodes }) => {
+                        info!(
+                            group_id = %req.group_id,
+                            member_id = %member_id_str,
+                            member_open_id = %member_open_id,
+                            message_id = %message_id,
+                            chat_type = ?chat_type,
+                            nodes = nodes,
+                            "✅ 消息已保存到数据库，已发布到成员连接所在的节点"
+                        );
+                    }
+                    Ok(Delivery::Broadcast) => {
+                        info!(
+                            group_id = %req.group_id,
+                            member_id = %member_id_str,
+                            member_open_id = %member_open_id,
+                            %topic,
+                            message_id = %message_id,
+                            chat_type = ?chat_type,
+                            "✅ 消息已保存到数据库，已发布到成员 topic"
+                        );
+                    }
+                    Err(e) => {
+                        error!(group_id = %req.group_id, member_id = %member_id_str, %topic, error = %e, message_id = %message_id, chat_type = ?chat_type, "消息MQTT发布失败，消息保留在 Redis 离线队列中");
+                    }
                 }
             }
             Err(e) => {
@@ -1194,7 +1244,7 @@ pub async fn send_group_message(
         }
     }
     
-    Ok(Json(serde_json::json!({"status": "ok"})))
+    Ok(Json(serde_json::json!({"status": "ok", "message_id": message_id, "timestamp_ms": now})))
 }
 
 pub async fn get_group_messages(
@@ -1206,6 +1256,7 @@ pub async fn get_group_messages(
 ) -> impl IntoResponse {
     let service = ImMessageService::with_redis(pool.clone(), redis_client.clone());
     let group_service = ImGroupService::new(pool.clone());
+    let card_service = ContactCardService::new(pool.clone(), redis_client.clone());
     let user_service = UserService::new(pool.clone());
     
     // 获取当前用户信息
@@ -1262,7 +1313,11 @@ pub async fn get_group_messages(
         
         if let Some(other_id) = other_user_open_id {
             match service.get_single_messages(&current_user_open_id, &other_id, since_sequence, limit).await {
-                Ok(messages) => {
+                Ok(mut messages) => {
+                    // 名片消息按对方当前资料填充名称和头像
+                    card_service
+                        .fill_cards(messages.iter_mut().map(|m| (m.message_content_type, &mut m.extra)).collect())
+                        .await;
                     // 将单聊消息转换为统一的格式返回
                     let converted_messages: Vec<serde_json::Value> = messages.iter().map(|msg| {
                         serde_json::json!({
@@ -1298,7 +1353,13 @@ pub async fn get_group_messages(
     } else {
         // 3人及以上：从群聊表查询
         match service.get_group_messages(&group_id, since_sequence, limit).await {
-            Ok(messages) => Ok(Json(serde_json::json!({"messages": messages}))),
+            Ok(mut messages) => {
+                // 名片消息按对方当前资料填充名称和头像
+                card_service
+                    .fill_cards(messages.iter_mut().map(|m| (m.message_content_type, &mut m.extra)).collect())
+                    .await;
+                Ok(Json(serde_json::json!({"messages": messages})))
+            },
             Err(e) => Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse::new(e, "获取群消息失败")),
@@ -1368,6 +1429,115 @@ pub async fn mark_group_message_read(
     }
 }
 
+/// 标记单聊语音消息为已播放
+pub async fn mark_single_message_played(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Path(message_id): Path<String>,
+) -> impl IntoResponse {
+    let service = ImMessageService::with_redis(pool.clone(), redis_client.clone());
+    let user_service = UserService::new(pool);
+    
+    // 只有接收者可以标记已播放（数据库中的 to_id 是 open_id）
+    let to_open_id = match user_service.get_by_id(user_id).await {
+        Ok(user) => user.get_external_id(),
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(ErrorCode::NotFound, "当前用户不存在")),
+            ));
+        }
+    };
+    
+    match service.mark_single_message_played(&message_id, &to_open_id).await {
+        Ok(_) => Ok(Json(serde_json::json!({"status": "ok"}))),
+        Err(ErrorCode::NotFound) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(ErrorCode::NotFound, "语音消息不存在")),
+        )),
+        Err(e) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(e, "标记语音消息已播放失败")),
+        )),
+    }
+}
+
+/// 标记群语音消息为已播放
+pub async fn mark_group_message_played(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Path((group_id, message_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let user_service = UserService::new(pool.clone());
+    let service = ImMessageService::with_redis(pool.clone(), redis_client.clone());
+    let group_service = ImGroupService::new(pool);
+    
+    let user = match user_service.get_by_id(user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            warn!("获取当前用户失败: {:?}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+    
+    let to_id = user.get_external_id();
+    
+    // 与已读标记一致：根据成员数决定使用哪种存储
+    let members = match group_service.get_group_members(&group_id).await {
+        Ok(members) => members,
+        Err(e) => {
+            warn!("获取群成员失败: {:?}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取群成员失败")),
+            ));
+        }
+    };
+    
+    let result = if members.len() == 2 {
+        // 2人聊天：使用单聊表的 played_status 字段
+        service.mark_single_message_played(&message_id, &to_id).await
+    } else {
+        // 3人及以上：使用 Redis 记录已播放用户
+        service.mark_group_message_played(&group_id, &message_id, &to_id).await
+    };
+    
+    match result {
+        Ok(_) => Ok(Json(serde_json::json!({"status": "ok"}))),
+        Err(ErrorCode::NotFound) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(ErrorCode::NotFound, "语音消息不存在")),
+        )),
+        Err(e) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(e, "标记语音消息已播放失败")),
+        )),
+    }
+}
+
+/// 获取群语音消息的已播放用户
+pub async fn get_group_message_played(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(_user_id): Extension<u64>,
+    Path((group_id, message_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let service = ImMessageService::with_redis(pool, redis_client);
+    
+    match service.get_group_message_played_users(&group_id, &message_id).await {
+        Ok(user_ids) => Ok(Json(serde_json::json!({"played_users": user_ids}))),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(e, "获取语音播放状态失败")),
+        )),
+    }
+}
+
 pub async fn get_group_message_status(
     Extension(pool): Extension<MySqlPool>,
     Extension(_user_id): Extension<u64>,
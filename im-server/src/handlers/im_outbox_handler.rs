@@ -21,8 +21,8 @@ pub struct UpdateOutboxStatusRequest {
 }
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub struct SetNextTryRequest {
-    #[allow(dead_code)]
     pub next_try_at: i64,
 }
 
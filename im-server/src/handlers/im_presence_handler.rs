@@ -0,0 +1,128 @@
+use axum::{extract::{Extension, Query}, http::StatusCode, response::IntoResponse, Json};
+use sqlx::MySqlPool;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::{
+    error::{ErrorCode, ErrorResponse},
+    model::LastSeenVisibility,
+    service::{ImUserService, PresenceService, UserService},
+    redis::RedisClient,
+};
+
+/// 单次最多查询的用户数
+const MAX_PRESENCE_IDS: usize = 200;
+
+#[derive(Deserialize)]
+pub struct PresenceQuery {
+    /// 逗号分隔的 open_id 列表
+    pub ids: String,
+}
+
+/// 批量查询用户在线状态（online / away / offline）和最后活跃时间
+/// 设备明细只返回给用户本人，最后活跃时间遵守被查询用户的隐私设置
+pub async fn get_presence(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Query(query): Query<PresenceQuery>,
+) -> impl IntoResponse {
+    let mut ids: Vec<String> = query
+        .ids
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    if ids.is_empty() || ids.len() > MAX_PRESENCE_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                ErrorCode::InvalidInput,
+                format!("ids 不能为空，且最多 {} 个", MAX_PRESENCE_IDS),
+            )),
+        ));
+    }
+
+    let current_open_id = match UserService::new(pool.clone()).get_by_id(user_id).await {
+        Ok(user) => user.get_external_id(),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+
+    let presences = match PresenceService::new(pool, redis_client).get_for_viewer(&ids, &current_open_id).await {
+        Ok(presences) => presences,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "查询在线状态失败")),
+            ));
+        }
+    };
+
+    Ok(Json(serde_json::json!({ "presences": presences })))
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePresencePrivacyRequest {
+    /// everyone / contacts / nobody
+    pub last_seen_visibility: LastSeenVisibility,
+}
+
+/// 获取当前用户的在线状态隐私设置
+pub async fn get_presence_privacy(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+) -> impl IntoResponse {
+    let open_id = match UserService::new(pool.clone()).get_by_id(user_id).await {
+        Ok(user) => user.get_external_id(),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+
+    let visibility = PresenceService::new(pool, redis_client).last_seen_visibility(&open_id).await;
+    Ok(Json(serde_json::json!({ "last_seen_visibility": visibility })))
+}
+
+/// 设置谁可以看到自己的最后在线时间：所有人、仅好友或所有人都不可见
+pub async fn update_presence_privacy(
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Json(req): Json<UpdatePresencePrivacyRequest>,
+) -> impl IntoResponse {
+    let open_id = match UserService::new(pool.clone()).get_by_id(user_id).await {
+        Ok(user) => user.get_external_id(),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+
+    match ImUserService::with_redis(pool, redis_client)
+        .update_last_seen_visibility(&open_id, req.last_seen_visibility)
+        .await
+    {
+        Ok(()) => Ok(Json(serde_json::json!({
+            "status": "ok",
+            "last_seen_visibility": req.last_seen_visibility,
+        }))),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(e, "更新隐私设置失败")),
+        )),
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{extract::{Extension, State}, http::StatusCode, response::IntoResponse, Json};
+use sqlx::MySqlPool;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, warn};
+use im_share::{ChatMessage, mqtt_user_topic, encode_message, now_timestamp, stamp_expiry};
+use im_share::expiry::TYPING;
+use crate::{
+    error::{ErrorCode, ErrorResponse},
+    service::{SubscriptionService, UserService, ImGroupService},
+    mqtt::MqttPublisher,
+    redis::RedisClient,
+};
+
+/// 输入状态的有效期：客户端超过这个时间没收到新的输入事件或停止事件，就自动隐藏"正在输入"
+pub const TYPING_TTL_MS: i64 = 5000;
+/// 同一用户在同一会话中"开始输入"事件的最小间隔，间隔内的重复事件直接丢弃
+const TYPING_MIN_INTERVAL_MS: u64 = 2000;
+
+#[derive(Deserialize)]
+pub struct TypingRequest {
+    /// 1=单聊，2=群聊
+    pub chat_type: i32,
+    /// 单聊为对方 open_id，群聊为 group_id
+    pub to_id: String,
+    /// true=开始输入，false=停止输入
+    #[serde(default = "default_typing")]
+    pub typing: bool,
+}
+
+fn default_typing() -> bool {
+    true
+}
+
+/// 发送"正在输入"状态：不落库，通过 MQTT（QoS 0，不做离线存储）推送给对方（群聊推送给其他群成员）
+/// 事件带 expires_at，客户端到期未收到刷新或停止事件时自动隐藏
+pub async fn send_typing(
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
+    Extension(user_id): Extension<u64>,
+    Json(req): Json<TypingRequest>,
+) -> impl IntoResponse {
+    let user_service = UserService::new(pool.clone());
+    let from_user = match user_service.get_by_id(user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(e, "获取用户信息失败")),
+            ));
+        }
+    };
+    let from_open_id = from_user.get_external_id();
+
+    // 确定接收者（open_id 列表）和推送消息中的 to_user_id
+    let (receivers, to_user_id) = match req.chat_type {
+        1 => {
+            if req.to_id.is_empty() || req.to_id == from_open_id {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(ErrorCode::InvalidInput, "无效的 to_id")),
+                ));
+            }
+            (vec![req.to_id.clone()], req.to_id.clone())
+        }
+        2 => {
+            // 统一 group_id 格式：确保有 group_ 前缀
+            let group_id = if req.to_id.starts_with("group_") {
+                req.to_id.clone()
+            } else {
+                format!("group_{}", req.to_id)
+            };
+            let members = ImGroupService::new(pool.clone())
+                .get_group_members(&group_id)
+                .await
+                .unwrap_or_default();
+            if !members.iter().any(|m| m.member_id == from_open_id) {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(ErrorResponse::new(ErrorCode::Forbidden, "您不是该群组成员")),
+                ));
+            }
+            let receivers = members
+                .into_iter()
+                .map(|m| m.member_id)
+                .filter(|member_id| *member_id != from_open_id)
+                .collect();
+            (receivers, group_id)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(ErrorCode::InvalidInput, "无效的 chat_type")),
+            ));
+        }
+    };
+
+    // 限流："开始输入"在最小间隔内只推送一次；"停止输入"总是推送，并清除限流标记，
+    // 让下一次开始输入可以立即推送
+    let rate_key = format!("typing:rate:{}:{}", from_open_id, to_user_id);
+    if req.typing {
+        match redis_client.set_nx_with_ttl_ms(&rate_key, "1", TYPING_MIN_INTERVAL_MS).await {
+            Ok(false) => return Ok(Json(serde_json::json!({"status": "ok", "throttled": true}))),
+            Ok(true) => {}
+            Err(e) => warn!(error = %e, "输入状态限流检查失败，继续推送"),
+        }
+    } else if let Err(e) = redis_client.del(&rate_key).await {
+        warn!(error = %e, "清除输入状态限流标记失败");
+    }
+
+    let now = now_timestamp();
+    let notification = ChatMessage {
+        message_id: uuid::Uuid::new_v4().to_string(),
+        from_user_id: from_open_id.clone(),
+        to_user_id: to_user_id.clone(),
+        message: serde_json::json!({
+            "type": "typing",
+            "from_id": from_open_id,
+            "to_id": to_user_id,
+            "chat_type": req.chat_type,
+            "typing": req.typing,
+            "expires_at": now + TYPING_TTL_MS,
+        }).to_string(),
+        timestamp_ms: now,
+        file_url: None,
+        file_name: None,
+        file_type: None,
+        chat_type: Some(req.chat_type),
+        link_preview: None,
+        voice: None,
+        location: None,
+        card: None,
+    };
+    let payload = match encode_message(&notification) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!(error = %e, "输入状态消息编码失败");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(ErrorCode::Internal, "输入状态消息编码失败")),
+            ));
+        }
+    };
+    // 输入状态只在短时间内有意义，在发送队列中等待过久的不再推送
+    let payload = stamp_expiry(payload, TYPING);
+
+    for receiver in &receivers {
+        let Ok(receiver_user) = user_service.get_by_open_id(receiver).await else {
+            warn!(receiver = %receiver, "无法找到接收者，跳过输入状态推送");
+            continue;
+        };
+        let topic = mqtt_user_topic(&receiver_user.get_mqtt_id().to_string());
+        if let Err(e) = publisher.publish_transient(&topic, payload.clone()).await {
+            warn!(receiver = %receiver, %topic, error = %e, "输入状态MQTT发布失败");
+        }
+    }
+
+    Ok(Json(serde_json::json!({"status": "ok", "throttled": false})))
+}
@@ -5,18 +5,22 @@ use std::sync::Arc;
 use uuid::Uuid;
 use sqlx::MySqlPool;
 use im_share::{
-    SendRequest, Target, ChatMessage, mqtt_user_topic, encode_message, get_group_members,
+    SendRequest, Target, ChatMessage, mqtt_user_topic, encode_message, get_group_members, PresenceRegistry, RouteRegistry, InboxLog, now_timestamp,
 };
+use im_share::expiry::is_kind_expired;
 use crate::{
     error::{ErrorCode, ErrorResponse},
     mqtt::MqttPublisher,
     service::{SubscriptionService, UserService, ImMessageService},
+    service::im_message_service::expiry_kind,
     model::ImSingleMessage,
+    redis::RedisClient,
 };
 
 pub async fn send_message(
-    State((publisher, subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
+    State((publisher, _subscription_service)): State<(MqttPublisher, Arc<SubscriptionService>)>,
     Extension(pool): Extension<MySqlPool>,
+    Extension(redis_client): Extension<Arc<RedisClient>>,
     Json(req): Json<SendRequest>,
 ) -> impl IntoResponse {
     let ts = SystemTime::now()
@@ -88,29 +92,14 @@ pub async fn send_message(
             }
         };
         
-        // 从数据库查询订阅ID并同步到内存（如果内存中没有）
-        let subscription_ids = {
-            let mut ids = subscription_service.get_subscription_ids(to_user.id);
-            if ids.is_empty() {
-                // 如果内存中没有，从数据库查询（只查询最近24小时内创建的订阅，过滤掉已不在线的用户）
-                if let Ok(db_subscriptions) = sqlx::query_scalar::<_, String>(
-                    "SELECT subscription_id FROM subscriptions 
-                     WHERE user_id = ? 
-                     AND created_at >= DATE_SUB(NOW(), INTERVAL 24 HOUR)
-                     ORDER BY created_at DESC"
-                )
-                .bind(to_user.id)
-                .fetch_all(&pool)
-                .await
-                {
-                    for sub_id in &db_subscriptions {
-                        subscription_service.add_subscription_id(sub_id.clone(), to_user.id);
-                    }
-                    ids = subscription_service.get_subscription_ids(to_user.id);
-                }
-            }
-            ids
-        };
+        // 从在线状态注册表判断用户是否在线（im-connect 在连接建立/心跳/断开时维护）
+        let is_online = PresenceRegistry::new(redis_client.clone())
+            .is_online(&open_id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(open_id = %open_id, error = %e, "查询在线状态失败，按离线处理");
+                false
+            });
         
         // 无论用户是否在线，都要保存消息到数据库
         // 如果用户在线，通过 MQTT 实时推送；如果离线，用户重连后可以从数据库获取
@@ -125,6 +114,10 @@ pub async fn send_message(
             file_name: req.file_name.clone(),
             file_type: req.file_type.clone(),
             chat_type: Some(1), // 1 = 单聊
+            link_preview: None,
+            voice: None,
+            location: None,
+            card: None,
         };
 
         // 正确处理编码错误
@@ -141,13 +134,15 @@ pub async fn send_message(
                 ));
             }
         };
+        // 分配收件箱序号：用户离线时也写入日志，下次连接时可以从日志补发
+        let payload = InboxLog::new(redis_client.clone()).stamp(&message.to_user_id, payload).await;
 
         // 如果用户在线，通过 MQTT 实时推送
-        if !subscription_ids.is_empty() {
-            // 发布到用户的 MQTT topic（基于雪花ID）
+        if is_online {
+            // 发布到用户连接所在节点的 topic（跨节点路由表），路由表中没有时发布到用户的 MQTT topic（基于雪花ID）
             let topic = mqtt_user_topic(&to_user_mqtt_id.to_string());
 
-            if let Err(e) = publisher.publish(&topic, payload.clone()).await {
+            if let Err(e) = publisher.deliver(&RouteRegistry::new(redis_client.clone()), &open_id, &topic, payload.clone()).await {
                 tracing::error!(user_id = %to_user_mqtt_id, %topic, error = %e, "MQTT 发布失败");
                 // 不返回错误，继续保存到数据库
             }
@@ -188,6 +183,7 @@ pub async fn send_message(
             message_time: message.timestamp_ms,
             message_content_type: 1, // 默认文本消息，可以根据 file_url 判断是否为文件
             read_status: 0, // 默认未读
+            played_status: 0,
             extra: None,
             del_flag: 1, // 未删除
             sequence: message.timestamp_ms, // 使用时间戳作为序列号
@@ -249,15 +245,15 @@ pub async fn get_messages(
 
     // 查询发送给当前用户的消息（离线消息）
     // 使用 im_single_message 表，直接使用 open_id 查询
-    // 重要：过滤掉通话邀请消息（message_content_type = 4），因为通话邀请是实时消息，过期后没有意义
+    // 重要：有时效的消息（通话邀请等）按 im_share::expiry 的策略过滤掉已过期的，过期后没有意义
     let current_open_id = current_user.get_external_id();
     let messages = match sqlx::query_as::<_, ImSingleMessageRow>(
         "SELECT message_id, from_id, to_id, message_body, message_time, 
-                message_content_type, read_status, extra, del_flag, sequence,
+                message_content_type, read_status, played_status, extra, del_flag, sequence,
                 message_random, create_time, update_time, version, reply_to,
                 to_type, file_url, file_name, file_type
          FROM im_single_message
-         WHERE to_id = ? AND message_time > ? AND del_flag = 1 AND message_content_type != 4
+         WHERE to_id = ? AND message_time > ? AND del_flag = 1
          ORDER BY message_time ASC
          LIMIT 100"
     )
@@ -277,8 +273,12 @@ pub async fn get_messages(
     };
 
     // 转换为 ChatMessage 格式
+    let now = now_timestamp();
     let mut result = Vec::new();
     for row in messages {
+        if expiry_kind(row.message_content_type).is_some_and(|kind| is_kind_expired(kind, row.message_time, now)) {
+            continue;
+        }
         result.push(json!({
             "message_id": row.message_id,
             "from_user_id": row.from_id,
@@ -288,6 +288,7 @@ pub async fn get_messages(
             "file_url": row.file_url,
             "file_name": row.file_name,
             "file_type": row.file_type,
+            "played_status": row.played_status,
         }));
     }
 
@@ -304,6 +305,7 @@ struct ImSingleMessageRow {
     message_time: i64,
     message_content_type: i32,
     read_status: i32,
+    played_status: i32,
     extra: Option<String>,
     del_flag: i16,
     sequence: i64,
@@ -1,7 +1,6 @@
 pub mod user_handler;
 pub mod auth_handler;
 pub mod message_handler;
-pub mod subscription_handler;
 pub mod friend_handler;
 pub mod im_user_handler;
 pub mod im_friendship_handler;
@@ -9,6 +8,10 @@ pub mod im_message_handler;
 pub mod im_chat_handler;
 pub mod im_group_handler;
 pub mod im_outbox_handler;
+pub mod im_export_handler;
+pub mod im_typing_handler;
+pub mod im_presence_handler;
+pub mod device_handler;
 pub mod upload_handler;
 pub mod webrtc_handler;
 
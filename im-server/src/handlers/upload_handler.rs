@@ -1,17 +1,20 @@
 use axum::{
-    extract::{Extension, Multipart},
+    extract::{Extension, Multipart, Query},
     http::StatusCode,
     response::{IntoResponse, Json, Response},
     body::Body,
 };
 use sqlx::MySqlPool;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 use crate::error::{ErrorCode, ErrorResponse};
 use crate::config::UploadSettings;
 use crate::middleware::auth::UserIdentity;
-use crate::service::{ImFriendshipService, ImGroupService};
+use crate::audio::analyze_audio;
+use crate::service::{ImExportService, ImFriendshipService, ImGroupService};
+use im_share::now_timestamp;
 use image::{ImageFormat, imageops::FilterType};
 use std::io::Cursor;
 
@@ -21,12 +24,24 @@ pub fn init_upload_dir(upload_path: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+/// 将 file_url（如 /api/upload/{open_id}/{file_name}）转换为上传目录下的相对路径
+/// 不是本服务上传的文件或路径不安全时返回 None
+pub fn local_upload_path(file_url: &str) -> Option<String> {
+    let start = file_url.find("/api/upload/")? + "/api/upload/".len();
+    let relative = file_url[start..].split(['?', '#']).next()?.trim_start_matches('/');
+    // 防止路径遍历
+    if relative.is_empty() || relative.contains("..") || relative.contains('\\') {
+        return None;
+    }
+    Some(relative.to_string())
+}
+
 /// 处理图片：压缩和生成缩略图
 async fn process_image(
     image_data: &[u8],
     upload_settings: &UploadSettings,
     unique_file_name: &str,
-    upload_dir: &PathBuf,
+    upload_dir: &Path,
     open_id: &str,
 ) -> Result<(Option<String>, Option<String>), String> {
     // 解码图片
@@ -67,7 +82,7 @@ async fn process_image(
     };
 
     let mut original_path = None;
-    let thumbnail_path;
+    
 
     // 保存原图（如果需要）
     if upload_settings.save_original {
@@ -134,19 +149,22 @@ async fn process_image(
     if let Err(e) = tokio::fs::write(&thumb_file_path, &thumb_buffer).await {
         return Err(format!("写入缩略图文件失败: {}", e));
     }
-    thumbnail_path = Some(format!("{}/{}", open_id, thumb_file_name));
+    let thumbnail_path = Some(format!("{}/{}", open_id, thumb_file_name));
 
     Ok((original_path, thumbnail_path))
 }
 
 /// 上传文件
+/// 语音消息的录音带 ?voice=true 上传，服务端校验格式并在响应中返回时长和波形（voice 字段）
 pub async fn upload_file(
     Extension(_pool): Extension<MySqlPool>,
     Extension(upload_settings): Extension<UploadSettings>,
     Extension(user_identity): Extension<UserIdentity>,
+    Query(params): Query<HashMap<String, String>>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
     let open_id = &user_identity.open_id;
+    let is_voice = params.get("voice").is_some_and(|v| v == "true" || v == "1");
     
     // 确保用户的上传目录存在
     let user_upload_dir = PathBuf::from(&upload_settings.path).join(open_id);
@@ -189,7 +207,7 @@ pub async fn upload_file(
                 }
             };
 
-            // 根据文件类型验证文件大小
+            // 根据文件类型验证文件大小（在解析文件内容之前）
             let is_image = content_type.starts_with("image/");
             let (max_size_mb, max_size_bytes) = if is_image {
                 (upload_settings.max_image_size_mb, upload_settings.max_image_size_mb * 1024 * 1024)
@@ -208,6 +226,38 @@ pub async fn upload_file(
                 ));
             }
 
+            // 语音消息（?voice=true）：校验格式并提取时长和波形
+            // 普通音频附件（mp3、flac 等）不解析，按普通文件保存
+            let voice = if is_voice {
+                match analyze_audio(&file_data) {
+                    Ok(voice) if voice.duration_ms == 0 => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(ErrorResponse::new(ErrorCode::InvalidInput, "语音时长无效")),
+                        ));
+                    }
+                    Ok(voice) if voice.duration_ms > upload_settings.max_voice_duration_secs * 1000 => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(ErrorResponse::new(
+                                ErrorCode::InvalidInput,
+                                format!("语音时长不能超过 {} 秒", upload_settings.max_voice_duration_secs),
+                            )),
+                        ));
+                    }
+                    Ok(voice) => Some(voice),
+                    Err(e) => {
+                        warn!(file_name = %file_name, error = %e, "语音文件校验失败");
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(ErrorResponse::with_details(ErrorCode::InvalidInput, "无效的语音文件", e)),
+                        ));
+                    }
+                }
+            } else {
+                None
+            };
+
             // 生成唯一文件名：UUID + 原始扩展名
             let file_path_buf = PathBuf::from(&file_name);
             let extension = file_path_buf
@@ -283,14 +333,15 @@ pub async fn upload_file(
             );
 
             // 返回文件信息（URL 中包含 open_id 路径）
-            return Ok((
-                StatusCode::OK,
-                Json(serde_json::json!({
-                    "url": format!("/api/upload/{}/{}", open_id, unique_file_name),
-                    "file_name": unique_file_name,
-                    "file_type": content_type,
-                })),
-            ));
+            let mut response = serde_json::json!({
+                "url": format!("/api/upload/{}/{}", open_id, unique_file_name),
+                "file_name": unique_file_name,
+                "file_type": content_type,
+            });
+            if let Some(voice) = voice {
+                response["voice"] = serde_json::json!(voice);
+            }
+            return Ok((StatusCode::OK, Json(response)));
         }
     }
 
@@ -404,6 +455,11 @@ pub async fn get_file(
         ));
     }
     
+    // 会话导出文件：仅导出者本人可下载，且下载链接过期后失效
+    if file_name.starts_with("exports/") {
+        return get_export_file(&upload_settings, &pool, current_open_id, file_owner_open_id, &file_name).await;
+    }
+
     // 权限检查：允许文件所有者、好友或同群组成员访问
     if file_owner_open_id != current_open_id {
         let friendship_service = ImFriendshipService::new(pool.clone());
@@ -525,3 +581,80 @@ pub async fn get_file(
         }
     }
 }
+
+/// 下载会话导出文件（路径格式：{open_id}/exports/{export_id}.zip）
+async fn get_export_file(
+    upload_settings: &UploadSettings,
+    pool: &MySqlPool,
+    current_open_id: &str,
+    file_owner_open_id: &str,
+    file_name: &str,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if file_owner_open_id != current_open_id {
+        warn!(
+            current_open_id = %current_open_id,
+            file_owner_open_id = %file_owner_open_id,
+            "用户尝试下载他人的会话导出文件"
+        );
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(
+                ErrorCode::Forbidden,
+                "无权访问此文件",
+            )),
+        ));
+    }
+
+    let export_id = file_name
+        .trim_start_matches("exports/")
+        .trim_end_matches(".zip");
+    let job = match ImExportService::new(pool.clone()).get_job(export_id, current_open_id).await {
+        Ok(job) => job,
+        Err(_) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new(
+                    ErrorCode::NotFound,
+                    "文件不存在",
+                )),
+            ));
+        }
+    };
+
+    let expired = job.expire_time.is_some_and(|t| t <= now_timestamp());
+    if job.status != "DONE" || expired {
+        return Err((
+            StatusCode::GONE,
+            Json(ErrorResponse::new(
+                ErrorCode::NotFound,
+                "导出文件已过期或尚未生成",
+            )),
+        ));
+    }
+
+    let file_path = PathBuf::from(&upload_settings.path)
+        .join(file_owner_open_id)
+        .join(file_name);
+    match tokio::fs::read(&file_path).await {
+        Ok(file_data) => {
+            let response = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/zip")
+                .header("Content-Disposition", format!("attachment; filename=\"violet-export-{}.zip\"", export_id))
+                .header("Cache-Control", "private, no-store")
+                .body(Body::from(file_data))
+                .unwrap();
+            Ok(response)
+        }
+        Err(e) => {
+            error!(file_name = %file_name, error = %e, "读取导出文件失败");
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new(
+                    ErrorCode::NotFound,
+                    "文件不存在",
+                )),
+            ))
+        }
+    }
+}
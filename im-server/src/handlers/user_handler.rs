@@ -119,10 +119,9 @@ pub async fn get_current_user(
     match user_service.get_by_open_id(&user_identity.open_id).await {
         Ok(mut user) => {
             // 确保用户有 open_id
-            if user.open_id.is_none() {
-                if let Ok(open_id) = user_service.ensure_open_id(user.id).await {
-                    user.open_id = Some(open_id);
-                }
+            if user.open_id.is_none()
+                && let Ok(open_id) = user_service.ensure_open_id(user.id).await {
+                user.open_id = Some(open_id);
             }
             Ok(Json(user))
         },
@@ -131,10 +130,9 @@ pub async fn get_current_user(
             match user_service.get_by_id(user_identity.db_id).await {
                 Ok(mut user) => {
                     // 确保用户有 open_id
-                    if user.open_id.is_none() {
-                        if let Ok(open_id) = user_service.ensure_open_id(user.id).await {
-                            user.open_id = Some(open_id);
-                        }
+                    if user.open_id.is_none()
+                        && let Ok(open_id) = user_service.ensure_open_id(user.id).await {
+                        user.open_id = Some(open_id);
                     }
                     Ok(Json(user))
                 },
@@ -9,10 +9,9 @@ use crate::{
 use tracing::{error, info, warn};
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub struct WebRtcTokenRequest {
-    #[allow(dead_code)]
     pub stream: String,  // 流名称，通常是 user_id 或 room_id
-    #[allow(dead_code)]
     pub publish: Option<bool>,  // 是否为推流，默认为 false（拉流）
 }
 
@@ -318,14 +317,11 @@ async fn check_stream_exists(
                         
                         if let Some(streams_array) = streams {
                             for stream_obj in streams_array {
-                                if let Some(stream_name) = stream_obj.get("name").and_then(|v| v.as_str()) {
-                                    if stream_name == stream {
-                                        if let Some(app_name) = stream_obj.get("app").and_then(|v| v.as_str()) {
-                                            if app_name == app {
-                                                return Ok(true);
-                                            }
-                                        }
-                                    }
+                                if let Some(stream_name) = stream_obj.get("name").and_then(|v| v.as_str())
+                                    && stream_name == stream
+                                    && let Some(app_name) = stream_obj.get("app").and_then(|v| v.as_str())
+                                    && app_name == app {
+                                    return Ok(true);
                                 }
                             }
                         }
@@ -605,23 +601,21 @@ pub async fn check_stream_players(
                                                          (session_type.is_empty() && 
                                                           obj.get("publish").and_then(|v| v.as_bool()) == Some(false));
                                     
-                                    if is_play_session {
-                                        if let Some(sess_stream) = session_stream {
-                                            if sess_stream == stream_name {
-                                                if let Some(sess_app) = session_app {
-                                                    if sess_app == app_name {
-                                                        player_count += 1;
-                                                        info!(
-                                                            stream = %stream_name,
-                                                            session_id = ?obj.get("id"),
-                                                            "找到播放会话"
-                                                        );
-                                                    }
-                                                } else {
-                                                    // 如果没有 app 字段，也认为匹配（兼容性）
-                                                    player_count += 1;
-                                                }
+                                    if is_play_session
+                                        && let Some(sess_stream) = session_stream
+                                        && sess_stream == stream_name {
+                                        if let Some(sess_app) = session_app {
+                                            if sess_app == app_name {
+                                                player_count += 1;
+                                                info!(
+                                                    stream = %stream_name,
+                                                    session_id = ?obj.get("id"),
+                                                    "找到播放会话"
+                                                );
                                             }
+                                        } else {
+                                            // 如果没有 app 字段，也认为匹配（兼容性）
+                                            player_count += 1;
                                         }
                                     }
                                 }
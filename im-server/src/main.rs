@@ -16,12 +16,14 @@ mod mqtt;
 mod dto;
 mod config;
 mod redis;
+mod audio;
 
 use crate::{
     mqtt::MqttPublisher,
     config::AppConfig,
     service::SubscriptionService,
 };
+use im_share::{Shutdown, shutdown_signal};
 use std::sync::Arc;
 
 
@@ -70,6 +72,129 @@ async fn main() {
     // 创建订阅 ID 管理服务
     let subscription_service = Arc::new(SubscriptionService::new());
 
+    // 优雅退出：收到 SIGTERM 后停止接受新连接，后台循环不再开始新的一轮，
+    // 进行中的请求、导出任务和在线状态通知在 [shutdown] deadline_secs 内完成
+    let shutdown = Shutdown::new();
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            shutdown.trigger();
+        });
+    }
+
+    // 定期清理过期的会话导出文件
+    {
+        let pool = pool.clone();
+        let upload_path = cfg.upload.path.clone();
+        let interval_secs = cfg.export.cleanup_interval_secs.max(1);
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let service = crate::service::ImExportService::new(pool);
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.triggered() => break,
+                }
+                let _guard = shutdown.guard();
+                match service.cleanup_expired(&upload_path).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!(count = count, "已清理过期的会话导出文件"),
+                    Err(e) => tracing::warn!(error = ?e, "清理过期的会话导出文件失败"),
+                }
+            }
+        });
+    }
+
+    // 消息保留策略：定期把过期消息迁移到归档表
+    if cfg.retention.enabled {
+        let pool = pool.clone();
+        let retention = cfg.retention.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let service = crate::service::ImRetentionService::new(pool);
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(retention.interval_secs.max(1)));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.triggered() => break,
+                }
+                let _guard = shutdown.guard();
+                match service.enforce(&retention).await {
+                    Ok(report) if report.total() > 0 => tracing::info!(
+                        single_moved = report.single_moved,
+                        group_moved = report.group_moved,
+                        group_overrides = ?report.group_overrides_moved,
+                        total = report.total(),
+                        "消息归档完成"
+                    ),
+                    Ok(_) => tracing::debug!("消息归档完成，没有需要迁移的消息"),
+                    Err(e) => tracing::warn!(error = ?e, "消息归档失败"),
+                }
+            }
+        });
+        tracing::info!(
+            single_days = cfg.retention.single_days,
+            group_days = cfg.retention.group_days,
+            group_overrides = cfg.retention.groups.len(),
+            "消息保留策略已启用"
+        );
+    }
+
+    // 在线状态变化：im-connect 在设备上线/下线/切换状态时发布通知，
+    // 这里去抖后把最终状态推送给好友（以及可选的同群成员）
+    {
+        let pool = pool.clone();
+        let redis_client = redis_client.clone();
+        let publisher = publisher.clone();
+        let settings = cfg.presence.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut rx = match publisher.subscribe(im_share::presence::PRESENCE_CHANGED_TOPIC).await {
+                Ok(rx) => rx,
+                Err(e) => {
+                    tracing::error!(error = %e, "订阅在线状态变化通知失败，好友将收不到在线状态推送");
+                    return;
+                }
+            };
+            let service = Arc::new(crate::service::PresenceService::new(pool, redis_client));
+            loop {
+                let received = tokio::select! {
+                    received = rx.recv() => received,
+                    _ = shutdown.triggered() => break,
+                };
+                let msg = match received {
+                    Ok(msg) => msg,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped = skipped, "在线状态变化通知积压，部分通知被跳过");
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                if msg.topic != im_share::presence::PRESENCE_CHANGED_TOPIC {
+                    continue;
+                }
+                let Ok(changed) = serde_json::from_slice::<im_share::PresenceChanged>(&msg.payload) else {
+                    continue;
+                };
+                if !service.begin_notify_debounce(&changed.open_id, settings.notify_debounce_ms).await {
+                    continue;
+                }
+                let service = service.clone();
+                let publisher = publisher.clone();
+                let settings = settings.clone();
+                // 去抖窗口已经占用，退出前仍要把最终状态推送出去
+                let guard = shutdown.guard();
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    tokio::time::sleep(std::time::Duration::from_millis(settings.notify_debounce_ms)).await;
+                    service.notify_if_changed(&publisher, &changed.open_id, &settings).await;
+                });
+            }
+        });
+    }
+
     // 创建路由
     let public_routes = crate::routes::create_public_routes(
         pool.clone(), 
@@ -77,21 +202,34 @@ async fn main() {
         cfg.upload.clone(),
         subscription_service.clone(),
         redis_client.clone(),
+        publisher.clone(),
+        cfg.device_policy.clone(),
     );
     let protected_routes = crate::routes::create_protected_routes(
         pool.clone(), 
-        cfg.jwt.clone(),
-        cfg.upload.clone(),
-        cfg.srs.clone(),
-        publisher,
+        &cfg,
+        publisher.clone(),
         subscription_service.clone(),
         redis_client.clone(),
+        shutdown.clone(),
     );
+    let internal_routes = crate::routes::create_internal_routes(
+        pool.clone(),
+        &cfg,
+        publisher.clone(),
+        subscription_service.clone(),
+        redis_client.clone(),
+        shutdown.clone(),
+    );
+    if cfg.internal.token.as_deref().is_none_or(str::is_empty) {
+        tracing::warn!("未配置 [internal] token，im-connect 无法转发客户端通过长连接发送的命令");
+    }
 
     // 组装应用，先合并路由再添加 /api 前缀
     // 注意：public_routes 必须在 protected_routes 之前，避免认证中间件拦截公开路由
     let app = Router::new()
         .nest("/api", public_routes.merge(protected_routes))
+        .nest("/internal", internal_routes)
         .layer(TraceLayer::new_for_http())
         .layer(RequestBodyLimitLayer::new(2 * 1024 * 1024)) // 允许最大 2MB 的请求体
         .layer(
@@ -109,7 +247,25 @@ async fn main() {
     
     tracing::info!("API 运行在 http://{}", addr);
 
+    let graceful = {
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    };
     axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(graceful)
         .await
         .unwrap();
+
+    // 已停止接受新连接且进行中的请求都已完成，等待后台任务
+    let deadline = std::time::Duration::from_secs(cfg.shutdown.deadline_secs);
+    if shutdown.wait_idle(deadline).await {
+        tracing::info!("后台任务已全部完成");
+    } else {
+        tracing::warn!(remaining = shutdown.active(), deadline_secs = cfg.shutdown.deadline_secs, "等待后台任务超时，强制退出");
+    }
+    // 断开 MQTT 前把已入队的发布写出到 broker
+    if let Err(e) = publisher.disconnect().await {
+        tracing::warn!(error = %e, "断开 MQTT 连接失败");
+    }
+    tracing::info!("im-server 已退出");
 }
\ No newline at end of file
@@ -11,8 +11,10 @@ use crate::{
     service::UserService,
     error::ErrorCode,
     redis::RedisClient,
+    config::InternalSettings,
 };
-use im_share::{verify_token, JwtSettings};
+use im_share::{verify_token, is_session_revoked, internal_token_matches, JwtSettings};
+use im_share::auth::{ACTING_USER_HEADER, INTERNAL_TOKEN_HEADER};
 
 /// 用户标识信息（用于在请求扩展中传递）
 #[derive(Clone, Debug)]
@@ -21,6 +23,8 @@ pub struct UserIdentity {
     pub db_id: u64,
     /// Open ID（用于外部标识，唯一标识符）
     pub open_id: String,
+    /// 登录会话（subscription_id），旧版 token 没有
+    pub session_id: Option<String>,
 }
 
 impl UserIdentity {
@@ -50,13 +54,8 @@ pub async fn auth_middleware(
         .headers()
         .get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
-        .and_then(|s| {
-            if s.starts_with("Bearer ") {
-                Some(s[7..].to_string())
-            } else {
-                None
-            }
-        });
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .map(|s| s.to_string());
 
     let token = match token {
         Some(t) => t,
@@ -85,6 +84,18 @@ pub async fn auth_middleware(
     let claims = verify_token(&token, &jwt_cfg)
         .map_err(|_| StatusCode::UNAUTHORIZED)?;
 
+    // 会话被挤下线或被远程踢出后，token 立即失效（Redis 不可用时跳过检查）
+    if let (Some(sid), Some(redis)) = (claims.sid.as_deref(), redis_client.as_ref()) {
+        match is_session_revoked(redis, sid).await {
+            Ok(true) => {
+                warn!(subscription_id = %sid, "会话已被吊销，拒绝请求");
+                return Err(StatusCode::UNAUTHORIZED);
+            },
+            Ok(false) => {},
+            Err(e) => warn!(subscription_id = %sid, error = %e, "检查会话吊销状态失败"),
+        }
+    }
+
     // 根据 token 中的 user_id 类型，查询用户信息
     // 如果 Redis 可用，使用带缓存的 UserService
     let user_service = if let Some(redis) = redis_client {
@@ -142,8 +153,9 @@ pub async fn auth_middleware(
     };
     
     // 确保用户有 open_id
-    let open_id = if user.open_id.is_none() {
-        match user_service.ensure_open_id(user.id).await {
+    let open_id = match user.open_id {
+        Some(open_id) => open_id,
+        None => match user_service.ensure_open_id(user.id).await {
             Ok(oid) => {
                 warn!(user_id = %user.id, open_id = %oid, "为用户生成 open_id（在认证中间件中）");
                 oid
@@ -152,15 +164,14 @@ pub async fn auth_middleware(
                 error!(user_id = %user.id, "生成 open_id 失败");
                 return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
-        }
-    } else {
-        user.open_id.unwrap()
+        },
     };
     
     // 创建用户标识信息
     let user_identity = UserIdentity {
         db_id: user.id,
         open_id,
+        session_id: claims.sid,
     };
     
     // 将用户标识信息添加到请求扩展中，供后续处理程序使用
@@ -171,3 +182,65 @@ pub async fn auth_middleware(
     Ok(next.run(request).await)
 }
 
+
+/// 内部接口认证：im-connect 以共享密钥证明身份，并通过请求头指明代为操作的用户（open_id）
+/// 不依赖用户的 JWT，token 过期后已经建立的长连接仍然可以发送命令；
+/// 注入与 auth_middleware 相同的用户标识，处理程序不需要区分调用方
+pub async fn internal_auth_middleware(
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let internal_cfg = request
+        .extensions()
+        .get::<InternalSettings>()
+        .cloned()
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(expected) = internal_cfg.token.as_deref().filter(|t| !t.is_empty()) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let headers = request.headers();
+    let provided = headers
+        .get(INTERNAL_TOKEN_HEADER)
+        .and_then(|h| h.to_str().ok());
+    if !provided.is_some_and(|token| internal_token_matches(expected, token)) {
+        warn!(path = %request.uri().path(), "内部接口鉴权失败");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let open_id = headers
+        .get(ACTING_USER_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let pool = request
+        .extensions()
+        .get::<MySqlPool>()
+        .cloned()
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user_service = match request.extensions().get::<Arc<RedisClient>>().cloned() {
+        Some(redis) => UserService::with_redis(pool, redis),
+        None => UserService::new(pool),
+    };
+    let user = match user_service.get_by_open_id(&open_id).await {
+        Ok(u) => u,
+        Err(ErrorCode::NotFound) => {
+            warn!(open_id = %open_id, "内部接口代为操作的用户不存在");
+            return Err(StatusCode::UNAUTHORIZED);
+        },
+        Err(_) => {
+            error!(open_id = %open_id, "查询用户失败");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let user_identity = UserIdentity {
+        db_id: user.id,
+        open_id,
+        session_id: None,
+    };
+    request.extensions_mut().insert(user_identity.clone());
+    request.extensions_mut().insert(user_identity.db_id);
+
+    Ok(next.run(request).await)
+}
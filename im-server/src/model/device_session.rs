@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// 登录会话（subscriptions 表中的一条记录，对应一台设备）
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DeviceSession {
+    pub subscription_id: String,
+    /// 设备类型（mobile / desktop / web 等），旧版客户端登录时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<String>,
+    /// 客户端上报的设备描述（型号、系统、浏览器等）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_info: Option<String>,
+    /// 登录时间（毫秒）
+    pub created_at: i64,
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// 会话导出任务
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ImExportJob {
+    pub export_id: String,
+    pub owner_id: String,
+    /// 导出范围：0全部会话，1单聊，2群聊
+    pub scope_type: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_id: Option<String>,
+    /// 导出格式，逗号分隔
+    pub formats: String,
+    /// 任务状态：PENDING / RUNNING / DONE / FAILED / EXPIRED
+    pub status: String,
+    #[serde(skip_serializing)]
+    pub file_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_count: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub create_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<i64>,
+}
+
+impl ImExportJob {
+    /// 下载地址（通过上传文件路由提供，仅在任务完成后可用）
+    pub fn download_url(&self) -> Option<String> {
+        if self.status != "DONE" {
+            return None;
+        }
+        self.file_path.as_ref().map(|p| format!("/api/upload/{}", p))
+    }
+}
@@ -10,6 +10,10 @@ pub struct ImSingleMessage {
     pub message_time: i64,
     pub message_content_type: i32,
     pub read_status: i32,
+    /// 语音消息播放状态（1已播放）
+    #[serde(default)]
+    #[sqlx(default)]
+    pub played_status: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra: Option<String>,
     pub del_flag: i16,
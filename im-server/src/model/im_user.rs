@@ -42,6 +42,10 @@ pub struct ImUserData {
     pub silent_flag: i32,
     pub user_type: i32,
     pub del_flag: i16,
+    /// 最后在线时间可见范围，见 LastSeenVisibility
+    #[serde(default)]
+    #[sqlx(default)]
+    pub last_seen_visibility: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -52,3 +56,32 @@ pub struct ImUserData {
     pub version: Option<i64>,
 }
 
+/// 最后在线时间可见范围（im_user_data.last_seen_visibility）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LastSeenVisibility {
+    /// 所有人可见
+    Everyone,
+    /// 仅好友可见
+    Contacts,
+    /// 所有人不可见
+    Nobody,
+}
+
+impl LastSeenVisibility {
+    pub fn from_i32(value: i32) -> Self {
+        match value {
+            1 => Self::Contacts,
+            2 => Self::Nobody,
+            _ => Self::Everyone,
+        }
+    }
+
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Self::Everyone => 0,
+            Self::Contacts => 1,
+            Self::Nobody => 2,
+        }
+    }
+}
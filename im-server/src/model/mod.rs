@@ -2,7 +2,7 @@ pub mod user;
 pub use user::User;
 
 pub mod im_user;
-pub use im_user::{ImUser, ImUserData};
+pub use im_user::{ImUser, ImUserData, LastSeenVisibility};
 
 pub mod im_friendship;
 pub use im_friendship::{ImFriendship, ImFriendshipRequest};
@@ -16,4 +16,10 @@ pub use im_message::{ImSingleMessage, ImGroupMessage, ImGroupMessageStatus, ImOu
 pub mod im_group;
 pub use im_group::{ImGroup, ImGroupMember};
 
+pub mod im_export;
+pub use im_export::ImExportJob;
+
+pub mod device_session;
+pub use device_session::DeviceSession;
+
 pub mod id_meta_info;
\ No newline at end of file
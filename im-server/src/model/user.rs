@@ -50,10 +50,9 @@ impl User {
     /// 内部服务使用，不对外暴露
     pub fn get_mqtt_id(&self) -> u64 {
         // 如果 open_id 是数字字符串（雪花算法生成的），解析它
-        if let Some(ref open_id) = self.open_id {
-            if let Ok(id) = open_id.parse::<u64>() {
-                return id;
-            }
+        if let Some(ref open_id) = self.open_id
+            && let Ok(id) = open_id.parse::<u64>() {
+            return id;
         }
         // 否则使用数据库 id
         self.id
@@ -65,10 +64,9 @@ impl User {
     pub fn get_external_id(&self) -> String {
         // 只使用 open_id，不再使用用户名、手机号或数据库ID
         // 如果 open_id 不存在或为空，返回错误（这种情况不应该发生）
-        if let Some(ref open_id) = self.open_id {
-            if !open_id.is_empty() {
-                return open_id.clone();
-            }
+        if let Some(ref open_id) = self.open_id
+            && !open_id.is_empty() {
+            return open_id.clone();
         }
         // 如果没有 open_id，这是一个错误情况（应该在认证中间件中已经确保）
         // 为了向后兼容，暂时使用 id，但应该尽快修复数据
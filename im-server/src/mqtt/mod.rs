@@ -1,5 +1,16 @@
 use std::sync::Arc;
-use im_share::{ImMqtt, MqttConfig};
+use im_share::{ImMqtt, MqttConfig, IncomingMessage, NodeDelivery, RouteRegistry, node_topic};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// 投递方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// 路由表中有用户的连接，已直接发布到所在节点的 topic（用户确定在线）
+    Routed { nodes: usize },
+    /// 路由表中没有连接（用户离线）或查询失败，发布到用户 topic，由订阅了该 topic 的节点转发
+    Broadcast,
+}
 
 #[derive(Clone)]
 pub struct MqttPublisher(Arc<ImMqtt>);
@@ -13,5 +24,58 @@ impl MqttPublisher {
     pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()> {
         self.0.publish(topic, payload).await
     }
-}
 
+    /// 向用户投递消息：先查跨节点路由表，用户有连接时直接发布到所在节点的 topic，否则发布到用户 topic
+    /// 发布到所有节点，只要有一个节点成功就视为已投递（失败节点的路由条目被移除）；全部失败时返回错误，由调用方转存离线消息
+    pub async fn deliver(&self, routes: &RouteRegistry, open_id: &str, topic: &str, payload: Vec<u8>) -> anyhow::Result<Delivery> {
+        let nodes = match routes.lookup(open_id).await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                warn!(open_id = %open_id, error = %e, "查询跨节点路由表失败，发布到用户 topic");
+                Vec::new()
+            }
+        };
+        if nodes.is_empty() {
+            self.publish(topic, payload).await?;
+            return Ok(Delivery::Broadcast);
+        }
+        let delivery = NodeDelivery {
+            topic: topic.to_string(),
+            payload: String::from_utf8(payload)?,
+        };
+        let envelope = serde_json::to_vec(&delivery)?;
+        let mut delivered = 0;
+        let mut last_error = None;
+        for node in &nodes {
+            match self.publish(&node_topic(&node.node_id), envelope.clone()).await {
+                Ok(()) => delivered += 1,
+                Err(e) => {
+                    warn!(open_id = %open_id, node_id = %node.node_id, error = %e, "发布到节点 topic 失败，移除该节点的路由");
+                    if let Err(e) = routes.unregister_node(open_id, node).await {
+                        warn!(open_id = %open_id, node_id = %node.node_id, error = %e, "移除节点路由失败");
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if delivered == 0 => Err(e),
+            _ => Ok(Delivery::Routed { nodes: delivered }),
+        }
+    }
+
+    /// 发布不需要离线存储的临时消息（QoS 0）
+    pub async fn publish_transient(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.0.publish_transient(topic, payload).await
+    }
+
+    /// 订阅 topic（同一个 MQTT 连接上的所有订阅共用一个接收通道，调用方需要按 topic 过滤）
+    pub async fn subscribe(&self, topic: &str) -> anyhow::Result<broadcast::Receiver<IncomingMessage>> {
+        self.0.subscribe(topic).await
+    }
+
+    /// 退出前断开连接，已入队的发布会先写出到 broker
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        self.0.disconnect().await
+    }
+}
@@ -3,17 +3,17 @@ use sqlx::MySqlPool;
 use std::sync::Arc;
 use crate::{
     handlers::{
-        user_handler, auth_handler, message_handler, subscription_handler, friend_handler,
+        user_handler, auth_handler, message_handler, friend_handler,
         im_user_handler, im_friendship_handler, im_message_handler, im_chat_handler, im_group_handler,
-        im_outbox_handler, upload_handler, webrtc_handler,
+        im_outbox_handler, im_export_handler, im_typing_handler, im_presence_handler, device_handler, upload_handler, webrtc_handler,
     },
-    middleware::auth::auth_middleware,
+    middleware::auth::{auth_middleware, internal_auth_middleware},
     mqtt::MqttPublisher,
-    config::{UploadSettings, SrsSettings},
+    config::{AppConfig, UploadSettings, DevicePolicySettings},
     service::SubscriptionService,
     redis::RedisClient,
 };
-use im_share::JwtSettings;
+use im_share::{JwtSettings, Shutdown};
 
 /// 路由信息结构
 #[derive(Debug, Clone)]
@@ -24,6 +24,7 @@ pub struct RouteInfo {
 }
 
 /// 获取所有路由信息
+#[allow(clippy::vec_init_then_push)]
 pub fn get_all_routes() -> Vec<RouteInfo> {
     let mut routes = Vec::new();
     
@@ -43,11 +44,6 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/users".to_string(),
         auth_required: false,
     });
-    routes.push(RouteInfo {
-        method: "GET".to_string(),
-        path: "/api/subscriptions/{subscription_id}/user".to_string(),
-        auth_required: false,
-    });
     routes.push(RouteInfo {
         method: "GET".to_string(),
         path: "/api/users/check-name/{name}".to_string(),
@@ -175,6 +171,11 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/im/friendship-requests".to_string(),
         auth_required: true,
     });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/im/friendship-requests/card".to_string(),
+        auth_required: true,
+    });
     routes.push(RouteInfo {
         method: "POST".to_string(),
         path: "/api/im/friendship-requests/{request_id}".to_string(),
@@ -200,6 +201,11 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/im/messages/single/{message_id}/read".to_string(),
         auth_required: true,
     });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/im/messages/single/{message_id}/played".to_string(),
+        auth_required: true,
+    });
     routes.push(RouteInfo {
         method: "POST".to_string(),
         path: "/api/im/messages/group".to_string(),
@@ -215,6 +221,16 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/im/messages/group/{group_id}/{message_id}/read".to_string(),
         auth_required: true,
     });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/im/messages/group/{group_id}/{message_id}/played".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/im/messages/group/{group_id}/{message_id}/played".to_string(),
+        auth_required: true,
+    });
     routes.push(RouteInfo {
         method: "GET".to_string(),
         path: "/api/im/messages/group/{group_id}/{message_id}/status".to_string(),
@@ -225,6 +241,41 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/im/messages/group/{group_id}/status".to_string(),
         auth_required: true,
     });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/im/typing".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/ws-ticket".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/devices".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "DELETE".to_string(),
+        path: "/api/devices/{subscription_id}".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/im/presence".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/im/presence/privacy".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "PUT".to_string(),
+        path: "/api/im/presence/privacy".to_string(),
+        auth_required: true,
+    });
     routes.push(RouteInfo {
         method: "GET".to_string(),
         path: "/api/im/chats".to_string(),
@@ -335,6 +386,21 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         path: "/api/im/outbox/{id}/sent".to_string(),
         auth_required: true,
     });
+    routes.push(RouteInfo {
+        method: "POST".to_string(),
+        path: "/api/im/exports".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/im/exports".to_string(),
+        auth_required: true,
+    });
+    routes.push(RouteInfo {
+        method: "GET".to_string(),
+        path: "/api/im/exports/{export_id}".to_string(),
+        auth_required: true,
+    });
     routes.push(RouteInfo {
         method: "POST".to_string(),
         path: "/api/upload".to_string(),
@@ -366,6 +432,21 @@ pub fn get_all_routes() -> Vec<RouteInfo> {
         auth_required: true,
     });
     
+    // 内部接口（im-connect 以共享密钥调用，nginx 不代理）
+    for (method, path) in [
+        ("POST", "/internal/im/messages/single"),
+        ("POST", "/internal/im/messages/single/{message_id}/read"),
+        ("POST", "/internal/im/messages/group"),
+        ("POST", "/internal/im/messages/group/{group_id}/{message_id}/read"),
+        ("POST", "/internal/im/typing"),
+    ] {
+        routes.push(RouteInfo {
+            method: method.to_string(),
+            path: path.to_string(),
+            auth_required: true,
+        });
+    }
+    
     routes
 }
 
@@ -423,12 +504,13 @@ pub fn create_public_routes(
     upload_settings: UploadSettings,
     subscription_service: Arc<SubscriptionService>,
     redis_client: Arc<RedisClient>,
+    publisher: MqttPublisher,
+    device_policy: DevicePolicySettings,
 ) -> Router {
     Router::new()
         .route("/auth/login", axum::routing::post(auth_handler::login))
         .route("/auth/register", axum::routing::post(user_handler::create_user))
         .route("/users", axum::routing::post(user_handler::create_user)) // 兼容前端
-        .route("/subscriptions/{subscription_id}/user", axum::routing::get(subscription_handler::get_user_id_by_subscription))
         // 公开API：检查昵称是否可用（不需要认证）
         .route("/users/check-name/{name}", axum::routing::get(user_handler::check_name_available))
         // 内部服务API：根据用户ID获取用户名（不需要认证）
@@ -445,17 +527,19 @@ pub fn create_public_routes(
         .layer(Extension(jwt_cfg))
         .layer(Extension(upload_settings))
         .layer(Extension(redis_client))
+        .layer(Extension(publisher))
+        .layer(Extension(device_policy))
         .with_state(subscription_service)
 }
 
+/// 各功能的配置（jwt、upload、srs、export、link_preview）从 config 中取出，作为 Extension 提供给处理函数
 pub fn create_protected_routes(
     pool: MySqlPool,
-    jwt_cfg: JwtSettings,
-    upload_settings: UploadSettings,
-    srs_settings: SrsSettings,
+    config: &AppConfig,
     publisher: MqttPublisher,
     subscription_service: Arc<SubscriptionService>,
     redis_client: Arc<RedisClient>,
+    shutdown: Shutdown,
 ) -> Router {
     Router::new()
         // 原有路由
@@ -478,6 +562,7 @@ pub fn create_protected_routes(
         .route("/im/friends/{to_id}/black", axum::routing::post(im_friendship_handler::black_friend))
         .route("/im/friendship-requests", axum::routing::get(im_friendship_handler::get_friendship_requests))
         .route("/im/friendship-requests", axum::routing::post(im_friendship_handler::create_friendship_request))
+        .route("/im/friendship-requests/card", axum::routing::post(im_friendship_handler::create_card_friendship_request))
         .route("/im/friendship-requests/{request_id}", axum::routing::post(im_friendship_handler::handle_friendship_request))
         // 调试接口：查看好友关系数据
         .route("/im/friends/debug", axum::routing::get(im_friendship_handler::debug_friendship_data))
@@ -485,11 +570,25 @@ pub fn create_protected_routes(
         .route("/im/messages/single", axum::routing::post(im_message_handler::send_single_message))
         .route("/im/messages/single", axum::routing::get(im_message_handler::get_single_messages))
         .route("/im/messages/single/{message_id}/read", axum::routing::post(im_message_handler::mark_single_message_read))
+        .route("/im/messages/single/{message_id}/played", axum::routing::post(im_message_handler::mark_single_message_played))
         .route("/im/messages/group", axum::routing::post(im_message_handler::send_group_message))
         .route("/im/messages/group/{group_id}", axum::routing::get(im_message_handler::get_group_messages))
         .route("/im/messages/group/{group_id}/{message_id}/read", axum::routing::post(im_message_handler::mark_group_message_read))
+        .route("/im/messages/group/{group_id}/{message_id}/played", axum::routing::post(im_message_handler::mark_group_message_played))
+        .route("/im/messages/group/{group_id}/{message_id}/played", axum::routing::get(im_message_handler::get_group_message_played))
         .route("/im/messages/group/{group_id}/{message_id}/status", axum::routing::get(im_message_handler::get_group_message_status))
         .route("/im/messages/group/{group_id}/status", axum::routing::get(im_message_handler::get_user_group_message_status))
+        // 正在输入状态（不落库，仅推送）
+        .route("/im/typing", axum::routing::post(im_typing_handler::send_typing))
+        // WebSocket 一次性票据（浏览器客户端握手使用）
+        .route("/ws-ticket", axum::routing::post(auth_handler::create_ws_ticket))
+        // 登录设备管理（查看设备、远程踢出）
+        .route("/devices", axum::routing::get(device_handler::list_devices))
+        .route("/devices/{subscription_id}", axum::routing::delete(device_handler::kick_device))
+        // 在线状态（Redis，由 im-connect 维护）
+        .route("/im/presence", axum::routing::get(im_presence_handler::get_presence))
+        .route("/im/presence/privacy", axum::routing::get(im_presence_handler::get_presence_privacy))
+        .route("/im/presence/privacy", axum::routing::put(im_presence_handler::update_presence_privacy))
         // IM 聊天会话相关路由
         .route("/im/chats", axum::routing::get(im_chat_handler::get_user_chats))
         .route("/im/chats", axum::routing::post(im_chat_handler::get_or_create_chat))
@@ -517,6 +616,10 @@ pub fn create_protected_routes(
         .route("/im/outbox/{id}", axum::routing::get(im_outbox_handler::get_outbox))
         .route("/im/outbox/{id}/status", axum::routing::put(im_outbox_handler::update_outbox_status))
         .route("/im/outbox/{id}/sent", axum::routing::post(im_outbox_handler::mark_sent))
+        // 会话导出路由（后台任务，导出文件通过上传文件路由下载）
+        .route("/im/exports", axum::routing::post(im_export_handler::create_export))
+        .route("/im/exports", axum::routing::get(im_export_handler::get_exports))
+        .route("/im/exports/{export_id}", axum::routing::get(im_export_handler::get_export))
         // 文件上传路由（需要认证）
         .route("/upload", axum::routing::post(upload_handler::upload_file))
         // 文件下载路由（需要认证，验证文件所有权）
@@ -533,10 +636,38 @@ pub fn create_protected_routes(
         .route("/webrtc/stream/players", axum::routing::get(webrtc_handler::check_stream_players))
         .layer(middleware::from_fn(auth_middleware))
         .layer(Extension(pool))
-        .layer(Extension(jwt_cfg))
-        .layer(Extension(upload_settings))
-        .layer(Extension(srs_settings))
+        .layer(Extension(config.jwt.clone()))
+        .layer(Extension(config.upload.clone()))
+        .layer(Extension(config.srs.clone()))
+        .layer(Extension(config.export.clone()))
+        .layer(Extension(config.link_preview.clone()))
         .layer(Extension(redis_client))
+        .layer(Extension(shutdown))
         .with_state((publisher, subscription_service))
 }
 
+/// 内部接口：im-connect 转发客户端通过长连接发送的命令，以 [internal] token 认证并指明代为操作的用户
+/// 与对应的 /api 路由使用同一组处理程序；挂在 /internal 下，nginx 只代理 /api/*，不对外暴露
+pub fn create_internal_routes(
+    pool: MySqlPool,
+    config: &AppConfig,
+    publisher: MqttPublisher,
+    subscription_service: Arc<SubscriptionService>,
+    redis_client: Arc<RedisClient>,
+    shutdown: Shutdown,
+) -> Router {
+    Router::new()
+        .route("/im/messages/single", axum::routing::post(im_message_handler::send_single_message))
+        .route("/im/messages/single/{message_id}/read", axum::routing::post(im_message_handler::mark_single_message_read))
+        .route("/im/messages/group", axum::routing::post(im_message_handler::send_group_message))
+        .route("/im/messages/group/{group_id}/{message_id}/read", axum::routing::post(im_message_handler::mark_group_message_read))
+        .route("/im/typing", axum::routing::post(im_typing_handler::send_typing))
+        .layer(middleware::from_fn(internal_auth_middleware))
+        .layer(Extension(pool))
+        .layer(Extension(config.internal.clone()))
+        .layer(Extension(config.upload.clone()))
+        .layer(Extension(config.link_preview.clone()))
+        .layer(Extension(redis_client))
+        .layer(Extension(shutdown))
+        .with_state((publisher, subscription_service))
+}
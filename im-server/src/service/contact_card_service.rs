@@ -0,0 +1,91 @@
+use crate::service::{ImUserService, UserService};
+use crate::service::im_message_service::CARD_MESSAGE_CONTENT_TYPE;
+use im_share::{ContactCard, RedisClient};
+use sqlx::MySqlPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 名片消息解析：extra 中只保存 {"card": {"open_id": ...}}，
+/// 名称和头像在读取时按对方当前资料解析，并遵守对方的隐私设置
+pub struct ContactCardService {
+    user_service: UserService,
+    im_user_service: ImUserService,
+}
+
+impl ContactCardService {
+    pub fn new(pool: MySqlPool, redis: Arc<RedisClient>) -> Self {
+        Self {
+            user_service: UserService::with_redis(pool.clone(), redis.clone()),
+            im_user_service: ImUserService::with_redis(pool, redis),
+        }
+    }
+
+    /// 解析名片引用的用户当前资料
+    /// - 用户不存在、已停用或被封禁：available = false，不返回名称和头像
+    /// - 管理员禁止添加好友：can_add_friend = false
+    /// - 加好友验证类型为需要验证：need_verification = true
+    pub async fn resolve(&self, open_id: &str) -> ContactCard {
+        let unavailable = ContactCard {
+            open_id: open_id.to_string(),
+            ..Default::default()
+        };
+
+        let Ok(user) = self.user_service.get_by_open_id(open_id).await else {
+            return unavailable;
+        };
+        // 没有 im_user_data 的用户按默认设置处理（无需验证、允许添加）
+        let user_data = self.im_user_service.get_user_data(open_id).await.ok();
+        if user_data.as_ref().is_some_and(|data| data.forbidden_flag == 1) {
+            return unavailable;
+        }
+
+        ContactCard {
+            open_id: open_id.to_string(),
+            name: Some(user.name),
+            avatar: user.file_name,
+            available: true,
+            can_add_friend: user_data.as_ref().is_none_or(|data| data.disable_add_friend == 0),
+            need_verification: user_data.as_ref().is_some_and(|data| data.friend_allow_type == 2),
+        }
+    }
+
+    /// 为消息列表中的名片消息填充名片资料（同一个 open_id 只解析一次）
+    pub async fn fill_cards(&self, messages: Vec<(i32, &mut Option<String>)>) {
+        let mut resolved: HashMap<String, ContactCard> = HashMap::new();
+        for (message_content_type, extra) in messages {
+            if message_content_type != CARD_MESSAGE_CONTENT_TYPE {
+                continue;
+            }
+            let Some(mut extra_json) = extra
+                .as_deref()
+                .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+            else {
+                continue;
+            };
+            let Some(open_id) = card_open_id(&extra_json) else {
+                continue;
+            };
+            let card = match resolved.get(&open_id) {
+                Some(card) => card.clone(),
+                None => {
+                    let card = self.resolve(&open_id).await;
+                    resolved.insert(open_id, card.clone());
+                    card
+                }
+            };
+            extra_json["card"] = serde_json::json!(card);
+            *extra = Some(extra_json.to_string());
+        }
+    }
+}
+
+/// 从 extra 中读取名片引用的 open_id
+pub fn card_open_id(extra: &serde_json::Value) -> Option<String> {
+    extra
+        .get("card")
+        .and_then(|card| card.get("open_id"))
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
@@ -0,0 +1,166 @@
+use crate::error::{ErrorCode, Result};
+use crate::model::DeviceSession;
+use crate::mqtt::MqttPublisher;
+use im_share::{KickReason, KickedEvent, RedisClient, mqtt_user_topic, revoke_session};
+use sqlx::MySqlPool;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// 设备类型最大长度
+pub const MAX_DEVICE_TYPE_LEN: usize = 32;
+/// 设备描述最大长度（与 subscriptions.device_info 列一致）
+pub const MAX_DEVICE_INFO_LEN: usize = 255;
+
+const SESSION_COLUMNS: &str = "subscription_id, device_type, device_info, \
+     COALESCE(CAST(UNIX_TIMESTAMP(created_at) AS SIGNED), 0) * 1000 AS created_at";
+
+/// 规范化客户端上报的设备类型（小写，只允许字母、数字、- 和 _），不合法时返回 None
+pub fn normalize_device_type(device_type: &str) -> Option<String> {
+    let device_type = device_type.trim().to_ascii_lowercase();
+    let valid = !device_type.is_empty()
+        && device_type.len() <= MAX_DEVICE_TYPE_LEN
+        && device_type.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(device_type)
+}
+
+/// 登录会话（设备）管理：带设备类型的登录、多设备策略、远程踢出
+pub struct DeviceSessionService {
+    pool: MySqlPool,
+    redis: Arc<RedisClient>,
+}
+
+impl DeviceSessionService {
+    pub fn new(pool: MySqlPool, redis: Arc<RedisClient>) -> Self {
+        Self { pool, redis }
+    }
+
+    /// 为一次带设备类型的登录创建新会话，返回 subscription_id
+    pub async fn create_session(&self, user_id: u64, device_type: &str, device_info: Option<&str>) -> Result<String> {
+        let subscription_id = format!("sub_{}", uuid::Uuid::new_v4().simple());
+        let device_info: Option<String> = device_info.map(|info| info.chars().take(MAX_DEVICE_INFO_LEN).collect());
+        sqlx::query(
+            "INSERT INTO subscriptions (subscription_id, user_id, device_type, device_info) VALUES (?, ?, ?, ?)"
+        )
+        .bind(&subscription_id)
+        .bind(user_id)
+        .bind(device_type)
+        .bind(&device_info)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id = %user_id, device_type = %device_type, error = %e, "创建登录会话失败");
+            ErrorCode::Database
+        })?;
+        Ok(subscription_id)
+    }
+
+    /// 用户的全部登录会话，最近登录的在前
+    pub async fn list_sessions(&self, user_id: u64) -> Result<Vec<DeviceSession>> {
+        let sql = format!(
+            "SELECT {} FROM subscriptions WHERE user_id = ? ORDER BY created_at DESC, id DESC",
+            SESSION_COLUMNS
+        );
+        sqlx::query_as::<_, DeviceSession>(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| {
+                error!(user_id = %user_id, error = %e, "查询登录会话失败");
+                ErrorCode::Database
+            })
+    }
+
+    pub async fn get_session(&self, user_id: u64, subscription_id: &str) -> Result<Option<DeviceSession>> {
+        let sql = format!(
+            "SELECT {} FROM subscriptions WHERE user_id = ? AND subscription_id = ?",
+            SESSION_COLUMNS
+        );
+        sqlx::query_as::<_, DeviceSession>(&sql)
+            .bind(user_id)
+            .bind(subscription_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| {
+                error!(user_id = %user_id, subscription_id = %subscription_id, error = %e, "查询登录会话失败");
+                ErrorCode::Database
+            })
+    }
+
+    /// 执行多设备策略：同类设备只保留最近登录的 limit 个会话，其余会话被挤下线
+    /// 返回被挤下线的会话
+    pub async fn enforce_limit(
+        &self,
+        publisher: &MqttPublisher,
+        user_id: u64,
+        open_id: &str,
+        device_type: &str,
+        limit: u32,
+        token_ttl_secs: u64,
+    ) -> Result<Vec<DeviceSession>> {
+        let sql = format!(
+            "SELECT {} FROM subscriptions WHERE user_id = ? AND device_type = ? ORDER BY created_at DESC, id DESC",
+            SESSION_COLUMNS
+        );
+        let sessions = sqlx::query_as::<_, DeviceSession>(&sql)
+            .bind(user_id)
+            .bind(device_type)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| {
+                error!(user_id = %user_id, device_type = %device_type, error = %e, "查询同类设备会话失败");
+                ErrorCode::Database
+            })?;
+
+        let evicted: Vec<DeviceSession> = sessions.into_iter().skip(limit.max(1) as usize).collect();
+        for session in &evicted {
+            self.revoke(publisher, open_id, session, KickReason::Replaced, token_ttl_secs).await?;
+        }
+        Ok(evicted)
+    }
+
+    /// 吊销会话：写入吊销标记使 token 立即失效，删除订阅记录，并通知该会话的连接下线
+    pub async fn revoke(
+        &self,
+        publisher: &MqttPublisher,
+        open_id: &str,
+        session: &DeviceSession,
+        reason: KickReason,
+        token_ttl_secs: u64,
+    ) -> Result<()> {
+        revoke_session(&self.redis, &session.subscription_id, reason, token_ttl_secs)
+            .await
+            .map_err(|e| {
+                error!(subscription_id = %session.subscription_id, error = %e, "写入会话吊销标记失败");
+                ErrorCode::Internal
+            })?;
+
+        sqlx::query("DELETE FROM subscriptions WHERE subscription_id = ?")
+            .bind(&session.subscription_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!(subscription_id = %session.subscription_id, error = %e, "删除订阅记录失败");
+                ErrorCode::Database
+            })?;
+
+        let event = KickedEvent::new(session.subscription_id.clone(), reason, session.device_type.clone());
+        match serde_json::to_vec(&event) {
+            Ok(payload) => {
+                if let Err(e) = publisher.publish(&mqtt_user_topic(open_id), payload).await {
+                    // 会话已吊销，连接最迟在下次重连时被拒绝
+                    warn!(open_id = %open_id, subscription_id = %session.subscription_id, error = %e, "推送下线通知失败");
+                }
+            }
+            Err(e) => warn!(error = %e, "序列化下线通知失败"),
+        }
+
+        info!(
+            open_id = %open_id,
+            subscription_id = %session.subscription_id,
+            device_type = ?session.device_type,
+            reason = reason.as_str(),
+            "登录会话已吊销"
+        );
+        Ok(())
+    }
+}
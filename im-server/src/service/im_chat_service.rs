@@ -272,7 +272,7 @@ impl ImChatService {
                         // 如果找到的记录 owner_id 不同，说明表结构有问题（主键只有 chat_id）
                         // 这种情况下，我们无法为同一个 chat_id 创建多条记录
                         // 只能返回错误，提示需要更新表结构
-                        if existing.owner_id.as_ref().map(|s| s.as_str()) != Some(&owner_id) {
+                        if existing.owner_id.as_deref() != Some(&owner_id) {
                             error!(
                                 chat_id = %chat_id, 
                                 owner_id = %owner_id, 
@@ -294,7 +294,6 @@ impl ImChatService {
     }
 
     /// 获取用户的聊天会话列表
-    #[allow(dead_code)]
     pub async fn get_user_chats(&self, owner_id: &str) -> Result<Vec<ImChat>> {
         let chats = sqlx::query_as::<_, ImChat>(
             "SELECT chat_id, chat_type, owner_id, to_id, is_mute, is_top, sequence, 
@@ -640,10 +639,10 @@ impl ImChatService {
                  AND del_flag = 1"
             )
             .bind(now)
-            .bind(&owner_id)
+            .bind(owner_id)
             .bind(&chat.to_id)
             .bind(&chat.to_id)
-            .bind(&owner_id)
+            .bind(owner_id)
             .execute(&self.pool)
             .await
             .map_err(|_| ErrorCode::Database)?;
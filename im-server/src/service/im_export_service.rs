@@ -0,0 +1,605 @@
+use crate::config::{ExportSettings, UploadSettings};
+use crate::error::{ErrorCode, Result};
+use crate::handlers::upload_handler::local_upload_path;
+use crate::model::{ImExportJob, ImGroupMessage, ImSingleMessage};
+use crate::service::{ImChatService, ImGroupService, ImMessageService};
+use im_share::now_timestamp;
+use serde::Serialize;
+use sqlx::MySqlPool;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+/// 支持的导出格式
+pub const EXPORT_FORMATS: [&str; 3] = ["json", "csv", "html"];
+
+/// 导出范围：全部会话
+pub const EXPORT_SCOPE_ALL: i32 = 0;
+/// 导出范围：单聊
+pub const EXPORT_SCOPE_SINGLE: i32 = 1;
+/// 导出范围：群聊
+pub const EXPORT_SCOPE_GROUP: i32 = 2;
+
+/// 分页拉取消息时每页的条数
+const EXPORT_PAGE_SIZE: i32 = 500;
+
+/// 导出中的一个会话
+#[derive(Debug, Clone, Serialize)]
+struct ExportConversation {
+    chat_id: String,
+    chat_type: i32,
+    to_id: String,
+    messages: Vec<ExportMessage>,
+}
+
+/// 导出中的一条消息（单聊和群聊统一格式）
+#[derive(Debug, Clone, Serialize)]
+struct ExportMessage {
+    message_id: String,
+    from_id: String,
+    to_id: String,
+    message_body: String,
+    message_time: i64,
+    message_content_type: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_type: Option<String>,
+    /// 附件在压缩包中的相对路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachment: Option<String>,
+}
+
+/// 未打包的附件（manifest.json 中列出）
+#[derive(Debug, Clone, Serialize)]
+struct UnavailableAttachment {
+    message_id: String,
+    file_url: String,
+    /// not_participant：上传者不是会话参与者；not_found：文件不存在
+    reason: &'static str,
+}
+
+impl ExportMessage {
+    fn from_single(msg: ImSingleMessage) -> Self {
+        Self {
+            message_id: msg.message_id,
+            from_id: msg.from_id,
+            to_id: msg.to_id,
+            message_body: msg.message_body,
+            message_time: msg.message_time,
+            message_content_type: msg.message_content_type,
+            reply_to: msg.reply_to,
+            file_url: msg.file_url,
+            file_name: msg.file_name,
+            file_type: msg.file_type,
+            attachment: None,
+        }
+    }
+
+    fn from_group(msg: ImGroupMessage) -> Self {
+        // 群消息的文件信息保存在 extra 字段中
+        let extra_json = msg.extra.as_deref()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok());
+        let extra_str = |key: &str| extra_json.as_ref()
+            .and_then(|v| v.get(key))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        Self {
+            file_url: extra_str("file_url"),
+            file_name: extra_str("file_name"),
+            file_type: extra_str("file_type"),
+            message_id: msg.message_id,
+            from_id: msg.from_id,
+            to_id: msg.group_id,
+            message_body: msg.message_body,
+            message_time: msg.message_time,
+            message_content_type: msg.message_content_type,
+            reply_to: msg.reply_to,
+            attachment: None,
+        }
+    }
+}
+
+pub struct ImExportService {
+    pool: MySqlPool,
+}
+
+impl ImExportService {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self { pool }
+    }
+
+    /// 创建导出任务
+    pub async fn create_job(&self, owner_id: &str, scope_type: i32, to_id: Option<&str>, formats: &[String]) -> Result<ImExportJob> {
+        let now = now_timestamp();
+        let export_id = uuid::Uuid::new_v4().to_string();
+
+        sqlx::query(
+            "INSERT INTO im_export_job
+             (export_id, owner_id, scope_type, to_id, formats, status, create_time, update_time)
+             VALUES (?, ?, ?, ?, ?, 'PENDING', ?, ?)"
+        )
+        .bind(&export_id)
+        .bind(owner_id)
+        .bind(scope_type)
+        .bind(to_id)
+        .bind(formats.join(","))
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("创建导出任务失败: owner_id={}, error={:?}", owner_id, e);
+            ErrorCode::Database
+        })?;
+
+        self.get_job(&export_id, owner_id).await
+    }
+
+    /// 获取导出任务（只能获取自己的任务）
+    pub async fn get_job(&self, export_id: &str, owner_id: &str) -> Result<ImExportJob> {
+        let job = sqlx::query_as::<_, ImExportJob>(
+            "SELECT export_id, owner_id, scope_type, to_id, formats, status, file_path, file_size,
+                    message_count, error, create_time, update_time, expire_time
+             FROM im_export_job
+             WHERE export_id = ? AND owner_id = ?"
+        )
+        .bind(export_id)
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        job.ok_or(ErrorCode::NotFound)
+    }
+
+    /// 获取用户的导出任务列表
+    pub async fn list_jobs(&self, owner_id: &str, limit: i32) -> Result<Vec<ImExportJob>> {
+        let jobs = sqlx::query_as::<_, ImExportJob>(
+            "SELECT export_id, owner_id, scope_type, to_id, formats, status, file_path, file_size,
+                    message_count, error, create_time, update_time, expire_time
+             FROM im_export_job
+             WHERE owner_id = ?
+             ORDER BY create_time DESC
+             LIMIT ?"
+        )
+        .bind(owner_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        Ok(jobs)
+    }
+
+    /// 更新任务状态
+    async fn update_status(&self, export_id: &str, status: &str) -> Result<()> {
+        sqlx::query("UPDATE im_export_job SET status = ?, update_time = ? WHERE export_id = ?")
+            .bind(status)
+            .bind(now_timestamp())
+            .bind(export_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| ErrorCode::Database)?;
+
+        Ok(())
+    }
+
+    /// 标记任务完成
+    async fn mark_done(&self, export_id: &str, file_path: &str, file_size: i64, message_count: i32, expire_time: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE im_export_job
+             SET status = 'DONE', file_path = ?, file_size = ?, message_count = ?, expire_time = ?, update_time = ?
+             WHERE export_id = ?"
+        )
+        .bind(file_path)
+        .bind(file_size)
+        .bind(message_count)
+        .bind(expire_time)
+        .bind(now_timestamp())
+        .bind(export_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        Ok(())
+    }
+
+    /// 标记任务失败
+    async fn mark_failed(&self, export_id: &str, error_msg: &str) -> Result<()> {
+        sqlx::query("UPDATE im_export_job SET status = 'FAILED', error = ?, update_time = ? WHERE export_id = ?")
+            .bind(error_msg)
+            .bind(now_timestamp())
+            .bind(export_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| ErrorCode::Database)?;
+
+        Ok(())
+    }
+
+    /// 执行导出任务（在后台任务中调用）
+    pub async fn run_export(&self, job: ImExportJob, upload_settings: UploadSettings, export_settings: ExportSettings) {
+        let export_id = job.export_id.clone();
+        if let Err(e) = self.update_status(&export_id, "RUNNING").await {
+            error!(export_id = %export_id, error = ?e, "更新导出任务状态失败");
+            return;
+        }
+
+        match self.build_export(&job, &upload_settings, &export_settings).await {
+            Ok((file_path, file_size, message_count)) => {
+                let expire_time = now_timestamp() + (export_settings.expire_hours * 3600 * 1000) as i64;
+                if let Err(e) = self.mark_done(&export_id, &file_path, file_size, message_count, expire_time).await {
+                    error!(export_id = %export_id, error = ?e, "更新导出任务结果失败");
+                    return;
+                }
+                info!(
+                    export_id = %export_id,
+                    owner_id = %job.owner_id,
+                    message_count = message_count,
+                    file_size = file_size,
+                    "会话导出完成"
+                );
+            }
+            Err(e) => {
+                warn!(export_id = %export_id, owner_id = %job.owner_id, error = %e, "会话导出失败");
+                if let Err(e) = self.mark_failed(&export_id, &e).await {
+                    error!(export_id = %export_id, error = ?e, "更新导出任务状态失败");
+                }
+            }
+        }
+    }
+
+    /// 生成导出压缩包，返回（相对上传目录的路径，文件大小，消息条数）
+    async fn build_export(&self, job: &ImExportJob, upload_settings: &UploadSettings, export_settings: &ExportSettings) -> std::result::Result<(String, i64, i32), String> {
+        let mut conversations = self.collect_conversations(job).await
+            .map_err(|e| format!("获取会话列表失败: {}", e))?;
+
+        // 分页拉取每个会话的消息，总条数不超过 max_messages
+        let message_service = ImMessageService::new(self.pool.clone());
+        let mut total = 0usize;
+        let mut truncated = false;
+        for conversation in conversations.iter_mut() {
+            let mut since_sequence = None;
+            loop {
+                if total >= export_settings.max_messages {
+                    truncated = true;
+                    break;
+                }
+                let remaining = (export_settings.max_messages - total).min(EXPORT_PAGE_SIZE as usize) as i32;
+                let (page, last_sequence) = if conversation.chat_type == EXPORT_SCOPE_GROUP {
+                    let messages = message_service.get_group_messages(&conversation.to_id, since_sequence, remaining).await
+                        .map_err(|e| format!("获取群聊消息失败: {}", e))?;
+                    let last = messages.last().and_then(|m| m.sequence);
+                    (messages.into_iter().map(ExportMessage::from_group).collect::<Vec<_>>(), last)
+                } else {
+                    let messages = message_service.get_single_messages(&job.owner_id, &conversation.to_id, since_sequence, remaining).await
+                        .map_err(|e| format!("获取单聊消息失败: {}", e))?;
+                    let last = messages.last().map(|m| m.sequence);
+                    (messages.into_iter().map(ExportMessage::from_single).collect::<Vec<_>>(), last)
+                };
+
+                let page_len = page.len();
+                total += page_len;
+                conversation.messages.extend(page);
+                if page_len < remaining as usize || last_sequence.is_none() {
+                    break;
+                }
+                since_sequence = last_sequence;
+            }
+        }
+
+        // 收集附件：只打包本服务上传目录中存在、且由会话参与者上传的文件（与下载接口的权限一致），
+        // 其余附件记入 manifest.json 的 unavailable_attachments
+        let upload_root = PathBuf::from(&upload_settings.path);
+        let group_service = ImGroupService::new(self.pool.clone());
+        let mut attachments: Vec<(String, PathBuf)> = Vec::new();
+        let mut unavailable: Vec<UnavailableAttachment> = Vec::new();
+        let mut seen = HashSet::new();
+        for conversation in conversations.iter_mut() {
+            let participants: HashSet<String> = if conversation.chat_type == EXPORT_SCOPE_GROUP {
+                group_service.get_group_members(&conversation.to_id).await
+                    .map_err(|e| format!("获取群成员失败: {}", e))?
+                    .into_iter()
+                    .map(|m| m.member_id)
+                    .collect()
+            } else {
+                [job.owner_id.clone(), conversation.to_id.clone()].into_iter().collect()
+            };
+
+            for message in conversation.messages.iter_mut() {
+                let Some(file_url) = message.file_url.as_deref() else {
+                    continue;
+                };
+                let Some(relative) = attachment_path(file_url) else {
+                    continue;
+                };
+                let zip_name = format!("attachments/{}", relative);
+                let local_path = upload_root.join(&relative);
+                // 上传路径格式为 {open_id}/{file_name}，第一段即上传者
+                let uploaded_by_participant = relative.split_once('/')
+                    .is_some_and(|(uploader, _)| participants.contains(uploader));
+                let reason = if !uploaded_by_participant {
+                    "not_participant"
+                } else if seen.contains(&zip_name) {
+                    message.attachment = Some(zip_name);
+                    continue;
+                } else if local_path.is_file() {
+                    seen.insert(zip_name.clone());
+                    attachments.push((zip_name.clone(), local_path));
+                    message.attachment = Some(zip_name);
+                    continue;
+                } else {
+                    "not_found"
+                };
+                unavailable.push(UnavailableAttachment {
+                    message_id: message.message_id.clone(),
+                    file_url: file_url.to_string(),
+                    reason,
+                });
+            }
+        }
+        if !unavailable.is_empty() {
+            info!(export_id = %job.export_id, count = unavailable.len(), "部分附件未打包");
+        }
+
+        let formats: Vec<&str> = job.formats.split(',').filter(|f| !f.is_empty()).collect();
+        let mut documents: Vec<(String, Vec<u8>)> = Vec::new();
+        for format in formats {
+            let content = match format {
+                "json" => render_json(job, &conversations, truncated)?,
+                "csv" => render_csv(&conversations),
+                "html" => render_html(job, &conversations, truncated),
+                _ => continue,
+            };
+            documents.push((format!("messages.{}", format), content));
+        }
+        documents.push(("manifest.json".to_string(), render_manifest(job, &attachments, &unavailable)?));
+
+        let relative_path = format!("{}/exports/{}.zip", job.owner_id, job.export_id);
+        let zip_path = upload_root.join(&relative_path);
+        let file_size = tokio::task::spawn_blocking(move || write_zip(&zip_path, documents, attachments))
+            .await
+            .map_err(|e| format!("导出任务异常退出: {}", e))??;
+
+        Ok((relative_path, file_size as i64, total as i32))
+    }
+
+    /// 根据导出范围确定要导出的会话
+    async fn collect_conversations(&self, job: &ImExportJob) -> Result<Vec<ExportConversation>> {
+        let conversation = |chat_id: String, chat_type: i32, to_id: String| ExportConversation {
+            chat_id,
+            chat_type,
+            to_id,
+            messages: Vec::new(),
+        };
+
+        match job.scope_type {
+            EXPORT_SCOPE_SINGLE | EXPORT_SCOPE_GROUP => {
+                let to_id = job.to_id.clone().ok_or(ErrorCode::InvalidInput)?;
+                let chat_id = if job.scope_type == EXPORT_SCOPE_GROUP {
+                    to_id.clone()
+                } else {
+                    let (min_id, max_id) = if job.owner_id <= to_id {
+                        (job.owner_id.as_str(), to_id.as_str())
+                    } else {
+                        (to_id.as_str(), job.owner_id.as_str())
+                    };
+                    format!("single_{}_{}", min_id, max_id)
+                };
+                Ok(vec![conversation(chat_id, job.scope_type, to_id)])
+            }
+            _ => {
+                let chat_service = ImChatService::new(self.pool.clone());
+                let chats = chat_service.get_user_chats(&job.owner_id).await?;
+                Ok(chats.into_iter()
+                    .map(|chat| {
+                        let is_group = chat.chat_type == EXPORT_SCOPE_GROUP || chat.to_id.starts_with("group_");
+                        let chat_type = if is_group { EXPORT_SCOPE_GROUP } else { EXPORT_SCOPE_SINGLE };
+                        conversation(chat.chat_id, chat_type, chat.to_id)
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// 清理过期的导出文件，返回清理的任务数
+    pub async fn cleanup_expired(&self, upload_path: &str) -> Result<usize> {
+        let now = now_timestamp();
+        let jobs = sqlx::query_as::<_, ImExportJob>(
+            "SELECT export_id, owner_id, scope_type, to_id, formats, status, file_path, file_size,
+                    message_count, error, create_time, update_time, expire_time
+             FROM im_export_job
+             WHERE status = 'DONE' AND expire_time IS NOT NULL AND expire_time <= ?"
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        for job in &jobs {
+            if let Some(file_path) = &job.file_path {
+                let path = PathBuf::from(upload_path).join(file_path);
+                if let Err(e) = tokio::fs::remove_file(&path).await
+                    && e.kind() != std::io::ErrorKind::NotFound {
+                        warn!(export_id = %job.export_id, error = %e, "删除过期导出文件失败");
+                        continue;
+                    }
+            }
+            self.update_status(&job.export_id, "EXPIRED").await?;
+        }
+
+        Ok(jobs.len())
+    }
+}
+
+/// 附件对应的上传目录相对路径，不打包其他导出文件
+fn attachment_path(file_url: &str) -> Option<String> {
+    local_upload_path(file_url).filter(|relative| relative.split('/').nth(1) != Some("exports"))
+}
+
+/// 生成 manifest.json：列出已打包和未打包的附件
+fn render_manifest(job: &ImExportJob, attachments: &[(String, PathBuf)], unavailable: &[UnavailableAttachment]) -> std::result::Result<Vec<u8>, String> {
+    let bundled: Vec<&str> = attachments.iter().map(|(name, _)| name.as_str()).collect();
+    serde_json::to_vec_pretty(&serde_json::json!({
+        "export_id": job.export_id,
+        "formats": job.formats,
+        "attachments": bundled,
+        "unavailable_attachments": unavailable,
+    }))
+    .map_err(|e| format!("生成 manifest 失败: {}", e))
+}
+
+fn render_json(job: &ImExportJob, conversations: &[ExportConversation], truncated: bool) -> std::result::Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(&serde_json::json!({
+        "export_id": job.export_id,
+        "owner_id": job.owner_id,
+        "exported_at": now_timestamp(),
+        "truncated": truncated,
+        "conversations": conversations,
+    }))
+    .map_err(|e| format!("生成 JSON 失败: {}", e))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_csv(conversations: &[ExportConversation]) -> Vec<u8> {
+    // 带 BOM，便于 Excel 正确识别 UTF-8
+    let mut out = String::from("\u{feff}chat_id,chat_type,message_id,from_id,to_id,message_time,time,message_content_type,message_body,reply_to,file_name,file_type,attachment\n");
+    for conversation in conversations {
+        for m in &conversation.messages {
+            let fields = [
+                conversation.chat_id.clone(),
+                conversation.chat_type.to_string(),
+                m.message_id.clone(),
+                m.from_id.clone(),
+                m.to_id.clone(),
+                m.message_time.to_string(),
+                format_time(m.message_time),
+                m.message_content_type.to_string(),
+                m.message_body.clone(),
+                m.reply_to.clone().unwrap_or_default(),
+                m.file_name.clone().unwrap_or_default(),
+                m.file_type.clone().unwrap_or_default(),
+                m.attachment.clone().unwrap_or_default(),
+            ];
+            let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            out.push_str(&line.join(","));
+            out.push('\n');
+        }
+    }
+    out.into_bytes()
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render_html(job: &ImExportJob, conversations: &[ExportConversation], truncated: bool) -> Vec<u8> {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n<title>聊天记录导出</title>\n<style>\n");
+    out.push_str("body{font-family:-apple-system,\"PingFang SC\",\"Microsoft YaHei\",sans-serif;background:#f5f5f5;margin:0;padding:24px;color:#222}\n");
+    out.push_str("h1{font-size:20px}h2{font-size:16px;margin-top:32px;border-bottom:1px solid #ddd;padding-bottom:6px}\n");
+    out.push_str(".msg{background:#fff;border-radius:6px;padding:8px 12px;margin:8px 0;max-width:720px}\n");
+    out.push_str(".msg.mine{background:#e1f3d8;margin-left:auto}\n.meta{font-size:12px;color:#888;margin-bottom:4px}\n");
+    out.push_str(".body{white-space:pre-wrap;word-break:break-word}\nimg{max-width:320px;display:block;margin-top:6px}\n");
+    out.push_str("</style>\n</head>\n<body>\n");
+    out.push_str(&format!(
+        "<h1>聊天记录导出</h1>\n<p class=\"meta\">用户：{} ｜ 导出时间：{}</p>\n",
+        html_escape(&job.owner_id),
+        format_time(now_timestamp()),
+    ));
+    if truncated {
+        out.push_str("<p class=\"meta\">消息数量超过导出上限，部分消息未导出</p>\n");
+    }
+
+    for conversation in conversations {
+        let title = if conversation.chat_type == EXPORT_SCOPE_GROUP { "群聊" } else { "单聊" };
+        out.push_str(&format!(
+            "<h2>{}：{}</h2>\n",
+            title,
+            html_escape(&conversation.to_id),
+        ));
+        for m in &conversation.messages {
+            let class = if m.from_id == job.owner_id { "msg mine" } else { "msg" };
+            out.push_str(&format!(
+                "<div class=\"{}\">\n<div class=\"meta\">{} · {}</div>\n<div class=\"body\">{}</div>\n",
+                class,
+                html_escape(&m.from_id),
+                format_time(m.message_time),
+                html_escape(&m.message_body),
+            ));
+            if let Some(attachment) = &m.attachment {
+                let name = m.file_name.as_deref().unwrap_or(attachment);
+                let is_image = m.file_type.as_deref().is_some_and(|t| t.starts_with("image/"));
+                if is_image {
+                    out.push_str(&format!("<img src=\"{}\" alt=\"{}\">\n", html_escape(attachment), html_escape(name)));
+                }
+                out.push_str(&format!("<a href=\"{}\">{}</a>\n", html_escape(attachment), html_escape(name)));
+            }
+            out.push_str("</div>\n");
+        }
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out.into_bytes()
+}
+
+fn format_time(timestamp_ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp_ms)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// 写入压缩包（阻塞操作），先写临时文件再重命名，返回文件大小
+fn write_zip(zip_path: &Path, documents: Vec<(String, Vec<u8>)>, attachments: Vec<(String, PathBuf)>) -> std::result::Result<u64, String> {
+    use zip::write::SimpleFileOptions;
+
+    if let Some(parent) = zip_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("创建导出目录失败: {}", e))?;
+    }
+    let tmp_path = zip_path.with_extension("zip.part");
+    let file = std::fs::File::create(&tmp_path).map_err(|e| format!("创建导出文件失败: {}", e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for (name, content) in documents {
+        zip.start_file(name, options).map_err(|e| format!("写入导出文件失败: {}", e))?;
+        zip.write_all(&content).map_err(|e| format!("写入导出文件失败: {}", e))?;
+    }
+    for (name, path) in attachments {
+        let mut source = match std::fs::File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "读取附件失败，跳过");
+                continue;
+            }
+        };
+        // 附件多为已压缩格式，直接存储
+        zip.start_file(name, SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored))
+            .map_err(|e| format!("写入附件失败: {}", e))?;
+        std::io::copy(&mut source, &mut zip).map_err(|e| format!("写入附件失败: {}", e))?;
+    }
+    zip.finish().map_err(|e| format!("写入导出文件失败: {}", e))?;
+
+    std::fs::rename(&tmp_path, zip_path).map_err(|e| format!("保存导出文件失败: {}", e))?;
+    let size = std::fs::metadata(zip_path).map(|m| m.len()).unwrap_or(0);
+    Ok(size)
+}
@@ -77,37 +77,31 @@ impl ImFriendshipService {
                 
                 // 收集所有可能的标识（去重）
                 let mut owner_ids: Vec<String> = Vec::new();
-                if let Some(ref id) = owner_open_id {
-                    if id != owner_id {
-                        owner_ids.push(id.clone());
-                    }
+                if let Some(ref id) = owner_open_id
+                    && id != owner_id {
+                    owner_ids.push(id.clone());
                 }
-                if let Some(ref id) = owner_name {
-                    if id != owner_id && !owner_ids.contains(id) {
-                        owner_ids.push(id.clone());
-                    }
+                if let Some(ref id) = owner_name
+                    && id != owner_id && !owner_ids.contains(id) {
+                    owner_ids.push(id.clone());
                 }
-                if let Some(ref id) = owner_phone {
-                    if id != owner_id && !owner_ids.contains(id) {
-                        owner_ids.push(id.clone());
-                    }
+                if let Some(ref id) = owner_phone
+                    && id != owner_id && !owner_ids.contains(id) {
+                    owner_ids.push(id.clone());
                 }
                 
                 let mut to_ids: Vec<String> = Vec::new();
-                if let Some(ref id) = to_open_id {
-                    if id != to_id {
-                        to_ids.push(id.clone());
-                    }
+                if let Some(ref id) = to_open_id
+                    && id != to_id {
+                    to_ids.push(id.clone());
                 }
-                if let Some(ref id) = to_name {
-                    if id != to_id && !to_ids.contains(id) {
-                        to_ids.push(id.clone());
-                    }
+                if let Some(ref id) = to_name
+                    && id != to_id && !to_ids.contains(id) {
+                    to_ids.push(id.clone());
                 }
-                if let Some(ref id) = to_phone {
-                    if id != to_id && !to_ids.contains(id) {
-                        to_ids.push(id.clone());
-                    }
+                if let Some(ref id) = to_phone
+                    && id != to_id && !to_ids.contains(id) {
+                    to_ids.push(id.clone());
                 }
                 
                 // 尝试所有可能的组合（包括原始ID）
@@ -199,29 +193,27 @@ impl ImFriendshipService {
         .flatten();
 
         // 如果找到用户，尝试用 open_id 查询
-        if let Some(open_id) = user_by_name {
-            if open_id != owner_id {
-                debug!("尝试使用 open_id 查询好友列表: open_id={}", open_id);
-                let friends_by_open_id = sqlx::query_as::<_, ImFriendship>(
-                    "SELECT owner_id, to_id, remark, del_flag, black, create_time, update_time, 
-                            sequence, black_sequence, add_source, extra, version 
-                     FROM im_friendship 
-                     WHERE owner_id = ? AND (del_flag IS NULL OR del_flag = 1) 
-                     AND (black IS NULL OR black = 1)
-                     ORDER BY sequence DESC, create_time DESC"
-                )
-                .bind(&open_id)
-                .fetch_all(&self.pool)
-                .await
-                .ok();
-
-                if let Some(friends) = friends_by_open_id {
-                    if !friends.is_empty() {
-                        warn!("使用 open_id 查询到好友列表: owner_id={}, open_id={}, count={}", 
-                              owner_id, open_id, friends.len());
-                        return Ok(friends);
-                    }
-                }
+        if let Some(open_id) = user_by_name
+            && open_id != owner_id {
+            debug!("尝试使用 open_id 查询好友列表: open_id={}", open_id);
+            let friends_by_open_id = sqlx::query_as::<_, ImFriendship>(
+                "SELECT owner_id, to_id, remark, del_flag, black, create_time, update_time, 
+                        sequence, black_sequence, add_source, extra, version 
+                 FROM im_friendship 
+                 WHERE owner_id = ? AND (del_flag IS NULL OR del_flag = 1) 
+                 AND (black IS NULL OR black = 1)
+                 ORDER BY sequence DESC, create_time DESC"
+            )
+            .bind(&open_id)
+            .fetch_all(&self.pool)
+            .await
+            .ok();
+
+            if let Some(friends) = friends_by_open_id
+                && !friends.is_empty() {
+                warn!("使用 open_id 查询到好友列表: owner_id={}, open_id={}, count={}", 
+                      owner_id, open_id, friends.len());
+                return Ok(friends);
             }
         }
 
@@ -236,29 +228,27 @@ impl ImFriendshipService {
         .flatten()
         .flatten();
 
-        if let Some(name) = user_by_open_id {
-            if name != owner_id && !name.is_empty() {
-                debug!("尝试使用用户名查询好友列表: name={}", name);
-                let friends_by_name = sqlx::query_as::<_, ImFriendship>(
-                    "SELECT owner_id, to_id, remark, del_flag, black, create_time, update_time, 
-                            sequence, black_sequence, add_source, extra, version 
-                     FROM im_friendship 
-                     WHERE owner_id = ? AND (del_flag IS NULL OR del_flag = 1) 
-                     AND (black IS NULL OR black = 1)
-                     ORDER BY sequence DESC, create_time DESC"
-                )
-                .bind(&name)
-                .fetch_all(&self.pool)
-                .await
-                .ok();
-
-                if let Some(friends) = friends_by_name {
-                    if !friends.is_empty() {
-                        warn!("使用用户名查询到好友列表: owner_id={}, name={}, count={}", 
-                              owner_id, name, friends.len());
-                        return Ok(friends);
-                    }
-                }
+        if let Some(name) = user_by_open_id
+            && name != owner_id && !name.is_empty() {
+            debug!("尝试使用用户名查询好友列表: name={}", name);
+            let friends_by_name = sqlx::query_as::<_, ImFriendship>(
+                "SELECT owner_id, to_id, remark, del_flag, black, create_time, update_time, 
+                        sequence, black_sequence, add_source, extra, version 
+                 FROM im_friendship 
+                 WHERE owner_id = ? AND (del_flag IS NULL OR del_flag = 1) 
+                 AND (black IS NULL OR black = 1)
+                 ORDER BY sequence DESC, create_time DESC"
+            )
+            .bind(&name)
+            .fetch_all(&self.pool)
+            .await
+            .ok();
+
+            if let Some(friends) = friends_by_name
+                && !friends.is_empty() {
+                warn!("使用用户名查询到好友列表: owner_id={}, name={}, count={}", 
+                      owner_id, name, friends.len());
+                return Ok(friends);
             }
         }
 
@@ -477,23 +467,20 @@ impl ImFriendshipService {
             warn!("接收者ID长度超过限制: {} > 100", request.to_id.len());
             return Err(ErrorCode::InvalidInput);
         }
-        if let Some(ref remark) = request.remark {
-            if remark.len() > 100 {
-                warn!("备注长度超过限制: {} > 100", remark.len());
-                return Err(ErrorCode::InvalidInput);
-            }
+        if let Some(ref remark) = request.remark
+            && remark.len() > 100 {
+            warn!("备注长度超过限制: {} > 100", remark.len());
+            return Err(ErrorCode::InvalidInput);
         }
-        if let Some(ref message) = request.message {
-            if message.len() > 500 {
-                warn!("验证消息长度超过限制: {} > 500", message.len());
-                return Err(ErrorCode::InvalidInput);
-            }
+        if let Some(ref message) = request.message
+            && message.len() > 500 {
+            warn!("验证消息长度超过限制: {} > 500", message.len());
+            return Err(ErrorCode::InvalidInput);
         }
-        if let Some(ref add_source) = request.add_source {
-            if add_source.len() > 100 {
-                warn!("添加来源长度超过限制: {} > 100", add_source.len());
-                return Err(ErrorCode::InvalidInput);
-            }
+        if let Some(ref add_source) = request.add_source
+            && add_source.len() > 100 {
+            warn!("添加来源长度超过限制: {} > 100", add_source.len());
+            return Err(ErrorCode::InvalidInput);
         }
 
         // 检查是否已经是好友
@@ -56,11 +56,10 @@ impl ImGroupService {
         }
 
         // 检查 introduction 长度（数据库限制为 VARCHAR(100)）
-        if let Some(ref intro) = group.introduction {
-            if intro.len() > 100 {
-                warn!("群组简介长度超过限制: {} > 100", intro.len());
-                return Err(ErrorCode::InvalidInput);
-            }
+        if let Some(ref intro) = group.introduction
+            && intro.len() > 100 {
+            warn!("群组简介长度超过限制: {} > 100", intro.len());
+            return Err(ErrorCode::InvalidInput);
         }
 
         let result = sqlx::query(
@@ -74,16 +73,16 @@ impl ImGroupService {
         .bind(&group.owner_id)
         .bind(group.group_type)
         .bind(&group.group_name)
-        .bind(&group.mute)
+        .bind(group.mute)
         .bind(group.apply_join_type)
         .bind(&group.avatar)
-        .bind(&group.max_member_count)
+        .bind(group.max_member_count)
         .bind(&group.introduction)
         .bind(&group.notification)
         .bind(now)
         .bind(now)
         .bind(&group.extra)
-        .bind(&group.verifier)
+        .bind(group.verifier)
         .execute(&self.pool)
         .await;
 
@@ -99,11 +98,10 @@ impl ImGroupService {
             Err(e) => {
                 error!("创建群组数据库错误: {:?}", e);
                 // 检查是否是重复键错误
-                if let sqlx::Error::Database(db_err) = &e {
-                    if db_err.message().contains("Duplicate entry") || db_err.message().contains("PRIMARY") {
-                        warn!("群组ID已存在: {}", group.group_id);
-                        return Err(ErrorCode::InvalidInput);
-                    }
+                if let sqlx::Error::Database(db_err) = &e
+                    && (db_err.message().contains("Duplicate entry") || db_err.message().contains("PRIMARY")) {
+                    warn!("群组ID已存在: {}", group.group_id);
+                    return Err(ErrorCode::InvalidInput);
                 }
                 Err(ErrorCode::Database)
             }
@@ -284,14 +282,12 @@ impl ImGroupService {
         }
 
         // 管理员不能移除其他管理员（只有群主可以）
-        if !is_owner {
-            if let Some(target) = target_member {
-                if target.role == 1 {
-                    warn!("管理员不能移除其他管理员: group_id={}, operator_id={}, member_id={}", 
-                          group_id, operator_id, member_id);
-                    return Err(ErrorCode::InvalidInput);
-                }
-            }
+        if !is_owner
+            && let Some(target) = target_member
+            && target.role == 1 {
+            warn!("管理员不能移除其他管理员: group_id={}, operator_id={}, member_id={}", 
+                  group_id, operator_id, member_id);
+            return Err(ErrorCode::InvalidInput);
         }
 
         // 执行删除
@@ -334,7 +330,7 @@ impl ImGroupService {
         }
 
         // 验证角色值（0=普通成员，1=管理员，2=群主）
-        if role < 0 || role > 2 {
+        if !(0..=2).contains(&role) {
             warn!("无效的角色值: {}", role);
             return Err(ErrorCode::InvalidInput);
         }
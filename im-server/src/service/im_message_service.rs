@@ -1,8 +1,57 @@
 use crate::model::{ImSingleMessage, ImGroupMessage, ImGroupMessageStatus};
 use crate::error::{ErrorCode, Result};
 use sqlx::MySqlPool;
-use im_share::{now_timestamp, RedisClient};
+use im_share::{now_timestamp, stamp_expiry, stamp_expiry_ttl, RedisClient};
+use im_share::expiry::{CALL_INVITE, expiry_ttl};
 use std::sync::Arc;
+use tracing::warn;
+
+/// 语音/视频通话邀请类型
+pub const CALL_INVITE_MESSAGE_CONTENT_TYPE: i32 = 4;
+/// 语音消息类型
+pub const VOICE_MESSAGE_CONTENT_TYPE: i32 = 5;
+/// 位置消息类型
+pub const LOCATION_MESSAGE_CONTENT_TYPE: i32 = 6;
+/// 名片消息类型
+pub const CARD_MESSAGE_CONTENT_TYPE: i32 = 7;
+
+/// 有有效期策略的消息类型（与 expiry_kind 一致）
+const EXPIRING_MESSAGE_CONTENT_TYPES: [i32; 1] = [CALL_INVITE_MESSAGE_CONTENT_TYPE];
+
+/// 消息类型对应的有效期策略（im_share::expiry），没有策略的类型永不过期
+pub fn expiry_kind(message_content_type: i32) -> Option<&'static str> {
+    match message_content_type {
+        CALL_INVITE_MESSAGE_CONTENT_TYPE => Some(CALL_INVITE),
+        _ => None,
+    }
+}
+
+/// 历史消息查询条件：按有效期策略排除已过期的消息（与 is_kind_expired 的判断相同）
+fn unexpired_condition(now_ms: i64) -> String {
+    EXPIRING_MESSAGE_CONTENT_TYPES
+        .iter()
+        .filter_map(|&content_type| {
+            let ttl_secs = expiry_ttl(expiry_kind(content_type)?)?;
+            let expired_before = now_ms - (ttl_secs as i64) * 1000;
+            Some(format!(" AND NOT (message_content_type = {} AND message_time <= {})", content_type, expired_before))
+        })
+        .collect()
+}
+
+/// 按消息类型给推送写入过期时间（expires_at）
+/// 通话邀请优先使用消息内容中的 timeout（秒），没有时使用策略表中的默认值
+pub fn stamp_message_expiry(payload: Vec<u8>, message_content_type: i32, message_body: &str) -> Vec<u8> {
+    let Some(kind) = expiry_kind(message_content_type) else {
+        return payload;
+    };
+    let timeout = serde_json::from_str::<serde_json::Value>(message_body)
+        .ok()
+        .and_then(|body| body.get("timeout")?.as_u64());
+    match timeout {
+        Some(ttl_secs) => stamp_expiry_ttl(payload, ttl_secs),
+        None => stamp_expiry(payload, kind),
+    }
+}
 
 pub struct ImMessageService {
     pool: MySqlPool,
@@ -72,14 +121,36 @@ impl ImMessageService {
     }
 
     /// 获取单聊消息列表
-    /// 重要：过滤掉通话邀请消息（message_content_type = 4），因为通话邀请是实时消息，过期后没有意义
+    /// 重要：按有效期策略过滤掉已过期的消息（通话邀请等），过期后没有意义
+    /// 超过保留期限的消息已迁移到归档表，归档消息都早于在线表中的消息，所以先查归档表再用在线表补足
     pub async fn get_single_messages(&self, from_id: &str, to_id: &str, since_sequence: Option<i64>, limit: i32) -> Result<Vec<ImSingleMessage>> {
-        let mut query = "SELECT message_id, from_id, to_id, message_body, message_time, message_content_type, 
-                                read_status, extra, del_flag, sequence, message_random, create_time, update_time, version, reply_to,
+        let mut messages = self.query_single_messages("im_single_message_archive", from_id, to_id, since_sequence, limit)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(error = %e, "查询单聊归档消息失败，仅查询在线消息");
+                Vec::new()
+            });
+
+        let remaining = limit - messages.len() as i32;
+        if remaining > 0 {
+            let since_sequence = messages.last().map(|m| m.sequence).or(since_sequence);
+            let recent = self.query_single_messages("im_single_message", from_id, to_id, since_sequence, remaining)
+                .await
+                .map_err(|_| ErrorCode::Database)?;
+            messages.extend(recent);
+        }
+
+        Ok(messages)
+    }
+
+    async fn query_single_messages(&self, table: &str, from_id: &str, to_id: &str, since_sequence: Option<i64>, limit: i32) -> std::result::Result<Vec<ImSingleMessage>, sqlx::Error> {
+        let mut query = format!("SELECT message_id, from_id, to_id, message_body, message_time, message_content_type, 
+                                read_status, played_status, extra, del_flag, sequence, message_random, create_time, update_time, version, reply_to,
                                 to_type, file_url, file_name, file_type
-                         FROM im_single_message 
+                         FROM {} 
                          WHERE ((from_id = ? AND to_id = ?) OR (from_id = ? AND to_id = ?)) 
-                         AND del_flag = 1 AND message_content_type != 4".to_string();
+                         AND del_flag = 1", table);
+        query.push_str(&unexpired_condition(now_timestamp()));
 
         if let Some(seq) = since_sequence {
             query.push_str(&format!(" AND sequence > {}", seq));
@@ -87,7 +158,7 @@ impl ImMessageService {
 
         query.push_str(" ORDER BY sequence ASC LIMIT ?");
 
-        let messages = sqlx::query_as::<_, ImSingleMessage>(&query)
+        sqlx::query_as::<_, ImSingleMessage>(&query)
             .bind(from_id)
             .bind(to_id)
             .bind(to_id)
@@ -95,9 +166,6 @@ impl ImMessageService {
             .bind(limit)
             .fetch_all(&self.pool)
             .await
-            .map_err(|_| ErrorCode::Database)?;
-
-        Ok(messages)
     }
 
     /// 标记消息为已读
@@ -119,6 +187,30 @@ impl ImMessageService {
         Ok(())
     }
 
+    /// 标记语音消息为已播放（播放同时视为已读）
+    pub async fn mark_single_message_played(&self, message_id: &str, to_id: &str) -> Result<()> {
+        let now = now_timestamp();
+
+        let result = sqlx::query(
+            "UPDATE im_single_message 
+             SET played_status = 1, read_status = 1, update_time = ?, version = version + 1 
+             WHERE message_id = ? AND to_id = ? AND message_content_type = ?"
+        )
+        .bind(now)
+        .bind(message_id)
+        .bind(to_id)
+        .bind(VOICE_MESSAGE_CONTENT_TYPE)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        if result.rows_affected() == 0 {
+            return Err(ErrorCode::NotFound);
+        }
+
+        Ok(())
+    }
+
     /// 保存群聊消息
     pub async fn save_group_message(&self, message: ImGroupMessage) -> Result<()> {
         let now = now_timestamp();
@@ -150,12 +242,34 @@ impl ImMessageService {
     }
 
     /// 获取群聊消息列表
-    /// 重要：过滤掉通话邀请消息（message_content_type = 4），因为通话邀请是实时消息，过期后没有意义
+    /// 重要：按有效期策略过滤掉已过期的消息（通话邀请等），过期后没有意义
+    /// 与单聊相同，先查归档表再用在线表补足
     pub async fn get_group_messages(&self, group_id: &str, since_sequence: Option<i64>, limit: i32) -> Result<Vec<ImGroupMessage>> {
-        let mut query = "SELECT message_id, group_id, from_id, message_body, message_time, message_content_type, 
+        let mut messages = self.query_group_messages("im_group_message_archive", group_id, since_sequence, limit)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(error = %e, "查询群聊归档消息失败，仅查询在线消息");
+                Vec::new()
+            });
+
+        let remaining = limit - messages.len() as i32;
+        if remaining > 0 {
+            let since_sequence = messages.last().and_then(|m| m.sequence).or(since_sequence);
+            let recent = self.query_group_messages("im_group_message", group_id, since_sequence, remaining)
+                .await
+                .map_err(|_| ErrorCode::Database)?;
+            messages.extend(recent);
+        }
+
+        Ok(messages)
+    }
+
+    async fn query_group_messages(&self, table: &str, group_id: &str, since_sequence: Option<i64>, limit: i32) -> std::result::Result<Vec<ImGroupMessage>, sqlx::Error> {
+        let mut query = format!("SELECT message_id, group_id, from_id, message_body, message_time, message_content_type, 
                                 extra, del_flag, sequence, message_random, create_time, update_time, version, reply_to 
-                         FROM im_group_message 
-                         WHERE group_id = ? AND del_flag = 1 AND message_content_type != 4".to_string();
+                         FROM {} 
+                         WHERE group_id = ? AND del_flag = 1", table);
+        query.push_str(&unexpired_condition(now_timestamp()));
 
         if let Some(seq) = since_sequence {
             query.push_str(&format!(" AND sequence > {}", seq));
@@ -163,14 +277,11 @@ impl ImMessageService {
 
         query.push_str(" ORDER BY sequence ASC LIMIT ?");
 
-        let messages = sqlx::query_as::<_, ImGroupMessage>(&query)
+        sqlx::query_as::<_, ImGroupMessage>(&query)
             .bind(group_id)
             .bind(limit)
             .fetch_all(&self.pool)
             .await
-            .map_err(|_| ErrorCode::Database)?;
-
-        Ok(messages)
     }
 
     /// 标记群消息为已读（使用 Redis）
@@ -185,6 +296,32 @@ impl ImMessageService {
         }
     }
 
+    /// 标记群语音消息为已播放（使用 Redis，播放同时视为已读）
+    pub async fn mark_group_message_played(&self, group_id: &str, message_id: &str, to_id: &str) -> Result<()> {
+        if let Some(ref redis) = self.redis {
+            redis.mark_group_message_played(group_id, message_id, to_id)
+                .await
+                .map_err(|_| ErrorCode::Internal)?;
+            redis.mark_group_message_read(group_id, message_id, to_id)
+                .await
+                .map_err(|_| ErrorCode::Internal)?;
+            Ok(())
+        } else {
+            Err(ErrorCode::Internal)
+        }
+    }
+
+    /// 获取群语音消息的已播放用户（使用 Redis）
+    pub async fn get_group_message_played_users(&self, group_id: &str, message_id: &str) -> Result<Vec<String>> {
+        if let Some(ref redis) = self.redis {
+            redis.get_group_message_played_users(group_id, message_id)
+                .await
+                .map_err(|_| ErrorCode::Internal)
+        } else {
+            Err(ErrorCode::Internal)
+        }
+    }
+
     /// 获取群消息的已读状态（使用 Redis）
     pub async fn get_group_message_status(&self, group_id: &str, message_id: &str) -> Result<Vec<ImGroupMessageStatus>> {
         if let Some(ref redis) = self.redis {
@@ -0,0 +1,218 @@
+use crate::config::RetentionSettings;
+use crate::error::{ErrorCode, Result};
+use im_share::now_timestamp;
+use serde::Serialize;
+use sqlx::MySqlPool;
+use tracing::error;
+
+/// 单聊消息表的列（与归档表一致）
+const SINGLE_MESSAGE_COLUMNS: &str = "message_id, from_id, to_id, message_body, message_time, message_content_type, \
+    read_status, played_status, extra, del_flag, sequence, message_random, create_time, update_time, version, reply_to, \
+    to_type, file_url, file_name, file_type";
+
+/// 群聊消息表的列（与归档表一致）
+const GROUP_MESSAGE_COLUMNS: &str = "message_id, group_id, from_id, message_body, message_time, message_content_type, \
+    extra, del_flag, sequence, message_random, create_time, update_time, version, reply_to";
+
+const DAY_MS: i64 = 24 * 3600 * 1000;
+
+/// 一次归档任务的执行结果
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RetentionReport {
+    /// 迁移的单聊消息条数
+    pub single_moved: u64,
+    /// 按默认保留期限迁移的群聊消息条数
+    pub group_moved: u64,
+    /// 按群组单独配置迁移的消息条数（group_id, 条数）
+    pub group_overrides_moved: Vec<(String, u64)>,
+}
+
+impl RetentionReport {
+    pub fn total(&self) -> u64 {
+        self.single_moved
+            + self.group_moved
+            + self.group_overrides_moved.iter().map(|(_, n)| n).sum::<u64>()
+    }
+}
+
+pub struct ImRetentionService {
+    pool: MySqlPool,
+}
+
+impl ImRetentionService {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self { pool }
+    }
+
+    /// 按保留策略把过期消息迁移到归档表
+    pub async fn enforce(&self, settings: &RetentionSettings) -> Result<RetentionReport> {
+        let now = now_timestamp();
+        let batch_size = settings.batch_size.max(1);
+        let mut report = RetentionReport::default();
+
+        // 单聊
+        if settings.single_days > 0 {
+            let cutoff = now - settings.single_days as i64 * DAY_MS;
+            report.single_moved = self.archive_all(
+                "im_single_message",
+                "im_single_message_archive",
+                SINGLE_MESSAGE_COLUMNS,
+                cutoff,
+                None,
+                batch_size,
+            ).await?;
+        }
+
+        // 按群组单独配置的保留期限
+        let overrides: Vec<(String, u32)> = settings.groups.iter()
+            .map(|(group_id, days)| (normalize_group_id(group_id), *days))
+            .collect();
+        for (group_id, days) in &overrides {
+            if *days == 0 {
+                continue;
+            }
+            let cutoff = now - *days as i64 * DAY_MS;
+            let moved = self.archive_all(
+                "im_group_message",
+                "im_group_message_archive",
+                GROUP_MESSAGE_COLUMNS,
+                cutoff,
+                Some(GroupFilter::Only(group_id)),
+                batch_size,
+            ).await?;
+            if moved > 0 {
+                report.group_overrides_moved.push((group_id.clone(), moved));
+            }
+        }
+
+        // 其余群组使用默认保留期限
+        if settings.group_days > 0 {
+            let cutoff = now - settings.group_days as i64 * DAY_MS;
+            let excluded: Vec<String> = overrides.into_iter().map(|(group_id, _)| group_id).collect();
+            report.group_moved = self.archive_all(
+                "im_group_message",
+                "im_group_message_archive",
+                GROUP_MESSAGE_COLUMNS,
+                cutoff,
+                Some(GroupFilter::Except(&excluded)),
+                batch_size,
+            ).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// 分批迁移，直到没有满足条件的消息
+    async fn archive_all(
+        &self,
+        table: &str,
+        archive_table: &str,
+        columns: &str,
+        cutoff: i64,
+        group_filter: Option<GroupFilter<'_>>,
+        batch_size: u32,
+    ) -> Result<u64> {
+        let mut total = 0u64;
+        loop {
+            let moved = self.archive_batch(table, archive_table, columns, cutoff, group_filter.as_ref(), batch_size).await?;
+            total += moved;
+            if moved < batch_size as u64 {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// 在一个事务中迁移一批消息：复制到归档表后从原表删除
+    async fn archive_batch(
+        &self,
+        table: &str,
+        archive_table: &str,
+        columns: &str,
+        cutoff: i64,
+        group_filter: Option<&GroupFilter<'_>>,
+        batch_size: u32,
+    ) -> Result<u64> {
+        let map_db_err = |e: sqlx::Error| {
+            error!(table = %table, error = %e, "归档消息失败");
+            ErrorCode::Database
+        };
+
+        let mut condition = "message_time < ?".to_string();
+        let mut group_binds: Vec<&str> = Vec::new();
+        match group_filter {
+            Some(GroupFilter::Only(group_id)) => {
+                condition.push_str(" AND group_id = ?");
+                group_binds.push(group_id);
+            }
+            Some(GroupFilter::Except(group_ids)) if !group_ids.is_empty() => {
+                condition.push_str(&format!(" AND group_id NOT IN ({})", vec!["?"; group_ids.len()].join(", ")));
+                group_binds.extend(group_ids.iter().map(|s| s.as_str()));
+            }
+            _ => {}
+        }
+
+        let mut tx = self.pool.begin().await.map_err(map_db_err)?;
+
+        let select_sql = format!(
+            "SELECT message_id FROM {} WHERE {} ORDER BY message_time ASC LIMIT ? FOR UPDATE",
+            table, condition
+        );
+        let mut select = sqlx::query_scalar::<_, String>(&select_sql).bind(cutoff);
+        for group_id in &group_binds {
+            select = select.bind(*group_id);
+        }
+        let message_ids = select
+            .bind(batch_size)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(map_db_err)?;
+
+        if message_ids.is_empty() {
+            tx.rollback().await.map_err(map_db_err)?;
+            return Ok(0);
+        }
+
+        let placeholders = vec!["?"; message_ids.len()].join(", ");
+        let insert_sql = format!(
+            "INSERT INTO {archive} ({columns}, archive_time)
+             SELECT {columns}, ? FROM {table} WHERE message_id IN ({placeholders})
+             ON DUPLICATE KEY UPDATE message_id = {archive}.message_id",
+            archive = archive_table,
+            columns = columns,
+            table = table,
+            placeholders = placeholders,
+        );
+        let mut insert = sqlx::query(&insert_sql).bind(now_timestamp());
+        for message_id in &message_ids {
+            insert = insert.bind(message_id);
+        }
+        insert.execute(&mut *tx).await.map_err(map_db_err)?;
+
+        let delete_sql = format!("DELETE FROM {} WHERE message_id IN ({})", table, placeholders);
+        let mut delete = sqlx::query(&delete_sql);
+        for message_id in &message_ids {
+            delete = delete.bind(message_id);
+        }
+        let deleted = delete.execute(&mut *tx).await.map_err(map_db_err)?.rows_affected();
+
+        tx.commit().await.map_err(map_db_err)?;
+        Ok(deleted)
+    }
+}
+
+/// 群聊消息的群组过滤条件
+enum GroupFilter<'a> {
+    /// 只迁移指定群组
+    Only(&'a str),
+    /// 排除单独配置了保留期限的群组
+    Except(&'a [String]),
+}
+
+/// 统一 group_id 格式：确保有 group_ 前缀
+fn normalize_group_id(group_id: &str) -> String {
+    if group_id.starts_with("group_") {
+        group_id.to_string()
+    } else {
+        format!("group_{}", group_id)
+    }
+}
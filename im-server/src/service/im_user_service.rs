@@ -1,4 +1,4 @@
-use crate::model::{ImUser, ImUserData};
+use crate::model::{ImUser, ImUserData, LastSeenVisibility};
 use crate::error::{ErrorCode, Result};
 use im_share::RedisClient;
 use sqlx::MySqlPool;
@@ -58,17 +58,15 @@ impl ImUserService {
             }
             
             // 通过 user_name 缓存
-            if let Some(ref user_name) = user.user_name {
-                if let Err(e) = redis.set_with_ttl(&self.cache_key_user_name(user_name), &user_json, ttl).await {
-                    tracing::warn!(error = ?e, "缓存 ImUser 信息失败 (user_name)");
-                }
+            if let Some(ref user_name) = user.user_name
+                && let Err(e) = redis.set_with_ttl(&self.cache_key_user_name(user_name), &user_json, ttl).await {
+                tracing::warn!(error = ?e, "缓存 ImUser 信息失败 (user_name)");
             }
             
             // 通过 mobile 缓存（如果有）
-            if let Some(ref mobile) = user.mobile {
-                if let Err(e) = redis.set_with_ttl(&self.cache_key_mobile(mobile), &user_json, ttl).await {
-                    tracing::warn!(error = ?e, "缓存 ImUser 信息失败 (mobile)");
-                }
+            if let Some(ref mobile) = user.mobile
+                && let Err(e) = redis.set_with_ttl(&self.cache_key_mobile(mobile), &user_json, ttl).await {
+                tracing::warn!(error = ?e, "缓存 ImUser 信息失败 (mobile)");
             }
         }
         Ok(())
@@ -281,6 +279,7 @@ impl ImUserService {
             silent_flag: 0,        // 默认未静音
             user_type: 1,          // 默认普通用户
             del_flag: 1,            // 默认未删除
+            last_seen_visibility: 0, // 默认所有人可见
             extra: None,
             create_time: Some(now),
             update_time: Some(now),
@@ -341,7 +340,7 @@ impl ImUserService {
         let user_data = sqlx::query_as::<_, ImUserData>(
             "SELECT user_id, name, avatar, gender, birthday, location, self_signature, 
                     friend_allow_type, forbidden_flag, disable_add_friend, silent_flag, 
-                    user_type, del_flag, extra, create_time, update_time, version 
+                    user_type, del_flag, last_seen_visibility, extra, create_time, update_time, version 
              FROM im_user_data 
              WHERE user_id = ? AND del_flag = 1"
         )
@@ -353,10 +352,9 @@ impl ImUserService {
         match user_data {
             Some(u) => {
                 // 缓存用户数据（TTL: 1小时）
-                if let Some(ref redis) = self.redis {
-                    if let Ok(data_json) = serde_json::to_string(&u) {
-                        let _ = redis.set_with_ttl(&self.cache_key_user_data(user_id), &data_json, 3600).await;
-                    }
+                if let Some(ref redis) = self.redis
+                    && let Ok(data_json) = serde_json::to_string(&u) {
+                    let _ = redis.set_with_ttl(&self.cache_key_user_data(user_id), &data_json, 3600).await;
                 }
                 Ok(u)
             },
@@ -378,6 +376,7 @@ impl ImUserService {
                         silent_flag: 0,
                         user_type: 1,
                         del_flag: 1,
+                        last_seen_visibility: 0,
                         extra: None,
                         create_time: Some(now),
                         update_time: Some(now),
@@ -391,10 +390,9 @@ impl ImUserService {
                     }
                     
                     // 缓存新创建的用户数据
-                    if let Some(ref redis) = self.redis {
-                        if let Ok(data_json) = serde_json::to_string(&default_user_data) {
-                            let _ = redis.set_with_ttl(&self.cache_key_user_data(user_id), &data_json, 3600).await;
-                        }
+                    if let Some(ref redis) = self.redis
+                        && let Ok(data_json) = serde_json::to_string(&default_user_data) {
+                        let _ = redis.set_with_ttl(&self.cache_key_user_data(user_id), &data_json, 3600).await;
                     }
                     
                     Ok(default_user_data)
@@ -417,7 +415,7 @@ impl ImUserService {
         .bind(&user_data.user_id)
         .bind(&user_data.name)
         .bind(&user_data.avatar)
-        .bind(&user_data.gender)
+        .bind(user_data.gender)
         .bind(&user_data.birthday)
         .bind(&user_data.location)
         .bind(&user_data.self_signature)
@@ -460,7 +458,7 @@ impl ImUserService {
         .bind(&user_data.user_id)
         .bind(&user_data.name)
         .bind(&user_data.avatar)
-        .bind(&user_data.gender)
+        .bind(user_data.gender)
         .bind(&user_data.birthday)
         .bind(&user_data.location)
         .bind(&user_data.self_signature)
@@ -484,5 +482,27 @@ impl ImUserService {
 
         Ok(())
     }
-}
 
+    /// 更新最后在线时间可见范围（不经过 upsert_user_data，避免客户端提交资料时覆盖隐私设置）
+    pub async fn update_last_seen_visibility(&self, user_id: &str, visibility: LastSeenVisibility) -> Result<()> {
+        // 确保 im_user_data 记录存在（不存在时会自动创建默认数据）
+        self.get_user_data(user_id).await?;
+
+        sqlx::query(
+            "UPDATE im_user_data SET last_seen_visibility = ?, update_time = ?, version = version + 1 
+             WHERE user_id = ? AND del_flag = 1"
+        )
+        .bind(visibility.as_i32())
+        .bind(now_timestamp())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| ErrorCode::Database)?;
+
+        if let Some(ref redis) = self.redis {
+            let _ = redis.del(&self.cache_key_user_data(user_id)).await;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,611 @@
+use crate::config::LinkPreviewSettings;
+use crate::redis::RedisClient;
+use im_share::LinkPreview;
+use reqwest::{Url, redirect::Policy};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// 文本消息类型
+pub const TEXT_MESSAGE_CONTENT_TYPE: i32 = 1;
+
+/// 最多跟随的重定向次数（每一跳都会重新做内网地址检查）
+const MAX_REDIRECTS: usize = 3;
+/// 抓取失败时的缓存时间（秒），避免反复请求同一个无效链接
+const NEGATIVE_CACHE_TTL_SECS: u64 = 600;
+const MAX_URL_LEN: usize = 2048;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 500;
+
+pub struct LinkPreviewService {
+    redis: Arc<RedisClient>,
+    settings: LinkPreviewSettings,
+}
+
+impl LinkPreviewService {
+    pub fn new(redis: Arc<RedisClient>, settings: LinkPreviewSettings) -> Self {
+        Self { redis, settings }
+    }
+
+    /// 为消息生成链接预览：只处理文本消息中的第一个链接，任何失败都只返回 None，不影响消息发送
+    /// 缓存未命中时最多等待 inline_wait_ms：抓取在后台任务中进行（最长 timeout_ms），
+    /// 等待超时后消息不带预览发出，抓取结果仍写入缓存，之后发送同一链接时直接使用
+    pub async fn preview_for_message(&self, message_content_type: i32, text: &str) -> Option<LinkPreview> {
+        if !self.settings.enabled || message_content_type != TEXT_MESSAGE_CONTENT_TYPE {
+            return None;
+        }
+        let url = find_first_url(text)?;
+        let cache_key = format!("link_preview:{}", url);
+
+        // 先查缓存（"null" 表示之前抓取失败）
+        if let Ok(Some(cached)) = self.redis.get(&cache_key).await {
+            return serde_json::from_str::<Option<LinkPreview>>(&cached).ok().flatten();
+        }
+
+        let fetch = tokio::spawn(fetch_and_cache(self.redis.clone(), self.settings.clone(), url.clone(), cache_key));
+        match tokio::time::timeout(Duration::from_millis(self.settings.inline_wait_ms), fetch).await {
+            Ok(Ok(preview)) => preview,
+            Ok(Err(e)) => {
+                warn!(url = %url, error = %e, "链接预览任务异常退出");
+                None
+            }
+            Err(_) => {
+                debug!(url = %url, "链接预览未能及时生成，消息不带预览发送，后台继续抓取");
+                None
+            }
+        }
+    }
+}
+
+/// 抓取链接预览并写入缓存（失败也缓存，避免反复请求同一个无效链接）
+async fn fetch_and_cache(redis: Arc<RedisClient>, settings: LinkPreviewSettings, url: Url, cache_key: String) -> Option<LinkPreview> {
+    let timeout = Duration::from_millis(settings.timeout_ms);
+    let allow_private_networks = settings.allow_private_networks;
+    let is_allowed = |ip: IpAddr| allow_private_networks || is_public_ip(ip);
+    let preview = match tokio::time::timeout(timeout, fetch_preview(&settings, url.clone(), &is_allowed)).await {
+        Ok(Ok(preview)) => preview,
+        Ok(Err(e)) => {
+            debug!(url = %url, error = %e, "生成链接预览失败");
+            None
+        }
+        Err(_) => {
+            debug!(url = %url, "生成链接预览超时");
+            None
+        }
+    };
+
+    let ttl = if preview.is_some() { settings.cache_ttl_secs } else { NEGATIVE_CACHE_TTL_SECS };
+    if let Ok(value) = serde_json::to_string(&preview)
+        && let Err(e) = redis.set_with_ttl(&cache_key, &value, ttl).await {
+            warn!(url = %url, error = %e, "缓存链接预览失败");
+        }
+
+    preview
+}
+
+/// 抓取页面并解析 OpenGraph 信息，每一跳都用 is_allowed 检查解析出的地址
+async fn fetch_preview(settings: &LinkPreviewSettings, url: Url, is_allowed: &impl Fn(IpAddr) -> bool) -> Result<Option<LinkPreview>, String> {
+    let mut current = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let addr = resolve_allowed_addr(&current, is_allowed).await?;
+        let host = current.host_str().ok_or("链接缺少主机名")?.to_string();
+
+        // 固定使用检查过的地址建立连接，防止 DNS 重绑定绕过内网检查
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .timeout(Duration::from_millis(settings.timeout_ms))
+            .resolve(&host, addr)
+            .user_agent("Mozilla/5.0 (compatible; VioletLinkPreview/1.0)")
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let mut response = client.get(current.clone())
+            .header(reqwest::header::ACCEPT, "text/html,application/xhtml+xml")
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_redirection() {
+            let location = response.headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or("重定向缺少 Location")?;
+            current = current.join(location).map_err(|e| e.to_string())?;
+            if !matches!(current.scheme(), "http" | "https") {
+                return Err("不支持的重定向协议".to_string());
+            }
+            continue;
+        }
+
+        if !response.status().is_success() {
+            return Err(format!("页面返回状态码 {}", response.status()));
+        }
+
+        let is_html = response.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/html") || ct.contains("application/xhtml"));
+        if !is_html {
+            return Ok(None);
+        }
+
+        // 只读取前 max_bytes 字节，OpenGraph 信息都在 <head> 中
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= settings.max_bytes {
+                body.truncate(settings.max_bytes);
+                break;
+            }
+        }
+
+        let html = String::from_utf8_lossy(&body);
+        return Ok(parse_preview(&url, &current, &html));
+    }
+
+    Err("重定向次数过多".to_string())
+}
+
+/// 解析主机地址，拒绝 is_allowed 不允许的地址（SSRF 防护：内网、回环等）
+async fn resolve_allowed_addr(url: &Url, is_allowed: &impl Fn(IpAddr) -> bool) -> Result<SocketAddr, String> {
+    let host = url.host_str().ok_or("链接缺少主机名")?;
+    let port = url.port_or_known_default().ok_or("无法确定端口")?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("解析域名失败: {}", e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err("解析域名失败".to_string());
+    }
+
+    if let Some(addr) = addrs.iter().find(|a| !is_allowed(a.ip())) {
+        return Err(format!("拒绝访问内网地址: {}", addr.ip()));
+    }
+
+    Ok(addrs[0])
+}
+
+/// 把链接预览写入消息的 extra 字段（JSON 对象），extra 不是 JSON 对象时保持不变
+pub fn attach_preview_to_extra(extra: Option<String>, preview: &LinkPreview) -> Option<String> {
+    let mut extra_json = match extra.as_deref() {
+        None | Some("") => serde_json::Map::new(),
+        Some(s) => match serde_json::from_str::<serde_json::Value>(s) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => return extra,
+        },
+    };
+    match serde_json::to_value(preview) {
+        Ok(value) => {
+            extra_json.insert("link_preview".to_string(), value);
+            Some(serde_json::Value::Object(extra_json).to_string())
+        }
+        Err(_) => extra,
+    }
+}
+
+/// 找到文本中的第一个 http/https 链接
+fn find_first_url(text: &str) -> Option<Url> {
+    let lower = text.to_ascii_lowercase();
+    let mut search_from = 0;
+    while search_from < lower.len() {
+        let rest = &lower[search_from..];
+        let start = match (rest.find("http://"), rest.find("https://")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => return None,
+        } + search_from;
+
+        let candidate: String = text[start..]
+            .chars()
+            .take_while(|c| !c.is_whitespace() && !matches!(c, '<' | '>' | '"' | '\'' | '`'))
+            .collect();
+        let candidate = candidate.trim_end_matches(|c: char| {
+            matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '}' | '。' | '，' | '！' | '？' | '）' | '、')
+                || !c.is_ascii()
+        });
+
+        if candidate.len() <= MAX_URL_LEN
+            && let Ok(url) = Url::parse(candidate)
+            && matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some() {
+                return Some(url);
+            }
+        search_from = start + "http://".len();
+    }
+    None
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || octets[0] == 0
+                // 100.64.0.0/10 运营商级 NAT
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+                // 198.18.0.0/15 基准测试
+                || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+                // 240.0.0.0/4 保留地址
+                || octets[0] >= 240)
+        }
+        IpAddr::V6(v6) => {
+            // 内嵌 IPv4 地址的格式按其中的 IPv4 地址判断
+            if let Some(v4) = embedded_ipv4(v6) {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let segments = v6.segments();
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // 64:ff9b:1::/48 本地 NAT64
+                || (segments[0] == 0x64 && segments[1] == 0xff9b && segments[2] == 1)
+                // 2001:db8::/32 文档地址
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+                // fc00::/7 唯一本地地址
+                || (segments[0] & 0xfe00) == 0xfc00
+                // fe80::/10 链路本地地址
+                || (segments[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// IPv6 地址中内嵌的 IPv4 地址：
+/// - ::ffff:a.b.c.d IPv4 映射地址
+/// - ::a.b.c.d IPv4 兼容地址（包括 ::1，按 0.0.0.1 处理）
+/// - 64:ff9b::/96 NAT64
+/// - 2002::/16 6to4（IPv4 地址在第 2、3 段）
+fn embedded_ipv4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = v6.segments();
+    let from_segments = |high: u16, low: u16| {
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low] | [0, 0, 0, 0, 0, 0, high, low] => Some(from_segments(high, low)),
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(from_segments(high, low)),
+        [0x2002, high, low, ..] => Some(from_segments(high, low)),
+        _ => None,
+    }
+}
+
+/// 从 HTML 中解析 OpenGraph 信息，没有标题和描述时返回 None
+fn parse_preview(original_url: &Url, page_url: &Url, html: &str) -> Option<LinkPreview> {
+    let lower = html.to_ascii_lowercase();
+    // OpenGraph 信息都在 <head> 中，找不到 </head> 时解析读取到的全部内容
+    let head_end = lower.find("</head>").unwrap_or(lower.len());
+    let head = &html[..head_end];
+    let head_lower = &lower[..head_end];
+
+    let mut preview = LinkPreview {
+        url: original_url.to_string(),
+        ..Default::default()
+    };
+    let mut fallback_description = None;
+    let mut twitter_title = None;
+    let mut twitter_image = None;
+
+    let mut pos = 0;
+    while let Some(offset) = head_lower[pos..].find("<meta") {
+        let start = pos + offset;
+        let Some(end) = head_lower[start..].find('>').map(|e| start + e) else {
+            break;
+        };
+        let attrs = parse_attributes(&head[start + "<meta".len()..end]);
+        pos = end + 1;
+
+        let key = attrs.iter()
+            .find(|(name, _)| name == "property" || name == "name")
+            .map(|(_, value)| value.to_ascii_lowercase());
+        let content = attrs.iter()
+            .find(|(name, _)| name == "content")
+            .map(|(_, value)| decode_entities(value).trim().to_string())
+            .filter(|value| !value.is_empty());
+        let (Some(key), Some(content)) = (key, content) else {
+            continue;
+        };
+
+        match key.as_str() {
+            "og:title" => preview.title = Some(content),
+            "og:description" => preview.description = Some(content),
+            "og:image" | "og:image:url" | "og:image:secure_url" if preview.image.is_none() => preview.image = Some(content),
+            "og:site_name" => preview.site_name = Some(content),
+            "twitter:title" => twitter_title = Some(content),
+            "twitter:image" => twitter_image = Some(content),
+            "description" | "twitter:description" if fallback_description.is_none() => fallback_description = Some(content),
+            _ => {}
+        }
+    }
+
+    if preview.title.is_none() {
+        preview.title = twitter_title.or_else(|| {
+            let start = head_lower.find("<title")?;
+            let content_start = start + head_lower[start..].find('>')? + 1;
+            let content_end = content_start + head_lower[content_start..].find("</title>")?;
+            Some(decode_entities(&head[content_start..content_end]).trim().to_string())
+        }).filter(|t| !t.is_empty());
+    }
+    if preview.description.is_none() {
+        preview.description = fallback_description;
+    }
+    if preview.image.is_none() {
+        preview.image = twitter_image;
+    }
+
+    // 图片地址可能是相对路径，只保留 http/https 地址
+    preview.image = preview.image
+        .and_then(|image| page_url.join(&image).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(|image| image.to_string());
+    preview.title = preview.title.map(|t| truncate_chars(&t, MAX_TITLE_CHARS));
+    preview.description = preview.description.map(|d| truncate_chars(&d, MAX_DESCRIPTION_CHARS));
+
+    if preview.title.is_none() && preview.description.is_none() {
+        return None;
+    }
+    Some(preview)
+}
+
+/// 解析标签属性：name="value" / name='value' / name=value
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut chars = tag.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() || c == '/' {
+            chars.next();
+            continue;
+        }
+
+        // 属性名
+        let name_start = i;
+        let mut name_end = tag.len();
+        while let Some(&(j, c)) = chars.peek() {
+            if c.is_whitespace() || c == '=' || c == '/' {
+                name_end = j;
+                break;
+            }
+            chars.next();
+        }
+        let name = tag[name_start..name_end].to_ascii_lowercase();
+
+        while chars.peek().is_some_and(|&(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().map(|&(_, c)| c) != Some('=') {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        chars.next();
+        while chars.peek().is_some_and(|&(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+
+        // 属性值
+        let value = match chars.peek().map(|&(_, c)| c) {
+            Some(quote @ ('"' | '\'')) => {
+                chars.next();
+                let value_start = chars.peek().map(|&(j, _)| j).unwrap_or(tag.len());
+                let mut value_end = tag.len();
+                for (j, c) in chars.by_ref() {
+                    if c == quote {
+                        value_end = j;
+                        break;
+                    }
+                }
+                &tag[value_start..value_end]
+            }
+            Some(_) => {
+                let value_start = chars.peek().map(|&(j, _)| j).unwrap_or(tag.len());
+                let mut value_end = tag.len();
+                while let Some(&(j, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        value_end = j;
+                        break;
+                    }
+                    chars.next();
+                }
+                &tag[value_start..value_end]
+            }
+            None => "",
+        };
+        attrs.push((name, value.to_string()));
+    }
+    attrs
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn truncate_chars(value: &str, max_chars: usize) -> String {
+    if value.chars().count() <= max_chars {
+        value.to_string()
+    } else {
+        let mut truncated: String = value.chars().take(max_chars).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::header, response::{Html, IntoResponse}, routing::get};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn test_settings(max_bytes: usize) -> LinkPreviewSettings {
+        LinkPreviewSettings {
+            enabled: true,
+            timeout_ms: 2000,
+            inline_wait_ms: 2000,
+            max_bytes,
+            cache_ttl_secs: 60,
+            allow_private_networks: false,
+        }
+    }
+
+    #[test]
+    fn is_public_ip_rejects_private_ipv4() {
+        for addr in ["10.0.0.1", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.169.254", "0.0.0.0",
+                     "100.64.0.1", "198.18.0.1", "224.0.0.1", "240.0.0.1", "255.255.255.255", "192.0.2.1"] {
+            assert!(!is_public_ip(ip(addr)), "{}", addr);
+        }
+        for addr in ["8.8.8.8", "1.1.1.1", "100.128.0.1", "198.20.0.1"] {
+            assert!(is_public_ip(ip(addr)), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn is_public_ip_rejects_private_ipv6() {
+        for addr in ["::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "ff02::1", "2001:db8::1", "64:ff9b:1::a00:1"] {
+            assert!(!is_public_ip(ip(addr)), "{}", addr);
+        }
+        assert!(is_public_ip(ip("2606:4700:4700::1111")));
+    }
+
+    #[test]
+    fn is_public_ip_checks_embedded_ipv4() {
+        // IPv4 映射、IPv4 兼容、NAT64、6to4 中内嵌的内网地址
+        for addr in ["::ffff:127.0.0.1", "::ffff:10.0.0.1", "::10.0.0.1", "::127.0.0.1",
+                     "64:ff9b::10.0.0.1", "64:ff9b::a9fe:a9fe", "2002:7f00:1::", "2002:c0a8:101::1"] {
+            assert!(!is_public_ip(ip(addr)), "{}", addr);
+        }
+        for addr in ["::ffff:8.8.8.8", "64:ff9b::8.8.8.8", "2002:808:808::1"] {
+            assert!(is_public_ip(ip(addr)), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn find_first_url_extracts_http_links() {
+        let url = find_first_url("看看这个 https://example.com/a?b=1。").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/a?b=1");
+        let url = find_first_url("(see http://example.com/path), thanks").unwrap();
+        assert_eq!(url.as_str(), "http://example.com/path");
+        let url = find_first_url("HTTPS://Example.com/x").unwrap();
+        assert_eq!(url.host_str(), Some("example.com"));
+        assert!(find_first_url("ftp://example.com 和 http:// 都不是").is_none());
+        assert!(find_first_url("没有链接").is_none());
+        let long = format!("https://example.com/{}", "a".repeat(MAX_URL_LEN));
+        assert!(find_first_url(&long).is_none());
+    }
+
+    #[test]
+    fn parse_preview_reads_open_graph() {
+        let page = Url::parse("https://example.com/post/1").unwrap();
+        let html = r#"<html><head>
+            <title>Fallback</title>
+            <meta property="og:title" content="Hello &amp; welcome">
+            <meta name='description' content='Plain description'>
+            <meta property=og:image content=/img/cover.png>
+            <meta property="og:site_name" content="Example">
+            </head><body><meta property="og:description" content="ignored"></body></html>"#;
+        let preview = parse_preview(&page, &page, html).unwrap();
+        assert_eq!(preview.url, "https://example.com/post/1");
+        assert_eq!(preview.title.as_deref(), Some("Hello & welcome"));
+        assert_eq!(preview.description.as_deref(), Some("Plain description"));
+        assert_eq!(preview.image.as_deref(), Some("https://example.com/img/cover.png"));
+        assert_eq!(preview.site_name.as_deref(), Some("Example"));
+    }
+
+    #[test]
+    fn parse_preview_falls_back_and_filters() {
+        let page = Url::parse("https://example.com/").unwrap();
+        let preview = parse_preview(&page, &page, "<head><title> 标题 </title><meta property=\"og:image\" content=\"javascript:alert(1)\"></head>").unwrap();
+        assert_eq!(preview.title.as_deref(), Some("标题"));
+        assert!(preview.image.is_none());
+
+        let long_title = "长".repeat(MAX_TITLE_CHARS + 10);
+        let preview = parse_preview(&page, &page, &format!("<meta property=\"og:title\" content=\"{}\">", long_title)).unwrap();
+        assert_eq!(preview.title.unwrap().chars().count(), MAX_TITLE_CHARS + 1);
+
+        assert!(parse_preview(&page, &page, "<html><body>no metadata</body></html>").is_none());
+    }
+
+    /// 本地 HTTP 服务：/page 返回带 OpenGraph 的页面，/big 在 OpenGraph 信息之前填充大量内容，
+    /// /redirect-local 重定向到 /page，/redirect-private 重定向到内网地址
+    async fn serve() -> SocketAddr {
+        async fn page() -> Html<&'static str> {
+            Html("<html><head><meta property=\"og:title\" content=\"Local page\"></head></html>")
+        }
+        async fn big() -> Html<String> {
+            Html(format!("<html><head><!-- {} --><meta property=\"og:title\" content=\"Too late\"></head></html>", "x".repeat(64 * 1024)))
+        }
+        fn redirect(location: &'static str) -> impl IntoResponse {
+            (axum::http::StatusCode::FOUND, [(header::LOCATION, location)])
+        }
+
+        let app = Router::new()
+            .route("/page", get(page))
+            .route("/big", get(big))
+            .route("/redirect-local", get(|| async { redirect("/page") }))
+            .route("/redirect-private", get(|| async { redirect("http://10.0.0.1/page") }))
+            .route("/redirect-mapped", get(|| async { redirect("http://[::ffff:192.168.0.1]/page") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        addr
+    }
+
+    /// 只允许本地测试服务所在的回环地址，其他地址按公网规则检查
+    fn allow_loopback(ip: IpAddr) -> bool {
+        ip.is_loopback() || is_public_ip(ip)
+    }
+
+    #[tokio::test]
+    async fn fetch_preview_follows_allowed_redirect() {
+        let addr = serve().await;
+        let url = Url::parse(&format!("http://{}/redirect-local", addr)).unwrap();
+        let preview = fetch_preview(&test_settings(64 * 1024), url.clone(), &allow_loopback).await.unwrap().unwrap();
+        assert_eq!(preview.url, url.to_string());
+        assert_eq!(preview.title.as_deref(), Some("Local page"));
+    }
+
+    #[tokio::test]
+    async fn fetch_preview_revalidates_redirect_target() {
+        let addr = serve().await;
+        for path in ["redirect-private", "redirect-mapped"] {
+            let url = Url::parse(&format!("http://{}/{}", addr, path)).unwrap();
+            let error = fetch_preview(&test_settings(64 * 1024), url, &allow_loopback).await.unwrap_err();
+            assert!(error.starts_with("拒绝访问内网地址"), "{}: {}", path, error);
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_preview_rejects_private_start_url() {
+        let addr = serve().await;
+        let url = Url::parse(&format!("http://{}/page", addr)).unwrap();
+        let error = fetch_preview(&test_settings(64 * 1024), url, &is_public_ip).await.unwrap_err();
+        assert!(error.starts_with("拒绝访问内网地址"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn fetch_preview_reads_at_most_max_bytes() {
+        let addr = serve().await;
+        let url = Url::parse(&format!("http://{}/big", addr)).unwrap();
+        assert!(fetch_preview(&test_settings(4096), url.clone(), &allow_loopback).await.unwrap().is_none());
+        let preview = fetch_preview(&test_settings(128 * 1024), url, &allow_loopback).await.unwrap().unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Too late"));
+    }
+}
@@ -6,6 +6,12 @@ pub mod im_message_service;
 pub mod im_chat_service;
 pub mod im_group_service;
 pub mod im_outbox_service;
+pub mod im_export_service;
+pub mod im_retention_service;
+pub mod link_preview_service;
+pub mod contact_card_service;
+pub mod presence_service;
+pub mod device_session_service;
 
 pub use user_service::UserService;
 pub use friend_service::FriendService;
@@ -15,4 +21,10 @@ pub use im_message_service::ImMessageService;
 pub use im_chat_service::ImChatService;
 pub use im_group_service::{ImGroupService, UpdateGroupRequest};
 pub use im_outbox_service::ImOutboxService;
+pub use im_export_service::ImExportService;
+pub use im_retention_service::ImRetentionService;
+pub use link_preview_service::LinkPreviewService;
+pub use contact_card_service::ContactCardService;
+pub use presence_service::PresenceService;
+pub use device_session_service::DeviceSessionService;
 pub use im_share::SubscriptionService;
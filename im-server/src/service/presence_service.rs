@@ -0,0 +1,180 @@
+use crate::config::PresenceSettings;
+use crate::error::{ErrorCode, Result};
+use crate::model::LastSeenVisibility;
+use crate::mqtt::MqttPublisher;
+use crate::service::{ImFriendshipService, ImGroupService, ImUserService, UserService};
+use im_share::{ChatMessage, PresenceRegistry, PresenceStatus, RedisClient, UserPresence, encode_message, mqtt_user_topic, now_timestamp, stamp_expiry};
+use im_share::expiry::PRESENCE;
+use sqlx::MySqlPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// 最近一次推送给好友的状态保留时长：30 天
+const LAST_NOTIFIED_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// 在线状态查询（遵守最后在线时间的隐私设置）和状态变化通知
+pub struct PresenceService {
+    pool: MySqlPool,
+    redis: Arc<RedisClient>,
+    registry: PresenceRegistry,
+}
+
+impl PresenceService {
+    pub fn new(pool: MySqlPool, redis: Arc<RedisClient>) -> Self {
+        Self {
+            registry: PresenceRegistry::new(redis.clone()),
+            pool,
+            redis,
+        }
+    }
+
+    /// 用户的最后在线时间可见范围（没有 im_user_data 时按所有人可见处理）
+    pub async fn last_seen_visibility(&self, open_id: &str) -> LastSeenVisibility {
+        ImUserService::with_redis(self.pool.clone(), self.redis.clone())
+            .get_user_data(open_id)
+            .await
+            .map(|data| LastSeenVisibility::from_i32(data.last_seen_visibility))
+            .unwrap_or(LastSeenVisibility::Everyone)
+    }
+
+    /// viewer 能否看到 owner 的最后在线时间
+    async fn last_seen_visible_to(&self, owner: &str, viewer: &str) -> bool {
+        if owner == viewer {
+            return true;
+        }
+        match self.last_seen_visibility(owner).await {
+            LastSeenVisibility::Everyone => true,
+            LastSeenVisibility::Contacts => ImFriendshipService::new(self.pool.clone())
+                .is_friend(owner, viewer)
+                .await
+                .unwrap_or(false),
+            LastSeenVisibility::Nobody => false,
+        }
+    }
+
+    /// 以 viewer 的身份批量查询在线状态
+    /// - 设备明细（device_id 即连接的 subscription_id）只返回给用户本人
+    /// - 最后在线时间按被查询用户的隐私设置隐藏
+    pub async fn get_for_viewer(&self, open_ids: &[String], viewer: &str) -> Result<Vec<UserPresence>> {
+        let mut presences = self.registry.get_many(open_ids).await.map_err(|e| {
+            warn!(error = %e, "查询在线状态失败");
+            ErrorCode::Internal
+        })?;
+        for presence in presences.iter_mut() {
+            if presence.open_id == viewer {
+                continue;
+            }
+            presence.devices.clear();
+            if !self.last_seen_visible_to(&presence.open_id, viewer).await {
+                presence.last_seen = None;
+            }
+        }
+        Ok(presences)
+    }
+
+    /// 开始一个去抖窗口，返回 false 表示窗口内已有待推送的变化（由那一次负责推送最终状态）
+    /// 多个 im-server 实例都会收到变化通知，也靠这个标记保证只推送一次
+    pub async fn begin_notify_debounce(&self, open_id: &str, debounce_ms: u64) -> bool {
+        let key = format!("presence:notify:pending:{}", open_id);
+        self.redis
+            .set_nx_with_ttl_ms(&key, "1", debounce_ms.max(1))
+            .await
+            .unwrap_or_else(|e| {
+                warn!(open_id = %open_id, error = %e, "在线状态通知去抖检查失败，直接推送");
+                true
+            })
+    }
+
+    /// 去抖窗口结束后调用：读取最新状态，和上一次推送的状态不同时推送给好友（以及可选的同群成员）
+    pub async fn notify_if_changed(&self, publisher: &MqttPublisher, open_id: &str, settings: &PresenceSettings) {
+        let presence = match self.registry.get(open_id).await {
+            Ok(presence) => presence,
+            Err(e) => {
+                warn!(open_id = %open_id, error = %e, "读取在线状态失败，跳过状态变化通知");
+                return;
+            }
+        };
+        let status = serde_json::json!(presence.status);
+        let status_str = status.as_str().unwrap_or_default();
+
+        let last_key = format!("presence:notify:last:{}", open_id);
+        if self.redis.get(&last_key).await.ok().flatten().as_deref() == Some(status_str) {
+            debug!(open_id = %open_id, status = %status_str, "在线状态没有变化，不推送");
+            return;
+        }
+        if let Err(e) = self.redis.set_with_ttl(&last_key, status_str, LAST_NOTIFIED_TTL_SECS).await {
+            warn!(open_id = %open_id, error = %e, "记录最近推送的在线状态失败");
+        }
+
+        let friends: HashSet<String> = ImFriendshipService::new(self.pool.clone())
+            .get_friends(open_id)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| f.to_id)
+            .collect();
+        let mut receivers = friends.clone();
+        if settings.notify_group_members {
+            let group_service = ImGroupService::new(self.pool.clone());
+            for group in group_service.get_user_groups(open_id).await.unwrap_or_default() {
+                for member in group_service.get_group_members(&group.group_id).await.unwrap_or_default() {
+                    receivers.insert(member.member_id);
+                }
+            }
+        }
+        receivers.remove(open_id);
+        if receivers.is_empty() {
+            return;
+        }
+
+        // 离线时才有意义的最后在线时间，按隐私设置决定带给谁
+        let visibility = self.last_seen_visibility(open_id).await;
+        let last_seen = if presence.status == PresenceStatus::Offline { presence.last_seen } else { None };
+
+        let user_service = UserService::with_redis(self.pool.clone(), self.redis.clone());
+        let now = now_timestamp();
+        let mut sent = 0usize;
+        for receiver in &receivers {
+            let show_last_seen = match visibility {
+                LastSeenVisibility::Everyone => true,
+                LastSeenVisibility::Contacts => friends.contains(receiver),
+                LastSeenVisibility::Nobody => false,
+            };
+            let notification = ChatMessage {
+                message_id: uuid::Uuid::new_v4().to_string(),
+                from_user_id: open_id.to_string(),
+                to_user_id: receiver.clone(),
+                message: serde_json::json!({
+                    "type": "presence",
+                    "open_id": open_id,
+                    "status": status,
+                    "last_seen": if show_last_seen { last_seen } else { None },
+                }).to_string(),
+                timestamp_ms: now,
+                file_url: None,
+                file_name: None,
+                file_type: None,
+                chat_type: None,
+                link_preview: None,
+                voice: None,
+                location: None,
+                card: None,
+            };
+            let Ok(payload) = encode_message(&notification) else {
+                continue;
+            };
+            let payload = stamp_expiry(payload, PRESENCE);
+            let Ok(receiver_user) = user_service.get_by_open_id(receiver).await else {
+                continue;
+            };
+            // 在线状态是临时信息，离线期间的变化没有意义（客户端上线后主动查询），使用 QoS 0
+            let topic = mqtt_user_topic(&receiver_user.get_mqtt_id().to_string());
+            match publisher.publish_transient(&topic, payload).await {
+                Ok(()) => sent += 1,
+                Err(e) => warn!(receiver = %receiver, %topic, error = %e, "在线状态通知MQTT发布失败"),
+            }
+        }
+        info!(open_id = %open_id, status = %status_str, receivers = sent, "已推送在线状态变化");
+    }
+}
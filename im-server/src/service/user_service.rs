@@ -68,10 +68,9 @@ impl UserService {
             }
             
             // 通过 open_id 缓存
-            if let Some(ref open_id) = user.open_id {
-                if let Err(e) = redis.set_with_ttl(&self.cache_key_open_id(open_id), &user_json, ttl).await {
-                    tracing::warn!(error = ?e, "缓存用户信息失败 (open_id)");
-                }
+            if let Some(ref open_id) = user.open_id
+                && let Err(e) = redis.set_with_ttl(&self.cache_key_open_id(open_id), &user_json, ttl).await {
+                tracing::warn!(error = ?e, "缓存用户信息失败 (open_id)");
             }
             
             // 通过 name 缓存
@@ -85,10 +84,9 @@ impl UserService {
             }
             
             // 通过 phone 缓存（如果有）
-            if let Some(ref phone) = user.phone {
-                if let Err(e) = redis.set_with_ttl(&self.cache_key_phone(phone), &user_json, ttl).await {
-                    tracing::warn!(error = ?e, "缓存用户信息失败 (phone)");
-                }
+            if let Some(ref phone) = user.phone
+                && let Err(e) = redis.set_with_ttl(&self.cache_key_phone(phone), &user_json, ttl).await {
+                tracing::warn!(error = ?e, "缓存用户信息失败 (phone)");
             }
         }
         Ok(())
@@ -336,19 +334,18 @@ impl UserService {
         }
         
         // 如果提供了手机号，检查是否已被占用
-        if let Some(ref phone_num) = phone {
-            if !phone_num.is_empty() {
-                // 简单的手机号格式验证（11位数字）
-                if phone_num.len() != 11 || !phone_num.chars().all(|c| c.is_ascii_digit()) {
-                    warn!("手机号格式不正确: {}", phone_num);
-                    return Err(ErrorCode::InvalidInput);
-                }
+        if let Some(ref phone_num) = phone
+            && !phone_num.is_empty() {
+            // 简单的手机号格式验证（11位数字）
+            if phone_num.len() != 11 || !phone_num.chars().all(|c| c.is_ascii_digit()) {
+                warn!("手机号格式不正确: {}", phone_num);
+                return Err(ErrorCode::InvalidInput);
+            }
                 
-                // 检查手机号是否已被占用
-                if self.get_by_phone(phone_num).await.is_ok() {
-                    warn!("手机号已被占用: {}", phone_num);
-                    return Err(ErrorCode::InvalidInput);
-                }
+            // 检查手机号是否已被占用
+            if self.get_by_phone(phone_num).await.is_ok() {
+                warn!("手机号已被占用: {}", phone_num);
+                return Err(ErrorCode::InvalidInput);
             }
         }
 
@@ -22,6 +22,9 @@ pub struct Claims {
     // 标识这是 open_id 的数字形式还是数据库 id
     #[serde(default)]
     pub is_open_id: bool,
+    // 登录会话（subscription_id），带设备类型登录时签发，会话被吊销后 token 立即失效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
     pub exp: i64,
     pub iat: i64,
 }
@@ -36,6 +39,7 @@ impl Claims {
         Claims {
             user_id: open_id_number,
             is_open_id: true,
+            sid: None,
             exp: exp.timestamp(),
             iat: now.timestamp(),
         }
@@ -49,6 +53,7 @@ impl Claims {
         Claims {
             user_id,
             is_open_id: false,
+            sid: None,
             exp: exp.timestamp(),
             iat: now.timestamp(),
         }
@@ -74,6 +79,19 @@ pub fn generate_token_with_open_id(open_id_number: u64, jwt_cfg: &JwtSettings) -
     Ok(token)
 }
 
+/// 为登录会话生成 token，使用 open_id（解析为数字），token 绑定会话的 subscription_id
+pub fn generate_session_token(open_id_number: u64, subscription_id: &str, jwt_cfg: &JwtSettings) -> anyhow::Result<String> {
+    let mut claims = Claims::new_with_open_id(open_id_number, jwt_cfg.expiration_hours);
+    claims.sid = Some(subscription_id.to_string());
+    let token = encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(jwt_cfg.secret.as_ref()),
+    )?;
+    
+    Ok(token)
+}
+
 /// 生成 token，使用数据库 id（向后兼容）
 pub fn generate_token(user_id: u64, jwt_cfg: &JwtSettings) -> anyhow::Result<String> {
     let claims = Claims::new_with_db_id(user_id, jwt_cfg.expiration_hours);
@@ -98,3 +116,17 @@ pub fn verify_token(token: &str, jwt_cfg: &JwtSettings) -> anyhow::Result<Claims
     Ok(token_data.claims)
 }
 
+
+// im-connect 调用 im-server 内部接口（/internal/*）时的服务认证：
+// 不使用用户的 JWT（连接可能比 token 活得久），以两边配置的共享密钥证明身份，并指明代为操作的用户
+
+/// 内部接口的共享密钥请求头
+pub const INTERNAL_TOKEN_HEADER: &str = "x-internal-token";
+/// 代为操作的用户（open_id）请求头
+pub const ACTING_USER_HEADER: &str = "x-acting-user";
+
+/// 比较共享密钥，耗时与内容无关
+pub fn internal_token_matches(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected.bytes().zip(provided.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
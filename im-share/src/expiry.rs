@@ -0,0 +1,179 @@
+use crate::utils::now_timestamp;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// 推送的有效期
+// 消息可以在信封中声明有效期，过期后不再推送、不再转存为离线消息，也不会在补发时出现：
+// - expires_at：过期时间（毫秒时间戳）
+// - ttl：有效期（秒），从 timestamp_ms 开始计算；同时存在时以 expires_at 为准
+// 生产方按消息类型从策略表取默认有效期写入 expires_at（stamp_expiry），消费方（MQTT 实时推送、
+// Redis 离线消息、收件箱日志补发、数据库离线消息查询）只看信封字段，不再解析消息内容
+// 没有声明有效期的消息永不过期
+
+/// 过期时间字段（毫秒时间戳）
+pub const EXPIRES_AT_FIELD: &str = "expires_at";
+/// 有效期字段（秒，从 timestamp_ms 开始计算）
+pub const TTL_FIELD: &str = "ttl";
+
+/// 语音/视频通话邀请
+pub const CALL_INVITE: &str = "call_invite";
+/// 正在输入
+pub const TYPING: &str = "typing";
+/// 好友在线状态变化
+pub const PRESENCE: &str = "presence";
+
+/// 内置的有效期策略（类型, 秒）
+const DEFAULT_POLICIES: &[(&str, u64)] = &[
+    (CALL_INVITE, 60),
+    (TYPING, 10),
+    (PRESENCE, 60),
+];
+
+static POLICIES: Lazy<RwLock<HashMap<String, u64>>> = Lazy::new(|| {
+    RwLock::new(
+        DEFAULT_POLICIES
+            .iter()
+            .map(|(kind, ttl)| (kind.to_string(), *ttl))
+            .collect(),
+    )
+});
+
+/// 注册或覆盖某类消息的有效期（秒）
+pub fn register_expiry_policy(kind: &str, ttl_secs: u64) {
+    POLICIES.write().unwrap().insert(kind.to_string(), ttl_secs);
+}
+
+/// 某类消息的有效期（秒），没有策略的类型永不过期
+pub fn expiry_ttl(kind: &str) -> Option<u64> {
+    POLICIES.read().unwrap().get(kind).copied()
+}
+
+/// 按类型的策略给消息写入 expires_at，已经声明有效期或没有策略时原样返回
+pub fn stamp_expiry(payload: Vec<u8>, kind: &str) -> Vec<u8> {
+    match expiry_ttl(kind) {
+        Some(ttl_secs) => stamp_expiry_ttl(payload, ttl_secs),
+        None => payload,
+    }
+}
+
+/// 按指定的有效期（秒）给消息写入 expires_at，已经声明有效期或不是 JSON 对象时原样返回
+pub fn stamp_expiry_ttl(payload: Vec<u8>, ttl_secs: u64) -> Vec<u8> {
+    let Ok(Value::Object(mut map)) = serde_json::from_slice::<Value>(&payload) else {
+        return payload;
+    };
+    if map.contains_key(EXPIRES_AT_FIELD) || map.contains_key(TTL_FIELD) {
+        return payload;
+    }
+    let created_at = map
+        .get("timestamp_ms")
+        .and_then(|t| t.as_i64())
+        .unwrap_or_else(now_timestamp);
+    map.insert(EXPIRES_AT_FIELD.to_string(), Value::from(created_at + (ttl_secs as i64) * 1000));
+    serde_json::to_vec(&map).unwrap_or(payload)
+}
+
+/// 消息声明的过期时间（毫秒时间戳）
+pub fn expires_at(message: &Value) -> Option<i64> {
+    if let Some(expires_at) = message.get(EXPIRES_AT_FIELD).and_then(|v| v.as_i64()) {
+        return Some(expires_at);
+    }
+    let ttl_secs = message.get(TTL_FIELD)?.as_i64()?;
+    let created_at = message.get("timestamp_ms")?.as_i64()?;
+    Some(created_at + ttl_secs * 1000)
+}
+
+/// 消息是否已过期
+pub fn is_expired(message: &Value, now_ms: i64) -> bool {
+    expires_at(message).is_some_and(|expires_at| now_ms >= expires_at)
+}
+
+/// 消息原文（JSON）是否已过期，不是 JSON 的消息永不过期
+pub fn is_payload_expired(payload: &str, now_ms: i64) -> bool {
+    serde_json::from_str::<Value>(payload).is_ok_and(|message| is_expired(&message, now_ms))
+}
+
+/// 没有信封的消息（数据库中的记录）按类型的策略判断是否过期
+pub fn is_kind_expired(kind: &str, created_at_ms: i64, now_ms: i64) -> bool {
+    expiry_ttl(kind).is_some_and(|ttl_secs| now_ms >= created_at_ms + (ttl_secs as i64) * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stamped(payload: Value, ttl_secs: u64) -> Value {
+        let bytes = stamp_expiry_ttl(serde_json::to_vec(&payload).unwrap(), ttl_secs);
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn stamp_expiry_ttl_counts_from_timestamp_ms() {
+        let message = stamped(json!({"timestamp_ms": 1_000}), 60);
+        assert_eq!(message[EXPIRES_AT_FIELD], 61_000);
+    }
+
+    #[test]
+    fn stamp_expiry_ttl_keeps_declared_expiry() {
+        let message = stamped(json!({"timestamp_ms": 1_000, "expires_at": 5_000}), 60);
+        assert_eq!(message[EXPIRES_AT_FIELD], 5_000);
+        let message = stamped(json!({"timestamp_ms": 1_000, "ttl": 5}), 60);
+        assert!(message.get(EXPIRES_AT_FIELD).is_none());
+    }
+
+    #[test]
+    fn stamp_expiry_ttl_leaves_non_objects_unchanged() {
+        for payload in [&b"not json"[..], b"[1, 2]", b"\"text\"", b"42"] {
+            assert_eq!(stamp_expiry_ttl(payload.to_vec(), 60), payload);
+        }
+    }
+
+    #[test]
+    fn stamp_expiry_uses_policy_table() {
+        let message: Value = serde_json::from_slice(&stamp_expiry(br#"{"timestamp_ms": 0}"#.to_vec(), TYPING)).unwrap();
+        assert_eq!(message[EXPIRES_AT_FIELD], 10_000);
+        let payload = br#"{"timestamp_ms": 0}"#.to_vec();
+        assert_eq!(stamp_expiry(payload.clone(), "no_such_kind"), payload);
+    }
+
+    #[test]
+    fn expires_at_prefers_explicit_expiry_over_ttl() {
+        assert_eq!(expires_at(&json!({"timestamp_ms": 1_000, "ttl": 10, "expires_at": 2_000})), Some(2_000));
+        assert_eq!(expires_at(&json!({"timestamp_ms": 1_000, "ttl": 10})), Some(11_000));
+        // ttl 需要 timestamp_ms 才能计算
+        assert_eq!(expires_at(&json!({"ttl": 10})), None);
+        assert_eq!(expires_at(&json!({"timestamp_ms": 1_000})), None);
+        assert_eq!(expires_at(&json!("text")), None);
+    }
+
+    #[test]
+    fn is_expired_at_boundary() {
+        let message = json!({"expires_at": 2_000});
+        assert!(!is_expired(&message, 1_999));
+        assert!(is_expired(&message, 2_000));
+        assert!(!is_expired(&json!({"timestamp_ms": 0}), i64::MAX));
+    }
+
+    #[test]
+    fn is_payload_expired_ignores_non_json() {
+        assert!(is_payload_expired(r#"{"expires_at": 1}"#, 2));
+        assert!(!is_payload_expired("not json", i64::MAX));
+        assert!(!is_payload_expired("[1]", i64::MAX));
+    }
+
+    #[test]
+    fn is_kind_expired_uses_policy_ttl() {
+        assert!(!is_kind_expired(CALL_INVITE, 0, 59_999));
+        assert!(is_kind_expired(CALL_INVITE, 0, 60_000));
+        assert!(!is_kind_expired("no_such_kind", 0, i64::MAX));
+    }
+
+    #[test]
+    fn registered_policy_overrides_default() {
+        register_expiry_policy("test_kind", 5);
+        assert_eq!(expiry_ttl("test_kind"), Some(5));
+        assert!(is_kind_expired("test_kind", 0, 5_000));
+    }
+}
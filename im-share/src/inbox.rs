@@ -0,0 +1,132 @@
+use crate::redis::RedisClient;
+use std::sync::Arc;
+use tracing::warn;
+
+// 用户收件箱日志（Redis），用于断线重连后按序号补发
+// - inbox:seq:{open_id}  序号计数器（INCR），每条需要可靠送达的推送分配一个递增序号
+// - inbox:log:{open_id}  ZSet，score = 序号，member = 带 seq 字段的消息 JSON
+// im-server 发布到用户 topic 之前先写日志，并把序号写进消息的 seq 字段；
+// 客户端记录最后确认的 seq，重连时带上，im-connect 从日志补发之后的消息，再用 seq 去掉实时推送中的重复
+// 正在输入、在线状态等临时推送不写日志、不带 seq
+
+/// 消息中的序号字段
+pub const SEQ_FIELD: &str = "seq";
+/// 每个用户最多保留的日志条数，更早的消息需要客户端通过历史消息接口同步
+pub const INBOX_LOG_MAX_ENTRIES: i64 = 1000;
+/// 日志保留时长：7 天（与 Redis 离线消息一致）
+const INBOX_LOG_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+/// 序号计数器保留时长：30 天，比日志长，避免用户不活跃一段时间后序号从头开始
+const INBOX_SEQ_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+fn seq_key(open_id: &str) -> String {
+    format!("inbox:seq:{}", open_id)
+}
+
+fn log_key(open_id: &str) -> String {
+    format!("inbox:log:{}", open_id)
+}
+
+/// 读取消息中的序号（没有序号的临时推送返回 None）
+pub fn message_seq(payload: &[u8]) -> Option<i64> {
+    serde_json::from_slice::<serde_json::Value>(payload)
+        .ok()?
+        .get(SEQ_FIELD)?
+        .as_i64()
+}
+
+/// 从某个序号之后补发的结果
+#[derive(Debug, Clone, Default)]
+pub struct InboxReplay {
+    /// (序号, 消息 JSON)，按序号从小到大
+    pub entries: Vec<(i64, String)>,
+    /// 日志中缺少客户端需要的部分（超出保留条数/时长，或计数器已重置），客户端需要通过历史消息接口补齐
+    pub truncated: bool,
+    /// 当前最新序号
+    pub latest_seq: i64,
+}
+
+#[derive(Clone)]
+pub struct InboxLog {
+    redis: Arc<RedisClient>,
+}
+
+impl InboxLog {
+    pub fn new(redis: Arc<RedisClient>) -> Self {
+        Self { redis }
+    }
+
+    /// 为发给 open_id 的消息分配序号并写入日志，返回带 seq 字段的消息
+    /// 不是 JSON 对象的消息原样返回，不写日志
+    pub async fn append(&self, open_id: &str, payload: &[u8]) -> Result<Vec<u8>, redis::RedisError> {
+        let Ok(serde_json::Value::Object(mut object)) = serde_json::from_slice(payload) else {
+            return Ok(payload.to_vec());
+        };
+
+        let seq_key = seq_key(open_id);
+        let log_key = log_key(open_id);
+        let mut conn = self.redis.get_connection().await;
+        let seq: i64 = redis::cmd("INCR")
+            .arg(&seq_key)
+            .query_async(&mut conn)
+            .await?;
+        object.insert(SEQ_FIELD.to_string(), seq.into());
+        let stamped = serde_json::to_vec(&object).unwrap_or_else(|_| payload.to_vec());
+
+        redis::pipe()
+            .cmd("ZADD").arg(&log_key).arg(seq).arg(&stamped).ignore()
+            .cmd("ZREMRANGEBYRANK").arg(&log_key).arg(0).arg(-(INBOX_LOG_MAX_ENTRIES + 1)).ignore()
+            .cmd("EXPIRE").arg(&log_key).arg(INBOX_LOG_TTL_SECS).ignore()
+            .cmd("EXPIRE").arg(&seq_key).arg(INBOX_SEQ_TTL_SECS).ignore()
+            .query_async::<()>(&mut conn)
+            .await?;
+        Ok(stamped)
+    }
+
+    /// 同 append，写日志失败时记录告警并返回原消息（仍可实时推送，只是无法按序号补发）
+    pub async fn stamp(&self, open_id: &str, payload: Vec<u8>) -> Vec<u8> {
+        match self.append(open_id, &payload).await {
+            Ok(stamped) => stamped,
+            Err(e) => {
+                warn!(open_id = %open_id, error = %e, "写入收件箱日志失败，消息不带序号推送");
+                payload
+            }
+        }
+    }
+
+    /// 读取 last_seq 之后的消息
+    pub async fn since(&self, open_id: &str, last_seq: i64) -> Result<InboxReplay, redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        let latest_seq: i64 = redis::cmd("GET")
+            .arg(seq_key(open_id))
+            .query_async::<Option<i64>>(&mut conn)
+            .await?
+            .unwrap_or(0);
+
+        // 客户端的序号比计数器还大，说明计数器过期后重新开始了，日志里的全部消息都是客户端没见过的
+        let counter_reset = last_seq > latest_seq;
+        let min = if counter_reset { "-inf".to_string() } else { format!("({}", last_seq) };
+        let raw: Vec<(String, f64)> = redis::cmd("ZRANGEBYSCORE")
+            .arg(log_key(open_id))
+            .arg(min)
+            .arg("+inf")
+            .arg("WITHSCORES")
+            .query_async(&mut conn)
+            .await?;
+        let entries: Vec<(i64, String)> = raw
+            .into_iter()
+            .map(|(message, score)| (score as i64, message))
+            .collect();
+
+        let truncated = counter_reset
+            || match entries.first() {
+                Some((first_seq, _)) => *first_seq > last_seq + 1,
+                None => latest_seq > last_seq,
+            };
+
+        Ok(InboxReplay {
+            entries,
+            truncated,
+            latest_seq,
+        })
+    }
+}
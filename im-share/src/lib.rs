@@ -7,16 +7,33 @@ pub mod snowflake;
 pub mod user;
 pub mod redis;
 pub mod auth;
+pub mod protocol;
+pub mod presence;
+pub mod routing;
+pub mod inbox;
+pub mod offline;
+pub mod expiry;
+pub mod ws_ticket;
+pub mod session;
+pub mod shutdown;
 
 // Re-exports for convenience
-pub use mqtt::{ImMqtt, MqttConfig, IncomingMessage};
-pub use model::{ChatMessage, Target, SendRequest};
-pub use utils::{mqtt_user_topic, encode_message, decode_message, now_timestamp, now_timestamp_seconds};
+pub use mqtt::{ImMqtt, MqttConfig, MqttState, IncomingMessage};
+pub use model::{ChatMessage, LinkPreview, VoiceInfo, LocationInfo, ContactCard, Target, SendRequest};
+pub use utils::{mqtt_user_topic, encode_message, decode_message, encode_frame, decode_frame, FrameCodecError, now_timestamp, now_timestamp_seconds};
 pub use group::{get_group_members, set_group_members};
-pub use subscription::{SubscriptionService, get_user_id_by_subscription, get_user_info_by_subscription};
+pub use subscription::{SubscriptionService, SubscriptionIdentity, store_subscription_identity, get_subscription_identity};
 pub use user::{get_snowflake_id_by_identifier, get_open_id_by_identifier};
 pub use snowflake::{generate_snowflake_id, generate_snowflake_id_with_config};
 pub use user::{get_username_by_id, clear_username_cache, get_cache_size};
 pub use redis::{RedisClient, RedisConfig};
-pub use auth::{JwtSettings, Claims, generate_token_with_open_id, generate_token, verify_token};
-
+pub use auth::{JwtSettings, Claims, generate_token_with_open_id, generate_session_token, generate_token, verify_token, internal_token_matches};
+pub use protocol::{ClientFrame, ClientCommand, ServerResponse, ServerFrame, ReconnectFrame, NoticeFrame, WireFormat};
+pub use presence::{PresenceRegistry, PresenceStatus, PresenceChanged, UserPresence, DevicePresence};
+pub use routing::{RouteRegistry, NodeRoute, NodeDelivery, node_topic};
+pub use expiry::{register_expiry_policy, stamp_expiry, stamp_expiry_ttl, is_expired, is_payload_expired};
+pub use offline::{OfflineQueue, OfflineBatch};
+pub use inbox::{InboxLog, InboxReplay, message_seq};
+pub use ws_ticket::{issue_ws_ticket, redeem_ws_ticket};
+pub use session::{KickedEvent, KickReason, revoke_session, is_session_revoked};
+pub use shutdown::{Shutdown, ShutdownGuard, shutdown_signal};
@@ -16,6 +16,77 @@ pub struct ChatMessage {
     /// 聊天类型：1=单聊，2=群聊
     #[serde(skip_serializing_if = "Option::is_none")]
     pub chat_type: Option<i32>,
+    /// 消息中第一个链接的预览信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_preview: Option<LinkPreview>,
+    /// 语音消息的时长和波形
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice: Option<VoiceInfo>,
+    /// 位置消息的坐标和地点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<LocationInfo>,
+    /// 名片消息引用的用户
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card: Option<ContactCard>,
+}
+
+/// 位置消息元数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocationInfo {
+    /// 纬度（-90 ~ 90）
+    pub latitude: f64,
+    /// 经度（-180 ~ 180）
+    pub longitude: f64,
+    /// 地点名称
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 详细地址
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// 名片消息：只保存 open_id，名称和头像在读取时按对方当前资料和隐私设置解析
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContactCard {
+    pub open_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    /// 用户不存在、已注销或被封禁时为 false，此时不返回名称和头像
+    #[serde(default)]
+    pub available: bool,
+    /// 对方是否允许通过名片添加好友
+    #[serde(default)]
+    pub can_add_friend: bool,
+    /// 添加好友是否需要对方验证
+    #[serde(default)]
+    pub need_verification: bool,
+}
+
+/// 语音消息元数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VoiceInfo {
+    /// 音频格式：opus / vorbis / m4a / wav
+    pub format: String,
+    /// 时长（毫秒）
+    pub duration_ms: u64,
+    /// 降采样后的波形，每个点取值 0-100
+    pub waveform: Vec<u8>,
+}
+
+/// 链接预览（OpenGraph 信息）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
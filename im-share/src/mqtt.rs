@@ -1,7 +1,11 @@
 use anyhow::Result;
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, Publish, QoS, SubscribeFilter};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, error, info, warn};
 
 #[derive(Clone, Debug)]
@@ -10,11 +14,18 @@ pub struct MqttConfig {
     pub port: u16,
     pub client_id: String,
     pub keep_alive_secs: u64,
+    pub clean_session: bool,
 }
 
 impl MqttConfig {
     pub fn new(host: impl Into<String>, port: u16, client_id: impl Into<String>) -> Self {
-        Self { host: host.into(), port, client_id: client_id.into(), keep_alive_secs: 30 }
+        Self { host: host.into(), port, client_id: client_id.into(), keep_alive_secs: 30, clean_session: false }
+    }
+
+    /// 设置 clean_session（默认 false，broker 为离线客户端保留会话和 QoS 1 消息）
+    pub fn with_clean_session(mut self, clean_session: bool) -> Self {
+        self.clean_session = clean_session;
+        self
     }
 }
 
@@ -24,172 +35,188 @@ pub struct IncomingMessage {
     pub payload: Vec<u8>,
 }
 
+/// 断开连接时等待事件循环写出已入队请求的最长时间
+const DISCONNECT_TIMEOUT_SECS: u64 = 5;
+/// 重连退避的初始等待时间
+const RECONNECT_INITIAL_DELAY_MS: u64 = 500;
+/// 重连退避的最长等待时间
+const RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+
+/// MQTT 连接状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum MqttState {
+    /// 首次连接中
+    Connecting,
+    /// 已连接（收到 ConnAck）
+    Connected,
+    /// 连接断开，正在按退避时间重连
+    Reconnecting { attempt: u32 },
+    /// 已调用 disconnect()，事件循环已退出
+    Closed,
+}
+
+/// 第 attempt 次重连前的等待时间：指数退避，上限 RECONNECT_MAX_DELAY_MS，
+/// 一半固定、一半随机，避免 broker 重启后大量客户端同时重连
+fn reconnect_delay(attempt: u32) -> Duration {
+    let base = RECONNECT_INITIAL_DELAY_MS
+        .saturating_mul(1u64 << attempt.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_DELAY_MS);
+    let jitter = RandomState::new().build_hasher().finish() % (base / 2 + 1);
+    Duration::from_millis(base / 2 + jitter)
+}
+
+/// 连接断开后自动重连：
+/// - 事件循环出错后按指数退避（带随机抖动）重新 poll，rumqttc 会重新建立连接，收到 ConnAck 后退避清零
+/// - 每次收到 ConnAck 都重新订阅所有已订阅的 topic（clean_session = true 或 broker 没有保留会话时订阅已丢失）
+/// - 连接状态通过 watch 通道暴露给调用方；未连接时 publish 直接返回错误，由调用方转存离线消息，不在内存中堆积
+/// - 只有 disconnect() 会让事件循环退出
 #[derive(Clone)]
 pub struct ImMqtt {
     client: AsyncClient,
     tx: broadcast::Sender<IncomingMessage>,
+    clean_session: bool,
+    /// 已订阅的 topic，重连后重新订阅
+    topics: Arc<Mutex<HashSet<String>>>,
+    state: watch::Receiver<MqttState>,
+    /// disconnect() 已调用，事件循环不再重连
+    stopping: Arc<watch::Sender<bool>>,
 }
 
 impl ImMqtt {
     pub fn connect(config: MqttConfig) -> Self {
         let mut options = MqttOptions::new(config.client_id, config.host, config.port);
         options.set_keep_alive(Duration::from_secs(config.keep_alive_secs));
-        // 设置 clean_session = false，让 broker 为离线客户端存储消息（QoS 1 或 2）
-        // 这样即使客户端突然断线，broker 也能在重连后推送离线消息
-        options.set_clean_session(false);
+        // im-connect 的连接池使用 clean_session = true：broker 不保留会话，重连后由 ConnAck 处理重新订阅
+        // 离线消息不依赖 broker 会话，由 im-server 写入 Redis 离线队列，客户端重连时补发
+        options.set_clean_session(config.clean_session);
         let (client, eventloop): (AsyncClient, EventLoop) = AsyncClient::new(options, 10);
         let (tx, _rx) = broadcast::channel(256);
-        let tx_clone = tx.clone();
-        tokio::spawn(async move {
-            let mut ev = eventloop;
-            let mut last_conn_ack_time = std::time::Instant::now();
-            let mut is_connected = false;
-            
-            loop {
-                match ev.poll().await {
-                    Ok(event) => {
-                        match event {
-                            Event::Incoming(Packet::Publish(p)) => {
-                                // 在移动之前先克隆 topic，用于后续日志
-                                let topic = p.topic.clone();
-                                let payload_len = p.payload.len();
-                                let qos = p.qos;
-                                
-                                // 尝试解析消息内容用于调试
-                                // 注意：p.payload 是 Bytes 类型，需要转换为 Vec<u8> 或使用 as_ref()
-                                let message_info = if let Ok(text) = String::from_utf8(p.payload.to_vec()) {
-                                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
-                                        format!("message_id={:?}, chat_type={:?}, from={:?}, to={:?}", 
-                                            json.get("message_id"),
-                                            json.get("chat_type"),
-                                            json.get("from_user_id"),
-                                            json.get("to_user_id"))
-                                    } else {
-                                        format!("payload_preview={}", text.chars().take(50).collect::<String>())
-                                    }
-                                } else {
-                                    "binary_payload".to_string()
-                                };
-                                
-                                // 检查是否有订阅者（在记录日志之前检查，以便在日志中包含这个信息）
-                                let receiver_count = tx_clone.receiver_count();
-                                
-                                info!(
-                                    topic = %topic, 
-                                    payload_len = payload_len, 
-                                    qos = ?qos,
-                                    message_info = %message_info,
-                                    receiver_count = receiver_count,
-                                    "✅ 收到MQTT消息（从broker推送，QoS {:?}，当前有 {} 个订阅者）",
-                                    qos,
-                                    receiver_count
-                                );
-                                
-                                if receiver_count == 0 {
-                                    // 没有订阅者（WebSocket 可能已断开），这是正常情况
-                                    // 但使用 warn 级别，因为这可能表示问题
-                                    warn!(
-                                        topic = %topic,
-                                        payload_len = payload_len,
-                                        message_info = %message_info,
-                                        "⚠️ MQTT消息无订阅者（WebSocket可能已断开或尚未订阅），消息将被丢弃"
-                                    );
-                                } else {
-                                    // 有订阅者，尝试发送
-                                    match tx_clone.send(IncomingMessage { topic: p.topic, payload: p.payload.to_vec() }) {
-                                        Ok(actual_receivers) => {
-                                            info!(
-                                                topic = %topic,
-                                                receiver_count = receiver_count,
-                                                actual_receivers = actual_receivers,
-                                                "✅ 消息已发送到broadcast channel（{} 个接收者）",
-                                                actual_receivers
-                                            );
-                                        },
-                                        Err(e) => {
-                                            // 发送失败（可能是 channel 已关闭），使用 warn 级别
-                                            warn!(
-                                                topic = %topic,
-                                                error = %e,
-                                                receiver_count = receiver_count,
-                                                "❌ 发送消息到broadcast channel失败（channel可能已关闭）"
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                            Event::Incoming(Packet::ConnAck(ack)) => {
-                                let now = std::time::Instant::now();
-                                let time_since_last_conn = now.duration_since(last_conn_ack_time);
-                                last_conn_ack_time = now;
-                                
-                                // 只有在连接状态改变或距离上次连接确认超过5秒时才记录日志
-                                if !is_connected || time_since_last_conn.as_secs() > 5 {
-                                    info!(session_present = ack.session_present, "MQTT 连接已建立，会话状态: {}", if ack.session_present { "已恢复" } else { "新会话" });
-                                }
-                                is_connected = true;
-                            }
-                            Event::Incoming(Packet::SubAck(sa)) => {
-                                info!(packet_id = sa.pkid, "✅ MQTT 订阅确认（broker将推送离线消息，QoS 1 + clean_session=false）");
-                                // 订阅确认后，broker应该会立即推送离线消息（如果有的话）
-                                // 使用 QoS 1 和 clean_session=false，broker会为已订阅的客户端存储消息
-                            }
-                            Event::Incoming(Packet::Disconnect) => {
-                                debug!("收到 MQTT Disconnect 包");
-                                is_connected = false;
-                            }
-                            Event::Outgoing(_) => {
-                                // 忽略出站事件
-                            }
-                            _ => {
-                                // 其他事件可以在这里处理
-                            }
-                        }
+        let (state_tx, state) = watch::channel(MqttState::Connecting);
+        let (stopping, _) = watch::channel(false);
+        let mqtt = Self {
+            client,
+            tx,
+            clean_session: config.clean_session,
+            topics: Arc::new(Mutex::new(HashSet::new())),
+            state,
+            stopping: Arc::new(stopping),
+        };
+        tokio::spawn(mqtt.clone().run(eventloop, state_tx));
+        mqtt
+    }
+
+    async fn run(self, mut ev: EventLoop, state_tx: watch::Sender<MqttState>) {
+        let mut stopping = self.stopping.subscribe();
+        let mut attempt: u32 = 0;
+        loop {
+            // 未连接时 disconnect() 不会再有 DISCONNECT 写出，直接退出
+            let polled = if *state_tx.borrow() == MqttState::Connected {
+                ev.poll().await
+            } else {
+                tokio::select! {
+                    polled = ev.poll() => polled,
+                    _ = stopping.wait_for(|stopping| *stopping) => break,
+                }
+            };
+            match polled {
+                Ok(Event::Incoming(Packet::Publish(p))) => forward_publish(&self.tx, p),
+                Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                    if attempt > 0 {
+                        info!(attempt = attempt, session_present = ack.session_present, "MQTT 重连成功");
+                    } else {
+                        info!(session_present = ack.session_present, "MQTT 连接已建立，会话状态: {}", if ack.session_present { "已恢复" } else { "新会话" });
+                    }
+                    attempt = 0;
+                    // 先更新状态再读取 topic 列表：未连接期间 subscribe() 只登记不发送，这里一定能看到
+                    state_tx.send_replace(MqttState::Connected);
+                    self.resubscribe();
+                }
+                Ok(Event::Incoming(Packet::SubAck(sa))) => {
+                    debug!(packet_id = sa.pkid, "MQTT 订阅确认");
+                }
+                Ok(Event::Incoming(Packet::Disconnect)) => {
+                    debug!("收到 MQTT Disconnect 包");
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                    // disconnect() 发出的 DISCONNECT 已写出，之前入队的发布也都已写出
+                    info!("MQTT DISCONNECT 已发送，退出事件循环");
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if *stopping.borrow() {
+                        info!(error = %e, "MQTT 连接已关闭（正在断开），退出事件循环");
+                        break;
                     }
-                    Err(e) => {
-                        let error_str = e.to_string();
-                        // 检查是否是连接关闭相关的错误（这些是正常的网络断开情况）
-                        let is_connection_closed = error_str.contains("Connection closed by peer")
-                            || error_str.contains("connection closed by peer")
-                            || error_str.contains("Connection closed")
-                            || error_str.contains("connection closed")
-                            || error_str.contains("Connection reset")
-                            || error_str.contains("connection reset")
-                            || error_str.contains("Broken pipe")
-                            || error_str.contains("broken pipe");
-                        
-                        // 注意：不需要设置 is_connected = false，因为后面会直接 break
-                        
-                        if is_connection_closed {
-                            // 连接关闭，直接退出循环，不再重连
-                            warn!(
-                                error = %e,
-                                "MQTT 连接已关闭，退出事件循环"
-                            );
-                            break;
-                        } else {
-                            // 其他错误，记录日志后退出
-                            error!(
-                                error = %e,
-                                "MQTT EventLoop 错误，退出事件循环"
-                            );
-                            break;
-                        }
+                    attempt = attempt.saturating_add(1);
+                    state_tx.send_replace(MqttState::Reconnecting { attempt });
+                    let delay = reconnect_delay(attempt);
+                    warn!(error = %e, attempt = attempt, delay_ms = delay.as_millis() as u64, "MQTT 连接断开，稍后重连");
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = stopping.wait_for(|stopping| *stopping) => break,
                     }
                 }
             }
+        }
+        state_tx.send_replace(MqttState::Closed);
+    }
+
+    /// 重新订阅所有已订阅的 topic
+    /// 在单独的任务中发送：请求通道已满时 subscribe 会等待事件循环取走请求，不能在事件循环中等待
+    fn resubscribe(&self) {
+        let filters: Vec<SubscribeFilter> = self
+            .topics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|topic| SubscribeFilter::new(topic.clone(), QoS::AtLeastOnce))
+            .collect();
+        if filters.is_empty() {
+            return;
+        }
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let count = filters.len();
+            match client.subscribe_many(filters).await {
+                Ok(()) => info!(topic_count = count, "MQTT 连接建立后已重新订阅"),
+                Err(e) => error!(topic_count = count, error = %e, "MQTT 重新订阅失败"),
+            }
         });
-        Self { client, tx }
+    }
+
+    /// 当前连接状态
+    pub fn state(&self) -> MqttState {
+        *self.state.borrow()
+    }
+
+    /// 订阅连接状态变化
+    pub fn watch_state(&self) -> watch::Receiver<MqttState> {
+        self.state.clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state() == MqttState::Connected
+    }
+
+    /// 未连接时直接失败，不让发布请求在重连期间堆积在请求通道中
+    fn ensure_connected(&self, topic: &str) -> Result<()> {
+        match self.state() {
+            MqttState::Connected => Ok(()),
+            state => {
+                warn!(topic = %topic, state = ?state, "MQTT 未连接，发布失败");
+                anyhow::bail!("MQTT 未连接（{:?}）", state)
+            }
+        }
     }
 
     pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
         // 使用 QoS::AtLeastOnce (QoS 1) 确保消息至少被传递一次
         // retain=false 表示不保留消息（retain消息会一直保留在broker上，直到被新的retain消息覆盖）
-        // 对于离线消息，QoS 1 + clean_session=false 已经足够，broker会为已订阅的客户端存储消息
-        // 
-        // 重要：broker只会为已经订阅过的客户端存储离线消息
-        // 如果消息在订阅之前发布，broker不会存储（因为客户端还没有订阅）
-        // 所以消息必须保存到数据库，作为离线消息的备份
+        // QoS 1 只保证送达当前在线的订阅者：im-connect 的连接池使用 clean_session=true，
+        // broker 不为断线的订阅者保存消息，断线期间的消息由 Redis 离线队列和数据库补发
+        self.ensure_connected(topic)?;
         let payload_len = payload.len();
         
         // 尝试解析 payload 以获取消息信息用于日志
@@ -231,39 +258,140 @@ impl ImMqtt {
         }
     }
 
+    /// 发布临时消息（QoS 0）：不重传，broker 也不会为离线客户端存储
+    /// 用于"正在输入"这类过期后就没有意义的状态
+    pub async fn publish_transient(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        self.ensure_connected(topic)?;
+        self.client.publish(topic, QoS::AtMostOnce, false, payload).await?;
+        Ok(())
+    }
+
     pub async fn subscribe(&self, topic: &str) -> Result<broadcast::Receiver<IncomingMessage>> {
-        // 使用 QoS::AtLeastOnce (QoS 1) 确保订阅至少被确认一次
-        // 配合 clean_session=false，broker会为这个订阅存储离线消息
-        // 
-        // 重要说明：
-        // 1. broker只会为已经订阅过的客户端存储消息（QoS 1或2）
-        // 2. 如果消息在订阅之前发布，broker不会存储（因为客户端还没有订阅）
-        // 3. 订阅确认后，broker会立即推送离线消息（如果有的话）
-        // 4. 使用固定的client_id（基于用户ID）确保会话可以恢复
-        self.client.subscribe(topic, QoS::AtLeastOnce).await?;
-        info!(topic = %topic, qos = "QoS 1", "MQTT订阅已发送（QoS 1），等待broker确认和推送离线消息");
-        // 返回接收者，注意：这里返回的是新的接收者，不会收到订阅之前发布的消息
-        // 但是broker会在订阅确认后推送离线消息（如果客户端之前订阅过且clean_session=false）
+        // 使用 QoS::AtLeastOnce (QoS 1) 订阅
+        // topic 先登记到本地集合：clean_session=true 时 broker 在断线后不保留订阅，
+        // 每次收到 ConnAck 都按这个集合重新订阅；未连接时只登记，连接建立后统一订阅
+        self.topics.lock().unwrap().insert(topic.to_string());
+        if self.is_connected() {
+            self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+            info!(topic = %topic, qos = "QoS 1", "MQTT订阅已发送（QoS 1），等待broker确认");
+        } else {
+            info!(topic = %topic, state = ?self.state(), "MQTT 未连接，连接建立后订阅");
+        }
+        // 返回新的接收者，不会收到订阅之前发布的消息；这些消息由 Redis 离线队列补发
         Ok(self.tx.subscribe())
     }
 
     pub async fn unsubscribe(&self, topic: &str) -> Result<()> {
-        self.client.unsubscribe(topic).await?;
+        self.topics.lock().unwrap().remove(topic);
+        if self.is_connected() {
+            self.client.unsubscribe(topic).await?;
+        } else if !self.clean_session {
+            // broker 保留了会话，重连后订阅仍然存在；请求排队到重连后发送，通道已满时放弃
+            self.client.try_unsubscribe(topic)?;
+        }
         Ok(())
     }
 
-    /// 断开 MQTT 连接
-    /// 注意：断开连接不会清除订阅信息（因为 clean_session=false）
-    /// 只有取消订阅（unsubscribe）才会清除订阅信息
+    /// 获取这个连接上所有订阅的消息接收者（不发送新的订阅请求）
+    pub fn messages(&self) -> broadcast::Receiver<IncomingMessage> {
+        self.tx.subscribe()
+    }
+
+    /// 断开 MQTT 连接，之后不再重连
+    /// 已连接时 DISCONNECT 排在已入队的发布/取消订阅之后，等事件循环把它们写出并退出后返回；
+    /// 正在重连时直接停止重连（最多等待 DISCONNECT_TIMEOUT_SECS）
+    /// clean_session=true（im-connect 连接池）时 broker 随断开丢弃会话和订阅，
+    /// 断开期间发布的消息不会被 broker 保存，由 Redis 离线队列补发
     pub async fn disconnect(&self) -> Result<()> {
-        // rumqttc 的 AsyncClient 在 drop 时会自动断开连接
-        // 但我们可以通过发送一个断开信号来确保连接及时断开
-        // 实际上，rumqttc 没有显式的 disconnect 方法
-        // 当 AsyncClient 被 drop 时，连接会自动断开
-        // 这里我们只是记录日志，实际的断开会在 drop 时发生
-        info!("MQTT 客户端断开请求（连接将在 drop 时断开）");
+        let mut state = self.state.clone();
+        if *state.borrow() == MqttState::Closed {
+            return Ok(());
+        }
+        self.stopping.send_replace(true);
+        let closed = tokio::time::timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS), async {
+            if self.is_connected() {
+                self.client.disconnect().await?;
+            }
+            state.wait_for(|state| *state == MqttState::Closed).await?;
+            anyhow::Ok(())
+        })
+        .await;
+        match closed {
+            Ok(Ok(())) => info!("MQTT 连接已断开"),
+            Ok(Err(e)) => warn!(error = %e, "断开 MQTT 连接时事件循环已退出"),
+            Err(_) => warn!("等待 MQTT 事件循环退出超时"),
+        }
         Ok(())
     }
 }
 
-
+/// 把 broker 推送的消息转发给本连接的所有接收者
+fn forward_publish(tx: &broadcast::Sender<IncomingMessage>, p: Publish) {
+    // 在移动之前先克隆 topic，用于后续日志
+    let topic = p.topic.clone();
+    let payload_len = p.payload.len();
+    let qos = p.qos;
+    
+    // 尝试解析消息内容用于调试
+    // 注意：p.payload 是 Bytes 类型，需要转换为 Vec<u8> 或使用 as_ref()
+    let message_info = if let Ok(text) = String::from_utf8(p.payload.to_vec()) {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
+            format!("message_id={:?}, chat_type={:?}, from={:?}, to={:?}", 
+                json.get("message_id"),
+                json.get("chat_type"),
+                json.get("from_user_id"),
+                json.get("to_user_id"))
+        } else {
+            format!("payload_preview={}", text.chars().take(50).collect::<String>())
+        }
+    } else {
+        "binary_payload".to_string()
+    };
+    
+    // 检查是否有订阅者（在记录日志之前检查，以便在日志中包含这个信息）
+    let receiver_count = tx.receiver_count();
+    
+    info!(
+        topic = %topic, 
+        payload_len = payload_len, 
+        qos = ?qos,
+        message_info = %message_info,
+        receiver_count = receiver_count,
+        "✅ 收到MQTT消息（从broker推送，QoS {:?}，当前有 {} 个订阅者）",
+        qos,
+        receiver_count
+    );
+    
+    if receiver_count == 0 {
+        // 没有订阅者（WebSocket 可能已断开），这是正常情况
+        // 但使用 warn 级别，因为这可能表示问题
+        warn!(
+            topic = %topic,
+            payload_len = payload_len,
+            message_info = %message_info,
+            "⚠️ MQTT消息无订阅者（WebSocket可能已断开或尚未订阅），消息将被丢弃"
+        );
+    } else {
+        // 有订阅者，尝试发送
+        match tx.send(IncomingMessage { topic: p.topic, payload: p.payload.to_vec() }) {
+            Ok(actual_receivers) => {
+                info!(
+                    topic = %topic,
+                    receiver_count = receiver_count,
+                    actual_receivers = actual_receivers,
+                    "✅ 消息已发送到broadcast channel（{} 个接收者）",
+                    actual_receivers
+                );
+            },
+            Err(e) => {
+                // 发送失败（可能是 channel 已关闭），使用 warn 级别
+                warn!(
+                    topic = %topic,
+                    error = %e,
+                    receiver_count = receiver_count,
+                    "❌ 发送消息到broadcast channel失败（channel可能已关闭）"
+                );
+            }
+        }
+    }
+}
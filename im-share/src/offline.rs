@@ -0,0 +1,452 @@
+use crate::expiry::is_payload_expired;
+use crate::inbox::{InboxLog, message_seq};
+use crate::redis::RedisClient;
+use crate::utils::now_timestamp;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+// 离线消息队列（Redis）
+// - offline:queue:{open_id}     ZSet，score = seq，member = 带 seq 字段的消息 JSON
+// - offline:overflow:{open_id}  超出条数上限被丢弃的消息中最大的 seq（溢出标记）
+// im-server 投递时用户不在任何节点上、im-connect 断开时还有没写出或没确认的推送，都写入这里
+// 读取时不删除：客户端确认后（没有开启送达确认的连接写入 socket 即视为确认）才按 seq 移除，补发途中连接断开不会丢消息；
+// 同一条消息重复写入只保留一份
+// 每个用户最多保留 OFFLINE_QUEUE_MAX_ENTRIES 条，超出时丢弃最早的并记录溢出标记，
+// im-connect 补发时据此推送 resync 帧，客户端通过历史消息接口补齐
+
+/// 每个用户最多保留的离线消息条数
+pub const OFFLINE_QUEUE_MAX_ENTRIES: i64 = 500;
+/// 离线消息保留时长：7 天
+const OFFLINE_QUEUE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+fn queue_key(open_id: &str) -> String {
+    format!("offline:queue:{}", open_id)
+}
+
+fn overflow_key(open_id: &str) -> String {
+    format!("offline:overflow:{}", open_id)
+}
+
+/// 一次读取的离线消息
+#[derive(Debug, Clone, Default)]
+pub struct OfflineBatch {
+    /// (序号, 消息 JSON)，按序号从小到大，已过期的不包含
+    pub entries: Vec<(i64, String)>,
+    /// 有消息因为超出条数上限被丢弃，值为被丢弃的最大序号，客户端需要通过历史消息接口补齐
+    pub overflowed_up_to: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct OfflineQueue {
+    redis: Arc<RedisClient>,
+}
+
+impl OfflineQueue {
+    pub fn new(redis: Arc<RedisClient>) -> Self {
+        Self { redis }
+    }
+
+    /// 写入一条离线消息
+    /// 没有序号的消息先分配序号（同时写入收件箱日志），不是 JSON 对象的消息和已过期的消息不存储
+    pub async fn push(&self, open_id: &str, message: &str) -> Result<(), redis::RedisError> {
+        if is_payload_expired(message, now_timestamp()) {
+            info!(open_id = %open_id, "消息已过期，不存储为离线消息");
+            return Ok(());
+        }
+        let (seq, message) = match message_seq(message.as_bytes()) {
+            Some(seq) => (seq, message.to_string()),
+            None => {
+                let stamped = InboxLog::new(self.redis.clone()).append(open_id, message.as_bytes()).await?;
+                let Some(seq) = message_seq(&stamped) else {
+                    warn!(open_id = %open_id, "消息不是 JSON 对象，无法存储为离线消息");
+                    return Ok(());
+                };
+                (seq, String::from_utf8_lossy(&stamped).into_owned())
+            }
+        };
+
+        let key = queue_key(open_id);
+        let mut conn = self.redis.get_connection().await;
+        // 写入后取出超出上限的最早几条（ZRANGE 0 -(上限+1)）一并删除
+        let (dropped,): (Vec<(String, f64)>,) = redis::pipe()
+            .cmd("ZADD").arg(&key).arg(seq).arg(&message).ignore()
+            .cmd("ZRANGE").arg(&key).arg(0).arg(-(OFFLINE_QUEUE_MAX_ENTRIES + 1)).arg("WITHSCORES")
+            .cmd("ZREMRANGEBYRANK").arg(&key).arg(0).arg(-(OFFLINE_QUEUE_MAX_ENTRIES + 1)).ignore()
+            .cmd("EXPIRE").arg(&key).arg(OFFLINE_QUEUE_TTL_SECS).ignore()
+            .query_async(&mut conn)
+            .await?;
+
+        if let Some(dropped_up_to) = dropped.iter().map(|(_, score)| *score as i64).max() {
+            let overflow_key = overflow_key(open_id);
+            let previous: Option<i64> = redis::cmd("GET")
+                .arg(&overflow_key)
+                .query_async(&mut conn)
+                .await?;
+            let dropped_up_to = previous.map_or(dropped_up_to, |p| p.max(dropped_up_to));
+            self.redis
+                .set_with_ttl(&overflow_key, &dropped_up_to.to_string(), OFFLINE_QUEUE_TTL_SECS)
+                .await?;
+            warn!(open_id = %open_id, dropped = dropped.len(), dropped_up_to = dropped_up_to, "离线消息超出上限，最早的消息已丢弃");
+        }
+        Ok(())
+    }
+
+    /// 读取全部离线消息（不删除），顺带清理已过期的消息
+    pub async fn peek(&self, open_id: &str) -> Result<OfflineBatch, redis::RedisError> {
+        let key = queue_key(open_id);
+        let mut conn = self.redis.get_connection().await;
+        let raw: Vec<(String, f64)> = redis::cmd("ZRANGE")
+            .arg(&key)
+            .arg(0)
+            .arg(-1)
+            .arg("WITHSCORES")
+            .query_async(&mut conn)
+            .await?;
+        let overflowed_up_to: Option<i64> = redis::cmd("GET")
+            .arg(overflow_key(open_id))
+            .query_async(&mut conn)
+            .await?;
+
+        let now = now_timestamp();
+        let (expired, entries): (Vec<_>, Vec<_>) = raw
+            .into_iter()
+            .map(|(message, score)| (score as i64, message))
+            .partition(|(_, message)| is_payload_expired(message, now));
+        if !expired.is_empty() {
+            let members: Vec<&String> = expired.iter().map(|(_, message)| message).collect();
+            redis::cmd("ZREM")
+                .arg(&key)
+                .arg(members)
+                .query_async::<()>(&mut conn)
+                .await?;
+            info!(open_id = %open_id, expired = expired.len(), "丢弃已过期的离线消息");
+        }
+
+        Ok(OfflineBatch { entries, overflowed_up_to })
+    }
+
+    /// 已送达的消息，按序号移除：先按序号范围取出候选消息，只把序号在 seqs 中的消息用一条 ZREM 删除
+    /// （范围内其他序号的消息可能还没有送达，例如发布时转存为离线消息的那一条）
+    pub async fn remove(&self, open_id: &str, seqs: &[i64]) -> Result<(), redis::RedisError> {
+        let (Some(min), Some(max)) = (seqs.iter().min(), seqs.iter().max()) else {
+            return Ok(());
+        };
+        let key = queue_key(open_id);
+        let mut conn = self.redis.get_connection().await;
+        let candidates: Vec<(String, f64)> = redis::cmd("ZRANGEBYSCORE")
+            .arg(&key)
+            .arg(*min)
+            .arg(*max)
+            .arg("WITHSCORES")
+            .query_async(&mut conn)
+            .await?;
+        let seqs: HashSet<i64> = seqs.iter().copied().collect();
+        let members: Vec<String> = candidates
+            .into_iter()
+            .filter(|(_, score)| seqs.contains(&(*score as i64)))
+            .map(|(message, _)| message)
+            .collect();
+        if members.is_empty() {
+            return Ok(());
+        }
+        redis::cmd("ZREM")
+            .arg(&key)
+            .arg(members)
+            .query_async::<()>(&mut conn)
+            .await
+    }
+
+    /// 移除序号不超过 seq 的消息（客户端按序号恢复时，这些消息已经处理过）
+    pub async fn remove_up_to(&self, open_id: &str, seq: i64) -> Result<(), redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("ZREMRANGEBYSCORE")
+            .arg(queue_key(open_id))
+            .arg("-inf")
+            .arg(seq)
+            .query_async::<()>(&mut conn)
+            .await
+    }
+
+    /// 已经通知客户端补齐被丢弃的消息后清除溢出标记
+    pub async fn clear_overflow(&self, open_id: &str) -> Result<(), redis::RedisError> {
+        self.redis.del(&overflow_key(open_id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::RedisConfig;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Clone)]
+    enum Value {
+        Str(String),
+        ZSet(Vec<(f64, String)>),
+    }
+
+    type Store = Arc<Mutex<HashMap<String, Value>>>;
+
+    /// 只实现离线队列用到的命令的内存 Redis（RESP2）
+    async fn start_fake_redis() -> (Arc<RedisClient>, Store) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let store: Store = Arc::default();
+        let server_store = store.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_store.clone()));
+            }
+        });
+        let config = RedisConfig::new("127.0.0.1".to_string(), port, 0, None);
+        (Arc::new(RedisClient::new(&config).await.unwrap()), store)
+    }
+
+    async fn serve(stream: TcpStream, store: Store) {
+        stream.set_nodelay(true).unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        while let Some(args) = read_command(&mut reader).await {
+            let reply = execute(&store, &args);
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<String> {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        Some(line.trim_end().to_string())
+    }
+
+    async fn read_command(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<Vec<String>> {
+        let count: usize = read_line(reader).await?.strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+            let mut buf = vec![0; len + 2];
+            reader.read_exact(&mut buf).await.ok()?;
+            buf.truncate(len);
+            args.push(String::from_utf8(buf).ok()?);
+        }
+        Some(args)
+    }
+
+    fn bulk(s: &str) -> String {
+        format!("${}\r\n{}\r\n", s.len(), s)
+    }
+
+    fn array(items: &[String]) -> String {
+        format!("*{}\r\n{}", items.len(), items.iter().map(|i| bulk(i)).collect::<String>())
+    }
+
+    fn rank_range(len: usize, start: &str, stop: &str) -> std::ops::Range<usize> {
+        let resolve = |i: &str| {
+            let i: i64 = i.parse().unwrap();
+            if i < 0 { len as i64 + i } else { i }
+        };
+        let start = resolve(start).max(0) as usize;
+        let stop = (resolve(stop) + 1).clamp(0, len as i64) as usize;
+        start.min(stop)..stop
+    }
+
+    fn score_bound(s: &str) -> f64 {
+        match s {
+            "-inf" => f64::NEG_INFINITY,
+            "+inf" | "inf" => f64::INFINITY,
+            _ => s.parse().unwrap(),
+        }
+    }
+
+    fn with_scores(entries: &[(f64, String)], args: &[String]) -> String {
+        let withscores = args.iter().any(|a| a.eq_ignore_ascii_case("WITHSCORES"));
+        let items: Vec<String> = entries
+            .iter()
+            .flat_map(|(score, member)| {
+                let mut item = vec![member.clone()];
+                if withscores {
+                    item.push(score.to_string());
+                }
+                item
+            })
+            .collect();
+        array(&items)
+    }
+
+    fn execute(store: &Store, args: &[String]) -> String {
+        let mut store = store.lock().unwrap();
+        let zset = |store: &mut HashMap<String, Value>, key: &str| -> Vec<(f64, String)> {
+            match store.get(key) {
+                Some(Value::ZSet(entries)) => entries.clone(),
+                _ => Vec::new(),
+            }
+        };
+        match args[0].to_ascii_uppercase().as_str() {
+            "PING" => "+PONG\r\n".to_string(),
+            "SELECT" | "EXPIRE" => ":1\r\n".to_string(),
+            "GET" => match store.get(&args[1]) {
+                Some(Value::Str(value)) => bulk(value),
+                _ => "$-1\r\n".to_string(),
+            },
+            "SET" => {
+                store.insert(args[1].clone(), Value::Str(args[2].clone()));
+                "+OK\r\n".to_string()
+            }
+            "DEL" => {
+                let removed = args[1..].iter().filter(|key| store.remove(*key).is_some()).count();
+                format!(":{}\r\n", removed)
+            }
+            "ZADD" => {
+                let mut entries = zset(&mut store, &args[1]);
+                let score: f64 = args[2].parse().unwrap();
+                let existed = entries.iter().any(|(_, m)| *m == args[3]);
+                entries.retain(|(_, m)| *m != args[3]);
+                entries.push((score, args[3].clone()));
+                entries.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                store.insert(args[1].clone(), Value::ZSet(entries));
+                format!(":{}\r\n", if existed { 0 } else { 1 })
+            }
+            "ZRANGE" => {
+                let entries = zset(&mut store, &args[1]);
+                let range = rank_range(entries.len(), &args[2], &args[3]);
+                with_scores(&entries[range], &args[4..])
+            }
+            "ZREMRANGEBYRANK" => {
+                let mut entries = zset(&mut store, &args[1]);
+                let range = rank_range(entries.len(), &args[2], &args[3]);
+                let removed = entries.drain(range).count();
+                store.insert(args[1].clone(), Value::ZSet(entries));
+                format!(":{}\r\n", removed)
+            }
+            "ZRANGEBYSCORE" => {
+                let (min, max) = (score_bound(&args[2]), score_bound(&args[3]));
+                let entries: Vec<_> = zset(&mut store, &args[1])
+                    .into_iter()
+                    .filter(|(score, _)| *score >= min && *score <= max)
+                    .collect();
+                with_scores(&entries, &args[4..])
+            }
+            "ZREMRANGEBYSCORE" => {
+                let (min, max) = (score_bound(&args[2]), score_bound(&args[3]));
+                let mut entries = zset(&mut store, &args[1]);
+                let before = entries.len();
+                entries.retain(|(score, _)| *score < min || *score > max);
+                let removed = before - entries.len();
+                store.insert(args[1].clone(), Value::ZSet(entries));
+                format!(":{}\r\n", removed)
+            }
+            "ZREM" => {
+                let mut entries = zset(&mut store, &args[1]);
+                let before = entries.len();
+                entries.retain(|(_, m)| !args[2..].contains(m));
+                let removed = before - entries.len();
+                store.insert(args[1].clone(), Value::ZSet(entries));
+                format!(":{}\r\n", removed)
+            }
+            _ => format!("-ERR unknown command '{}'\r\n", args[0]),
+        }
+    }
+
+    fn message(seq: i64) -> String {
+        format!(r#"{{"seq":{},"message_id":"m{}"}}"#, seq, seq)
+    }
+
+    fn queued_seqs(store: &Store, open_id: &str) -> Vec<i64> {
+        match store.lock().unwrap().get(&queue_key(open_id)) {
+            Some(Value::ZSet(entries)) => entries.iter().map(|(score, _)| *score as i64).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn push_keeps_one_copy_per_message() {
+        let (redis, store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        queue.push("u1", &message(1)).await.unwrap();
+        queue.push("u1", &message(1)).await.unwrap();
+        queue.push("u1", &message(2)).await.unwrap();
+        assert_eq!(queued_seqs(&store, "u1"), vec![1, 2]);
+
+        let batch = queue.peek("u1").await.unwrap();
+        assert_eq!(batch.entries, vec![(1, message(1)), (2, message(2))]);
+        assert_eq!(batch.overflowed_up_to, None);
+    }
+
+    #[tokio::test]
+    async fn push_skips_expired_messages() {
+        let (redis, store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        queue.push("u1", r#"{"seq":1,"expires_at":1}"#).await.unwrap();
+        assert!(queued_seqs(&store, "u1").is_empty());
+    }
+
+    #[tokio::test]
+    async fn push_over_cap_drops_oldest_and_records_overflow() {
+        let (redis, store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        let total = OFFLINE_QUEUE_MAX_ENTRIES + 3;
+        for seq in 1..=total {
+            queue.push("u1", &message(seq)).await.unwrap();
+        }
+
+        let seqs = queued_seqs(&store, "u1");
+        assert_eq!(seqs.len() as i64, OFFLINE_QUEUE_MAX_ENTRIES);
+        assert_eq!(seqs.first(), Some(&4));
+        assert_eq!(seqs.last(), Some(&total));
+
+        let batch = queue.peek("u1").await.unwrap();
+        assert_eq!(batch.overflowed_up_to, Some(3));
+
+        queue.clear_overflow("u1").await.unwrap();
+        assert_eq!(queue.peek("u1").await.unwrap().overflowed_up_to, None);
+    }
+
+    #[tokio::test]
+    async fn overflow_marker_only_moves_forward() {
+        let (redis, _store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        for seq in 10..10 + OFFLINE_QUEUE_MAX_ENTRIES + 1 {
+            queue.push("u1", &message(seq)).await.unwrap();
+        }
+        assert_eq!(queue.peek("u1").await.unwrap().overflowed_up_to, Some(10));
+
+        // 比队列中所有消息都早的消息写入后立即被丢弃，不会把溢出标记往回改
+        queue.push("u1", &message(5)).await.unwrap();
+        assert_eq!(queue.peek("u1").await.unwrap().overflowed_up_to, Some(10));
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_only_the_given_seqs() {
+        let (redis, store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        for seq in 1..=5 {
+            queue.push("u1", &message(seq)).await.unwrap();
+        }
+
+        // 2 和 4 没有写给客户端，不能因为落在范围内被一并删除
+        queue.remove("u1", &[5, 1, 3]).await.unwrap();
+        assert_eq!(queued_seqs(&store, "u1"), vec![2, 4]);
+
+        queue.remove("u1", &[]).await.unwrap();
+        queue.remove("u1", &[7, 8]).await.unwrap();
+        assert_eq!(queued_seqs(&store, "u1"), vec![2, 4]);
+    }
+
+    #[tokio::test]
+    async fn remove_up_to_deletes_everything_at_or_below_seq() {
+        let (redis, store) = start_fake_redis().await;
+        let queue = OfflineQueue::new(redis);
+        for seq in 1..=4 {
+            queue.push("u1", &message(seq)).await.unwrap();
+        }
+        queue.remove_up_to("u1", 2).await.unwrap();
+        assert_eq!(queued_seqs(&store, "u1"), vec![3, 4]);
+    }
+}
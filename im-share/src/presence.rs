@@ -0,0 +1,240 @@
+use crate::redis::RedisClient;
+use crate::utils::now_timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// 在线状态注册表（Redis）
+// im-connect 在连接建立、心跳和断开时写入，im-server 查询
+// - presence:devices:{open_id}  Hash，field = 设备（subscription_id），value = DeviceEntry JSON
+// - presence:last_seen:{open_id} 最后活跃时间（毫秒时间戳）
+// 每个设备条目带心跳时间，超过 PRESENCE_DEVICE_TTL_SECS 没有心跳视为已断开（进程崩溃等未正常清理的情况），
+// 读取时顺带清理；整个 Hash 也设置同样的过期时间，所有设备都停止心跳后自动消失
+
+/// im-connect 刷新设备心跳的间隔（秒）
+pub const PRESENCE_HEARTBEAT_SECS: u64 = 30;
+/// 设备条目的有效期（秒），允许错过两次心跳
+pub const PRESENCE_DEVICE_TTL_SECS: u64 = 90;
+/// 最后活跃时间保留时长：30 天
+const LAST_SEEN_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+/// im-connect 在设备上线、下线或切换状态后发布到这个 topic，im-server 订阅后向好友推送状态变化
+pub const PRESENCE_CHANGED_TOPIC: &str = "system/presence/changed";
+
+/// PRESENCE_CHANGED_TOPIC 上的消息，只携带 open_id，订阅方自行从注册表读取最新状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceChanged {
+    pub open_id: String,
+}
+
+/// 在线状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+/// Redis 中保存的设备条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DeviceEntry {
+    status: PresenceStatus,
+    connected_at: i64,
+    heartbeat_at: i64,
+}
+
+/// 单个设备的在线状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DevicePresence {
+    pub device_id: String,
+    pub status: PresenceStatus,
+    pub connected_at: i64,
+    pub heartbeat_at: i64,
+}
+
+/// 用户的在线状态（多设备汇总）
+/// - 任一设备在线：online
+/// - 有设备连接但全部处于离开状态：away
+/// - 没有设备连接：offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub open_id: String,
+    pub status: PresenceStatus,
+    /// 最后活跃时间（毫秒时间戳），从未连接过为 None
+    pub last_seen: Option<i64>,
+    pub devices: Vec<DevicePresence>,
+}
+
+#[derive(Clone)]
+pub struct PresenceRegistry {
+    redis: Arc<RedisClient>,
+}
+
+fn devices_key(open_id: &str) -> String {
+    format!("presence:devices:{}", open_id)
+}
+
+fn last_seen_key(open_id: &str) -> String {
+    format!("presence:last_seen:{}", open_id)
+}
+
+impl PresenceRegistry {
+    pub fn new(redis: Arc<RedisClient>) -> Self {
+        Self { redis }
+    }
+
+    /// 设备上线（WebSocket 连接建立）
+    pub async fn connect(&self, open_id: &str, device_id: &str) -> Result<(), redis::RedisError> {
+        let now = now_timestamp();
+        let entry = DeviceEntry {
+            status: PresenceStatus::Online,
+            connected_at: now,
+            heartbeat_at: now,
+        };
+        self.write_entry(open_id, device_id, &entry).await
+    }
+
+    /// 刷新设备心跳，保持当前状态（在线/离开）不变
+    /// 条目已过期被清理时按在线状态重新写入
+    pub async fn heartbeat(&self, open_id: &str, device_id: &str) -> Result<(), redis::RedisError> {
+        let now = now_timestamp();
+        let entry = match self.read_entry(open_id, device_id).await? {
+            Some(entry) => DeviceEntry { heartbeat_at: now, ..entry },
+            None => DeviceEntry {
+                status: PresenceStatus::Online,
+                connected_at: now,
+                heartbeat_at: now,
+            },
+        };
+        self.write_entry(open_id, device_id, &entry).await
+    }
+
+    /// 客户端主动切换状态（在线/离开），offline 等同于断开
+    pub async fn set_status(&self, open_id: &str, device_id: &str, status: PresenceStatus) -> Result<(), redis::RedisError> {
+        if status == PresenceStatus::Offline {
+            return self.disconnect(open_id, device_id).await;
+        }
+        let now = now_timestamp();
+        let connected_at = self
+            .read_entry(open_id, device_id)
+            .await?
+            .map(|entry| entry.connected_at)
+            .unwrap_or(now);
+        let entry = DeviceEntry {
+            status,
+            connected_at,
+            heartbeat_at: now,
+        };
+        self.write_entry(open_id, device_id, &entry).await
+    }
+
+    /// 设备下线（WebSocket 连接断开）
+    pub async fn disconnect(&self, open_id: &str, device_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("HDEL")
+            .arg(devices_key(open_id))
+            .arg(device_id)
+            .query_async::<()>(&mut conn)
+            .await?;
+        self.redis
+            .set_with_ttl(&last_seen_key(open_id), &now_timestamp().to_string(), LAST_SEEN_TTL_SECS)
+            .await
+    }
+
+    /// 查询用户在线状态
+    pub async fn get(&self, open_id: &str) -> Result<UserPresence, redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        let key = devices_key(open_id);
+        let raw: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await?;
+
+        let expire_before = now_timestamp() - (PRESENCE_DEVICE_TTL_SECS as i64) * 1000;
+        let mut devices = Vec::new();
+        let mut stale = Vec::new();
+        for (device_id, value) in raw {
+            match serde_json::from_str::<DeviceEntry>(&value) {
+                Ok(entry) if entry.heartbeat_at >= expire_before => devices.push(DevicePresence {
+                    device_id,
+                    status: entry.status,
+                    connected_at: entry.connected_at,
+                    heartbeat_at: entry.heartbeat_at,
+                }),
+                _ => stale.push(device_id),
+            }
+        }
+        if !stale.is_empty() {
+            redis::cmd("HDEL")
+                .arg(&key)
+                .arg(&stale)
+                .query_async::<()>(&mut conn)
+                .await?;
+        }
+        devices.sort_by_key(|d| d.connected_at);
+
+        let status = if devices.iter().any(|d| d.status == PresenceStatus::Online) {
+            PresenceStatus::Online
+        } else if devices.is_empty() {
+            PresenceStatus::Offline
+        } else {
+            PresenceStatus::Away
+        };
+        // 在线设备的最新心跳即为最后活跃时间，没有在线设备时取断开时记录的时间
+        let last_seen = match devices.iter().map(|d| d.heartbeat_at).max() {
+            Some(heartbeat_at) => Some(heartbeat_at),
+            None => self
+                .redis
+                .get(&last_seen_key(open_id))
+                .await?
+                .and_then(|s| s.parse().ok()),
+        };
+
+        Ok(UserPresence {
+            open_id: open_id.to_string(),
+            status,
+            last_seen,
+            devices,
+        })
+    }
+
+    /// 批量查询用户在线状态
+    pub async fn get_many(&self, open_ids: &[String]) -> Result<Vec<UserPresence>, redis::RedisError> {
+        let mut result = Vec::with_capacity(open_ids.len());
+        for open_id in open_ids {
+            result.push(self.get(open_id).await?);
+        }
+        Ok(result)
+    }
+
+    /// 用户是否有设备连接（在线或离开都算）
+    pub async fn is_online(&self, open_id: &str) -> Result<bool, redis::RedisError> {
+        Ok(self.get(open_id).await?.status != PresenceStatus::Offline)
+    }
+
+    async fn read_entry(&self, open_id: &str, device_id: &str) -> Result<Option<DeviceEntry>, redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        let value: Option<String> = redis::cmd("HGET")
+            .arg(devices_key(open_id))
+            .arg(device_id)
+            .query_async(&mut conn)
+            .await?;
+        Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+    }
+
+    async fn write_entry(&self, open_id: &str, device_id: &str, entry: &DeviceEntry) -> Result<(), redis::RedisError> {
+        let key = devices_key(open_id);
+        let value = serde_json::to_string(entry).unwrap_or_default();
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("HSET")
+            .arg(&key)
+            .arg(device_id)
+            .arg(value)
+            .query_async::<()>(&mut conn)
+            .await?;
+        self.redis.expire(&key, PRESENCE_DEVICE_TTL_SECS).await?;
+        self.redis
+            .set_with_ttl(&last_seen_key(open_id), &entry.heartbeat_at.to_string(), LAST_SEEN_TTL_SECS)
+            .await
+    }
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use crate::presence::PresenceStatus;
+
+// WebSocket 上的客户端请求 / 服务端响应协议
+// 客户端发送：{"id": "req-1", "type": "send_message", "data": {...}}
+// 服务端响应：{"type": "response", "id": "req-1", "ok": true, "data": {...}}
+// 服务端推送的聊天消息保持原有格式（ChatMessage JSON），不包在响应帧里
+// 帧编码（JSON / MessagePack）在握手时通过 Sec-WebSocket-Protocol 协商，见 WireFormat
+// 需要可靠送达的推送带递增的 seq 字段，客户端重连时通过 ?last_seq= 或第一帧 resume 命令从断点继续
+
+/// 握手时通过 Sec-WebSocket-Protocol 协商编码，按服务端偏好排序（未协商时使用 JSON 文本帧，兼容旧客户端）
+pub const MSGPACK_SUBPROTOCOL: &str = "im.msgpack";
+pub const JSON_SUBPROTOCOL: &str = "im.json";
+pub const SUBPROTOCOLS: [&str; 2] = [MSGPACK_SUBPROTOCOL, JSON_SUBPROTOCOL];
+
+/// WebSocket 帧编码
+/// - Json：文本帧，帧结构见本文件
+/// - MessagePack：二进制帧，结构与 JSON 完全相同（字段名保留），只是换成 MessagePack 编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        match name {
+            JSON_SUBPROTOCOL => Some(Self::Json),
+            MSGPACK_SUBPROTOCOL => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Self::Json => JSON_SUBPROTOCOL,
+            Self::MessagePack => MSGPACK_SUBPROTOCOL,
+        }
+    }
+
+    pub fn is_binary(self) -> bool {
+        self == Self::MessagePack
+    }
+}
+
+/// 服务端发往客户端的帧
+/// 推送保持原有结构（ChatMessage 等 JSON 对象，im-connect 不关心具体类型），响应帧、resync 帧、reconnect 帧和系统通知帧带 type 字段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServerFrame {
+    Response(ServerResponse),
+    Resync(ResyncFrame),
+    Reconnect(ReconnectFrame),
+    Notice(NoticeFrame),
+    Push(serde_json::Value),
+}
+
+/// 响应帧的 type 字段
+pub const RESPONSE_FRAME_TYPE: &str = "response";
+
+/// 补发不完整时推送的帧的 type 字段
+pub const RESYNC_FRAME_TYPE: &str = "resync";
+
+/// 收件箱日志中已经没有客户端需要的全部消息（超出保留范围），
+/// 客户端需要通过历史消息接口补齐 last_seq 之后缺失的消息，之后按 latest_seq 继续
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResyncFrame {
+    #[serde(rename = "type")]
+    pub frame_type: String,
+    pub last_seq: i64,
+    pub latest_seq: i64,
+}
+
+impl ResyncFrame {
+    pub fn new(last_seq: i64, latest_seq: i64) -> Self {
+        Self {
+            frame_type: RESYNC_FRAME_TYPE.to_string(),
+            last_seq,
+            latest_seq,
+        }
+    }
+}
+
+/// 服务端即将关闭连接时推送的帧的 type 字段
+pub const RECONNECT_FRAME_TYPE: &str = "reconnect";
+/// 推送 reconnect 帧后关闭连接使用的 WebSocket 关闭码（1012 Service Restart），客户端应按 retry_after_ms 重连
+pub const RECONNECT_CLOSE_CODE: u16 = 1012;
+/// 客户端接收太慢、发送队列溢出时关闭连接使用的关闭码（1013 Try Again Later）
+/// 积压的消息已转存为离线消息，客户端应稍后重连并带上 last_seq
+pub const SLOW_CONSUMER_CLOSE_CODE: u16 = 1013;
+/// 连接被管理员强制断开时使用的关闭码，会话仍然有效，客户端可以重连
+pub const ADMIN_DISCONNECT_CLOSE_CODE: u16 = 4002;
+/// 连续多个心跳周期没有收到客户端的任何帧（包括 pong），连接视为已失效；客户端可以立即重连
+pub const HEARTBEAT_TIMEOUT_CLOSE_CODE: u16 = 4008;
+/// 节点连接数已满（1013 Try Again Later），客户端应退避后重连（负载均衡可能分配到其他节点）
+pub const NODE_FULL_CLOSE_CODE: u16 = 1013;
+/// 同一用户或同一 IP 的连接数超过上限，客户端不应自动重连，需要先关闭其他连接
+pub const CONNECTION_LIMIT_CLOSE_CODE: u16 = 4029;
+
+/// 节点即将下线（发布/重启），连接随后被关闭
+/// 客户端应在 retry_after_ms 之后重连（负载均衡会分配到其他节点），带上 last_seq 从断点继续；
+/// 每个连接的等待时间带随机抖动，避免所有客户端同时重连
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectFrame {
+    #[serde(rename = "type")]
+    pub frame_type: String,
+    pub reason: String,
+    pub retry_after_ms: u64,
+}
+
+impl ReconnectFrame {
+    pub fn new(reason: impl Into<String>, retry_after_ms: u64) -> Self {
+        Self {
+            frame_type: RECONNECT_FRAME_TYPE.to_string(),
+            reason: reason.into(),
+            retry_after_ms,
+        }
+    }
+
+    /// 重连等待时间在 [min_ms, max_ms] 内随机
+    pub fn jittered(reason: impl Into<String>, min_ms: u64, max_ms: u64) -> Self {
+        let span = max_ms.saturating_sub(min_ms);
+        let jitter = if span == 0 {
+            0
+        } else {
+            (uuid::Uuid::new_v4().as_u128() % (span as u128 + 1)) as u64
+        };
+        Self::new(reason, min_ms + jitter)
+    }
+}
+
+/// 系统通知帧的 type 字段
+pub const NOTICE_FRAME_TYPE: &str = "server_notice";
+
+/// 管理员向所有在线连接广播的系统通知（维护公告等），不做离线存储
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoticeFrame {
+    #[serde(rename = "type")]
+    pub frame_type: String,
+    pub message: String,
+    /// info / warning / critical，客户端据此决定展示方式
+    pub level: String,
+    pub timestamp_ms: i64,
+}
+
+impl NoticeFrame {
+    pub fn new(message: impl Into<String>, level: impl Into<String>) -> Self {
+        Self {
+            frame_type: NOTICE_FRAME_TYPE.to_string(),
+            message: message.into(),
+            level: level.into(),
+            timestamp_ms: crate::utils::now_timestamp(),
+        }
+    }
+}
+
+/// 客户端发送的请求帧
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientFrame {
+    /// 请求ID，由客户端生成，响应帧原样带回
+    pub id: String,
+    #[serde(flatten)]
+    pub command: ClientCommand,
+}
+
+/// 客户端命令
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClientCommand {
+    /// 发送消息
+    SendMessage(SendMessageCommand),
+    /// 标记消息已读
+    MarkRead(MarkReadCommand),
+    /// 正在输入
+    Typing(TypingCommand),
+    /// 确认已收到推送的消息
+    Ack(AckCommand),
+    /// 切换当前设备的在线状态（online / away）
+    SetPresence(SetPresenceCommand),
+    /// 断线重连后从最后确认的序号继续接收（只能作为连接建立后的第一帧）
+    Resume(ResumeCommand),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendMessageCommand {
+    /// 1=单聊，2=群聊
+    pub chat_type: i32,
+    /// 单聊为对方 open_id，群聊为 group_id
+    pub to_id: String,
+    pub message_body: String,
+    #[serde(default = "default_message_content_type")]
+    pub message_content_type: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkReadCommand {
+    /// 1=单聊，2=群聊
+    pub chat_type: i32,
+    pub message_id: String,
+    /// 群聊消息所在的群组ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypingCommand {
+    /// 1=单聊，2=群聊
+    pub chat_type: i32,
+    /// 单聊为对方 open_id，群聊为 group_id
+    pub to_id: String,
+    /// true=开始输入，false=停止输入
+    #[serde(default = "default_typing")]
+    pub typing: bool,
+}
+
+/// 确认已处理的推送，可以按 seq 或 message_id 确认（连接时开启 ack 后，未确认的推送会被重发）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AckCommand {
+    #[serde(default)]
+    pub seqs: Vec<i64>,
+    #[serde(default)]
+    pub message_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPresenceCommand {
+    pub status: PresenceStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeCommand {
+    /// 客户端已处理的最后一条推送的 seq
+    pub last_seq: i64,
+}
+
+fn default_message_content_type() -> i32 {
+    1
+}
+
+fn default_typing() -> bool {
+    true
+}
+
+/// 服务端对请求帧的响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerResponse {
+    #[serde(rename = "type")]
+    pub frame_type: String,
+    /// 对应请求帧的 id（请求帧无法解析时为空字符串）
+    pub id: String,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ResponseError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseError {
+    /// 错误码，与 im-server 的 ErrorCode 一致（如 INVALID_INPUT），连接层错误使用 BAD_FRAME / UPSTREAM
+    pub code: String,
+    pub message: String,
+}
+
+impl ServerResponse {
+    pub fn ok(id: impl Into<String>, data: Option<serde_json::Value>) -> Self {
+        Self {
+            frame_type: RESPONSE_FRAME_TYPE.to_string(),
+            id: id.into(),
+            ok: true,
+            data,
+            error: None,
+        }
+    }
+
+    pub fn error(id: impl Into<String>, code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            frame_type: RESPONSE_FRAME_TYPE.to_string(),
+            id: id.into(),
+            ok: false,
+            data: None,
+            error: Some(ResponseError {
+                code: code.into(),
+                message: message.into(),
+            }),
+        }
+    }
+}
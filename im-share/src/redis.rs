@@ -83,6 +83,21 @@ impl RedisClient {
             .await
     }
     
+    /// 键不存在时才设置（带过期时间，单位：毫秒），返回是否设置成功
+    /// 可用于简单的限流和去重
+    pub async fn set_nx_with_ttl_ms(&self, key: &str, value: &str, ttl_ms: u64) -> Result<bool, redis::RedisError> {
+        let mut conn = self.get_connection().await;
+        let result: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut conn)
+            .await?;
+        Ok(result.is_some())
+    }
+    
     /// 设置键值对（永久）
     pub async fn set(&self, key: &str, value: &str) -> Result<(), redis::RedisError> {
         let mut conn = self.get_connection().await;
@@ -144,117 +159,6 @@ impl RedisClient {
             .await
     }
     
-    
-    /// 添加离线消息到队列（使用 open_id）
-    /// 使用 Redis List 存储，key: offline:message:{open_id}
-    /// 使用 RPUSH 将新消息追加到列表末尾，确保消息按时间顺序（从旧到新）存储
-    pub async fn add_offline_message(&self, open_id: &str, message: &str) -> Result<(), redis::RedisError> {
-        use tracing::info;
-        let key = format!("offline:message:{}", open_id);
-        let mut conn = self.get_connection().await;
-        
-        // 尝试解析消息以获取 chat_type 用于日志
-        let chat_type_info = if let Ok(json) = serde_json::from_str::<serde_json::Value>(message) {
-            format!("chat_type={:?}", json.get("chat_type"))
-        } else {
-            "无法解析JSON".to_string()
-        };
-        
-        info!(
-            open_id = %open_id, 
-            key = %key, 
-            %chat_type_info,
-            message_preview = if message.len() > 100 { format!("{}...", &message[..100]) } else { message.to_string() },
-            "执行Redis RPUSH操作，存储离线消息"
-        );
-        
-        let result = redis::cmd("RPUSH")
-            .arg(&key)
-            .arg(message)
-            .query_async::<i64>(&mut conn)
-            .await?;
-        
-        info!(
-            open_id = %open_id, 
-            key = %key, 
-            list_length = result, 
-            %chat_type_info,
-            "✅ Redis RPUSH成功，列表长度: {}",
-            result
-        );
-        
-        // 设置过期时间：7天
-        redis::cmd("EXPIRE")
-            .arg(&key)
-            .arg(604800u64) // 7 * 24 * 60 * 60
-            .query_async::<()>(&mut conn)
-            .await?;
-        
-        info!(
-            open_id = %open_id, 
-            key = %key, 
-            %chat_type_info,
-            "Redis EXPIRE设置成功，过期时间7天"
-        );
-        
-        Ok(())
-    }
-    
-    /// 获取并删除所有离线消息（使用 open_id）
-    /// 返回消息列表，按时间顺序（从旧到新）
-    /// LRANGE 0 -1 从左到右获取所有消息，由于使用 RPUSH，消息已按从旧到新顺序存储
-    pub async fn get_and_clear_offline_messages(&self, open_id: &str) -> Result<Vec<String>, redis::RedisError> {
-        use tracing::{info, debug};
-        let key = format!("offline:message:{}", open_id);
-        let mut conn = self.get_connection().await;
-        
-        // 获取所有消息（从左到右，即从旧到新，因为使用 RPUSH 追加）
-        // 如果 key 不存在，LRANGE 会返回空列表，无需先检查 EXISTS
-        let messages: Vec<String> = redis::cmd("LRANGE")
-            .arg(&key)
-            .arg(0)
-            .arg(-1)
-            .query_async(&mut conn)
-            .await?;
-        
-        // 只在有消息时输出详细日志，没有消息时使用 debug 级别
-        if !messages.is_empty() {
-            info!(
-                open_id = %open_id, 
-                key = %key, 
-                message_count = messages.len(), 
-                "从Redis获取到 {} 条离线消息", 
-                messages.len()
-            );
-            
-            // 删除key
-            redis::cmd("DEL")
-                .arg(&key)
-                .query_async::<()>(&mut conn)
-                .await?;
-            debug!(open_id = %open_id, key = %key, "已删除Redis离线消息key");
-        } else {
-            debug!(
-                open_id = %open_id, 
-                key = %key, 
-                "Redis中没有离线消息"
-            );
-        }
-        
-        Ok(messages)
-    }
-    
-    /// 获取离线消息数量（使用 open_id）
-    pub async fn get_offline_message_count(&self, open_id: &str) -> Result<usize, redis::RedisError> {
-        let key = format!("offline:message:{}", open_id);
-        let mut conn = self.get_connection().await;
-        let count: i64 = redis::cmd("LLEN")
-            .arg(&key)
-            .query_async(&mut conn)
-            .await?;
-        Ok(count as usize)
-    }
-
     // ========== 群消息已读状态相关方法（使用 Redis Set） ==========
     
     /// 标记群消息为已读
@@ -330,5 +234,36 @@ impl RedisClient {
         }
         Ok(())
     }
+    
+    /// 标记群语音消息为已播放
+    /// key: group:played:{group_id}:{message_id}
+    /// 与已读状态一样使用 Set 存储已播放用户的 open_id
+    pub async fn mark_group_message_played(&self, group_id: &str, message_id: &str, user_id: &str) -> Result<(), redis::RedisError> {
+        let key = format!("group:played:{}:{}", group_id, message_id);
+        let mut conn = self.get_connection().await;
+        let _: i64 = redis::cmd("SADD")
+            .arg(&key)
+            .arg(user_id)
+            .query_async(&mut conn)
+            .await?;
+        // 设置过期时间：30天
+        let _: i64 = redis::cmd("EXPIRE")
+            .arg(&key)
+            .arg(2592000u64)
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+    
+    /// 获取群语音消息的已播放用户列表
+    pub async fn get_group_message_played_users(&self, group_id: &str, message_id: &str) -> Result<Vec<String>, redis::RedisError> {
+        let key = format!("group:played:{}:{}", group_id, message_id);
+        let mut conn = self.get_connection().await;
+        let users: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await?;
+        Ok(users)
+    }
 }
 
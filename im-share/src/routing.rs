@@ -0,0 +1,145 @@
+use crate::presence::PRESENCE_DEVICE_TTL_SECS;
+use crate::redis::RedisClient;
+use crate::utils::now_timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// 跨节点路由表（Redis）：用户的连接分别在哪个 im-connect 节点上
+// im-connect 在连接建立、心跳和断开时写入，im-server 投递消息时查询，直接发布到所在节点的 topic
+// - route:user:{open_id}  Hash，field = {node_id}:{connection_id}，value = RouteEntry JSON
+// 条目随在线状态心跳刷新，超过 ROUTE_TTL_SECS 没有刷新视为已断开（节点崩溃等未正常清理的情况），读取时顺带清理；
+// 整个 Hash 也设置同样的过期时间
+
+/// 路由条目的有效期（秒），与设备在线状态一致
+pub const ROUTE_TTL_SECS: u64 = PRESENCE_DEVICE_TTL_SECS;
+
+/// 节点 topic，每个 im-connect 节点启动时订阅自己的 topic
+pub fn node_topic(node_id: &str) -> String {
+    format!("im/node/{}", node_id)
+}
+
+/// 发布到节点 topic 的消息：节点收到后按 topic 转发给本地订阅了该用户 topic 的连接，与从 broker 收到的用户 topic 消息相同
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeDelivery {
+    /// 用户 topic（mqtt_user_topic）
+    pub topic: String,
+    pub payload: String,
+}
+
+/// Redis 中保存的路由条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RouteEntry {
+    node_id: String,
+    connection_id: u64,
+    heartbeat_at: i64,
+}
+
+/// 用户在某个节点上的连接
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeRoute {
+    pub node_id: String,
+    pub connection_ids: Vec<u64>,
+}
+
+#[derive(Clone)]
+pub struct RouteRegistry {
+    redis: Arc<RedisClient>,
+}
+
+fn routes_key(open_id: &str) -> String {
+    format!("route:user:{}", open_id)
+}
+
+fn route_field(node_id: &str, connection_id: u64) -> String {
+    format!("{}:{}", node_id, connection_id)
+}
+
+impl RouteRegistry {
+    pub fn new(redis: Arc<RedisClient>) -> Self {
+        Self { redis }
+    }
+
+    /// 登记或刷新连接所在的节点（连接建立和每次心跳时调用）
+    pub async fn register(&self, open_id: &str, node_id: &str, connection_id: u64) -> Result<(), redis::RedisError> {
+        let key = routes_key(open_id);
+        let entry = RouteEntry {
+            node_id: node_id.to_string(),
+            connection_id,
+            heartbeat_at: now_timestamp(),
+        };
+        let value = serde_json::to_string(&entry).unwrap_or_default();
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("HSET")
+            .arg(&key)
+            .arg(route_field(node_id, connection_id))
+            .arg(value)
+            .query_async::<()>(&mut conn)
+            .await?;
+        self.redis.expire(&key, ROUTE_TTL_SECS).await
+    }
+
+    /// 连接断开
+    pub async fn unregister(&self, open_id: &str, node_id: &str, connection_id: u64) -> Result<(), redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("HDEL")
+            .arg(routes_key(open_id))
+            .arg(route_field(node_id, connection_id))
+            .query_async::<()>(&mut conn)
+            .await
+    }
+
+    /// 移除用户在某个节点上的全部连接（向该节点投递失败时调用，节点上仍然存在的连接下次心跳时重新登记）
+    pub async fn unregister_node(&self, open_id: &str, route: &NodeRoute) -> Result<(), redis::RedisError> {
+        if route.connection_ids.is_empty() {
+            return Ok(());
+        }
+        let fields: Vec<String> = route.connection_ids
+            .iter()
+            .map(|connection_id| route_field(&route.node_id, *connection_id))
+            .collect();
+        let mut conn = self.redis.get_connection().await;
+        redis::cmd("HDEL")
+            .arg(routes_key(open_id))
+            .arg(&fields)
+            .query_async::<()>(&mut conn)
+            .await
+    }
+
+    /// 用户当前连接所在的节点，没有连接时为空
+    pub async fn lookup(&self, open_id: &str) -> Result<Vec<NodeRoute>, redis::RedisError> {
+        let mut conn = self.redis.get_connection().await;
+        let key = routes_key(open_id);
+        let raw: Vec<(String, String)> = redis::cmd("HGETALL")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await?;
+
+        let expire_before = now_timestamp() - (ROUTE_TTL_SECS as i64) * 1000;
+        let mut nodes: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        let mut stale = Vec::new();
+        for (field, value) in raw {
+            match serde_json::from_str::<RouteEntry>(&value) {
+                Ok(entry) if entry.heartbeat_at >= expire_before => {
+                    nodes.entry(entry.node_id).or_default().push(entry.connection_id);
+                }
+                _ => stale.push(field),
+            }
+        }
+        if !stale.is_empty() {
+            redis::cmd("HDEL")
+                .arg(&key)
+                .arg(&stale)
+                .query_async::<()>(&mut conn)
+                .await?;
+        }
+
+        Ok(nodes
+            .into_iter()
+            .map(|(node_id, mut connection_ids)| {
+                connection_ids.sort_unstable();
+                NodeRoute { node_id, connection_ids }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use crate::redis::RedisClient;
+
+// 登录会话（设备）
+// 带设备类型登录时每次登录创建一条 subscriptions 记录作为会话，token 的 sid 字段为该会话的 subscription_id
+// 同类设备超过上限时最早的会话被挤下线，用户也可以远程踢出自己的设备：
+// - 删除 subscriptions 记录
+// - 写入 session:revoked:{subscription_id}（值为原因），保留到该会话签发的 token 全部过期；
+//   im-server 认证中间件和 im-connect 握手都会检查，被吊销会话的 token 立即失效
+// - 向用户 topic 推送 kicked 事件，im-connect 只转发给对应会话的连接并随后关闭连接
+
+/// 被踢下线事件的 type 字段
+pub const KICKED_EVENT_TYPE: &str = "kicked";
+/// 推送 kicked 事件后 im-connect 关闭连接使用的 WebSocket 关闭码，客户端收到后不应自动重连
+pub const KICKED_CLOSE_CODE: u16 = 4001;
+
+/// 被踢下线的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KickReason {
+    /// 同类设备登录数超过上限，被新登录的设备挤下线
+    Replaced,
+    /// 用户在其他设备上远程踢出
+    Remote,
+}
+
+impl KickReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KickReason::Replaced => "replaced",
+            KickReason::Remote => "remote",
+        }
+    }
+}
+
+/// 推送给用户 topic 的被踢下线事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KickedEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// 被踢下线的会话
+    pub subscription_id: String,
+    pub reason: KickReason,
+    /// 被踢会话的设备类型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<String>,
+    pub timestamp: i64,
+}
+
+impl KickedEvent {
+    pub fn new(subscription_id: String, reason: KickReason, device_type: Option<String>) -> Self {
+        Self {
+            event_type: KICKED_EVENT_TYPE.to_string(),
+            subscription_id,
+            reason,
+            device_type,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+fn revoked_key(subscription_id: &str) -> String {
+    format!("session:revoked:{}", subscription_id)
+}
+
+/// 吊销会话，ttl_secs 应不短于 token 有效期
+pub async fn revoke_session(
+    redis: &RedisClient,
+    subscription_id: &str,
+    reason: KickReason,
+    ttl_secs: u64,
+) -> Result<(), redis::RedisError> {
+    redis.set_with_ttl(&revoked_key(subscription_id), reason.as_str(), ttl_secs.max(1)).await
+}
+
+/// 会话是否已被吊销
+pub async fn is_session_revoked(redis: &RedisClient, subscription_id: &str) -> Result<bool, redis::RedisError> {
+    redis.exists(&revoked_key(subscription_id)).await
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+use tracing::{info, warn};
+
+// 优雅退出
+// 收到 SIGTERM / Ctrl+C 后 Shutdown 进入退出状态：服务停止接受新的请求和连接，后台循环不再开始新的一轮；
+// 进行中的工作（WebSocket 连接、后台任务）持有 ShutdownGuard，全部结束或超过截止时间后进程再退出
+
+/// 等待退出信号（SIGTERM 或 Ctrl+C）
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!(error = %e, "监听 Ctrl+C 失败");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "监听 SIGTERM 失败");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("收到 Ctrl+C，开始优雅退出"),
+        _ = terminate => info!("收到 SIGTERM，开始优雅退出"),
+    }
+}
+
+struct ShutdownState {
+    triggered: watch::Sender<bool>,
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+/// 进程的退出状态和进行中的工作计数
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<ShutdownState>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(ShutdownState {
+                triggered: watch::Sender::new(false),
+                active: AtomicUsize::new(0),
+                idle: Notify::new(),
+            }),
+        }
+    }
+
+    /// 进入退出状态
+    pub fn trigger(&self) {
+        self.state.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.state.triggered.borrow()
+    }
+
+    /// 等待进入退出状态（已经处于退出状态时立即返回）
+    pub async fn triggered(&self) {
+        let mut rx = self.state.triggered.subscribe();
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// 登记一项进行中的工作，guard 被 drop 时结束
+    pub fn guard(&self) -> ShutdownGuard {
+        self.state.active.fetch_add(1, Ordering::SeqCst);
+        ShutdownGuard { state: self.state.clone() }
+    }
+
+    /// 进行中的工作数
+    pub fn active(&self) -> usize {
+        self.state.active.load(Ordering::SeqCst)
+    }
+
+    /// 等待进行中的工作全部结束，超时返回 false
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let idle = async {
+            loop {
+                // 先创建 Notified 再检查计数，避免检查之后、等待之前的通知丢失
+                let notified = self.state.idle.notified();
+                if self.active() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, idle).await.is_ok()
+    }
+}
+
+/// 一项进行中的工作
+pub struct ShutdownGuard {
+    state: Arc<ShutdownState>,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        if self.state.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.state.idle.notify_waiters();
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
+use crate::redis::RedisClient;
 
 /// 订阅 ID 管理服务
 /// 维护用户 ID 和订阅 ID 的映射关系
@@ -26,10 +28,9 @@ impl SubscriptionService {
         let mut user_subs = self.user_subscriptions.write().unwrap();
         
         // 如果用户已有订阅 ID，返回第一个
-        if let Some(subs) = user_subs.get(&user_id) {
-            if !subs.is_empty() {
-                return subs[0].clone();
-            }
+        if let Some(subs) = user_subs.get(&user_id)
+            && !subs.is_empty() {
+            return subs[0].clone();
         }
 
         // 生成新的订阅 ID
@@ -39,7 +40,7 @@ impl SubscriptionService {
         let mut subs = self.subscriptions.write().unwrap();
         subs.insert(subscription_id.clone(), user_id);
         
-        user_subs.entry(user_id).or_insert_with(Vec::new).push(subscription_id.clone());
+        user_subs.entry(user_id).or_default().push(subscription_id.clone());
         
         subscription_id
     }
@@ -52,7 +53,7 @@ impl SubscriptionService {
         subs.insert(subscription_id.clone(), user_id);
         
         let mut user_subs = self.user_subscriptions.write().unwrap();
-        user_subs.entry(user_id).or_insert_with(Vec::new).push(subscription_id.clone());
+        user_subs.entry(user_id).or_default().push(subscription_id.clone());
         
         subscription_id
     }
@@ -100,7 +101,7 @@ impl SubscriptionService {
         subs.insert(subscription_id.clone(), user_id);
         
         let mut user_subs = self.user_subscriptions.write().unwrap();
-        let user_sub_list = user_subs.entry(user_id).or_insert_with(Vec::new);
+        let user_sub_list = user_subs.entry(user_id).or_default();
         if !user_sub_list.contains(&subscription_id) {
             user_sub_list.push(subscription_id);
         }
@@ -113,129 +114,41 @@ impl Default for SubscriptionService {
     }
 }
 
-/// 根据订阅 ID 从服务器查询用户 ID（返回snowflake_id用于MQTT）
-/// 这是一个客户端函数，用于 im-connect 服务查询用户 ID
-pub async fn get_user_id_by_subscription(server_url: &str, subscription_id: &str) -> anyhow::Result<u64> {
-    let url = format!("{}/api/subscriptions/{}/user", server_url, subscription_id);
-    let response = reqwest::get(&url).await?;
-    
-    if !response.status().is_success() {
-        anyhow::bail!("HTTP 状态码: {}", response.status());
-    }
-    
-    let json: serde_json::Value = response.json().await?;
-    
-    // 优先使用snowflake_id，如果没有则使用user_id（向后兼容）
-    if let Some(snowflake_id) = json.get("snowflake_id").and_then(|v| v.as_u64()) {
-        Ok(snowflake_id)
-    } else {
-        json.get("user_id")
-            .and_then(|v| v.as_u64())
-            .ok_or_else(|| anyhow::anyhow!("用户 ID 不存在"))
-    }
+// 订阅 ID 对应的用户身份（Redis）
+// - subscription:user:{subscription_id}  SubscriptionIdentity JSON，保留到该次登录签发的 token 过期
+// im-server 登录时写入，im-connect 握手时遇到旧格式 token（token 中是数据库 ID）据此得到 open_id 和 MQTT ID，
+// 不再通过 HTTP 调用 im-server
+
+/// 订阅 ID 对应的用户身份
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionIdentity {
+    /// 数据库 ID
+    pub user_id: u64,
+    /// MQTT ID（open_id 的数字形式）
+    pub mqtt_id: u64,
+    pub open_id: String,
 }
 
-/// 根据订阅 ID 从服务器查询用户信息（返回snowflake_id和open_id）
-/// 这是一个客户端函数，用于 im-connect 服务查询用户信息
-/// 返回 (snowflake_id, open_id) 元组
-/// 包含重试机制，最多重试3次
-pub async fn get_user_info_by_subscription(server_url: &str, subscription_id: &str) -> anyhow::Result<(u64, String)> {
-    use tracing::{warn, error};
-    
-    let url = format!("{}/api/subscriptions/{}/user", server_url, subscription_id);
-    const MAX_RETRIES: u32 = 3;
-    const RETRY_DELAY_MS: u64 = 1000;
-    
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .map_err(|e| anyhow::anyhow!("创建 HTTP 客户端失败: {}", e))?;
-    
-    let mut last_error = None;
-    
-    for attempt in 1..=MAX_RETRIES {
-        match client.get(&url).send().await {
-            Ok(response) => {
-                if !response.status().is_success() {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_else(|_| "未知错误".to_string());
-                    
-                    if attempt < MAX_RETRIES {
-                        warn!(
-                            url = %url, 
-                            status = %status, 
-                            attempt = attempt,
-                            max_retries = MAX_RETRIES,
-                            "im-server API 返回错误，将重试"
-                        );
-                        tokio::time::sleep(std::time::Duration::from_millis(RETRY_DELAY_MS * attempt as u64)).await;
-                        continue;
-                    } else {
-                        error!(
-                            url = %url, 
-                            status = %status, 
-                            error = %error_text,
-                            "im-server API 返回错误，已达到最大重试次数"
-                        );
-                        anyhow::bail!("HTTP 状态码: {}, 错误: {}", status, error_text);
-                    }
-                }
-                
-                let json: serde_json::Value = response.json().await?;
-                
-                // 获取 user_id（数据库ID）
-                let user_id = json.get("user_id")
-                    .and_then(|v| v.as_u64())
-                    .ok_or_else(|| anyhow::anyhow!("用户 ID 不存在"))?;
-                
-                // 获取 snowflake_id（用于 MQTT）
-                let snowflake_id = if let Some(snowflake_id) = json.get("snowflake_id").and_then(|v| v.as_u64()) {
-                    snowflake_id
-                } else {
-                    user_id
-                };
-                
-                // 获取 open_id（用于 Redis 离线消息）
-                // 如果 open_id 不存在，使用 user_id 作为后备（与存储时的逻辑一致）
-                // 存储时：user.open_id.clone().unwrap_or_else(|| user.id.to_string())
-                let open_id = json.get("open_id")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| user_id.to_string()); // 如果没有 open_id，使用 user_id 的字符串形式作为后备
-                
-                return Ok((snowflake_id, open_id));
-            }
-            Err(e) => {
-                last_error = Some(e);
-                
-                if attempt < MAX_RETRIES {
-                    warn!(
-                        url = %url, 
-                        error = %last_error.as_ref().unwrap(),
-                        attempt = attempt,
-                        max_retries = MAX_RETRIES,
-                        "连接 im-server 失败，将重试。请确保 im-server 正在运行在 {}",
-                        server_url
-                    );
-                    tokio::time::sleep(std::time::Duration::from_millis(RETRY_DELAY_MS * attempt as u64)).await;
-                } else {
-                    error!(
-                        url = %url, 
-                        error = %last_error.as_ref().unwrap(),
-                        "连接 im-server 失败，已达到最大重试次数。请检查：\n  1. im-server 是否正在运行？\n  2. im-server 是否运行在 {}？\n  3. 网络连接是否正常？",
-                        server_url
-                    );
-                }
-            }
-        }
-    }
-    
-    // 如果所有重试都失败了
-    anyhow::bail!(
-        "连接 im-server 失败: {} (URL: {}). 请确保 im-server 正在运行在 {}",
-        last_error.unwrap(),
-        url,
-        server_url
-    )
+fn identity_key(subscription_id: &str) -> String {
+    format!("subscription:user:{}", subscription_id)
 }
 
+/// 登录时记录订阅 ID 对应的用户身份，ttl_secs 应不短于 token 有效期
+pub async fn store_subscription_identity(
+    redis: &RedisClient,
+    subscription_id: &str,
+    identity: &SubscriptionIdentity,
+    ttl_secs: u64,
+) -> Result<(), redis::RedisError> {
+    let value = serde_json::to_string(identity).unwrap_or_default();
+    redis.set_with_ttl(&identity_key(subscription_id), &value, ttl_secs.max(1)).await
+}
+
+/// 查询订阅 ID 对应的用户身份，没有记录（从未登录或已过期）时返回 None
+pub async fn get_subscription_identity(
+    redis: &RedisClient,
+    subscription_id: &str,
+) -> Result<Option<SubscriptionIdentity>, redis::RedisError> {
+    let value = redis.get(&identity_key(subscription_id)).await?;
+    Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+}
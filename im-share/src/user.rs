@@ -195,11 +195,10 @@ pub async fn get_open_id_by_identifier(server_url: &str, user_identifier: &str)
     if let Ok(_open_id_number) = user_identifier.parse::<u64>() {
         // 尝试通过 open_id 查询用户，验证它是否是有效的 open_id
         let test_url = format!("{}/api/users/{}/snowflake_id", server_url, user_identifier);
-        if let Ok(test_response) = client.get(&test_url).send().await {
-            if test_response.status().is_success() {
-                // 如果查询成功，说明这是有效的 open_id，直接返回
-                return Ok(user_identifier.to_string());
-            }
+        if let Ok(test_response) = client.get(&test_url).send().await
+            && test_response.status().is_success() {
+            // 如果查询成功，说明这是有效的 open_id，直接返回
+            return Ok(user_identifier.to_string());
         }
         // 如果查询失败，继续下面的逻辑
     }
@@ -221,11 +220,10 @@ pub async fn get_open_id_by_identifier(server_url: &str, user_identifier: &str)
                             .map(|s| s.to_string());
                         
                         // 如果返回的 open_id 是数字字符串（长度较长，可能是真正的 open_id），使用它
-                        if let Some(ref oid) = returned_open_id {
-                            if oid.parse::<u64>().is_ok() && oid.len() > 10 {
-                                // 看起来是真正的 open_id（数字字符串且长度较长）
-                                return Ok(oid.clone());
-                            }
+                        if let Some(ref oid) = returned_open_id
+                            && oid.parse::<u64>().is_ok() && oid.len() > 10 {
+                            // 看起来是真正的 open_id（数字字符串且长度较长）
+                            return Ok(oid.clone());
                         }
                         
                         // 如果返回的 open_id 看起来不像真正的 open_id（可能是用户名），
@@ -1,4 +1,7 @@
 use crate::model::ChatMessage;
+use crate::protocol::WireFormat;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn mqtt_user_topic(user_id: &str) -> String {
@@ -13,6 +16,33 @@ pub fn decode_message(bytes: &[u8]) -> serde_json::Result<ChatMessage> {
     serde_json::from_slice(bytes)
 }
 
+/// WebSocket 帧编解码错误
+#[derive(Debug, thiserror::Error)]
+pub enum FrameCodecError {
+    #[error("JSON 编解码失败: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("MessagePack 编码失败: {0}")]
+    MsgPackEncode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack 解码失败: {0}")]
+    MsgPackDecode(#[from] rmp_serde::decode::Error),
+}
+
+/// 按协商的编码序列化 WebSocket 帧（MessagePack 保留字段名，结构与 JSON 一致）
+pub fn encode_frame<T: Serialize + ?Sized>(format: WireFormat, frame: &T) -> Result<Vec<u8>, FrameCodecError> {
+    match format {
+        WireFormat::Json => Ok(serde_json::to_vec(frame)?),
+        WireFormat::MessagePack => Ok(rmp_serde::to_vec_named(frame)?),
+    }
+}
+
+/// 按协商的编码解析 WebSocket 帧
+pub fn decode_frame<T: DeserializeOwned>(format: WireFormat, bytes: &[u8]) -> Result<T, FrameCodecError> {
+    match format {
+        WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+        WireFormat::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+    }
+}
+
 /// 获取当前时间戳（毫秒）
 pub fn now_timestamp() -> i64 {
     SystemTime::now()
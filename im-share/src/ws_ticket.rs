@@ -0,0 +1,46 @@
+use crate::redis::RedisClient;
+
+// WebSocket 一次性票据
+// 浏览器无法在 WebSocket 握手中设置 Authorization 头，而把长期 JWT 放进 URL 会出现在代理和访问日志里。
+// 客户端先用 JWT 调用 im-server 的 POST /api/ws-ticket 换取短期票据，握手时通过 ?ticket= 或
+// Sec-WebSocket-Protocol: ticket.<票据> 提交，im-connect 兑换一次后立即删除
+// - ws:ticket:{ticket}  票据对应的 JWT（im-connect 兑换后仍按正常流程校验），WS_TICKET_TTL_SECS 后过期
+
+/// 票据有效期（秒），只需要覆盖从换取到发起握手的时间
+pub const WS_TICKET_TTL_SECS: u64 = 30;
+/// 通过 Sec-WebSocket-Protocol 提交票据时的前缀
+pub const WS_TICKET_SUBPROTOCOL_PREFIX: &str = "ticket.";
+
+fn ticket_key(ticket: &str) -> String {
+    format!("ws:ticket:{}", ticket)
+}
+
+/// 为已认证的 token 签发一次性票据
+pub async fn issue_ws_ticket(redis: &RedisClient, token: &str) -> Result<String, redis::RedisError> {
+    let ticket = uuid::Uuid::new_v4().simple().to_string();
+    redis.set_with_ttl(&ticket_key(&ticket), token, WS_TICKET_TTL_SECS).await?;
+    Ok(ticket)
+}
+
+/// 兑换票据，返回对应的 token；票据不存在、已过期或已被兑换过时返回 None
+/// 读取和删除在同一个事务里完成，同一票据并发兑换时只有一次成功
+pub async fn redeem_ws_ticket(redis: &RedisClient, ticket: &str) -> Result<Option<String>, redis::RedisError> {
+    let key = ticket_key(ticket);
+    let mut conn = redis.get_connection().await;
+    let (token,): (Option<String>,) = redis::pipe()
+        .atomic()
+        .cmd("GET").arg(&key)
+        .cmd("DEL").arg(&key).ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(token)
+}
+
+/// 从 Sec-WebSocket-Protocol 请求头中取出票据
+pub fn ticket_from_subprotocols(header: &str) -> Option<&str> {
+    header
+        .split(',')
+        .map(str::trim)
+        .find_map(|protocol| protocol.strip_prefix(WS_TICKET_SUBPROTOCOL_PREFIX))
+        .filter(|ticket| !ticket.is_empty())
+}
@@ -58,6 +58,33 @@ CREATE TABLE `im_chat` (
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
 /*!40101 SET character_set_client = @saved_cs_client */;
 
+--
+-- Table structure for table `im_export_job`
+--
+
+DROP TABLE IF EXISTS `im_export_job`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `im_export_job` (
+  `export_id` varchar(64) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '导出任务ID',
+  `owner_id` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '发起导出的用户ID（open_id）',
+  `scope_type` int NOT NULL COMMENT '导出范围：0全部会话，1单聊，2群聊',
+  `to_id` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '单聊对方用户ID或群组ID（全部会话时为空）',
+  `formats` varchar(64) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '导出格式，逗号分隔：json,csv,html',
+  `status` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT 'PENDING' COMMENT '任务状态：PENDING / RUNNING / DONE / FAILED / EXPIRED',
+  `file_path` varchar(512) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '导出文件相对上传目录的路径',
+  `file_size` bigint DEFAULT NULL COMMENT '导出文件大小（字节）',
+  `message_count` int DEFAULT NULL COMMENT '导出的消息条数',
+  `error` text COLLATE utf8mb4_unicode_ci COMMENT '失败原因',
+  `create_time` bigint NOT NULL COMMENT '创建时间',
+  `update_time` bigint DEFAULT NULL COMMENT '更新时间',
+  `expire_time` bigint DEFAULT NULL COMMENT '下载链接过期时间',
+  PRIMARY KEY (`export_id`),
+  KEY `idx_export_owner` (`owner_id`),
+  KEY `idx_export_status_expire` (`status`,`expire_time`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci COMMENT='会话导出任务';
+/*!40101 SET character_set_client = @saved_cs_client */;
+
 --
 -- Table structure for table `im_friendship`
 --
@@ -203,6 +230,35 @@ CREATE TABLE `im_group_message` (
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
 /*!40101 SET character_set_client = @saved_cs_client */;
 
+--
+-- Table structure for table `im_group_message_archive`
+--
+
+DROP TABLE IF EXISTS `im_group_message_archive`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `im_group_message_archive` (
+  `message_id` varchar(512) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '消息ID',
+  `group_id` varchar(255) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '群组ID',
+  `from_id` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '发送者用户ID',
+  `message_body` text COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '消息内容',
+  `message_time` bigint NOT NULL COMMENT '发送时间',
+  `message_content_type` int NOT NULL COMMENT '消息类型',
+  `extra` text COLLATE utf8mb4_unicode_ci COMMENT '扩展字段',
+  `del_flag` smallint NOT NULL COMMENT '删除标识（1正常，0删除）',
+  `sequence` bigint DEFAULT NULL COMMENT '消息序列',
+  `message_random` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '随机标识',
+  `create_time` bigint NOT NULL COMMENT '创建时间',
+  `update_time` bigint DEFAULT NULL COMMENT '更新时间',
+  `version` bigint DEFAULT NULL COMMENT '版本信息',
+  `reply_to` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '被引用的消息 ID',
+  `archive_time` bigint NOT NULL COMMENT '归档时间',
+  PRIMARY KEY (`message_id`),
+  KEY `idx_group_msg_archive_group` (`group_id`),
+  KEY `idx_sequence` (`sequence`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci COMMENT='群聊消息归档表：超过保留期限的消息从 im_group_message 迁移至此';
+/*!40101 SET character_set_client = @saved_cs_client */;
+
 --
 -- Table structure for table `im_outbox`
 --
@@ -243,6 +299,7 @@ CREATE TABLE `im_single_message` (
   `message_time` bigint NOT NULL COMMENT '发送时间',
   `message_content_type` int NOT NULL COMMENT '消息类型',
   `read_status` int NOT NULL COMMENT '阅读状态（1已读）',
+  `played_status` int NOT NULL DEFAULT '0' COMMENT '语音播放状态（1已播放）',
   `extra` text COLLATE utf8mb4_unicode_ci COMMENT '扩展字段',
   `del_flag` smallint NOT NULL COMMENT '删除标识（1正常，0删除）',
   `sequence` bigint NOT NULL COMMENT '消息序列',
@@ -262,6 +319,42 @@ CREATE TABLE `im_single_message` (
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
 /*!40101 SET character_set_client = @saved_cs_client */;
 
+--
+-- Table structure for table `im_single_message_archive`
+--
+
+DROP TABLE IF EXISTS `im_single_message_archive`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `im_single_message_archive` (
+  `message_id` varchar(512) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '消息ID',
+  `from_id` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '发送者用户ID',
+  `to_id` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '接收者用户ID',
+  `message_body` text COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '消息内容',
+  `message_time` bigint NOT NULL COMMENT '发送时间',
+  `message_content_type` int NOT NULL COMMENT '消息类型',
+  `read_status` int NOT NULL COMMENT '阅读状态（1已读）',
+  `played_status` int NOT NULL DEFAULT '0' COMMENT '语音播放状态（1已播放）',
+  `extra` text COLLATE utf8mb4_unicode_ci COMMENT '扩展字段',
+  `del_flag` smallint NOT NULL COMMENT '删除标识（1正常，0删除）',
+  `sequence` bigint NOT NULL COMMENT '消息序列',
+  `message_random` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '随机标识',
+  `create_time` bigint DEFAULT NULL COMMENT '创建时间',
+  `update_time` bigint DEFAULT NULL COMMENT '更新时间',
+  `version` bigint DEFAULT NULL COMMENT '版本信息',
+  `reply_to` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '被引用的消息 ID',
+  `to_type` enum('User','Group') COLLATE utf8mb4_unicode_ci DEFAULT 'User' COMMENT '接收者类型：User=用户，Group=群组',
+  `file_url` varchar(512) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '文件URL',
+  `file_name` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '文件名',
+  `file_type` varchar(64) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '文件类型',
+  `archive_time` bigint NOT NULL COMMENT '归档时间',
+  PRIMARY KEY (`message_id`),
+  KEY `idx_private_archive_from` (`from_id`),
+  KEY `idx_private_archive_to` (`to_id`),
+  KEY `idx_sequence` (`sequence`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci COMMENT='单聊消息归档表：超过保留期限的消息从 im_single_message 迁移至此';
+/*!40101 SET character_set_client = @saved_cs_client */;
+
 --
 -- Table structure for table `im_user_data`
 --
@@ -283,6 +376,7 @@ CREATE TABLE `im_user_data` (
   `silent_flag` int NOT NULL COMMENT '禁言标识（1禁言）',
   `user_type` int NOT NULL COMMENT '用户类型（1普通用户，2客服，3机器人）',
   `del_flag` smallint NOT NULL COMMENT '删除标识（1正常，0删除）',
+  `last_seen_visibility` int NOT NULL DEFAULT '0' COMMENT '最后在线时间可见范围（0所有人，1仅好友，2所有人不可见）',
   `extra` varchar(1000) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '扩展字段',
   `create_time` bigint DEFAULT NULL COMMENT '创建时间',
   `update_time` bigint DEFAULT NULL COMMENT '更新时间',
@@ -302,6 +396,7 @@ CREATE TABLE `subscriptions` (
   `id` bigint unsigned NOT NULL AUTO_INCREMENT,
   `subscription_id` varchar(64) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '订阅ID，格式：sub_{uuid}',
   `user_id` bigint unsigned NOT NULL COMMENT '用户ID',
+  `device_type` varchar(32) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '设备类型（mobile/desktop/web 等，旧版客户端为空）',
   `device_info` varchar(255) COLLATE utf8mb4_unicode_ci DEFAULT NULL COMMENT '设备信息（可选）',
   `created_at` timestamp NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
   `updated_at` timestamp NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP COMMENT '更新时间',
@@ -310,6 +405,7 @@ CREATE TABLE `subscriptions` (
   UNIQUE KEY `subscription_id` (`subscription_id`),
   KEY `idx_subscription_id` (`subscription_id`),
   KEY `idx_user_id` (`user_id`),
+  KEY `idx_user_device_type` (`user_id`,`device_type`),
   KEY `idx_expires_at` (`expires_at`),
   CONSTRAINT `subscriptions_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
 ) ENGINE=InnoDB AUTO_INCREMENT=7 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;